@@ -0,0 +1,20 @@
+//! The auxiliary vector, which tells a program about itself and the system it's running on.
+//!
+//! A program starts with its stack pointer at `argc`, followed by the `argv` pointers and a null,
+//! the `envp` pointers and a null, and then the auxiliary vector: pairs of a type and a value,
+//! ending with [`AT_NULL`]. The stack pointer is 16-byte aligned. The types match Linux's.
+
+/// Ends the vector.
+pub const AT_NULL: u64 = 0;
+/// The address of the program headers in memory.
+pub const AT_PHDR: u64 = 3;
+/// The size of a program header.
+pub const AT_PHENT: u64 = 4;
+/// The number of program headers.
+pub const AT_PHNUM: u64 = 5;
+/// The page size.
+pub const AT_PAGESZ: u64 = 6;
+/// The program's entry point.
+pub const AT_ENTRY: u64 = 9;
+/// The address of 16 random bytes, for seeding stack protectors and the like.
+pub const AT_RANDOM: u64 = 25;
@@ -0,0 +1,99 @@
+use core::fmt;
+
+/// Why a system call failed.
+///
+/// The numbers match Linux's, for familiarity.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Errno(u16);
+
+impl Errno {
+    /// Bad file descriptor.
+    pub const EBADF: Errno = Errno(9);
+    /// Out of memory.
+    pub const ENOMEM: Errno = Errno(12);
+    /// Bad address: a pointer argument doesn't point at user memory with the right permissions.
+    pub const EFAULT: Errno = Errno(14);
+    /// Invalid argument.
+    pub const EINVAL: Errno = Errno(22);
+    /// No such system call.
+    pub const ENOSYS: Errno = Errno(38);
+
+    /// The highest error number [`decode`] recognizes.
+    pub const MAX: u16 = 4095;
+
+    /// The error with number `errno`, if it's in range.
+    pub const fn new(errno: u16) -> Option<Self> {
+        if errno == 0 || errno > Self::MAX {
+            None
+        } else {
+            Some(Self(errno))
+        }
+    }
+
+    pub const fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// The error's name, if it's one this crate knows.
+    pub const fn name(&self) -> Option<&'static str> {
+        Some(match *self {
+            Self::EBADF => "EBADF",
+            Self::ENOMEM => "ENOMEM",
+            Self::EFAULT => "EFAULT",
+            Self::EINVAL => "EINVAL",
+            Self::ENOSYS => "ENOSYS",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Debug for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "Errno({})", self.0),
+        }
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Turns a system call's result into the value it returns in `rax`.
+pub const fn encode(result: Result<u64, Errno>) -> u64 {
+    match result {
+        Ok(value) => value,
+        Err(errno) => (errno.0 as u64).wrapping_neg(),
+    }
+}
+
+/// Turns the value a system call returned in `rax` back into its result.
+///
+/// The highest [`Errno::MAX`] values are errors, so no system call returns a value that large.
+pub const fn decode(value: u64) -> Result<u64, Errno> {
+    let errno = value.wrapping_neg();
+    if errno != 0 && errno <= Errno::MAX as u64 {
+        Err(Errno(errno as u16))
+    } else {
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn errors_round_trip_through_return_values() {
+        for result in [Ok(0), Ok(42), Ok(u64::MAX - 4095), Err(Errno::EFAULT)] {
+            assert_eq!(result, decode(encode(result)));
+        }
+        assert_eq!(Err(Errno::ENOSYS), decode(-38i64 as u64));
+        assert_eq!(Some(Errno::ENOSYS), Errno::new(38));
+        assert_eq!(None, Errno::new(0));
+        assert_eq!(None, Errno::new(Errno::MAX + 1));
+    }
+}
@@ -0,0 +1,72 @@
+//! The interface between the Roxy kernel and user programs: system call numbers, error numbers
+//! and the structures system calls pass around.
+//!
+//! Everything here is part of the ABI, so numbers and layouts only ever get added to, never
+//! changed.
+//!
+//! A system call is made with the `syscall` instruction, with its number in `rax` and up to six
+//! arguments in `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`. It returns a value in `rax`, which is
+//! either a result or a negated [`Errno`]; [`decode`] tells them apart. `rcx` and `r11` are
+//! clobbered, and every other register is preserved.
+
+#![no_std]
+
+pub mod auxv;
+mod errno;
+pub mod nr;
+#[cfg(target_arch = "x86_64")]
+pub mod raw;
+
+pub use errno::{decode, encode, Errno};
+
+/// The file descriptor for standard input.
+pub const STDIN: u64 = 0;
+/// The file descriptor for standard output.
+pub const STDOUT: u64 = 1;
+/// The file descriptor for standard error.
+pub const STDERR: u64 = 2;
+
+/// The most bytes a single [`nr::WRITE`] writes. Larger writes are cut short.
+pub const MAX_WRITE: usize = 4096;
+
+/// A clock, for [`nr::CLOCK_GET`].
+pub type ClockId = u64;
+
+/// Time since boot, which never goes backwards.
+pub const CLOCK_MONOTONIC: ClockId = 0;
+/// Time since the UNIX epoch, in UTC.
+pub const CLOCK_REALTIME: ClockId = 1;
+
+/// A point in time or a duration, in seconds and nanoseconds.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timespec {
+    pub seconds: u64,
+    /// Always less than 1,000,000,000.
+    pub nanoseconds: u64,
+}
+
+impl Timespec {
+    pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self {
+            seconds: nanos / Self::NANOS_PER_SEC,
+            nanoseconds: nanos % Self::NANOS_PER_SEC,
+        }
+    }
+
+    /// Whether `nanoseconds` is in range.
+    pub const fn is_valid(&self) -> bool {
+        self.nanoseconds < Self::NANOS_PER_SEC
+    }
+}
+
+impl From<core::time::Duration> for Timespec {
+    fn from(duration: core::time::Duration) -> Self {
+        Self {
+            seconds: duration.as_secs(),
+            nanoseconds: duration.subsec_nanos().into(),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! System call numbers.
+
+/// Ends the calling thread's stay in user mode. Takes the exit status, and doesn't return.
+pub const EXIT: u64 = 0;
+/// Lets other threads run. Returns 0.
+pub const YIELD: u64 = 1;
+/// Writes bytes to a file descriptor. Takes the descriptor, a pointer to the bytes and their
+/// length, and returns how many were written, which is at most [`MAX_WRITE`](crate::MAX_WRITE).
+pub const WRITE: u64 = 2;
+/// Reads a clock. Takes a [`ClockId`](crate::ClockId) and a pointer to a
+/// [`Timespec`](crate::Timespec) to fill in. Returns 0.
+pub const CLOCK_GET: u64 = 3;
+/// Sleeps for at least as long as the [`Timespec`](crate::Timespec) it takes a pointer to. Returns
+/// 0.
+pub const SLEEP: u64 = 4;
+/// Returns the calling thread's ID.
+pub const THREAD_ID: u64 = 5;
+
+/// One more than the highest system call number.
+pub const COUNT: usize = 6;
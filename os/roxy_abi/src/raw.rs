@@ -0,0 +1,113 @@
+//! Making system calls, for user programs.
+//!
+//! These pass their arguments through untouched and return `rax` as is; pass it to
+//! [`decode`](crate::decode) for a `Result`.
+
+use core::arch::asm;
+
+/// Makes system call `nr` with no arguments.
+///
+/// # Safety
+///
+/// The system call must be safe to make, as its documentation describes.
+pub unsafe fn syscall0(nr: u64) -> u64 {
+    let ret;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") nr => ret,
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// Makes system call `nr` with one argument.
+///
+/// # Safety
+///
+/// As with [`syscall0`].
+pub unsafe fn syscall1(nr: u64, a0: u64) -> u64 {
+    let ret;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") nr => ret,
+            in("rdi") a0,
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// Makes system call `nr` with two arguments.
+///
+/// # Safety
+///
+/// As with [`syscall0`].
+pub unsafe fn syscall2(nr: u64, a0: u64, a1: u64) -> u64 {
+    let ret;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") nr => ret,
+            in("rdi") a0,
+            in("rsi") a1,
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// Makes system call `nr` with three arguments.
+///
+/// # Safety
+///
+/// As with [`syscall0`].
+pub unsafe fn syscall3(nr: u64, a0: u64, a1: u64, a2: u64) -> u64 {
+    let ret;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") nr => ret,
+            in("rdi") a0,
+            in("rsi") a1,
+            in("rdx") a2,
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack)
+        );
+    }
+    ret
+}
+
+/// Makes system call `nr` with six arguments.
+///
+/// # Safety
+///
+/// As with [`syscall0`].
+pub unsafe fn syscall6(nr: u64, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64, a5: u64) -> u64 {
+    let ret;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") nr => ret,
+            in("rdi") a0,
+            in("rsi") a1,
+            in("rdx") a2,
+            in("r10") a3,
+            in("r8") a4,
+            in("r9") a5,
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack)
+        );
+    }
+    ret
+}
@@ -0,0 +1,241 @@
+use alloc::{vec, vec::Vec};
+
+use super::{
+    AccessType, AmlError, BufferField, FieldKind, FieldUnit, Interpreter, Object, Path, PciAddress,
+    RegionSpace, UpdateRule, Value,
+};
+
+/// The width of each access to a field, in bits.
+fn access_width(field: &FieldUnit) -> u64 {
+    match field.access {
+        AccessType::Word => 16,
+        AccessType::DWord => 32,
+        AccessType::QWord => 64,
+        AccessType::Any | AccessType::Byte | AccessType::Buffer => 8,
+    }
+}
+
+fn get_bit(bytes: &[u8], bit: u64) -> bool {
+    bytes[(bit / 8) as usize] & 1 << (bit % 8) != 0
+}
+
+fn set_bit(bytes: &mut [u8], bit: u64, value: bool) {
+    let byte = &mut bytes[(bit / 8) as usize];
+    if value {
+        *byte |= 1 << (bit % 8);
+    } else {
+        *byte &= !(1 << (bit % 8));
+    }
+}
+
+/// Returns bits read from a field as an integer if they fit, or a buffer otherwise.
+fn field_value(bytes: Vec<u8>, bit_length: u64, integer_bytes: usize) -> Value {
+    if bit_length <= integer_bytes as u64 * 8 {
+        Value::Integer(
+            bytes
+                .iter()
+                .enumerate()
+                .fold(0, |v, (i, &b)| v | (b as u64) << (i * 8)),
+        )
+    } else {
+        Value::buffer(bytes)
+    }
+}
+
+/// The bytes to write to a field, padded or truncated to its length.
+fn field_bytes(value: &Value, bit_length: u64) -> Result<Vec<u8>, AmlError> {
+    let mut bytes = value.to_bytes(8)?;
+    bytes.resize(bit_length.div_ceil(8) as usize, 0);
+    Ok(bytes)
+}
+
+pub(super) fn read_buffer_field(
+    field: &BufferField,
+    integer_bytes: usize,
+) -> Result<Value, AmlError> {
+    let buffer = field.buffer.lock();
+    if field.bit_offset + field.bit_length > buffer.len() as u64 * 8 {
+        return Err(AmlError::IndexOutOfBounds);
+    }
+    let mut bytes = vec![0; field.bit_length.div_ceil(8) as usize];
+    for bit in 0..field.bit_length {
+        set_bit(&mut bytes, bit, get_bit(&buffer, field.bit_offset + bit));
+    }
+    Ok(field_value(bytes, field.bit_length, integer_bytes))
+}
+
+pub(super) fn write_buffer_field(field: &BufferField, value: &Value) -> Result<(), AmlError> {
+    // Convert before locking, in case the value is the buffer itself.
+    let bytes = field_bytes(value, field.bit_length)?;
+    let mut buffer = field.buffer.lock();
+    if field.bit_offset + field.bit_length > buffer.len() as u64 * 8 {
+        return Err(AmlError::IndexOutOfBounds);
+    }
+    for bit in 0..field.bit_length {
+        set_bit(&mut buffer, field.bit_offset + bit, get_bit(&bytes, bit));
+    }
+    Ok(())
+}
+
+impl Interpreter {
+    pub(super) fn read_field(&mut self, field: &FieldUnit) -> Result<Value, AmlError> {
+        let width = access_width(field);
+        let mut bytes = vec![0; field.bit_length.div_ceil(8) as usize];
+        let end = field.bit_offset + field.bit_length;
+        for unit in field.bit_offset / width..end.div_ceil(width) {
+            let unit_start = unit * width;
+            let value = self.read_unit(field, unit_start / 8, width)?;
+            for bit in field.bit_offset.max(unit_start)..end.min(unit_start + width) {
+                let set = value & 1 << (bit - unit_start) != 0;
+                set_bit(&mut bytes, bit - field.bit_offset, set);
+            }
+        }
+        Ok(field_value(bytes, field.bit_length, self.integer_bytes))
+    }
+
+    pub(super) fn write_field(&mut self, field: &FieldUnit, value: &Value) -> Result<(), AmlError> {
+        let width = access_width(field);
+        let bytes = field_bytes(value, field.bit_length)?;
+        let end = field.bit_offset + field.bit_length;
+        for unit in field.bit_offset / width..end.div_ceil(width) {
+            let unit_start = unit * width;
+            let first = field.bit_offset.max(unit_start);
+            let last = end.min(unit_start + width);
+
+            let mut value = if first == unit_start && last == unit_start + width {
+                0
+            } else {
+                match field.update {
+                    UpdateRule::Preserve => self.read_unit(field, unit_start / 8, width)?,
+                    UpdateRule::WriteAsOnes => u64::MAX >> (64 - width),
+                    UpdateRule::WriteAsZeros => 0,
+                }
+            };
+            for bit in first..last {
+                let mask = 1 << (bit - unit_start);
+                if get_bit(&bytes, bit - field.bit_offset) {
+                    value |= mask;
+                } else {
+                    value &= !mask;
+                }
+            }
+            self.write_unit(field, unit_start / 8, width, value)?;
+        }
+        Ok(())
+    }
+
+    fn read_unit(&mut self, field: &FieldUnit, offset: u64, width: u64) -> Result<u64, AmlError> {
+        match &field.kind {
+            FieldKind::Region(region) => self.read_region(region, offset, width),
+            FieldKind::Index { index, data } => {
+                self.store_named(index, Value::Integer(offset))?;
+                self.eval_named(data, None)?.as_integer()
+            }
+            FieldKind::Bank {
+                region,
+                bank,
+                value,
+            } => {
+                self.store_named(bank, Value::Integer(*value))?;
+                self.read_region(region, offset, width)
+            }
+        }
+    }
+
+    fn write_unit(
+        &mut self,
+        field: &FieldUnit,
+        offset: u64,
+        width: u64,
+        value: u64,
+    ) -> Result<(), AmlError> {
+        match &field.kind {
+            FieldKind::Region(region) => self.write_region(region, offset, width, value),
+            FieldKind::Index { index, data } => {
+                self.store_named(index, Value::Integer(offset))?;
+                self.store_named(data, Value::Integer(value))
+            }
+            FieldKind::Bank {
+                region,
+                bank,
+                value: bank_value,
+            } => {
+                self.store_named(bank, Value::Integer(*bank_value))?;
+                self.write_region(region, offset, width, value)
+            }
+        }
+    }
+
+    fn region(&self, path: &Path) -> Result<super::OpRegion, AmlError> {
+        match self.namespace.get(path) {
+            Some(Object::OpRegion(region)) => Ok(*region),
+            _ => Err(AmlError::WrongType("OperationRegion")),
+        }
+    }
+
+    fn read_region(&mut self, path: &Path, offset: u64, width: u64) -> Result<u64, AmlError> {
+        let region = self.region(path)?;
+        let address = region.offset + offset;
+        let width = width as u8;
+        match region.space {
+            RegionSpace::SystemMemory => Ok(self.handler.read_memory(address, width)),
+            RegionSpace::SystemIo => Ok(self.handler.read_io(address as u16, width)),
+            RegionSpace::PciConfig => {
+                let pci = self.pci_address(path)?;
+                Ok(self.handler.read_pci(pci, address as u16, width))
+            }
+            space => Err(AmlError::UnsupportedRegionSpace(space)),
+        }
+    }
+
+    fn write_region(
+        &mut self,
+        path: &Path,
+        offset: u64,
+        width: u64,
+        value: u64,
+    ) -> Result<(), AmlError> {
+        let region = self.region(path)?;
+        let address = region.offset + offset;
+        let width = width as u8;
+        match region.space {
+            RegionSpace::SystemMemory => self.handler.write_memory(address, width, value),
+            RegionSpace::SystemIo => self.handler.write_io(address as u16, width, value),
+            RegionSpace::PciConfig => {
+                let pci = self.pci_address(path)?;
+                self.handler.write_pci(pci, address as u16, width, value);
+            }
+            space => return Err(AmlError::UnsupportedRegionSpace(space)),
+        }
+        Ok(())
+    }
+
+    /// Works out which PCI function a configuration space region belongs to, from the `_ADR` of
+    /// the device it's declared in and the `_BBN` and `_SEG` of the root bridge above that.
+    fn pci_address(&mut self, region: &Path) -> Result<PciAddress, AmlError> {
+        let device = region.parent().ok_or(AmlError::InvalidName)?;
+        let address = self
+            .evaluate_integer_if_present(&device.child("_ADR"))?
+            .unwrap_or(0);
+
+        let mut bus = None;
+        let mut segment = None;
+        let mut ancestor = Some(device);
+        while let Some(path) = ancestor {
+            if bus.is_none() {
+                bus = self.evaluate_integer_if_present(&path.child("_BBN"))?;
+            }
+            if segment.is_none() {
+                segment = self.evaluate_integer_if_present(&path.child("_SEG"))?;
+            }
+            ancestor = path.parent();
+        }
+
+        Ok(PciAddress {
+            segment: segment.unwrap_or(0) as u16,
+            bus: bus.unwrap_or(0) as u8,
+            device: (address >> 16) as u8,
+            function: address as u8,
+        })
+    }
+}
@@ -0,0 +1,172 @@
+use alloc::collections::BTreeMap;
+use core::time::Duration;
+use spinning_top::Spinlock;
+use x86_64::{instructions::port::Port, PhysAddr, VirtAddr};
+
+use super::{Handler, PciAddress};
+use crate::{acpi, sched, time, vmm};
+
+const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
+const PCI_CONFIG_DATA: u16 = 0xCFC;
+const PAGE_SIZE: u64 = 4096;
+
+/// Accesses the real hardware.
+pub struct KernelHandler {
+    /// Physical pages that have been mapped for memory operation regions.
+    pages: Spinlock<BTreeMap<u64, VirtAddr>>,
+}
+
+impl KernelHandler {
+    pub fn new() -> Self {
+        Self {
+            pages: Spinlock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Maps the page holding `address`, reusing the mapping if we've seen the page before.
+    fn map(&self, address: u64) -> VirtAddr {
+        let page = address & !(PAGE_SIZE - 1);
+        let mut pages = self.pages.lock();
+        let virt = *pages.entry(page).or_insert_with(|| {
+            vmm::get()
+                .map_mmio(PhysAddr::new(page), PAGE_SIZE)
+                .expect("to map an AML memory region")
+        });
+        virt + (address - page)
+    }
+
+    /// The ECAM address of a function's configuration space, if the MCFG covers it.
+    fn ecam(&self, address: PciAddress, offset: u16) -> Option<VirtAddr> {
+        let entry = acpi::mcfg()?
+            .entries
+            .iter()
+            .find(|e| e.segment_group == address.segment)?;
+        let base = entry.config_address(address.bus, address.device, address.function)?;
+        Some(self.map(base.as_u64() + offset as u64))
+    }
+
+    /// Selects a configuration space dword through the legacy I/O port mechanism.
+    unsafe fn select_legacy(address: PciAddress, offset: u16) {
+        let value = 1 << 31
+            | (address.bus as u32) << 16
+            | (address.device as u32) << 11
+            | (address.function as u32) << 8
+            | (offset as u32 & 0xFC);
+        unsafe { Port::<u32>::new(PCI_CONFIG_ADDRESS).write(value) };
+    }
+}
+
+impl Default for KernelHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe fn read_ptr(ptr: VirtAddr, width: u8) -> u64 {
+    unsafe {
+        match width {
+            8 => ptr.as_ptr::<u8>().read_volatile() as u64,
+            16 => ptr.as_ptr::<u16>().read_volatile() as u64,
+            32 => ptr.as_ptr::<u32>().read_volatile() as u64,
+            _ => ptr.as_ptr::<u64>().read_volatile(),
+        }
+    }
+}
+
+unsafe fn write_ptr(ptr: VirtAddr, width: u8, value: u64) {
+    unsafe {
+        match width {
+            8 => ptr.as_mut_ptr::<u8>().write_volatile(value as u8),
+            16 => ptr.as_mut_ptr::<u16>().write_volatile(value as u16),
+            32 => ptr.as_mut_ptr::<u32>().write_volatile(value as u32),
+            _ => ptr.as_mut_ptr::<u64>().write_volatile(value),
+        }
+    }
+}
+
+unsafe fn read_port(port: u16, width: u8) -> u64 {
+    unsafe {
+        match width {
+            8 => Port::<u8>::new(port).read() as u64,
+            16 => Port::<u16>::new(port).read() as u64,
+            32 => Port::<u32>::new(port).read() as u64,
+            // There are no 64 bit ports, so split the access.
+            _ => read_port(port, 32) | read_port(port + 4, 32) << 32,
+        }
+    }
+}
+
+unsafe fn write_port(port: u16, width: u8, value: u64) {
+    unsafe {
+        match width {
+            8 => Port::<u8>::new(port).write(value as u8),
+            16 => Port::<u16>::new(port).write(value as u16),
+            32 => Port::<u32>::new(port).write(value as u32),
+            _ => {
+                write_port(port, 32, value & 0xFFFF_FFFF);
+                write_port(port + 4, 32, value >> 32);
+            }
+        }
+    }
+}
+
+// SAFETY: Firmware is trusted to describe its own hardware correctly in its operation regions,
+// which is where every address below comes from.
+impl Handler for KernelHandler {
+    fn read_memory(&self, address: u64, width: u8) -> u64 {
+        unsafe { read_ptr(self.map(address), width) }
+    }
+
+    fn write_memory(&self, address: u64, width: u8, value: u64) {
+        unsafe { write_ptr(self.map(address), width, value) }
+    }
+
+    fn read_io(&self, port: u16, width: u8) -> u64 {
+        unsafe { read_port(port, width) }
+    }
+
+    fn write_io(&self, port: u16, width: u8, value: u64) {
+        unsafe { write_port(port, width, value) }
+    }
+
+    fn read_pci(&self, address: PciAddress, offset: u16, width: u8) -> u64 {
+        if let Some(ptr) = self.ecam(address, offset) {
+            return unsafe { read_ptr(ptr, width) };
+        }
+        unsafe {
+            Self::select_legacy(address, offset);
+            read_port(PCI_CONFIG_DATA + (offset & 3), width)
+        }
+    }
+
+    fn write_pci(&self, address: PciAddress, offset: u16, width: u8, value: u64) {
+        if let Some(ptr) = self.ecam(address, offset) {
+            return unsafe { write_ptr(ptr, width, value) };
+        }
+        unsafe {
+            Self::select_legacy(address, offset);
+            write_port(PCI_CONFIG_DATA + (offset & 3), width, value)
+        }
+    }
+
+    fn stall(&self, micros: u64) {
+        time::delay(Duration::from_micros(micros));
+    }
+
+    fn sleep(&self, millis: u64) {
+        let duration = Duration::from_millis(millis);
+        if sched::can_sleep() && time::calibration().is_some() {
+            sched::sleep_until(time::now() + duration);
+        } else {
+            // During boot, or in a context that can't sleep.
+            time::delay(duration);
+        }
+    }
+
+    fn timer(&self) -> u64 {
+        (time::now()
+            .duration_since(time::Instant::default())
+            .as_nanos()
+            / 100) as u64
+    }
+}
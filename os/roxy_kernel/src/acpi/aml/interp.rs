@@ -0,0 +1,1333 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::cmp::Ordering;
+
+use super::{
+    field, opcode::*, AccessType, AmlError, BufferField, FieldKind, FieldUnit, Interpreter, Method,
+    MethodBody, NameSeg, NameString, Object, OpRegion, Path, Reference, UpdateRule, Value,
+};
+
+/// How deeply methods may call each other.
+const MAX_DEPTH: usize = 64;
+/// How many times a `While` may loop before we assume the firmware is stuck.
+const MAX_LOOP_ITERATIONS: u64 = 0x10_0000;
+/// What `Revision` reports.
+const INTERPRETER_REVISION: u64 = 1;
+
+/// A position in a stream of AML.
+pub(super) struct Cursor {
+    bytes: &'static [u8],
+    pos: usize,
+}
+
+impl Cursor {
+    pub(super) fn new(bytes: &'static [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn peek(&self) -> Result<u8, AmlError> {
+        self.peek_at(0).ok_or(AmlError::UnexpectedEnd)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(self.pos + offset).copied()
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), AmlError> {
+        self.take(n).map(|_| ())
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'static [u8], AmlError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + n)
+            .ok_or(AmlError::UnexpectedEnd)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'static [u8] {
+        let rest = &self.bytes[self.pos.min(self.bytes.len())..];
+        self.pos = self.bytes.len();
+        rest
+    }
+
+    fn byte(&mut self) -> Result<u8, AmlError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16, AmlError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn dword(&mut self) -> Result<u32, AmlError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn qword(&mut self) -> Result<u64, AmlError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a PkgLength, which counts its own bytes too.
+    fn pkg_length(&mut self) -> Result<usize, AmlError> {
+        let lead = self.byte()?;
+        let extra = (lead >> 6) as usize;
+        if extra == 0 {
+            return Ok((lead & 0x3F) as usize);
+        }
+        let mut length = (lead & 0x0F) as usize;
+        for i in 0..extra {
+            length |= (self.byte()? as usize) << (4 + 8 * i);
+        }
+        Ok(length)
+    }
+
+    /// Reads a PkgLength and returns the rest of the package it describes.
+    fn pkg(&mut self) -> Result<&'static [u8], AmlError> {
+        let start = self.pos;
+        let end = start + self.pkg_length()?;
+        if end < self.pos || end > self.bytes.len() {
+            return Err(AmlError::UnexpectedEnd);
+        }
+        let body = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(body)
+    }
+
+    fn name_seg(&mut self) -> Result<NameSeg, AmlError> {
+        let bytes: [u8; 4] = self.take(4)?.try_into().unwrap();
+        if !NameSeg::is_lead_char(bytes[0]) || !bytes[1..].iter().all(|&c| NameSeg::is_name_char(c))
+        {
+            return Err(AmlError::InvalidName);
+        }
+        Ok(NameSeg(bytes))
+    }
+
+    fn name_string(&mut self) -> Result<NameString, AmlError> {
+        let mut name = NameString {
+            root: false,
+            parents: 0,
+            segments: Vec::new(),
+        };
+        if self.peek()? == ROOT_CHAR {
+            self.byte()?;
+            name.root = true;
+        } else {
+            while self.peek()? == PARENT_PREFIX {
+                self.byte()?;
+                name.parents += 1;
+            }
+        }
+
+        let count = match self.peek()? {
+            ZERO => {
+                self.byte()?;
+                0
+            }
+            DUAL_NAME_PREFIX => {
+                self.byte()?;
+                2
+            }
+            MULTI_NAME_PREFIX => {
+                self.byte()?;
+                self.byte()? as usize
+            }
+            c if NameSeg::is_lead_char(c) => 1,
+            _ => return Err(AmlError::InvalidName),
+        };
+        for _ in 0..count {
+            name.segments.push(self.name_seg()?);
+        }
+        Ok(name)
+    }
+}
+
+fn is_name_start(c: u8) -> bool {
+    matches!(
+        c,
+        ROOT_CHAR | PARENT_PREFIX | DUAL_NAME_PREFIX | MULTI_NAME_PREFIX
+    ) || NameSeg::is_lead_char(c)
+}
+
+/// The arguments and locals of a running method.
+pub(super) struct Frame {
+    args: Vec<Value>,
+    locals: [Value; 8],
+    /// Objects the method has created, which go away when it returns. `None` while loading a
+    /// table, where objects are permanent.
+    created: Option<Vec<Path>>,
+}
+
+impl Frame {
+    pub(super) fn table() -> Self {
+        Self {
+            args: Vec::new(),
+            locals: Default::default(),
+            created: None,
+        }
+    }
+
+    fn method(args: Vec<Value>) -> Self {
+        Self {
+            args,
+            locals: Default::default(),
+            created: Some(Vec::new()),
+        }
+    }
+}
+
+pub(super) enum Flow {
+    Normal,
+    Return(Value),
+    Break,
+    Continue,
+}
+
+/// Somewhere a result can be stored.
+#[derive(Debug)]
+enum Target {
+    Null,
+    Local(usize),
+    Arg(usize),
+    Debug,
+    Name(Path),
+    Reference(Reference),
+}
+
+impl Interpreter {
+    fn mask(&self, value: u64) -> u64 {
+        if self.integer_bytes == 4 {
+            value & 0xFFFF_FFFF
+        } else {
+            value
+        }
+    }
+
+    fn ones(&self) -> u64 {
+        self.mask(u64::MAX)
+    }
+
+    fn boolean(&self, value: bool) -> Value {
+        Value::Integer(if value { self.ones() } else { 0 })
+    }
+
+    /// Creates a named object, to be cleaned up with the method if one is running.
+    fn define(
+        &mut self,
+        name: &NameString,
+        scope: &Path,
+        frame: &mut Frame,
+        object: Object,
+    ) -> Result<Path, AmlError> {
+        let path = name.resolve(scope).ok_or(AmlError::InvalidName)?;
+        self.namespace.insert(path.clone(), object)?;
+        if let Some(created) = &mut frame.created {
+            created.push(path.clone());
+        }
+        Ok(path)
+    }
+
+    fn search(&self, name: &NameString, scope: &Path) -> Result<Path, AmlError> {
+        self.namespace
+            .search(name, scope)
+            .ok_or_else(|| AmlError::NameNotFound(name.to_string()))
+    }
+
+    pub(super) fn invoke(
+        &mut self,
+        path: &Path,
+        method: &Method,
+        args: Vec<Value>,
+    ) -> Result<Value, AmlError> {
+        if args.len() != method.arg_count as usize {
+            return Err(AmlError::ArgumentCount {
+                expected: method.arg_count,
+                found: args.len(),
+            });
+        }
+        match method.body {
+            MethodBody::Native(f) => f(&args),
+            MethodBody::Aml(body) => {
+                if self.depth >= MAX_DEPTH {
+                    return Err(AmlError::RecursionLimit);
+                }
+                self.depth += 1;
+                let mut frame = Frame::method(args);
+                let result = self.exec_term_list(&mut Cursor::new(body), path, &mut frame);
+                self.depth -= 1;
+
+                for created in frame.created.into_iter().flatten().rev() {
+                    self.namespace.remove(&created);
+                }
+                match result? {
+                    Flow::Return(value) => Ok(value),
+                    _ => Ok(Value::Uninitialized),
+                }
+            }
+        }
+    }
+
+    pub(super) fn exec_term_list(
+        &mut self,
+        c: &mut Cursor,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<Flow, AmlError> {
+        while !c.at_end() {
+            match self.exec_term(c, scope, frame)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec_term(
+        &mut self,
+        c: &mut Cursor,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<Flow, AmlError> {
+        match c.peek()? {
+            NAME => {
+                c.byte()?;
+                let name = c.name_string()?;
+                let value = self.eval(c, scope, frame)?;
+                self.define(&name, scope, frame, Object::Name(value))?;
+            }
+            SCOPE => {
+                c.byte()?;
+                let mut body = Cursor::new(c.pkg()?);
+                let name = body.name_string()?;
+                let path = name.resolve(scope).ok_or(AmlError::InvalidName)?;
+                if self.namespace.get(&path).is_none() {
+                    return Err(AmlError::NameNotFound(name.to_string()));
+                }
+                return self.exec_term_list(&mut body, &path, frame);
+            }
+            ALIAS => {
+                c.byte()?;
+                let source = c.name_string()?;
+                let alias = c.name_string()?;
+                let target = self.search(&source, scope)?;
+                self.define(&alias, scope, frame, Object::Alias(target))?;
+            }
+            METHOD => {
+                c.byte()?;
+                let mut body = Cursor::new(c.pkg()?);
+                let name = body.name_string()?;
+                let flags = body.byte()?;
+                let method = Method {
+                    arg_count: flags & 0b111,
+                    serialized: flags & 0b1000 != 0,
+                    sync_level: flags >> 4,
+                    body: MethodBody::Aml(body.rest()),
+                };
+                self.define(&name, scope, frame, Object::Method(method))?;
+            }
+            EXTERNAL => {
+                c.byte()?;
+                c.name_string()?;
+                c.skip(2)?;
+            }
+            op @ (CREATE_BIT_FIELD | CREATE_BYTE_FIELD | CREATE_WORD_FIELD | CREATE_DWORD_FIELD
+            | CREATE_QWORD_FIELD) => {
+                c.byte()?;
+                let source = self.eval(c, scope, frame)?;
+                let index = self.eval_integer(c, scope, frame)?;
+                let name = c.name_string()?;
+                let (bit_offset, bit_length) = match op {
+                    CREATE_BIT_FIELD => (index, 1),
+                    CREATE_BYTE_FIELD => (index * 8, 8),
+                    CREATE_WORD_FIELD => (index * 8, 16),
+                    CREATE_DWORD_FIELD => (index * 8, 32),
+                    _ => (index * 8, 64),
+                };
+                self.create_buffer_field(source, bit_offset, bit_length, &name, scope, frame)?;
+            }
+            IF => return self.exec_if(c, scope, frame),
+            ELSE => {
+                // An Else without an If.
+                c.byte()?;
+                c.pkg()?;
+            }
+            WHILE => return self.exec_while(c, scope, frame),
+            RETURN => {
+                c.byte()?;
+                let value = self.eval(c, scope, frame)?;
+                return Ok(Flow::Return(value));
+            }
+            BREAK => {
+                c.byte()?;
+                return Ok(Flow::Break);
+            }
+            CONTINUE => {
+                c.byte()?;
+                return Ok(Flow::Continue);
+            }
+            NOOP | BREAKPOINT => {
+                c.byte()?;
+            }
+            NOTIFY => {
+                c.byte()?;
+                let target = self.target(c, scope, frame)?;
+                let value = self.eval_integer(c, scope, frame)?;
+                log::debug!("AML notify {:?}: {:#X}", target, value);
+            }
+            EXT_PREFIX => return self.exec_ext(c, scope, frame),
+            _ => {
+                self.eval(c, scope, frame)?;
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec_ext(
+        &mut self,
+        c: &mut Cursor,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<Flow, AmlError> {
+        let op = c.peek_at(1).ok_or(AmlError::UnexpectedEnd)?;
+        match op {
+            EXT_MUTEX => {
+                c.skip(2)?;
+                let name = c.name_string()?;
+                let sync_level = c.byte()? & 0xF;
+                self.define(&name, scope, frame, Object::Mutex { sync_level })?;
+            }
+            EXT_EVENT => {
+                c.skip(2)?;
+                let name = c.name_string()?;
+                self.define(&name, scope, frame, Object::Event)?;
+            }
+            EXT_OP_REGION => {
+                c.skip(2)?;
+                let name = c.name_string()?;
+                let space = c.byte()?.into();
+                let offset = self.eval_integer(c, scope, frame)?;
+                let length = self.eval_integer(c, scope, frame)?;
+                let region = OpRegion {
+                    space,
+                    offset,
+                    length,
+                };
+                self.define(&name, scope, frame, Object::OpRegion(region))?;
+            }
+            EXT_FIELD => {
+                c.skip(2)?;
+                let mut body = Cursor::new(c.pkg()?);
+                let region = self.search(&body.name_string()?, scope)?;
+                let flags = body.byte()?;
+                self.define_fields(&mut body, FieldKind::Region(region), flags, scope, frame)?;
+            }
+            EXT_INDEX_FIELD => {
+                c.skip(2)?;
+                let mut body = Cursor::new(c.pkg()?);
+                let index = self.search(&body.name_string()?, scope)?;
+                let data = self.search(&body.name_string()?, scope)?;
+                let flags = body.byte()?;
+                self.define_fields(
+                    &mut body,
+                    FieldKind::Index { index, data },
+                    flags,
+                    scope,
+                    frame,
+                )?;
+            }
+            EXT_BANK_FIELD => {
+                c.skip(2)?;
+                let mut body = Cursor::new(c.pkg()?);
+                let region = self.search(&body.name_string()?, scope)?;
+                let bank = self.search(&body.name_string()?, scope)?;
+                let value = self.eval_integer(&mut body, scope, frame)?;
+                let flags = body.byte()?;
+                let kind = FieldKind::Bank {
+                    region,
+                    bank,
+                    value,
+                };
+                self.define_fields(&mut body, kind, flags, scope, frame)?;
+            }
+            EXT_DEVICE | EXT_PROCESSOR | EXT_POWER_RES | EXT_THERMAL_ZONE => {
+                c.skip(2)?;
+                let mut body = Cursor::new(c.pkg()?);
+                let name = body.name_string()?;
+                let object = match op {
+                    EXT_DEVICE => Object::Device,
+                    EXT_PROCESSOR => Object::Processor {
+                        id: body.byte()?,
+                        block_address: body.dword()?,
+                        block_length: body.byte()?,
+                    },
+                    EXT_POWER_RES => Object::PowerResource {
+                        system_level: body.byte()?,
+                        resource_order: body.word()?,
+                    },
+                    _ => Object::ThermalZone,
+                };
+                let path = self.define(&name, scope, frame, object)?;
+                return self.exec_term_list(&mut body, &path, frame);
+            }
+            EXT_CREATE_FIELD => {
+                c.skip(2)?;
+                let source = self.eval(c, scope, frame)?;
+                let bit_offset = self.eval_integer(c, scope, frame)?;
+                let bit_length = self.eval_integer(c, scope, frame)?;
+                let name = c.name_string()?;
+                self.create_buffer_field(source, bit_offset, bit_length, &name, scope, frame)?;
+            }
+            EXT_STALL => {
+                c.skip(2)?;
+                let micros = self.eval_integer(c, scope, frame)?;
+                self.handler.stall(micros);
+            }
+            EXT_SLEEP => {
+                c.skip(2)?;
+                let millis = self.eval_integer(c, scope, frame)?;
+                self.handler.sleep(millis);
+            }
+            // There's only ever one thread in the interpreter, so synchronization objects are
+            // no-ops.
+            EXT_RELEASE | EXT_SIGNAL | EXT_RESET => {
+                c.skip(2)?;
+                self.target(c, scope, frame)?;
+            }
+            EXT_FATAL => {
+                c.skip(2)?;
+                let kind = c.byte()?;
+                let code = c.dword()?;
+                let arg = self.eval_integer(c, scope, frame)?;
+                return Err(AmlError::Fatal { kind, code, arg });
+            }
+            EXT_DATA_REGION => return Err(AmlError::Unsupported("DataTableRegion")),
+            EXT_LOAD | EXT_LOAD_TABLE => return Err(AmlError::Unsupported("Load")),
+            _ => {
+                self.eval(c, scope, frame)?;
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec_if(
+        &mut self,
+        c: &mut Cursor,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<Flow, AmlError> {
+        c.byte()?;
+        let mut body = Cursor::new(c.pkg()?);
+        let predicate = self.eval_integer(&mut body, scope, frame)? != 0;
+        let else_body = if c.peek_at(0) == Some(ELSE) {
+            c.byte()?;
+            Some(c.pkg()?)
+        } else {
+            None
+        };
+
+        if predicate {
+            self.exec_term_list(&mut body, scope, frame)
+        } else if let Some(else_body) = else_body {
+            self.exec_term_list(&mut Cursor::new(else_body), scope, frame)
+        } else {
+            Ok(Flow::Normal)
+        }
+    }
+
+    fn exec_while(
+        &mut self,
+        c: &mut Cursor,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<Flow, AmlError> {
+        c.byte()?;
+        let body = c.pkg()?;
+        for _ in 0..MAX_LOOP_ITERATIONS {
+            let mut body = Cursor::new(body);
+            if self.eval_integer(&mut body, scope, frame)? == 0 {
+                return Ok(Flow::Normal);
+            }
+            match self.exec_term_list(&mut body, scope, frame)? {
+                Flow::Break => return Ok(Flow::Normal),
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+                Flow::Normal | Flow::Continue => {}
+            }
+        }
+        Err(AmlError::LoopLimit)
+    }
+
+    fn define_fields(
+        &mut self,
+        body: &mut Cursor,
+        kind: FieldKind,
+        flags: u8,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<(), AmlError> {
+        const RESERVED_FIELD: u8 = 0x00;
+        const ACCESS_FIELD: u8 = 0x01;
+        const CONNECT_FIELD: u8 = 0x02;
+        const EXTENDED_ACCESS_FIELD: u8 = 0x03;
+
+        let mut access = AccessType::from_flags(flags);
+        let update = UpdateRule::from_flags(flags);
+        let mut bit_offset = 0;
+        while !body.at_end() {
+            match body.peek()? {
+                RESERVED_FIELD => {
+                    body.byte()?;
+                    bit_offset += body.pkg_length()? as u64;
+                }
+                ACCESS_FIELD => {
+                    body.byte()?;
+                    access = AccessType::from_flags(body.byte()?);
+                    body.byte()?;
+                }
+                CONNECT_FIELD => {
+                    body.byte()?;
+                    if body.peek()? == BUFFER {
+                        self.eval(body, scope, frame)?;
+                    } else {
+                        body.name_string()?;
+                    }
+                }
+                EXTENDED_ACCESS_FIELD => {
+                    body.byte()?;
+                    access = AccessType::from_flags(body.byte()?);
+                    body.skip(2)?;
+                }
+                _ => {
+                    let name = NameString {
+                        root: false,
+                        parents: 0,
+                        segments: alloc::vec![body.name_seg()?],
+                    };
+                    let bit_length = body.pkg_length()? as u64;
+                    let field = FieldUnit {
+                        kind: kind.clone(),
+                        bit_offset,
+                        bit_length,
+                        access,
+                        update,
+                    };
+                    self.define(&name, scope, frame, Object::Field(field))?;
+                    bit_offset += bit_length;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn create_buffer_field(
+        &mut self,
+        source: Value,
+        bit_offset: u64,
+        bit_length: u64,
+        name: &NameString,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<(), AmlError> {
+        let Value::Buffer(buffer) = self.resolve_reference(source)? else {
+            return Err(AmlError::WrongType("Buffer"));
+        };
+        if bit_offset + bit_length > buffer.lock().len() as u64 * 8 {
+            return Err(AmlError::IndexOutOfBounds);
+        }
+        let field = BufferField {
+            buffer,
+            bit_offset,
+            bit_length,
+        };
+        self.define(name, scope, frame, Object::BufferField(field))?;
+        Ok(())
+    }
+
+    fn eval_integer(
+        &mut self,
+        c: &mut Cursor,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<u64, AmlError> {
+        let value = self.eval(c, scope, frame)?;
+        self.integer(value)
+    }
+
+    fn integer(&mut self, value: Value) -> Result<u64, AmlError> {
+        self.resolve_reference(value)?.as_integer()
+    }
+
+    /// Reads through a reference, or returns anything else as it is.
+    fn resolve_reference(&mut self, value: Value) -> Result<Value, AmlError> {
+        match value {
+            Value::Reference(r) => self.deref(&r),
+            other => Ok(other),
+        }
+    }
+
+    /// Evaluates a TermArg.
+    fn eval(&mut self, c: &mut Cursor, scope: &Path, frame: &mut Frame) -> Result<Value, AmlError> {
+        let op = c.peek()?;
+        if is_name_start(op) {
+            let name = c.name_string()?;
+            let path = self.search(&name, scope)?;
+            return self.eval_named(&path, Some((c, scope, frame)));
+        }
+
+        c.byte()?;
+        match op {
+            ZERO => Ok(Value::Integer(0)),
+            ONE => Ok(Value::Integer(1)),
+            ONES => Ok(Value::Integer(self.ones())),
+            BYTE_PREFIX => Ok(Value::Integer(c.byte()? as u64)),
+            WORD_PREFIX => Ok(Value::Integer(c.word()? as u64)),
+            DWORD_PREFIX => Ok(Value::Integer(c.dword()? as u64)),
+            QWORD_PREFIX => Ok(Value::Integer(self.mask(c.qword()?))),
+            STRING_PREFIX => {
+                let mut s = String::new();
+                loop {
+                    match c.byte()? {
+                        0 => break,
+                        b => s.push(b as char),
+                    }
+                }
+                Ok(Value::String(s))
+            }
+            BUFFER => {
+                let mut body = Cursor::new(c.pkg()?);
+                let size = self.eval_integer(&mut body, scope, frame)? as usize;
+                let mut bytes = body.rest().to_vec();
+                bytes.resize(size.max(bytes.len()), 0);
+                Ok(Value::buffer(bytes))
+            }
+            PACKAGE => {
+                let mut body = Cursor::new(c.pkg()?);
+                let count = body.byte()? as usize;
+                self.package(&mut body, count, scope, frame)
+            }
+            VAR_PACKAGE => {
+                let mut body = Cursor::new(c.pkg()?);
+                let count = self.eval_integer(&mut body, scope, frame)? as usize;
+                self.package(&mut body, count, scope, frame)
+            }
+            LOCAL0..=LOCAL7 => Ok(frame.locals[(op - LOCAL0) as usize].clone()),
+            ARG0..=ARG6 => {
+                // Arguments holding references are dereferenced automatically.
+                let arg = frame.args.get((op - ARG0) as usize).cloned();
+                self.resolve_reference(arg.unwrap_or_default())
+            }
+            STORE => {
+                let value = self.eval(c, scope, frame)?;
+                let value = self.resolve_reference(value)?;
+                let target = self.target(c, scope, frame)?;
+                self.store(&target, value.clone(), frame)?;
+                Ok(value)
+            }
+            REF_OF => match self.target(c, scope, frame)? {
+                Target::Name(path) => Ok(Value::Reference(Reference::Named(path))),
+                Target::Reference(r) => Ok(Value::Reference(r)),
+                _ => Err(AmlError::Unsupported("RefOf on a local or argument")),
+            },
+            ADD | SUBTRACT | MULTIPLY | SHIFT_LEFT | SHIFT_RIGHT | AND | NAND | OR | NOR | XOR
+            | MOD => {
+                let a = self.eval_integer(c, scope, frame)?;
+                let b = self.eval_integer(c, scope, frame)?;
+                let result = match op {
+                    ADD => a.wrapping_add(b),
+                    SUBTRACT => a.wrapping_sub(b),
+                    MULTIPLY => a.wrapping_mul(b),
+                    SHIFT_LEFT => a.checked_shl(b.try_into().unwrap_or(u32::MAX)).unwrap_or(0),
+                    SHIFT_RIGHT => a.checked_shr(b.try_into().unwrap_or(u32::MAX)).unwrap_or(0),
+                    AND => a & b,
+                    NAND => !(a & b),
+                    OR => a | b,
+                    NOR => !(a | b),
+                    XOR => a ^ b,
+                    _ => a.checked_rem(b).ok_or(AmlError::DivideByZero)?,
+                };
+                let result = Value::Integer(self.mask(result));
+                self.store_result(c, scope, frame, result)
+            }
+            NOT | FIND_SET_LEFT_BIT | FIND_SET_RIGHT_BIT => {
+                let a = self.eval_integer(c, scope, frame)?;
+                let result = match op {
+                    NOT => !a,
+                    FIND_SET_LEFT_BIT => (64 - a.leading_zeros()) as u64,
+                    _ if a == 0 => 0,
+                    _ => (a.trailing_zeros() + 1) as u64,
+                };
+                let result = Value::Integer(self.mask(result));
+                self.store_result(c, scope, frame, result)
+            }
+            INCREMENT | DECREMENT => {
+                let target = self.target(c, scope, frame)?;
+                let value = self.read_target(&target, frame)?;
+                let value = self.integer(value)?;
+                let result = self.mask(match op {
+                    INCREMENT => value.wrapping_add(1),
+                    _ => value.wrapping_sub(1),
+                });
+                self.store(&target, Value::Integer(result), frame)?;
+                Ok(Value::Integer(result))
+            }
+            DIVIDE => {
+                let dividend = self.eval_integer(c, scope, frame)?;
+                let divisor = self.eval_integer(c, scope, frame)?;
+                if divisor == 0 {
+                    return Err(AmlError::DivideByZero);
+                }
+                let remainder = self.target(c, scope, frame)?;
+                self.store(&remainder, Value::Integer(dividend % divisor), frame)?;
+                let quotient = Value::Integer(dividend / divisor);
+                self.store_result(c, scope, frame, quotient)
+            }
+            CONCAT => {
+                let a = self.eval(c, scope, frame)?;
+                let b = self.eval(c, scope, frame)?;
+                let result = self.concat(a, b)?;
+                self.store_result(c, scope, frame, result)
+            }
+            CONCAT_RES => {
+                const END_TAG: u8 = 0x79;
+                let a = self.eval(c, scope, frame)?.to_bytes(self.integer_bytes)?;
+                let b = self.eval(c, scope, frame)?.to_bytes(self.integer_bytes)?;
+                let strip_end = |bytes: &[u8]| match bytes {
+                    [rest @ .., END_TAG, _] => rest.to_vec(),
+                    other => other.to_vec(),
+                };
+                let mut result = strip_end(&a);
+                result.extend(strip_end(&b));
+                result.extend([END_TAG, 0]);
+                self.store_result(c, scope, frame, Value::buffer(result))
+            }
+            DEREF_OF => match self.eval(c, scope, frame)? {
+                Value::Reference(r) => self.deref(&r),
+                Value::String(name) => {
+                    let path = Path::parse(&name).ok_or(AmlError::InvalidName)?;
+                    self.eval_named(&path, None)
+                }
+                _ => Err(AmlError::WrongType("Reference")),
+            },
+            SIZE_OF => {
+                let target = self.target(c, scope, frame)?;
+                let value = self.read_target(&target, frame)?;
+                let size = match self.resolve_reference(value)? {
+                    Value::String(s) => s.len(),
+                    Value::Buffer(b) => b.lock().len(),
+                    Value::Package(p) => p.lock().len(),
+                    _ => return Err(AmlError::WrongType("String, Buffer or Package")),
+                };
+                Ok(Value::Integer(size as u64))
+            }
+            INDEX => {
+                let source = self.eval(c, scope, frame)?;
+                let index = self.eval_integer(c, scope, frame)? as usize;
+                let reference = match self.resolve_reference(source)? {
+                    Value::Buffer(b) if index < b.lock().len() => Reference::BufferIndex(b, index),
+                    Value::Package(p) if index < p.lock().len() => {
+                        Reference::PackageIndex(p, index)
+                    }
+                    Value::Buffer(_) | Value::Package(_) => return Err(AmlError::IndexOutOfBounds),
+                    _ => return Err(AmlError::WrongType("Buffer or Package")),
+                };
+                self.store_result(c, scope, frame, Value::Reference(reference))
+            }
+            MATCH => self.eval_match(c, scope, frame),
+            OBJECT_TYPE => {
+                let code = match self.target(c, scope, frame)? {
+                    Target::Name(path) => self.object_type(&path),
+                    Target::Debug => 16,
+                    target => {
+                        let value = self.read_target(&target, frame)?;
+                        self.resolve_reference(value)?.type_code()
+                    }
+                };
+                Ok(Value::Integer(code))
+            }
+            LAND | LOR => {
+                let a = self.eval_integer(c, scope, frame)? != 0;
+                let b = self.eval_integer(c, scope, frame)? != 0;
+                Ok(self.boolean(if op == LAND { a && b } else { a || b }))
+            }
+            LNOT => {
+                let a = self.eval_integer(c, scope, frame)?;
+                Ok(self.boolean(a == 0))
+            }
+            LEQUAL | LGREATER | LLESS => {
+                let a = self.eval(c, scope, frame)?;
+                let b = self.eval(c, scope, frame)?;
+                let ordering = self.compare(a, b)?;
+                Ok(self.boolean(
+                    ordering
+                        == match op {
+                            LEQUAL => Ordering::Equal,
+                            LGREATER => Ordering::Greater,
+                            _ => Ordering::Less,
+                        },
+                ))
+            }
+            TO_BUFFER => {
+                let value = self.eval(c, scope, frame)?;
+                let bytes = self
+                    .resolve_reference(value)?
+                    .to_bytes(self.integer_bytes)?;
+                self.store_result(c, scope, frame, Value::buffer(bytes))
+            }
+            TO_DECIMAL_STRING | TO_HEX_STRING => {
+                let value = self.eval(c, scope, frame)?;
+                let string = match (self.resolve_reference(value)?, op) {
+                    (Value::Integer(i), TO_DECIMAL_STRING) => i.to_string(),
+                    (Value::Integer(i), _) => format!("0x{:X}", i),
+                    (Value::Buffer(b), TO_DECIMAL_STRING) => {
+                        let b = b.lock();
+                        b.iter().map(u8::to_string).collect::<Vec<_>>().join(",")
+                    }
+                    (Value::Buffer(b), _) => {
+                        let b = b.lock();
+                        b.iter()
+                            .map(|byte| format!("0x{:02X}", byte))
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    }
+                    (Value::String(s), _) => s,
+                    _ => return Err(AmlError::WrongType("Integer, String or Buffer")),
+                };
+                self.store_result(c, scope, frame, Value::String(string))
+            }
+            TO_INTEGER => {
+                let value = self.eval(c, scope, frame)?;
+                let integer = match self.resolve_reference(value)? {
+                    Value::String(s) => parse_integer(&s),
+                    other => other.as_integer()?,
+                };
+                let integer = Value::Integer(self.mask(integer));
+                self.store_result(c, scope, frame, integer)
+            }
+            TO_STRING => {
+                let value = self.eval(c, scope, frame)?;
+                let bytes = self
+                    .resolve_reference(value)?
+                    .to_bytes(self.integer_bytes)?;
+                let length = self.eval_integer(c, scope, frame)?;
+                let string = bytes
+                    .iter()
+                    .take(length.try_into().unwrap_or(usize::MAX))
+                    .take_while(|&&b| b != 0)
+                    .map(|&b| b as char)
+                    .collect();
+                self.store_result(c, scope, frame, Value::String(string))
+            }
+            COPY_OBJECT => {
+                let value = self.eval(c, scope, frame)?;
+                let target = self.target(c, scope, frame)?;
+                match &target {
+                    Target::Name(path) => {
+                        let object = self.namespace.get_mut(path).ok_or(AmlError::InvalidName)?;
+                        *object = Object::Name(value.deep_copy());
+                    }
+                    _ => self.store(&target, value.clone(), frame)?,
+                }
+                Ok(value)
+            }
+            MID => {
+                let source = self.eval(c, scope, frame)?;
+                let index = self.eval_integer(c, scope, frame)? as usize;
+                let length = self.eval_integer(c, scope, frame)? as usize;
+                let result = match self.resolve_reference(source)? {
+                    Value::String(s) => {
+                        Value::String(s.chars().skip(index).take(length).collect::<String>())
+                    }
+                    Value::Buffer(b) => {
+                        let b = b.lock();
+                        Value::buffer(b.iter().skip(index).take(length).copied().collect())
+                    }
+                    _ => return Err(AmlError::WrongType("String or Buffer")),
+                };
+                self.store_result(c, scope, frame, result)
+            }
+            EXT_PREFIX => self.eval_ext(c, scope, frame),
+            other => Err(AmlError::UnknownOpcode(other)),
+        }
+    }
+
+    fn eval_ext(
+        &mut self,
+        c: &mut Cursor,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<Value, AmlError> {
+        match c.byte()? {
+            EXT_COND_REF_OF => {
+                // Unlike everywhere else, the name not existing isn't an error.
+                let reference = if is_name_start(c.peek()?) {
+                    let name = c.name_string()?;
+                    self.namespace
+                        .search(&name, scope)
+                        .map(|path| Value::Reference(Reference::Named(path)))
+                } else {
+                    match self.target(c, scope, frame)? {
+                        Target::Reference(r) => Some(Value::Reference(r)),
+                        target => Some(self.read_target(&target, frame)?),
+                    }
+                };
+                let target = self.target(c, scope, frame)?;
+                match reference {
+                    Some(reference) => {
+                        self.store(&target, reference, frame)?;
+                        Ok(Value::Integer(self.ones()))
+                    }
+                    None => Ok(Value::Integer(0)),
+                }
+            }
+            EXT_REVISION => Ok(Value::Integer(INTERPRETER_REVISION)),
+            EXT_DEBUG => Ok(Value::Uninitialized),
+            EXT_TIMER => Ok(Value::Integer(self.handler.timer())),
+            op @ (EXT_FROM_BCD | EXT_TO_BCD) => {
+                let value = self.eval_integer(c, scope, frame)?;
+                let result = if op == EXT_FROM_BCD {
+                    (0..16)
+                        .rev()
+                        .fold(0, |v, digit| v * 10 + ((value >> (digit * 4)) & 0xF))
+                } else {
+                    let mut value = value;
+                    let mut result = 0;
+                    for digit in 0..16 {
+                        result |= (value % 10) << (digit * 4);
+                        value /= 10;
+                    }
+                    result
+                };
+                let result = Value::Integer(self.mask(result));
+                self.store_result(c, scope, frame, result)
+            }
+            EXT_ACQUIRE => {
+                self.target(c, scope, frame)?;
+                c.word()?;
+                // Acquired, rather than timed out.
+                Ok(Value::Integer(0))
+            }
+            EXT_WAIT => {
+                self.target(c, scope, frame)?;
+                self.eval_integer(c, scope, frame)?;
+                Ok(Value::Integer(0))
+            }
+            other => Err(AmlError::UnknownExtOpcode(other)),
+        }
+    }
+
+    fn eval_match(
+        &mut self,
+        c: &mut Cursor,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<Value, AmlError> {
+        let package = self.eval(c, scope, frame)?;
+        let Value::Package(package) = self.resolve_reference(package)? else {
+            return Err(AmlError::WrongType("Package"));
+        };
+        let op1 = c.byte()?;
+        let operand1 = self.eval_integer(c, scope, frame)?;
+        let op2 = c.byte()?;
+        let operand2 = self.eval_integer(c, scope, frame)?;
+        let start = self.eval_integer(c, scope, frame)? as usize;
+
+        let matches = |op: u8, element: u64, operand: u64| match op {
+            0 => true,
+            1 => element == operand,
+            2 => element <= operand,
+            3 => element < operand,
+            4 => element >= operand,
+            5 => element > operand,
+            _ => false,
+        };
+        let elements = package.lock().clone();
+        for (i, element) in elements.iter().enumerate().skip(start) {
+            let Ok(element) = element.as_integer() else {
+                continue;
+            };
+            if matches(op1, element, operand1) && matches(op2, element, operand2) {
+                return Ok(Value::Integer(i as u64));
+            }
+        }
+        Ok(Value::Integer(self.ones()))
+    }
+
+    fn package(
+        &mut self,
+        body: &mut Cursor,
+        count: usize,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<Value, AmlError> {
+        let mut elements = Vec::with_capacity(count);
+        while !body.at_end() {
+            // Names in packages are references to objects, not evaluated.
+            if is_name_start(body.peek()?) {
+                let name = body.name_string()?;
+                elements.push(match self.namespace.search(&name, scope) {
+                    Some(path) => Value::Object(path),
+                    None => Value::Unresolved(name.to_string()),
+                });
+            } else {
+                elements.push(self.eval(body, scope, frame)?);
+            }
+        }
+        elements.resize(count.max(elements.len()), Value::Uninitialized);
+        Ok(Value::package(elements))
+    }
+
+    fn concat(&mut self, a: Value, b: Value) -> Result<Value, AmlError> {
+        let a = self.resolve_reference(a)?;
+        let b = self.resolve_reference(b)?;
+        Ok(match a {
+            Value::Integer(_) => {
+                let mut bytes = a.to_bytes(self.integer_bytes)?;
+                let b = Value::Integer(b.as_integer()?);
+                bytes.extend(b.to_bytes(self.integer_bytes)?);
+                Value::buffer(bytes)
+            }
+            Value::Buffer(_) => {
+                let mut bytes = a.to_bytes(self.integer_bytes)?;
+                bytes.extend(b.to_bytes(self.integer_bytes)?);
+                Value::buffer(bytes)
+            }
+            _ => Value::String(a.to_aml_string()? + &b.to_aml_string()?),
+        })
+    }
+
+    fn compare(&mut self, a: Value, b: Value) -> Result<Ordering, AmlError> {
+        let a = self.resolve_reference(a)?;
+        let b = self.resolve_reference(b)?;
+        match a {
+            Value::Integer(a) => Ok(a.cmp(&b.as_integer()?)),
+            Value::String(a) => Ok(a.as_bytes().cmp(b.to_aml_string()?.as_bytes())),
+            Value::Buffer(a) => {
+                let a = a.lock().clone();
+                Ok(a.as_slice().cmp(b.to_bytes(self.integer_bytes)?.as_slice()))
+            }
+            _ => Err(AmlError::WrongType("Integer, String or Buffer")),
+        }
+    }
+
+    fn object_type(&self, path: &Path) -> u64 {
+        match self.namespace.get(path) {
+            Some(Object::Alias(target)) => self.object_type(target),
+            Some(object) => object.type_code(),
+            None => 0,
+        }
+    }
+
+    /// Evaluates a named object. Methods are invoked, taking their arguments from `call`.
+    pub(super) fn eval_named(
+        &mut self,
+        path: &Path,
+        call: Option<(&mut Cursor, &Path, &mut Frame)>,
+    ) -> Result<Value, AmlError> {
+        let object = self
+            .namespace
+            .get(path)
+            .cloned()
+            .ok_or_else(|| AmlError::NameNotFound(path.to_string()))?;
+        match object {
+            Object::Alias(target) => self.eval_named(&target, call),
+            Object::Method(method) => {
+                let mut args = Vec::new();
+                if let Some((c, scope, frame)) = call {
+                    for _ in 0..method.arg_count {
+                        args.push(self.eval(c, scope, frame)?);
+                    }
+                }
+                self.invoke(path, &method, args)
+            }
+            Object::Name(value) => Ok(value),
+            Object::Field(field) => self.read_field(&field),
+            Object::BufferField(field) => field::read_buffer_field(&field, self.integer_bytes),
+            _ => Ok(Value::Object(path.clone())),
+        }
+    }
+
+    /// Parses a SuperName or Target.
+    fn target(
+        &mut self,
+        c: &mut Cursor,
+        scope: &Path,
+        frame: &mut Frame,
+    ) -> Result<Target, AmlError> {
+        match c.peek()? {
+            ZERO => {
+                c.byte()?;
+                Ok(Target::Null)
+            }
+            op @ LOCAL0..=LOCAL7 => {
+                c.byte()?;
+                Ok(Target::Local((op - LOCAL0) as usize))
+            }
+            op @ ARG0..=ARG6 => {
+                c.byte()?;
+                Ok(Target::Arg((op - ARG0) as usize))
+            }
+            EXT_PREFIX if c.peek_at(1) == Some(EXT_DEBUG) => {
+                c.skip(2)?;
+                Ok(Target::Debug)
+            }
+            op if is_name_start(op) => {
+                let name = c.name_string()?;
+                Ok(Target::Name(self.search(&name, scope)?))
+            }
+            _ => match self.eval(c, scope, frame)? {
+                Value::Reference(r) => Ok(Target::Reference(r)),
+                _ => Err(AmlError::WrongType("Reference")),
+            },
+        }
+    }
+
+    fn store_result(
+        &mut self,
+        c: &mut Cursor,
+        scope: &Path,
+        frame: &mut Frame,
+        value: Value,
+    ) -> Result<Value, AmlError> {
+        let target = self.target(c, scope, frame)?;
+        self.store(&target, value.clone(), frame)?;
+        Ok(value)
+    }
+
+    fn read_target(&mut self, target: &Target, frame: &mut Frame) -> Result<Value, AmlError> {
+        match target {
+            Target::Null | Target::Debug => Ok(Value::Uninitialized),
+            Target::Local(i) => Ok(frame.locals[*i].clone()),
+            Target::Arg(i) => {
+                let arg = frame.args.get(*i).cloned();
+                self.resolve_reference(arg.unwrap_or_default())
+            }
+            Target::Name(path) => self.eval_named(path, None),
+            Target::Reference(r) => self.deref(r),
+        }
+    }
+
+    fn store(&mut self, target: &Target, value: Value, frame: &mut Frame) -> Result<(), AmlError> {
+        match target {
+            Target::Null => Ok(()),
+            Target::Debug => {
+                log::debug!("AML debug: {}", value);
+                Ok(())
+            }
+            Target::Local(i) => {
+                frame.locals[*i] = value.deep_copy();
+                Ok(())
+            }
+            Target::Arg(i) => {
+                if let Some(Value::Reference(r)) = frame.args.get(*i) {
+                    let r = r.clone();
+                    return self.store_reference(&r, value);
+                }
+                if frame.args.len() <= *i {
+                    frame.args.resize(*i + 1, Value::Uninitialized);
+                }
+                frame.args[*i] = value.deep_copy();
+                Ok(())
+            }
+            Target::Name(path) => self.store_named(path, value),
+            Target::Reference(r) => self.store_reference(r, value),
+        }
+    }
+
+    /// Stores to a named object, converting the value to the type of the object.
+    pub(super) fn store_named(&mut self, path: &Path, value: Value) -> Result<(), AmlError> {
+        let object = self
+            .namespace
+            .get(path)
+            .cloned()
+            .ok_or_else(|| AmlError::NameNotFound(path.to_string()))?;
+        let value = self.resolve_reference(value)?;
+        let converted = match object {
+            Object::Alias(target) => return self.store_named(&target, value),
+            Object::Field(field) => return self.write_field(&field, &value),
+            Object::BufferField(field) => return field::write_buffer_field(&field, &value),
+            Object::Name(Value::Integer(_))
+                if matches!(
+                    value,
+                    Value::Integer(_) | Value::String(_) | Value::Buffer(_)
+                ) =>
+            {
+                Value::Integer(self.mask(value.as_integer()?))
+            }
+            Object::Name(Value::Buffer(buffer)) if !matches!(value, Value::Package(_)) => {
+                // Buffers keep their size, and are updated in place so buffer fields still work.
+                let bytes = value.to_bytes(self.integer_bytes)?;
+                let mut buffer = buffer.lock();
+                let n = bytes.len().min(buffer.len());
+                buffer.fill(0);
+                buffer[..n].copy_from_slice(&bytes[..n]);
+                return Ok(());
+            }
+            Object::Name(Value::String(_)) if !matches!(value, Value::Package(_)) => {
+                Value::String(value.to_aml_string()?)
+            }
+            Object::Name(_) => value.deep_copy(),
+            _ => return Err(AmlError::WrongType("Data object")),
+        };
+        *self.namespace.get_mut(path).unwrap() = Object::Name(converted);
+        Ok(())
+    }
+
+    fn store_reference(&mut self, reference: &Reference, value: Value) -> Result<(), AmlError> {
+        match reference {
+            Reference::Named(path) => self.store_named(path, value),
+            Reference::BufferIndex(buffer, index) => {
+                let byte = self.integer(value)? as u8;
+                *buffer
+                    .lock()
+                    .get_mut(*index)
+                    .ok_or(AmlError::IndexOutOfBounds)? = byte;
+                Ok(())
+            }
+            Reference::PackageIndex(package, index) => {
+                let value = self.resolve_reference(value)?.deep_copy();
+                *package
+                    .lock()
+                    .get_mut(*index)
+                    .ok_or(AmlError::IndexOutOfBounds)? = value;
+                Ok(())
+            }
+        }
+    }
+
+    fn deref(&mut self, reference: &Reference) -> Result<Value, AmlError> {
+        match reference {
+            Reference::Named(path) => self.eval_named(path, None),
+            Reference::BufferIndex(buffer, index) => buffer
+                .lock()
+                .get(*index)
+                .map(|&b| Value::Integer(b as u64))
+                .ok_or(AmlError::IndexOutOfBounds),
+            Reference::PackageIndex(package, index) => package
+                .lock()
+                .get(*index)
+                .cloned()
+                .ok_or(AmlError::IndexOutOfBounds),
+        }
+    }
+}
+
+/// Parses a string the way `ToInteger` does: decimal, or hex with a `0x` prefix.
+fn parse_integer(s: &str) -> u64 {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => hex
+            .chars()
+            .map_while(|c| c.to_digit(16))
+            .fold(0u64, |v, d| v.wrapping_mul(16).wrapping_add(d as u64)),
+        None => s
+            .chars()
+            .map_while(|c| c.to_digit(10))
+            .fold(0u64, |v, d| v.wrapping_mul(10).wrapping_add(d as u64)),
+    }
+}
@@ -0,0 +1,513 @@
+//! An interpreter for ACPI Machine Language, which builds the namespace described by the DSDT and
+//! SSDTs and evaluates the objects in it.
+//!
+//! Method bodies are only parsed when they're called, by which time every table has been loaded
+//! and every name they refer to exists. Operation regions are accessed through a [`Handler`], so
+//! the interpreter runs on the host in tests.
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use conquer_once::spin::OnceCell;
+use spinning_top::Spinlock;
+
+use super::{Signature, SDT_HEADER_SIZE};
+
+mod field;
+mod handler;
+mod interp;
+mod name;
+mod namespace;
+mod opcode;
+mod value;
+pub use handler::KernelHandler;
+pub use name::*;
+pub use namespace::Namespace;
+pub use value::*;
+
+use interp::{Cursor, Frame};
+
+static INTERPRETER: OnceCell<Spinlock<Interpreter>> = OnceCell::uninit();
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AmlError {
+    /// The AML stopped in the middle of an object.
+    UnexpectedEnd,
+    UnknownOpcode(u8),
+    UnknownExtOpcode(u8),
+    InvalidName,
+    NameNotFound(String),
+    AlreadyExists(Path),
+    /// An operand couldn't be converted to the type an operator needed.
+    WrongType(&'static str),
+    ArgumentCount {
+        expected: u8,
+        found: usize,
+    },
+    DivideByZero,
+    IndexOutOfBounds,
+    UnsupportedRegionSpace(RegionSpace),
+    RecursionLimit,
+    LoopLimit,
+    /// The firmware executed a `Fatal` operator.
+    Fatal {
+        kind: u8,
+        code: u32,
+        arg: u64,
+    },
+    Unsupported(&'static str),
+}
+
+/// The PCI function a configuration space operation region belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PciAddress {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+/// Accesses hardware on behalf of the interpreter. Widths are in bits.
+pub trait Handler: Send {
+    fn read_memory(&self, address: u64, width: u8) -> u64;
+    fn write_memory(&self, address: u64, width: u8, value: u64);
+    fn read_io(&self, port: u16, width: u8) -> u64;
+    fn write_io(&self, port: u16, width: u8, value: u64);
+    fn read_pci(&self, address: PciAddress, offset: u16, width: u8) -> u64;
+    fn write_pci(&self, address: PciAddress, offset: u16, width: u8, value: u64);
+    fn stall(&self, micros: u64);
+    fn sleep(&self, millis: u64);
+    /// A monotonic count of 100ns intervals, for the `Timer` operator.
+    fn timer(&self) -> u64;
+}
+
+bitflags::bitflags! {
+    /// What a device's `_STA` reports.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct DeviceStatus: u64 {
+        const PRESENT = 1 << 0;
+        const ENABLED = 1 << 1;
+        const SHOWN = 1 << 2;
+        const FUNCTIONING = 1 << 3;
+        const BATTERY_PRESENT = 1 << 4;
+    }
+}
+
+pub struct Interpreter {
+    namespace: Namespace,
+    handler: Box<dyn Handler>,
+    /// 4 if the DSDT predates ACPI 2.0 and integers are 32 bits wide, otherwise 8.
+    integer_bytes: usize,
+    /// How many methods are running.
+    depth: usize,
+}
+
+impl Interpreter {
+    pub fn new(handler: Box<dyn Handler>) -> Self {
+        Self {
+            namespace: Namespace::new(),
+            handler,
+            integer_bytes: 8,
+            depth: 0,
+        }
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    /// Loads a DSDT or SSDT, creating the objects it defines.
+    pub fn load_table(&mut self, table: &'static [u8]) -> Result<(), AmlError> {
+        let aml = table
+            .get(SDT_HEADER_SIZE..)
+            .ok_or(AmlError::UnexpectedEnd)?;
+        if table[0..4] == Signature::DSDT.0 && table[8] < 2 {
+            self.integer_bytes = 4;
+        }
+        let mut frame = Frame::table();
+        self.exec_term_list(&mut Cursor::new(aml), &Path::root(), &mut frame)?;
+        Ok(())
+    }
+
+    /// Evaluates an object: methods are called with `args`, fields are read, and data objects are
+    /// returned as they are.
+    pub fn evaluate(&mut self, path: &Path, args: Vec<Value>) -> Result<Value, AmlError> {
+        match self.namespace.get(path) {
+            Some(Object::Method(method)) => {
+                let method = *method;
+                self.invoke(path, &method, args)
+            }
+            Some(_) if !args.is_empty() => Err(AmlError::ArgumentCount {
+                expected: 0,
+                found: args.len(),
+            }),
+            _ => self.eval_named(path, None),
+        }
+    }
+
+    /// Like [`evaluate`](Self::evaluate), but returns `None` if the object doesn't exist, as is
+    /// the case for most optional methods.
+    pub fn evaluate_if_present(
+        &mut self,
+        path: &Path,
+        args: Vec<Value>,
+    ) -> Result<Option<Value>, AmlError> {
+        if self.namespace.get(path).is_none() {
+            return Ok(None);
+        }
+        self.evaluate(path, args).map(Some)
+    }
+
+    fn evaluate_integer_if_present(&mut self, path: &Path) -> Result<Option<u64>, AmlError> {
+        self.evaluate_if_present(path, Vec::new())?
+            .map(|value| value.as_integer())
+            .transpose()
+    }
+
+    /// Every device, processor and thermal zone in the namespace.
+    pub fn devices(&self) -> Vec<Path> {
+        self.namespace
+            .iter()
+            .filter(|(_, object)| {
+                matches!(
+                    object,
+                    Object::Device | Object::Processor { .. } | Object::ThermalZone
+                )
+            })
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Evaluates a device's `_STA`. Devices without one are present and working.
+    pub fn device_status(&mut self, device: &Path) -> Result<DeviceStatus, AmlError> {
+        Ok(self
+            .evaluate_integer_if_present(&device.child("_STA"))?
+            .map_or(DeviceStatus::all(), DeviceStatus::from_bits_truncate))
+    }
+
+    /// Evaluates a device's `_HID`, decoding EISA IDs into strings such as `PNP0A03`.
+    pub fn hardware_id(&mut self, device: &Path) -> Result<Option<String>, AmlError> {
+        match self.evaluate_if_present(&device.child("_HID"), Vec::new())? {
+            Some(Value::Integer(id)) => Ok(Some(eisa_id_to_string(id as u32))),
+            Some(Value::String(id)) => Ok(Some(id)),
+            Some(_) => Err(AmlError::WrongType("Integer or String")),
+            None => Ok(None),
+        }
+    }
+
+    /// Runs `\_SB._INI` and then the `_INI` of every present device, as the OS must after loading
+    /// the tables. Children of devices that are neither present nor functioning are skipped.
+    pub fn initialize_devices(&mut self) -> Result<(), AmlError> {
+        self.evaluate_if_present(&Path::root().child("_SB").child("_INI"), Vec::new())?;
+
+        let mut absent: Vec<Path> = Vec::new();
+        for device in self.devices() {
+            if absent.iter().any(|a| device.starts_with(a)) {
+                continue;
+            }
+            let status = self.device_status(&device).unwrap_or_else(|e| {
+                log::warn!("Failed to evaluate {}._STA: {:?}", device, e);
+                DeviceStatus::empty()
+            });
+            if !status.contains(DeviceStatus::PRESENT) {
+                if !status.contains(DeviceStatus::FUNCTIONING) {
+                    absent.push(device);
+                }
+                continue;
+            }
+            if let Err(e) = self.evaluate_if_present(&device.child("_INI"), Vec::new()) {
+                log::warn!("Failed to run {}._INI: {:?}", device, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Loads the DSDT and SSDTs, tells the firmware we use the I/O APIC and initializes devices.
+pub fn init() {
+    let Some(dsdt) = super::dsdt() else {
+        log::warn!("No DSDT, so no AML namespace");
+        return;
+    };
+    let mut interpreter = Interpreter::new(Box::new(KernelHandler::new()));
+    // A table that fails part way through still leaves the objects before the failure, which
+    // are usually enough to be useful.
+    if let Err(e) = interpreter.load_table(dsdt) {
+        log::warn!("Failed to load DSDT: {:?}", e);
+    }
+    let ssdts = super::tables()
+        .into_iter()
+        .flat_map(|tables| tables.find_all(Signature::SSDT));
+    for ssdt in ssdts {
+        if let Err(e) = interpreter.load_table(ssdt) {
+            log::warn!("Failed to load SSDT: {:?}", e);
+        }
+    }
+
+    // 1 selects the APIC interrupt model.
+    let pic = Path::root().child("_PIC");
+    if let Err(e) = interpreter.evaluate_if_present(&pic, alloc::vec![Value::Integer(1)]) {
+        log::warn!("Failed to run \\_PIC: {:?}", e);
+    }
+    if let Err(e) = interpreter.initialize_devices() {
+        log::warn!("Failed to initialize devices: {:?}", e);
+    }
+
+    let devices = interpreter.devices();
+    log::info!(
+        "AML namespace loaded: {} objects, {} devices",
+        interpreter.namespace().iter().count(),
+        devices.len()
+    );
+    if log::log_enabled!(log::Level::Debug) {
+        for device in &devices {
+            match interpreter.hardware_id(device) {
+                Ok(Some(id)) => log::debug!("  {} ({})", device, id),
+                _ => log::debug!("  {}", device),
+            }
+        }
+    }
+
+    INTERPRETER.init_once(|| Spinlock::new(interpreter));
+}
+
+/// Returns the interpreter, if [`init`] has loaded the DSDT.
+pub fn interpreter() -> Option<&'static Spinlock<Interpreter>> {
+    INTERPRETER.get()
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{collections::BTreeMap, sync::Arc, vec};
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Sixteen bytes of I/O ports, and nothing else.
+    struct MockHandler {
+        io: Arc<Spinlock<BTreeMap<u16, u8>>>,
+    }
+
+    impl Handler for MockHandler {
+        fn read_memory(&self, _: u64, _: u8) -> u64 {
+            0
+        }
+        fn write_memory(&self, _: u64, _: u8, _: u64) {}
+        fn read_io(&self, port: u16, width: u8) -> u64 {
+            let io = self.io.lock();
+            (0..width as u16 / 8).fold(0, |v, i| {
+                v | (*io.get(&(port + i)).unwrap_or(&0) as u64) << (i * 8)
+            })
+        }
+        fn write_io(&self, port: u16, width: u8, value: u64) {
+            let mut io = self.io.lock();
+            for i in 0..width as u16 / 8 {
+                io.insert(port + i, (value >> (i * 8)) as u8);
+            }
+        }
+        fn read_pci(&self, _: PciAddress, _: u16, _: u8) -> u64 {
+            0
+        }
+        fn write_pci(&self, _: PciAddress, _: u16, _: u8, _: u64) {}
+        fn stall(&self, _: u64) {}
+        fn sleep(&self, _: u64) {}
+        fn timer(&self) -> u64 {
+            0
+        }
+    }
+
+    /// Prefixes `body` with `op` and a PkgLength.
+    fn pkg(op: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut bytes = op.to_vec();
+        match body.len() + 1 {
+            length @ ..64 => bytes.push(length as u8),
+            _ => {
+                let length = body.len() + 2;
+                bytes.extend([0x40 | (length & 0xF) as u8, (length >> 4) as u8]);
+            }
+        }
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    fn interpreter(io: Arc<Spinlock<BTreeMap<u16, u8>>>) -> Interpreter {
+        let aml = [
+            // Name (INIT, Zero)
+            b"\x08INIT\x00".to_vec(),
+            // Scope (\_SB) { Device (PCI0) { ... } Device (ABSN) { ... } }
+            pkg(
+                b"\x10",
+                &[
+                    b"\\_SB_".to_vec(),
+                    pkg(
+                        b"\x5B\x82",
+                        &[
+                            b"PCI0".to_vec(),
+                            // Name (_HID, EisaId ("PNP0A03"))
+                            b"\x08_HID\x0C\x41\xD0\x0A\x03".to_vec(),
+                            // Method (_STA) { Return (0x0F) }
+                            pkg(b"\x14", b"_STA\x00\xA4\x0A\x0F"),
+                            // Method (_INI) { Store (One, \INIT) }
+                            pkg(b"\x14", b"_INI\x00\x70\x01\\INIT"),
+                        ]
+                        .concat(),
+                    ),
+                    pkg(
+                        b"\x5B\x82",
+                        &[
+                            b"ABSN".to_vec(),
+                            pkg(b"\x14", b"_STA\x00\xA4\x00"),
+                            pkg(
+                                b"\x5B\x82",
+                                &[
+                                    b"CHLD".to_vec(),
+                                    pkg(b"\x14", b"_INI\x00\x70\x0A\x02\\INIT"),
+                                ]
+                                .concat(),
+                            ),
+                        ]
+                        .concat(),
+                    ),
+                ]
+                .concat(),
+            ),
+            // Name (_S5, Package (4) { 5, Zero, Zero, Zero })
+            b"\x08_S5_\x12\x07\x04\x0A\x05\x00\x00\x00".to_vec(),
+            // Method (FACT, 1) {
+            //     If (LLess (Arg0, 2)) { Return (One) }
+            //     Return (Multiply (Arg0, FACT (Subtract (Arg0, One))))
+            // }
+            pkg(
+                b"\x14",
+                &[
+                    b"FACT\x01".to_vec(),
+                    pkg(b"\xA0", b"\x95\x68\x0A\x02\xA4\x01"),
+                    b"\xA4\x77\x68FACT\x74\x68\x01\x00\x00".to_vec(),
+                ]
+                .concat(),
+            ),
+            // Method (SUM) {
+            //     Store (Zero, Local0)
+            //     Store (Zero, Local1)
+            //     While (LLess (Local0, 10)) { Increment (Local0); Add (Local1, Local0, Local1) }
+            //     Return (Local1)
+            // }
+            pkg(
+                b"\x14",
+                &[
+                    b"SUM_\x00\x70\x00\x60\x70\x00\x61".to_vec(),
+                    pkg(b"\xA2", b"\x95\x60\x0A\x0A\x75\x60\x72\x61\x60\x61"),
+                    b"\xA4\x61".to_vec(),
+                ]
+                .concat(),
+            ),
+            // Method (BUFT) {
+            //     Name (BUF0, Buffer (4) {})
+            //     CreateWordField (BUF0, One, WRD0)
+            //     Store (0x1234, WRD0)
+            //     Return (BUF0)
+            // }
+            pkg(
+                b"\x14",
+                &[
+                    b"BUFT\x00\x08BUF0".to_vec(),
+                    pkg(b"\x11", b"\x0A\x04"),
+                    b"\x8BBUF0\x01WRD0\x70\x0B\x34\x12WRD0\xA4BUF0".to_vec(),
+                ]
+                .concat(),
+            ),
+            // OperationRegion (GIO, SystemIO, 0x80, 4)
+            b"\x5B\x80GIO_\x01\x0A\x80\x0A\x04".to_vec(),
+            // Field (GIO, ByteAcc, NoLock, Preserve) { Offset (1), FLGA, 3, FLGB, 5, WRD, 16 }
+            pkg(b"\x5B\x81", b"GIO_\x01\x00\x08FLGA\x03FLGB\x05WRD_\x10"),
+            // Method (FLDT) { Store (5, FLGB); Return (WRD) }
+            pkg(b"\x14", b"FLDT\x00\x70\x0A\x05FLGB\xA4WRD_"),
+        ]
+        .concat();
+
+        let mut table = super::super::build_table(b"DSDT", &aml);
+        table[8] = 2;
+        let mut interpreter = Interpreter::new(Box::new(MockHandler { io }));
+        interpreter.load_table(table.leak()).unwrap();
+        interpreter
+    }
+
+    fn path(path: &str) -> Path {
+        Path::parse(path).unwrap()
+    }
+
+    #[test]
+    pub fn evaluates_methods() {
+        let mut aml = interpreter(Default::default());
+
+        let fact = aml.evaluate(&path("\\FACT"), vec![Value::Integer(5)]);
+        assert!(matches!(fact, Ok(Value::Integer(120))));
+        assert!(matches!(
+            aml.evaluate(&path("\\SUM"), Vec::new()),
+            Ok(Value::Integer(55))
+        ));
+
+        // Objects created by a method go away when it returns, so this works twice.
+        for _ in 0..2 {
+            let Ok(Value::Buffer(buffer)) = aml.evaluate(&path("\\BUFT"), Vec::new()) else {
+                panic!("BUFT didn't return a buffer");
+            };
+            assert_eq!(&[0, 0x34, 0x12, 0], buffer.lock().as_slice());
+        }
+        assert!(aml.namespace().get(&path("\\BUFT.BUF0")).is_none());
+
+        let Ok(Value::Package(s5)) = aml.evaluate(&path("\\_S5"), Vec::new()) else {
+            panic!("_S5 isn't a package");
+        };
+        assert_eq!(5, s5.lock()[0].as_integer().unwrap());
+
+        assert!(matches!(
+            aml.evaluate(&path("\\FACT"), Vec::new()),
+            Err(AmlError::ArgumentCount {
+                expected: 1,
+                found: 0
+            })
+        ));
+    }
+
+    #[test]
+    pub fn reads_and_writes_fields() {
+        let io = Arc::new(Spinlock::new(BTreeMap::from([
+            (0x81, 0x07),
+            (0x82, 0xCD),
+            (0x83, 0xAB),
+        ])));
+        let mut aml = interpreter(io.clone());
+
+        let word = aml.evaluate(&path("\\FLDT"), Vec::new());
+        assert!(matches!(word, Ok(Value::Integer(0xABCD))));
+        // FLGB was written without disturbing FLGA.
+        assert_eq!(Some(&0x2F), io.lock().get(&0x81));
+        assert!(matches!(
+            aml.evaluate(&path("\\FLGA"), Vec::new()),
+            Ok(Value::Integer(7))
+        ));
+    }
+
+    #[test]
+    pub fn enumerates_and_initializes_devices() {
+        let mut aml = interpreter(Default::default());
+
+        let devices = aml.devices();
+        assert!(devices.contains(&path("\\_SB.PCI0")));
+        assert!(devices.contains(&path("\\_SB.ABSN.CHLD")));
+        assert_eq!(
+            Some("PNP0A03".into()),
+            aml.hardware_id(&path("\\_SB.PCI0")).unwrap()
+        );
+        assert_eq!(
+            DeviceStatus::empty(),
+            aml.device_status(&path("\\_SB.ABSN")).unwrap()
+        );
+
+        // The child of a device that isn't present isn't initialized.
+        aml.initialize_devices().unwrap();
+        assert!(matches!(
+            aml.evaluate(&path("\\INIT"), Vec::new()),
+            Ok(Value::Integer(1))
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A four character name segment, such as `_SB_` or `PCI0`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NameSeg(pub [u8; 4]);
+
+impl NameSeg {
+    pub fn is_lead_char(c: u8) -> bool {
+        c.is_ascii_uppercase() || c == b'_'
+    }
+
+    pub fn is_name_char(c: u8) -> bool {
+        Self::is_lead_char(c) || c.is_ascii_digit()
+    }
+
+    /// Parses a segment written in ASL, where trailing underscores may be left off.
+    pub fn parse(name: &str) -> Option<Self> {
+        let bytes = name.as_bytes();
+        if bytes.is_empty() || bytes.len() > 4 || !Self::is_lead_char(bytes[0]) {
+            return None;
+        }
+        let mut seg = [b'_'; 4];
+        for (i, &c) in bytes.iter().enumerate() {
+            if !Self::is_name_char(c) {
+                return None;
+            }
+            seg[i] = c;
+        }
+        Some(NameSeg(seg))
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.0).unwrap_or("????")
+    }
+}
+
+impl fmt::Debug for NameSeg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// An absolute path to an object in the namespace.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Path(Vec<NameSeg>);
+
+impl Path {
+    pub const fn root() -> Self {
+        Path(Vec::new())
+    }
+
+    /// Parses an absolute path such as `\_SB.PCI0._CRS`. The leading backslash is optional.
+    pub fn parse(path: &str) -> Option<Self> {
+        let path = path.strip_prefix('\\').unwrap_or(path);
+        if path.is_empty() {
+            return Some(Self::root());
+        }
+        path.split('.')
+            .map(NameSeg::parse)
+            .collect::<Option<_>>()
+            .map(Path)
+    }
+
+    pub fn segments(&self) -> &[NameSeg] {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn last(&self) -> Option<NameSeg> {
+        self.0.last().copied()
+    }
+
+    pub fn parent(&self) -> Option<Path> {
+        (!self.is_root()).then(|| Path(self.0[..self.0.len() - 1].to_vec()))
+    }
+
+    pub fn join(&self, seg: NameSeg) -> Path {
+        let mut path = self.clone();
+        path.0.push(seg);
+        path
+    }
+
+    /// Appends a child given as a string, such as `_STA`.
+    ///
+    /// Panics if `name` isn't a valid name segment.
+    pub fn child(&self, name: &str) -> Path {
+        self.join(NameSeg::parse(name).expect("a valid name segment"))
+    }
+
+    pub fn starts_with(&self, prefix: &Path) -> bool {
+        self.0.starts_with(&prefix.0)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\\")?;
+        for (i, seg) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", seg.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// A name as written in AML, which may be relative to the scope it appears in.
+#[derive(Clone, PartialEq, Eq)]
+pub struct NameString {
+    pub root: bool,
+    /// The number of `^` prefixes.
+    pub parents: usize,
+    pub segments: Vec<NameSeg>,
+}
+
+impl NameString {
+    pub fn is_null(&self) -> bool {
+        !self.root && self.parents == 0 && self.segments.is_empty()
+    }
+
+    /// Whether the namespace search rules apply, which is only the case for bare name segments.
+    pub fn is_searchable(&self) -> bool {
+        !self.root && self.parents == 0 && self.segments.len() == 1
+    }
+
+    /// Resolves the name against `scope` as written, without searching parent scopes.
+    pub fn resolve(&self, scope: &Path) -> Option<Path> {
+        let mut path = if self.root {
+            Path::root()
+        } else {
+            let segments = scope.segments();
+            Path(segments[..segments.len().checked_sub(self.parents)?].to_vec())
+        };
+        path.0.extend_from_slice(&self.segments);
+        Some(path)
+    }
+}
+
+impl fmt::Display for NameString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.root {
+            write!(f, "\\")?;
+        }
+        for _ in 0..self.parents {
+            write!(f, "^")?;
+        }
+        for (i, seg) in self.segments.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", seg.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for NameString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
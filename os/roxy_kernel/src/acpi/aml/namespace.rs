@@ -0,0 +1,135 @@
+use alloc::{collections::BTreeMap, string::ToString, vec::Vec};
+
+use super::{AmlError, Method, MethodBody, NameString, Object, Path, Value};
+
+/// The tree of named objects defined by the DSDT and SSDTs.
+pub struct Namespace {
+    objects: BTreeMap<Path, Object>,
+}
+
+/// The `_OSI` strings we claim to support. Firmware tends to be best tested against Windows.
+const SUPPORTED_INTERFACES: &[&str] = &[
+    "Windows 2000",
+    "Windows 2001",
+    "Windows 2001 SP1",
+    "Windows 2001.1",
+    "Windows 2006",
+    "Windows 2009",
+    "Windows 2012",
+    "Windows 2015",
+    "Module Device",
+    "Processor Device",
+    "3.0 Thermal Model",
+    "Extended Address Space Descriptor",
+];
+
+fn osi(args: &[Value]) -> Result<Value, AmlError> {
+    let Some(Value::String(interface)) = args.first() else {
+        return Err(AmlError::WrongType("String"));
+    };
+    let supported = SUPPORTED_INTERFACES.contains(&interface.as_str());
+    Ok(Value::Integer(if supported { u64::MAX } else { 0 }))
+}
+
+impl Namespace {
+    /// Creates a namespace holding just the predefined objects.
+    pub fn new() -> Self {
+        let mut objects = BTreeMap::new();
+        objects.insert(Path::root(), Object::Scope);
+        for scope in ["_GPE", "_PR", "_SB", "_SI", "_TZ"] {
+            objects.insert(Path::root().child(scope), Object::Scope);
+        }
+        objects.insert(
+            Path::root().child("_OS"),
+            Object::Name(Value::String("Microsoft Windows NT".to_string())),
+        );
+        objects.insert(Path::root().child("_REV"), Object::Name(Value::Integer(2)));
+        objects.insert(Path::root().child("_GL"), Object::Mutex { sync_level: 0 });
+        objects.insert(
+            Path::root().child("_OSI"),
+            Object::Method(Method {
+                arg_count: 1,
+                serialized: false,
+                sync_level: 0,
+                body: MethodBody::Native(osi),
+            }),
+        );
+        Self { objects }
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Object> {
+        self.objects.get(path)
+    }
+
+    pub fn get_mut(&mut self, path: &Path) -> Option<&mut Object> {
+        self.objects.get_mut(path)
+    }
+
+    /// Adds an object. Its parent must already exist.
+    pub fn insert(&mut self, path: Path, object: Object) -> Result<(), AmlError> {
+        let parent = path.parent().ok_or(AmlError::AlreadyExists(Path::root()))?;
+        if !self.objects.contains_key(&parent) {
+            return Err(AmlError::NameNotFound(parent.to_string()));
+        }
+        if self.objects.contains_key(&path) {
+            return Err(AmlError::AlreadyExists(path));
+        }
+        self.objects.insert(path, object);
+        Ok(())
+    }
+
+    /// Removes an object and everything beneath it.
+    pub fn remove(&mut self, path: &Path) {
+        let doomed = self
+            .subtree(path)
+            .map(|(p, _)| p.clone())
+            .collect::<Vec<_>>();
+        for path in doomed {
+            self.objects.remove(&path);
+        }
+    }
+
+    /// Finds the object a name refers to from within `scope`. Bare name segments are searched for
+    /// in each enclosing scope in turn, up to the root.
+    pub fn search(&self, name: &NameString, scope: &Path) -> Option<Path> {
+        if !name.is_searchable() {
+            return name
+                .resolve(scope)
+                .filter(|path| self.objects.contains_key(path));
+        }
+        let mut scope = Some(scope.clone());
+        while let Some(current) = scope {
+            let path = current.join(name.segments[0]);
+            if self.objects.contains_key(&path) {
+                return Some(path);
+            }
+            scope = current.parent();
+        }
+        None
+    }
+
+    /// Iterates over `path` and everything beneath it, in depth first order.
+    pub fn subtree<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = (&'a Path, &'a Object)> {
+        self.objects
+            .range(path.clone()..)
+            .take_while(move |(p, _)| p.starts_with(path))
+    }
+
+    /// Iterates over the objects directly beneath `path`.
+    pub fn children<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = (&'a Path, &'a Object)> {
+        let depth = path.segments().len() + 1;
+        self.subtree(path)
+            .filter(move |(p, _)| p.segments().len() == depth)
+    }
+
+    /// Iterates over every object, in depth first order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &Object)> {
+        self.objects.iter()
+    }
+}
+
+impl Default for Namespace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
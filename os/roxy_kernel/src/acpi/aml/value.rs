@@ -0,0 +1,360 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt;
+use spinning_top::Spinlock;
+
+use super::{AmlError, Path};
+
+/// A buffer, shared between everything that refers to it, so buffer fields and index references
+/// can write through to it.
+pub type Buffer = Arc<Spinlock<Vec<u8>>>;
+/// A package, shared for the same reason as [`Buffer`].
+pub type Package = Arc<Spinlock<Vec<Value>>>;
+
+/// The result of evaluating AML.
+#[derive(Clone, Debug, Default)]
+pub enum Value {
+    #[default]
+    Uninitialized,
+    Integer(u64),
+    String(String),
+    Buffer(Buffer),
+    Package(Package),
+    /// An object that isn't data, such as a device, or a name inside a package.
+    Object(Path),
+    /// A name inside a package that doesn't resolve to anything.
+    Unresolved(String),
+    Reference(Reference),
+}
+
+/// A reference to something that can be read and written, as made by `RefOf` and `Index`.
+#[derive(Clone, Debug)]
+pub enum Reference {
+    Named(Path),
+    BufferIndex(Buffer, usize),
+    PackageIndex(Package, usize),
+}
+
+impl Value {
+    pub fn buffer(bytes: Vec<u8>) -> Value {
+        Value::Buffer(Arc::new(Spinlock::new(bytes)))
+    }
+
+    pub fn package(elements: Vec<Value>) -> Value {
+        Value::Package(Arc::new(Spinlock::new(elements)))
+    }
+
+    /// Copies buffers and packages, rather than sharing them, as `Store` does.
+    pub fn deep_copy(&self) -> Value {
+        match self {
+            Value::Buffer(b) => Value::buffer(b.lock().clone()),
+            Value::Package(p) => Value::package(p.lock().iter().map(Value::deep_copy).collect()),
+            other => other.clone(),
+        }
+    }
+
+    /// The type code returned by `ObjectType`.
+    pub fn type_code(&self) -> u64 {
+        match self {
+            Value::Uninitialized | Value::Unresolved(_) => 0,
+            Value::Integer(_) => 1,
+            Value::String(_) => 2,
+            Value::Buffer(_) => 3,
+            Value::Package(_) => 4,
+            Value::Object(_) | Value::Reference(_) => 0,
+        }
+    }
+
+    /// Converts to an integer, the way AML does implicitly: strings are parsed as hex, and
+    /// buffers are read as little endian.
+    pub fn as_integer(&self) -> Result<u64, AmlError> {
+        match self {
+            Value::Integer(i) => Ok(*i),
+            Value::String(s) => Ok(parse_hex_prefix(s)),
+            Value::Buffer(b) => {
+                let b = b.lock();
+                Ok(b.iter()
+                    .take(8)
+                    .enumerate()
+                    .fold(0, |v, (i, byte)| v | (*byte as u64) << (i * 8)))
+            }
+            _ => Err(AmlError::WrongType("Integer")),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, AmlError> {
+        self.as_integer().map(|i| i != 0)
+    }
+
+    /// Converts to a buffer. `integer_bytes` is the width of integers in bytes.
+    pub fn to_bytes(&self, integer_bytes: usize) -> Result<Vec<u8>, AmlError> {
+        match self {
+            Value::Integer(i) => Ok(i.to_le_bytes()[..integer_bytes].to_vec()),
+            Value::String(s) => {
+                let mut bytes = s.as_bytes().to_vec();
+                if !bytes.is_empty() {
+                    bytes.push(0);
+                }
+                Ok(bytes)
+            }
+            Value::Buffer(b) => Ok(b.lock().clone()),
+            _ => Err(AmlError::WrongType("Buffer")),
+        }
+    }
+
+    /// Converts to a string, the way AML does implicitly.
+    pub fn to_aml_string(&self) -> Result<String, AmlError> {
+        match self {
+            Value::Integer(i) => Ok(format!("{:X}", i)),
+            Value::String(s) => Ok(s.clone()),
+            Value::Buffer(b) => {
+                let b = b.lock();
+                Ok(b.iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect::<Vec<_>>()
+                    .join(" "))
+            }
+            Value::Object(path) => Ok(path.to_string()),
+            _ => Err(AmlError::WrongType("String")),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Uninitialized => write!(f, "Uninitialized"),
+            Value::Integer(i) => write!(f, "{:#X}", i),
+            Value::String(s) => write!(f, "\"{}\"", s),
+            Value::Buffer(b) => write!(f, "Buffer {:02X?}", b.lock().as_slice()),
+            Value::Package(p) => {
+                write!(f, "Package {{")?;
+                for (i, element) in p.lock().iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { " " } else { ", " }, element)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Object(path) => write!(f, "{}", path),
+            Value::Unresolved(name) => write!(f, "{}", name),
+            Value::Reference(r) => write!(f, "Reference to {:?}", r),
+        }
+    }
+}
+
+/// Parses leading hex digits, ignoring anything after them.
+fn parse_hex_prefix(s: &str) -> u64 {
+    let s = s.trim_start();
+    let s = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    s.chars()
+        .map_while(|c| c.to_digit(16))
+        .fold(0u64, |v, d| v.wrapping_shl(4) | d as u64)
+}
+
+/// Decodes a compressed EISA ID, as produced by ASL's `EisaId("PNP0A03")`.
+pub fn eisa_id_to_string(id: u32) -> String {
+    let id = id.swap_bytes();
+    let letter = |shift: u32| (((id >> shift) & 0x1F) as u8 + 0x40) as char;
+    format!(
+        "{}{}{}{:04X}",
+        letter(26),
+        letter(21),
+        letter(16),
+        id & 0xFFFF
+    )
+}
+
+/// An object in the namespace.
+#[derive(Clone, Debug)]
+pub enum Object {
+    /// A data object, declared with `Name`.
+    Name(Value),
+    /// A predefined scope, such as `\_SB`.
+    Scope,
+    Device,
+    Processor {
+        id: u8,
+        block_address: u32,
+        block_length: u8,
+    },
+    PowerResource {
+        system_level: u8,
+        resource_order: u16,
+    },
+    ThermalZone,
+    Method(Method),
+    OpRegion(OpRegion),
+    Field(FieldUnit),
+    BufferField(BufferField),
+    Mutex {
+        sync_level: u8,
+    },
+    Event,
+    Alias(Path),
+}
+
+impl Object {
+    /// The type code returned by `ObjectType`.
+    pub fn type_code(&self) -> u64 {
+        match self {
+            Object::Name(value) => value.type_code(),
+            Object::Field(_) => 5,
+            Object::Device | Object::Scope => 6,
+            Object::Event => 7,
+            Object::Method(_) => 8,
+            Object::Mutex { .. } => 9,
+            Object::OpRegion(_) => 10,
+            Object::PowerResource { .. } => 11,
+            Object::Processor { .. } => 12,
+            Object::ThermalZone => 13,
+            Object::BufferField(_) => 14,
+            Object::Alias(_) => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum MethodBody {
+    Aml(&'static [u8]),
+    /// Implemented by the interpreter, like `\_OSI`.
+    Native(fn(&[Value]) -> Result<Value, AmlError>),
+}
+
+impl fmt::Debug for MethodBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MethodBody::Aml(body) => write!(f, "Aml({} bytes)", body.len()),
+            MethodBody::Native(_) => write!(f, "Native"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Method {
+    pub arg_count: u8,
+    pub serialized: bool,
+    pub sync_level: u8,
+    pub body: MethodBody,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfig,
+    EmbeddedControl,
+    SmBus,
+    SystemCmos,
+    PciBarTarget,
+    Ipmi,
+    GeneralPurposeIo,
+    GenericSerialBus,
+    Pcc,
+    Other(u8),
+}
+
+impl From<u8> for RegionSpace {
+    fn from(space: u8) -> Self {
+        match space {
+            0 => RegionSpace::SystemMemory,
+            1 => RegionSpace::SystemIo,
+            2 => RegionSpace::PciConfig,
+            3 => RegionSpace::EmbeddedControl,
+            4 => RegionSpace::SmBus,
+            5 => RegionSpace::SystemCmos,
+            6 => RegionSpace::PciBarTarget,
+            7 => RegionSpace::Ipmi,
+            8 => RegionSpace::GeneralPurposeIo,
+            9 => RegionSpace::GenericSerialBus,
+            0xA => RegionSpace::Pcc,
+            other => RegionSpace::Other(other),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OpRegion {
+    pub space: RegionSpace,
+    pub offset: u64,
+    pub length: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessType {
+    Any,
+    Byte,
+    Word,
+    DWord,
+    QWord,
+    Buffer,
+}
+
+impl AccessType {
+    pub fn from_flags(flags: u8) -> Self {
+        match flags & 0xF {
+            1 => AccessType::Byte,
+            2 => AccessType::Word,
+            3 => AccessType::DWord,
+            4 => AccessType::QWord,
+            5 => AccessType::Buffer,
+            _ => AccessType::Any,
+        }
+    }
+}
+
+/// What happens to the bits of an access unit that aren't part of the field being written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateRule {
+    Preserve,
+    WriteAsOnes,
+    WriteAsZeros,
+}
+
+impl UpdateRule {
+    pub fn from_flags(flags: u8) -> Self {
+        match (flags >> 5) & 0b11 {
+            1 => UpdateRule::WriteAsOnes,
+            2 => UpdateRule::WriteAsZeros,
+            _ => UpdateRule::Preserve,
+        }
+    }
+}
+
+/// Where a field's bits come from.
+#[derive(Clone, Debug)]
+pub enum FieldKind {
+    Region(Path),
+    /// Accessed by writing the offset to `index` and then accessing `data`.
+    Index {
+        index: Path,
+        data: Path,
+    },
+    /// Accessed by writing `value` to `bank` and then accessing the region.
+    Bank {
+        region: Path,
+        bank: Path,
+        value: u64,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct FieldUnit {
+    pub kind: FieldKind,
+    pub bit_offset: u64,
+    pub bit_length: u64,
+    pub access: AccessType,
+    pub update: UpdateRule,
+}
+
+#[derive(Clone, Debug)]
+pub struct BufferField {
+    pub buffer: Buffer,
+    pub bit_offset: u64,
+    pub bit_length: u64,
+}
@@ -0,0 +1,229 @@
+use x86_64::PhysAddr;
+
+use super::{
+    read_u16, read_u32, read_u64, validate, AcpiError, AddressSpace, GenericAddress, Signature,
+};
+
+/// Length of the ACPI 1.0 FADT, which ends right before the reset register.
+const FADT_V1_LENGTH: usize = 116;
+
+bitflags::bitflags! {
+    /// The fixed feature flags.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct FadtFlags: u32 {
+        const WBINVD = 1 << 0;
+        const WBINVD_FLUSH = 1 << 1;
+        const PROC_C1 = 1 << 2;
+        const P_LVL2_UP = 1 << 3;
+        const PWR_BUTTON = 1 << 4;
+        const SLP_BUTTON = 1 << 5;
+        const FIX_RTC = 1 << 6;
+        const RTC_S4 = 1 << 7;
+        const TMR_VAL_EXT = 1 << 8;
+        const DCK_CAP = 1 << 9;
+        /// The reset register is supported.
+        const RESET_REG_SUP = 1 << 10;
+        const SEALED_CASE = 1 << 11;
+        const HEADLESS = 1 << 12;
+        const CPU_SW_SLP = 1 << 13;
+        const PCI_EXP_WAK = 1 << 14;
+        const USE_PLATFORM_CLOCK = 1 << 15;
+        const S4_RTC_STS_VALID = 1 << 16;
+        const REMOTE_POWER_ON_CAPABLE = 1 << 17;
+        const FORCE_APIC_CLUSTER_MODEL = 1 << 18;
+        const FORCE_APIC_PHYSICAL_DESTINATION_MODE = 1 << 19;
+        /// There is no fixed hardware; sleep states go through the sleep control register.
+        const HW_REDUCED_ACPI = 1 << 20;
+        const LOW_POWER_S0_IDLE_CAPABLE = 1 << 21;
+    }
+}
+
+bitflags::bitflags! {
+    /// The IA-PC boot architecture flags, describing legacy devices.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct IapcBootArch: u16 {
+        const LEGACY_DEVICES = 1 << 0;
+        /// There is an 8042 keyboard controller.
+        const KBC_8042 = 1 << 1;
+        const VGA_NOT_PRESENT = 1 << 2;
+        const MSI_NOT_SUPPORTED = 1 << 3;
+        const PCIE_ASPM_CONTROLS = 1 << 4;
+        const CMOS_RTC_NOT_PRESENT = 1 << 5;
+    }
+}
+
+/// The Fixed ACPI Description Table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fadt {
+    pub revision: u8,
+    pub firmware_ctrl: PhysAddr,
+    pub dsdt: PhysAddr,
+    pub preferred_pm_profile: u8,
+    /// The ISA IRQ that System Control Interrupts arrive on.
+    pub sci_interrupt: u16,
+    /// The port that `acpi_enable` and `acpi_disable` are written to, or 0 if ACPI is always on.
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event_block: Option<GenericAddress>,
+    pub pm1b_event_block: Option<GenericAddress>,
+    pub pm1a_control_block: Option<GenericAddress>,
+    pub pm1b_control_block: Option<GenericAddress>,
+    pub pm_timer_block: Option<GenericAddress>,
+    /// The CMOS register holding the century, or 0 if there isn't one.
+    pub century: u8,
+    pub boot_arch: IapcBootArch,
+    pub flags: FadtFlags,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+    pub sleep_control_register: Option<GenericAddress>,
+    pub sleep_status_register: Option<GenericAddress>,
+}
+
+impl Fadt {
+    pub fn parse(table: &[u8]) -> Result<Self, AcpiError> {
+        validate(table, Signature::FADT)?;
+        let length = read_u32(table, 4) as usize;
+        if length < FADT_V1_LENGTH {
+            return Err(AcpiError::TableTooShort(Signature::FADT));
+        }
+        let table = &table[..length];
+
+        // Later revisions grew 64-bit versions of the 1.0 fields, which win when present.
+        let u64_at = |offset: usize| (table.len() >= offset + 8).then(|| read_u64(table, offset));
+        let gas_at = |offset: usize| {
+            (table.len() >= offset + 12)
+                .then(|| GenericAddress::parse(&table[offset..]))
+                .filter(|gas| gas.address != 0)
+        };
+        let block = |x_offset: usize, offset: usize, length: u8| {
+            gas_at(x_offset).or_else(|| {
+                let port = read_u32(table, offset) as u64;
+                (port != 0).then_some(GenericAddress {
+                    address_space: AddressSpace::SystemIo,
+                    bit_width: length * 8,
+                    bit_offset: 0,
+                    access_size: 0,
+                    address: port,
+                    mapped: None,
+                })
+            })
+        };
+        let pm1_event_length = table[88];
+        let pm1_control_length = table[89];
+        let pm_timer_length = table[91];
+
+        Ok(Self {
+            revision: table[8],
+            firmware_ctrl: PhysAddr::new(
+                u64_at(132)
+                    .filter(|&addr| addr != 0)
+                    .unwrap_or(read_u32(table, 36) as u64),
+            ),
+            dsdt: PhysAddr::new(
+                u64_at(140)
+                    .filter(|&addr| addr != 0)
+                    .unwrap_or(read_u32(table, 40) as u64),
+            ),
+            preferred_pm_profile: table[45],
+            sci_interrupt: read_u16(table, 46),
+            smi_command_port: read_u32(table, 48),
+            acpi_enable: table[52],
+            acpi_disable: table[53],
+            // The event blocks hold a status and an enable register, each half the length.
+            pm1a_event_block: block(148, 56, pm1_event_length),
+            pm1b_event_block: block(160, 60, pm1_event_length),
+            pm1a_control_block: block(172, 64, pm1_control_length),
+            pm1b_control_block: block(184, 68, pm1_control_length),
+            pm_timer_block: block(208, 76, pm_timer_length),
+            century: table[108],
+            boot_arch: IapcBootArch::from_bits_retain(read_u16(table, 109)),
+            flags: FadtFlags::from_bits_retain(read_u32(table, 112)),
+            reset_register: gas_at(116),
+            reset_value: table.get(128).copied().unwrap_or(0),
+            sleep_control_register: gas_at(244),
+            sleep_status_register: gas_at(256),
+        })
+    }
+
+    /// The CMOS register holding the century, if the RTC has one.
+    pub fn century_register(&self) -> Option<u8> {
+        (self.century != 0).then_some(self.century)
+    }
+
+    /// Maps every memory mapped register, so that accessing one later doesn't need the VMM.
+    pub fn map_registers(&mut self) -> Result<(), AcpiError> {
+        for register in [
+            &mut self.pm1a_event_block,
+            &mut self.pm1b_event_block,
+            &mut self.pm1a_control_block,
+            &mut self.pm1b_control_block,
+            &mut self.pm_timer_block,
+            &mut self.reset_register,
+            &mut self.sleep_control_register,
+            &mut self.sleep_status_register,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            register.map()?;
+        }
+        Ok(())
+    }
+
+    /// Whether there is an 8042 keyboard controller. ACPI 1.0 tables predate the flag, so they
+    /// are assumed to have one.
+    pub fn has_8042(&self) -> bool {
+        self.revision < 2 || self.boot_arch.contains(IapcBootArch::KBC_8042)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+
+    use super::{super::build_table, *};
+
+    #[test]
+    pub fn prefers_extended_blocks_and_falls_back_to_legacy_ports() {
+        let mut body = vec![0u8; 276 - 36];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            body[offset - 36..offset - 36 + bytes.len()].copy_from_slice(bytes)
+        };
+        put(40, &0x7FE0_0000u32.to_le_bytes());
+        put(46, &9u16.to_le_bytes());
+        put(56, &0x600u32.to_le_bytes());
+        put(64, &0x604u32.to_le_bytes());
+        put(88, &[4, 2, 0, 4]);
+        put(108, &[0x32]);
+        put(112, &FadtFlags::RESET_REG_SUP.bits().to_le_bytes());
+        // Reset register: port 0xCF9, written with 0x06
+        put(116, &[1, 8, 0, 1]);
+        put(120, &0xCF9u64.to_le_bytes());
+        put(128, &[0x06]);
+        // X_PM1a_EVT_BLK overrides the legacy port
+        put(148, &[1, 32, 0, 2]);
+        put(152, &0xB000u64.to_le_bytes());
+        let table = build_table(b"FACP", &body);
+
+        let fadt = Fadt::parse(&table).unwrap();
+        assert_eq!(PhysAddr::new(0x7FE0_0000), fadt.dsdt);
+        assert_eq!(9, fadt.sci_interrupt);
+        assert_eq!(0xB000, fadt.pm1a_event_block.unwrap().address);
+        assert_eq!(
+            Some(GenericAddress {
+                address_space: AddressSpace::SystemIo,
+                bit_width: 16,
+                bit_offset: 0,
+                access_size: 0,
+                address: 0x604,
+                mapped: None,
+            }),
+            fadt.pm1a_control_block
+        );
+        assert_eq!(None, fadt.pm1b_control_block);
+        assert_eq!(Some(0x32), fadt.century_register());
+        assert_eq!(0xCF9, fadt.reset_register.unwrap().address);
+        assert_eq!(0x06, fadt.reset_value);
+    }
+}
@@ -0,0 +1,27 @@
+use super::{read_u16, read_u32, validate, AcpiError, GenericAddress, Signature, SDT_HEADER_SIZE};
+
+/// The HPET Description Table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HpetTable {
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    pub hpet_number: u8,
+    /// The minimum periodic tick, in main counter ticks, that won't lose interrupts.
+    pub minimum_tick: u16,
+}
+
+impl HpetTable {
+    pub fn parse(table: &[u8]) -> Result<Self, AcpiError> {
+        validate(table, Signature::HPET)?;
+        if table.len() < SDT_HEADER_SIZE + 20 {
+            return Err(AcpiError::TableTooShort(Signature::HPET));
+        }
+
+        Ok(Self {
+            event_timer_block_id: read_u32(table, 36),
+            base_address: GenericAddress::parse(&table[40..52]),
+            hpet_number: table[52],
+            minimum_tick: read_u16(table, 53),
+        })
+    }
+}
@@ -0,0 +1,269 @@
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+use super::{read_u16, read_u32, read_u64, validate, AcpiError, Signature, SDT_HEADER_SIZE};
+
+/// Interrupt pin polarity, from the MPS INTI flags used throughout the MADT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    /// Conforms to the specification of the bus.
+    Conforming,
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// Interrupt trigger mode, from the MPS INTI flags used throughout the MADT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Conforms to the specification of the bus.
+    Conforming,
+    Edge,
+    Level,
+}
+
+fn inti_flags(flags: u16) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0b11 {
+        0b01 => Polarity::ActiveHigh,
+        0b11 => Polarity::ActiveLow,
+        _ => Polarity::Conforming,
+    };
+    let trigger = match (flags >> 2) & 0b11 {
+        0b01 => TriggerMode::Edge,
+        0b11 => TriggerMode::Level,
+        _ => TriggerMode::Conforming,
+    };
+    (polarity, trigger)
+}
+
+/// A processor, described by either a Processor Local APIC or a Processor Local x2APIC entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Processor {
+    pub processor_uid: u32,
+    pub apic_id: u32,
+    /// The processor is ready to use.
+    pub enabled: bool,
+    /// The processor is disabled, but can be brought online later.
+    pub online_capable: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// The first Global System Interrupt handled by this I/O APIC.
+    pub gsi_base: u32,
+}
+
+/// Describes how an ISA IRQ is actually wired to a Global System Interrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+/// A local APIC LINT pin wired to NMI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// The processor this applies to, or `None` for all processors.
+    pub processor_uid: Option<u32>,
+    pub lint: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+/// The Multiple APIC Description Table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    /// The system also has a dual 8259 PIC setup.
+    pub pcat_compat: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptSourceOverride>,
+    pub local_apic_nmis: Vec<LocalApicNmi>,
+}
+
+impl Madt {
+    pub fn parse(table: &[u8]) -> Result<Self, AcpiError> {
+        validate(table, Signature::MADT)?;
+        let length = read_u32(table, 4) as usize;
+        if length < SDT_HEADER_SIZE + 8 {
+            return Err(AcpiError::TableTooShort(Signature::MADT));
+        }
+
+        let mut madt = Madt {
+            local_apic_address: PhysAddr::new(read_u32(table, 36) as u64),
+            pcat_compat: read_u32(table, 40) & 1 != 0,
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            local_apic_nmis: Vec::new(),
+        };
+
+        let mut entries = &table[SDT_HEADER_SIZE + 8..length];
+        while entries.len() >= 2 {
+            let (kind, len) = (entries[0], entries[1] as usize);
+            if len < 2 || len > entries.len() {
+                return Err(AcpiError::TableTooShort(Signature::MADT));
+            }
+            let entry = &entries[..len];
+            entries = &entries[len..];
+
+            match (kind, len) {
+                (0, 8..) => madt.processors.push(Processor {
+                    processor_uid: entry[2] as u32,
+                    apic_id: entry[3] as u32,
+                    enabled: read_u32(entry, 4) & 1 != 0,
+                    online_capable: read_u32(entry, 4) & 2 != 0,
+                }),
+                (1, 12..) => madt.io_apics.push(IoApicInfo {
+                    id: entry[2],
+                    address: PhysAddr::new(read_u32(entry, 4) as u64),
+                    gsi_base: read_u32(entry, 8),
+                }),
+                (2, 10..) => {
+                    let (polarity, trigger) = inti_flags(read_u16(entry, 8));
+                    madt.overrides.push(InterruptSourceOverride {
+                        bus: entry[2],
+                        source: entry[3],
+                        gsi: read_u32(entry, 4),
+                        polarity,
+                        trigger,
+                    })
+                }
+                (4, 6..) => {
+                    let (polarity, trigger) = inti_flags(read_u16(entry, 3));
+                    madt.local_apic_nmis.push(LocalApicNmi {
+                        processor_uid: (entry[2] != 0xFF).then_some(entry[2] as u32),
+                        lint: entry[5],
+                        polarity,
+                        trigger,
+                    })
+                }
+                (5, 12..) => madt.local_apic_address = PhysAddr::new(read_u64(entry, 4)),
+                (9, 16..) => madt.processors.push(Processor {
+                    processor_uid: read_u32(entry, 12),
+                    apic_id: read_u32(entry, 4),
+                    enabled: read_u32(entry, 8) & 1 != 0,
+                    online_capable: read_u32(entry, 8) & 2 != 0,
+                }),
+                (0xA, 12..) => {
+                    let (polarity, trigger) = inti_flags(read_u16(entry, 2));
+                    let uid = read_u32(entry, 4);
+                    madt.local_apic_nmis.push(LocalApicNmi {
+                        processor_uid: (uid != 0xFFFF_FFFF).then_some(uid),
+                        lint: entry[8],
+                        polarity,
+                        trigger,
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        Ok(madt)
+    }
+
+    /// Maps an ISA IRQ to its Global System Interrupt, polarity and trigger mode,
+    /// taking interrupt source overrides into account.
+    pub fn isa_irq(&self, irq: u8) -> (u32, Polarity, TriggerMode) {
+        match self
+            .overrides
+            .iter()
+            .find(|o| o.bus == 0 && o.source == irq)
+        {
+            Some(o) => (o.gsi, o.polarity, o.trigger),
+            None => (irq as u32, Polarity::Conforming, TriggerMode::Conforming),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use x86_64::PhysAddr;
+
+    use super::{super::build_table, *};
+
+    #[test]
+    pub fn parses_qemu_style_madt() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0xFEE0_0000u32.to_le_bytes());
+        body.extend_from_slice(&1u32.to_le_bytes());
+        // Two local APICs, the second one disabled
+        body.extend_from_slice(&[0, 8, 0, 0, 1, 0, 0, 0]);
+        body.extend_from_slice(&[0, 8, 1, 1, 2, 0, 0, 0]);
+        // One I/O APIC
+        body.extend_from_slice(&[1, 12, 0, 0, 0x00, 0x00, 0xC0, 0xFE, 0, 0, 0, 0]);
+        // IRQ 0 -> GSI 2, IRQ 9 -> GSI 9 level/active high
+        body.extend_from_slice(&[2, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+        body.extend_from_slice(&[2, 10, 0, 9, 9, 0, 0, 0, 0x0D, 0]);
+        // LINT1 is NMI on all processors
+        body.extend_from_slice(&[4, 6, 0xFF, 0, 0, 1]);
+        let table = build_table(b"APIC", &body);
+
+        let madt = Madt::parse(&table).unwrap();
+        assert_eq!(PhysAddr::new(0xFEE0_0000), madt.local_apic_address);
+        assert!(madt.pcat_compat);
+        assert_eq!(
+            &[
+                Processor {
+                    processor_uid: 0,
+                    apic_id: 0,
+                    enabled: true,
+                    online_capable: false
+                },
+                Processor {
+                    processor_uid: 1,
+                    apic_id: 1,
+                    enabled: false,
+                    online_capable: true
+                },
+            ],
+            madt.processors.as_slice()
+        );
+        assert_eq!(
+            &[IoApicInfo {
+                id: 0,
+                address: PhysAddr::new(0xFEC0_0000),
+                gsi_base: 0
+            }],
+            madt.io_apics.as_slice()
+        );
+        assert_eq!(
+            (2, Polarity::Conforming, TriggerMode::Conforming),
+            madt.isa_irq(0)
+        );
+        assert_eq!(
+            (9, Polarity::ActiveHigh, TriggerMode::Level),
+            madt.isa_irq(9)
+        );
+        assert_eq!(
+            (4, Polarity::Conforming, TriggerMode::Conforming),
+            madt.isa_irq(4)
+        );
+        assert_eq!(
+            &[LocalApicNmi {
+                processor_uid: None,
+                lint: 1,
+                polarity: Polarity::Conforming,
+                trigger: TriggerMode::Conforming,
+            }],
+            madt.local_apic_nmis.as_slice()
+        );
+    }
+
+    #[test]
+    pub fn rejects_bad_checksum() {
+        let mut table = build_table(b"APIC", &[0; 8]);
+        table[20] ^= 0xFF;
+        assert_eq!(
+            Err(AcpiError::BadChecksum(Signature::MADT)),
+            Madt::parse(&table)
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+use super::{read_u16, read_u32, read_u64, validate, AcpiError, Signature, SDT_HEADER_SIZE};
+
+/// The PCI Express memory mapped configuration space table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+/// The Enhanced Configuration Access Mechanism region for a range of buses in one segment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct McfgEntry {
+    /// The address of the configuration space of bus 0, even if `start_bus` is higher.
+    pub base_address: PhysAddr,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl McfgEntry {
+    /// The address of the 4 KiB configuration space of a function, if this entry covers its bus.
+    pub fn config_address(&self, bus: u8, device: u8, function: u8) -> Option<PhysAddr> {
+        if !(self.start_bus..=self.end_bus).contains(&bus) || device >= 32 || function >= 8 {
+            return None;
+        }
+        let offset = (bus as u64) << 20 | (device as u64) << 15 | (function as u64) << 12;
+        Some(self.base_address + offset)
+    }
+}
+
+impl Mcfg {
+    pub fn parse(table: &[u8]) -> Result<Self, AcpiError> {
+        validate(table, Signature::MCFG)?;
+        let length = read_u32(table, 4) as usize;
+        if length < SDT_HEADER_SIZE + 8 {
+            return Err(AcpiError::TableTooShort(Signature::MCFG));
+        }
+
+        let entries = table[SDT_HEADER_SIZE + 8..length]
+            .as_chunks::<16>()
+            .0
+            .iter()
+            .map(|entry| McfgEntry {
+                base_address: PhysAddr::new(read_u64(entry, 0)),
+                segment_group: read_u16(entry, 8),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+}
@@ -0,0 +1,519 @@
+//! Discovery and parsing of the static ACPI tables.
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::fmt;
+use x86_64::{instructions::port::Port, PhysAddr, VirtAddr};
+
+use crate::vmm;
+
+pub mod aml;
+mod fadt;
+mod hpet;
+mod madt;
+mod mcfg;
+mod slit;
+mod srat;
+pub use fadt::*;
+pub use hpet::*;
+pub use madt::*;
+pub use mcfg::*;
+pub use slit::*;
+pub use srat::*;
+
+/// Size of the header shared by every System Description Table.
+pub const SDT_HEADER_SIZE: usize = 36;
+
+static TABLES: OnceCell<AcpiTables> = OnceCell::uninit();
+static MADT: OnceCell<Option<Madt>> = OnceCell::uninit();
+static FADT: OnceCell<Option<Fadt>> = OnceCell::uninit();
+static HPET: OnceCell<Option<HpetTable>> = OnceCell::uninit();
+static MCFG: OnceCell<Option<Mcfg>> = OnceCell::uninit();
+static SLIT: OnceCell<Option<Slit>> = OnceCell::uninit();
+static SRAT: OnceCell<Option<Srat>> = OnceCell::uninit();
+
+/// A four-character ACPI table signature, such as `APIC` or `FACP`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Signature(pub [u8; 4]);
+
+impl Signature {
+    pub const DSDT: Signature = Signature(*b"DSDT");
+    pub const FADT: Signature = Signature(*b"FACP");
+    pub const HPET: Signature = Signature(*b"HPET");
+    pub const MADT: Signature = Signature(*b"APIC");
+    pub const MCFG: Signature = Signature(*b"MCFG");
+    pub const RSDT: Signature = Signature(*b"RSDT");
+    pub const SLIT: Signature = Signature(*b"SLIT");
+    pub const SRAT: Signature = Signature(*b"SRAT");
+    pub const SSDT: Signature = Signature(*b"SSDT");
+    pub const XSDT: Signature = Signature(*b"XSDT");
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.0).unwrap_or("????")
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The RSDP signature or checksum is wrong.
+    InvalidRsdp,
+    /// A table is shorter than its own header claims, or than its fixed fields require.
+    TableTooShort(Signature),
+    /// A table's checksum does not sum to zero.
+    BadChecksum(Signature),
+    /// A table was found where another was expected.
+    UnexpectedSignature {
+        expected: Signature,
+        found: Signature,
+    },
+    /// A register lives in an address space we can't access.
+    UnsupportedAddressSpace(AddressSpace),
+    /// A memory mapped register couldn't be mapped.
+    MapFailed,
+    /// A memory mapped register was accessed without being mapped by [`GenericAddress::map`].
+    NotMapped,
+}
+
+/// The address space a [`GenericAddress`] lives in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfiguration,
+    Other(u8),
+}
+
+/// A Generic Address Structure, describing the location of a register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub address_space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    /// 0 for undefined (legacy), otherwise 1 (byte) to 4 (qword).
+    pub access_size: u8,
+    pub address: u64,
+    /// Where [`Self::map`] mapped a memory mapped register.
+    mapped: Option<VirtAddr>,
+}
+
+impl GenericAddress {
+    /// Parses the 12 byte structure at the start of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Self {
+        Self {
+            address_space: match bytes[0] {
+                0 => AddressSpace::SystemMemory,
+                1 => AddressSpace::SystemIo,
+                2 => AddressSpace::PciConfiguration,
+                other => AddressSpace::Other(other),
+            },
+            bit_width: bytes[1],
+            bit_offset: bytes[2],
+            access_size: bytes[3],
+            address: read_u64(bytes, 4),
+            mapped: None,
+        }
+    }
+
+    /// Maps the register, if it's memory mapped and isn't mapped already, so that it can be read
+    /// and written.
+    pub fn map(&mut self) -> Result<(), AcpiError> {
+        if self.address_space == AddressSpace::SystemMemory && self.mapped.is_none() {
+            let addr = vmm::get()
+                .map_mmio(PhysAddr::new(self.address), self.access_width() as u64 / 8)
+                .map_err(|_| AcpiError::MapFailed)?;
+            self.mapped = Some(addr);
+        }
+        Ok(())
+    }
+
+    /// The width of each access, in bits.
+    fn access_width(&self) -> u8 {
+        match self.access_size {
+            1..=4 => 8 << (self.access_size - 1),
+            _ => (self.bit_width + self.bit_offset)
+                .next_power_of_two()
+                .clamp(8, 64),
+        }
+    }
+
+    /// Reads the register.
+    ///
+    /// Memory mapped registers must have been mapped with [`Self::map`].
+    ///
+    /// # Safety
+    ///
+    /// Reading the register must not have side effects that break memory safety.
+    pub unsafe fn read(&self) -> Result<u64, AcpiError> {
+        let value = match self.address_space {
+            AddressSpace::SystemIo => unsafe {
+                let port = self.address as u16;
+                match self.access_width() {
+                    8 => Port::<u8>::new(port).read() as u64,
+                    16 => Port::<u16>::new(port).read() as u64,
+                    _ => Port::<u32>::new(port).read() as u64,
+                }
+            },
+            AddressSpace::SystemMemory => {
+                let width = self.access_width();
+                let addr = self.mapped.ok_or(AcpiError::NotMapped)?;
+                unsafe {
+                    match width {
+                        8 => core::ptr::read_volatile(addr.as_ptr::<u8>()) as u64,
+                        16 => core::ptr::read_volatile(addr.as_ptr::<u16>()) as u64,
+                        32 => core::ptr::read_volatile(addr.as_ptr::<u32>()) as u64,
+                        _ => core::ptr::read_volatile(addr.as_ptr::<u64>()),
+                    }
+                }
+            }
+            other => return Err(AcpiError::UnsupportedAddressSpace(other)),
+        };
+        Ok(value >> self.bit_offset)
+    }
+
+    /// Writes the register. See [`Self::read`].
+    ///
+    /// # Safety
+    ///
+    /// Writing the register must not have side effects that break memory safety.
+    pub unsafe fn write(&self, value: u64) -> Result<(), AcpiError> {
+        let value = value << self.bit_offset;
+        match self.address_space {
+            AddressSpace::SystemIo => unsafe {
+                let port = self.address as u16;
+                match self.access_width() {
+                    8 => Port::<u8>::new(port).write(value as u8),
+                    16 => Port::<u16>::new(port).write(value as u16),
+                    _ => Port::<u32>::new(port).write(value as u32),
+                }
+            },
+            AddressSpace::SystemMemory => {
+                let width = self.access_width();
+                let addr = self.mapped.ok_or(AcpiError::NotMapped)?;
+                unsafe {
+                    match width {
+                        8 => core::ptr::write_volatile(addr.as_mut_ptr::<u8>(), value as u8),
+                        16 => core::ptr::write_volatile(addr.as_mut_ptr::<u16>(), value as u16),
+                        32 => core::ptr::write_volatile(addr.as_mut_ptr::<u32>(), value as u32),
+                        _ => core::ptr::write_volatile(addr.as_mut_ptr::<u64>(), value),
+                    }
+                }
+            }
+            other => return Err(AcpiError::UnsupportedAddressSpace(other)),
+        }
+        Ok(())
+    }
+}
+
+/// The set of tables listed in the RSDT or XSDT.
+pub struct AcpiTables {
+    revision: u8,
+    tables: Vec<(Signature, PhysAddr)>,
+}
+
+impl AcpiTables {
+    /// The ACPI revision reported by the RSDP.
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    /// Iterates over the signature and physical address of every table.
+    pub fn iter(&self) -> impl Iterator<Item = (Signature, PhysAddr)> + '_ {
+        self.tables.iter().copied()
+    }
+
+    /// Returns the contents of the first table with the given signature.
+    pub fn find(&self, signature: Signature) -> Option<&'static [u8]> {
+        self.find_all(signature).next()
+    }
+
+    /// Returns the contents of every table with the given signature.
+    pub fn find_all(&self, signature: Signature) -> impl Iterator<Item = &'static [u8]> + '_ {
+        self.tables
+            .iter()
+            .filter(move |(s, _)| *s == signature)
+            .map(|(_, addr)| unsafe {
+                // SAFETY: Every table in the list was validated when it was discovered.
+                table_bytes(*addr)
+            })
+    }
+}
+
+/// Locates and validates the RSDP and the RSDT/XSDT it points at.
+///
+/// # Safety
+///
+/// `rsdp_addr` must be the physical address of the RSDP, as handed over by the bootloader.
+pub unsafe fn init(rsdp_addr: PhysAddr) -> Result<&'static AcpiTables, AcpiError> {
+    let rsdp = unsafe { core::slice::from_raw_parts(vmm::phys_to_virt(rsdp_addr).as_ptr(), 36) };
+    if &rsdp[0..8] != b"RSD PTR " || checksum(&rsdp[0..20]) != 0 {
+        return Err(AcpiError::InvalidRsdp);
+    }
+
+    let revision = rsdp[15];
+    let (root, signature, entry_size) = if revision >= 2 {
+        // The extended checksum covers as many bytes as the length says, which is never more than
+        // the structure.
+        let length = read_u32(rsdp, 20) as usize;
+        if !(20..=36).contains(&length) {
+            return Err(AcpiError::InvalidRsdp);
+        }
+        let extended =
+            unsafe { core::slice::from_raw_parts(vmm::phys_to_virt(rsdp_addr).as_ptr(), length) };
+        if checksum(extended) != 0 {
+            return Err(AcpiError::InvalidRsdp);
+        }
+        (read_u64(rsdp, 24), Signature::XSDT, 8)
+    } else {
+        (read_u32(rsdp, 16) as u64, Signature::RSDT, 4)
+    };
+
+    let root = unsafe { table_bytes(PhysAddr::new(root)) };
+    validate(root, signature)?;
+
+    let mut tables = Vec::new();
+    for entry in root[SDT_HEADER_SIZE..].chunks_exact(entry_size) {
+        let addr = PhysAddr::new(match entry_size {
+            8 => read_u64(entry, 0),
+            _ => read_u32(entry, 0) as u64,
+        });
+        let table = unsafe { table_bytes(addr) };
+        let signature = table_signature(table);
+        match validate(table, signature) {
+            Ok(()) => tables.push((signature, addr)),
+            Err(e) => log::warn!("Skipping invalid ACPI table at {:?}: {:?}", addr, e),
+        }
+    }
+
+    let tables = TABLES.get_or_init(|| AcpiTables { revision, tables });
+    log_summary(tables);
+    Ok(tables)
+}
+
+fn log_summary(tables: &AcpiTables) {
+    log::info!(
+        "ACPI revision {}, {} tables:",
+        tables.revision(),
+        tables.tables.len()
+    );
+    for (signature, addr) in tables.iter() {
+        log::debug!("  {:?} at {:#X}", signature, addr);
+    }
+    if let Some(madt) = madt() {
+        log::info!(
+            "  MADT: {} processors ({} enabled), {} I/O APICs, {} interrupt overrides",
+            madt.processors.len(),
+            madt.processors.iter().filter(|p| p.enabled).count(),
+            madt.io_apics.len(),
+            madt.overrides.len(),
+        );
+    }
+    if let Some(fadt) = fadt() {
+        log::info!(
+            "  FADT: revision {}, SCI on IRQ {}, DSDT at {:#X}{}",
+            fadt.revision,
+            fadt.sci_interrupt,
+            fadt.dsdt,
+            if fadt.flags.contains(FadtFlags::HW_REDUCED_ACPI) {
+                ", hardware-reduced"
+            } else {
+                ""
+            }
+        );
+    }
+    if let Some(hpet) = hpet() {
+        log::info!("  HPET: registers at {:#X}", hpet.base_address.address);
+    }
+    if let Some(mcfg) = mcfg() {
+        for entry in &mcfg.entries {
+            log::info!(
+                "  MCFG: segment {} buses {}-{} at {:#X}",
+                entry.segment_group,
+                entry.start_bus,
+                entry.end_bus,
+                entry.base_address
+            );
+        }
+    }
+    if let Some(srat) = srat() {
+        let mut domains = srat
+            .processors
+            .iter()
+            .map(|p| p.proximity_domain)
+            .chain(srat.memory.iter().map(|m| m.proximity_domain))
+            .collect::<Vec<_>>();
+        domains.sort_unstable();
+        domains.dedup();
+        log::info!(
+            "  SRAT: {} proximity domains, {} processors, {} memory ranges",
+            domains.len(),
+            srat.processors.len(),
+            srat.memory.len()
+        );
+    }
+    if let Some(slit) = slit() {
+        log::info!("  SLIT: {} localities", slit.localities());
+    }
+}
+
+/// Returns the ACPI tables, if [`init`] has succeeded.
+pub fn tables() -> Option<&'static AcpiTables> {
+    TABLES.get()
+}
+
+/// Returns the parsed MADT, if the firmware provided a valid one.
+pub fn madt() -> Option<&'static Madt> {
+    MADT.get_or_init(|| {
+        let table = tables()?.find(Signature::MADT)?;
+        Madt::parse(table)
+            .inspect_err(|e| log::warn!("Failed to parse MADT: {:?}", e))
+            .ok()
+    })
+    .as_ref()
+}
+
+/// Returns the parsed FADT, if the firmware provided a valid one.
+pub fn fadt() -> Option<&'static Fadt> {
+    FADT.get_or_init(|| {
+        let table = tables()?.find(Signature::FADT)?;
+        let mut fadt = Fadt::parse(table)
+            .inspect_err(|e| log::warn!("Failed to parse FADT: {:?}", e))
+            .ok()?;
+        if let Err(e) = fadt.map_registers() {
+            log::warn!("Failed to map FADT registers: {:?}", e);
+        }
+        Some(fadt)
+    })
+    .as_ref()
+}
+
+/// Returns the Differentiated System Description Table, which holds the bulk of the AML and is
+/// found through the FADT rather than the XSDT.
+pub fn dsdt() -> Option<&'static [u8]> {
+    let addr = fadt()?.dsdt;
+    if addr.is_null() {
+        return None;
+    }
+    // SAFETY: The FADT was validated, so this points at the DSDT.
+    let table = unsafe { table_bytes(addr) };
+    validate(table, Signature::DSDT)
+        .inspect_err(|e| log::warn!("Invalid DSDT: {:?}", e))
+        .ok()?;
+    Some(table)
+}
+
+/// Returns the parsed HPET table, if the firmware provided a valid one.
+pub fn hpet() -> Option<&'static HpetTable> {
+    HPET.get_or_init(|| {
+        let table = tables()?.find(Signature::HPET)?;
+        HpetTable::parse(table)
+            .inspect_err(|e| log::warn!("Failed to parse HPET table: {:?}", e))
+            .ok()
+    })
+    .as_ref()
+}
+
+/// Returns the parsed MCFG, if the firmware provided a valid one.
+pub fn mcfg() -> Option<&'static Mcfg> {
+    MCFG.get_or_init(|| {
+        let table = tables()?.find(Signature::MCFG)?;
+        Mcfg::parse(table)
+            .inspect_err(|e| log::warn!("Failed to parse MCFG: {:?}", e))
+            .ok()
+    })
+    .as_ref()
+}
+
+/// Returns the parsed SLIT, if the firmware provided a valid one.
+pub fn slit() -> Option<&'static Slit> {
+    SLIT.get_or_init(|| {
+        let table = tables()?.find(Signature::SLIT)?;
+        Slit::parse(table)
+            .inspect_err(|e| log::warn!("Failed to parse SLIT: {:?}", e))
+            .ok()
+    })
+    .as_ref()
+}
+
+/// Returns the parsed SRAT, if the firmware provided a valid one.
+pub fn srat() -> Option<&'static Srat> {
+    SRAT.get_or_init(|| {
+        let table = tables()?.find(Signature::SRAT)?;
+        Srat::parse(table)
+            .inspect_err(|e| log::warn!("Failed to parse SRAT: {:?}", e))
+            .ok()
+    })
+    .as_ref()
+}
+
+/// Maps a table through the physical memory map, using the length from its header.
+///
+/// # Safety
+///
+/// `addr` must point at an ACPI System Description Table.
+unsafe fn table_bytes(addr: PhysAddr) -> &'static [u8] {
+    let ptr: *const u8 = vmm::phys_to_virt(addr).as_ptr();
+    let header = unsafe { core::slice::from_raw_parts(ptr, SDT_HEADER_SIZE) };
+    let length = read_u32(header, 4) as usize;
+    unsafe { core::slice::from_raw_parts(ptr, length.max(SDT_HEADER_SIZE)) }
+}
+
+fn table_signature(table: &[u8]) -> Signature {
+    Signature([table[0], table[1], table[2], table[3]])
+}
+
+/// Checks the signature, length and checksum of a System Description Table.
+pub fn validate(table: &[u8], expected: Signature) -> Result<(), AcpiError> {
+    if table.len() < SDT_HEADER_SIZE {
+        return Err(AcpiError::TableTooShort(expected));
+    }
+    let found = table_signature(table);
+    if found != expected {
+        return Err(AcpiError::UnexpectedSignature { expected, found });
+    }
+    if (read_u32(table, 4) as usize) > table.len() {
+        return Err(AcpiError::TableTooShort(expected));
+    }
+    if checksum(table) != 0 {
+        return Err(AcpiError::BadChecksum(expected));
+    }
+    Ok(())
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Builds a table with a valid header and checksum around the given body.
+#[cfg(test)]
+fn build_table(signature: &[u8; 4], body: &[u8]) -> std::vec::Vec<u8> {
+    let mut table = std::vec::Vec::new();
+    table.extend_from_slice(signature);
+    table.extend_from_slice(&((SDT_HEADER_SIZE + body.len()) as u32).to_le_bytes());
+    table.push(1); // Revision
+    table.push(0); // Checksum
+    table.extend_from_slice(b"ROXYOS");
+    table.extend_from_slice(b"ROXYTEST");
+    table.extend_from_slice(&[0; 12]);
+    table.extend_from_slice(body);
+    let sum = table.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+    table[9] = 0u8.wrapping_sub(sum);
+    table
+}
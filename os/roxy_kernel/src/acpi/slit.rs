@@ -0,0 +1,67 @@
+use alloc::vec::Vec;
+
+use super::{read_u64, validate, AcpiError, Signature, SDT_HEADER_SIZE};
+
+/// The System Locality Information Table, giving the relative distance between each pair of
+/// proximity domains. A node's distance to itself is normalized to 10.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Slit {
+    localities: usize,
+    distances: Vec<u8>,
+}
+
+impl Slit {
+    pub fn parse(table: &[u8]) -> Result<Self, AcpiError> {
+        validate(table, Signature::SLIT)?;
+        if table.len() < SDT_HEADER_SIZE + 8 {
+            return Err(AcpiError::TableTooShort(Signature::SLIT));
+        }
+        let localities = read_u64(table, SDT_HEADER_SIZE) as usize;
+        let start = SDT_HEADER_SIZE + 8;
+        let distances = localities
+            .checked_mul(localities)
+            .and_then(|n| table.get(start..start + n))
+            .ok_or(AcpiError::TableTooShort(Signature::SLIT))?;
+        Ok(Self {
+            localities,
+            distances: distances.to_vec(),
+        })
+    }
+
+    pub fn localities(&self) -> usize {
+        self.localities
+    }
+
+    /// The distance from one proximity domain to another, if both are described.
+    pub fn distance(&self, from: u32, to: u32) -> Option<u8> {
+        let (from, to) = (from as usize, to as usize);
+        if from >= self.localities || to >= self.localities {
+            return None;
+        }
+        // 0xFF means the domains can't reach each other at all.
+        self.distances.get(from * self.localities + to).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::{super::build_table, *};
+
+    #[test]
+    pub fn parses_distance_matrix() {
+        let mut body = Vec::from(2u64.to_le_bytes());
+        body.extend_from_slice(&[10, 21, 21, 10]);
+        let slit = Slit::parse(&build_table(b"SLIT", &body)).unwrap();
+        assert_eq!(2, slit.localities());
+        assert_eq!(Some(10), slit.distance(1, 1));
+        assert_eq!(Some(21), slit.distance(0, 1));
+        assert_eq!(None, slit.distance(0, 2));
+
+        assert_eq!(
+            Err(AcpiError::TableTooShort(Signature::SLIT)),
+            Slit::parse(&build_table(b"SLIT", &body[..10]))
+        );
+    }
+}
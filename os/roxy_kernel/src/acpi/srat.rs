@@ -0,0 +1,142 @@
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+use super::{read_u32, read_u64, validate, AcpiError, Signature, SDT_HEADER_SIZE};
+
+/// Associates a processor with a proximity domain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProcessorAffinity {
+    pub apic_id: u32,
+    pub proximity_domain: u32,
+    pub enabled: bool,
+}
+
+/// Associates a range of physical memory with a proximity domain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAffinity {
+    pub base: PhysAddr,
+    pub length: u64,
+    pub proximity_domain: u32,
+    pub enabled: bool,
+    pub hot_pluggable: bool,
+    pub non_volatile: bool,
+}
+
+impl MemoryAffinity {
+    pub fn contains(&self, addr: PhysAddr) -> bool {
+        addr >= self.base && addr.as_u64() - self.base.as_u64() < self.length
+    }
+}
+
+/// The System Resource Affinity Table, which describes the NUMA topology.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Srat {
+    pub processors: Vec<ProcessorAffinity>,
+    pub memory: Vec<MemoryAffinity>,
+}
+
+impl Srat {
+    pub fn parse(table: &[u8]) -> Result<Self, AcpiError> {
+        validate(table, Signature::SRAT)?;
+        let length = read_u32(table, 4) as usize;
+        if length < SDT_HEADER_SIZE + 12 {
+            return Err(AcpiError::TableTooShort(Signature::SRAT));
+        }
+
+        let mut srat = Srat {
+            processors: Vec::new(),
+            memory: Vec::new(),
+        };
+
+        let mut entries = &table[SDT_HEADER_SIZE + 12..length];
+        while entries.len() >= 2 {
+            let (kind, len) = (entries[0], entries[1] as usize);
+            if len < 2 || len > entries.len() {
+                return Err(AcpiError::TableTooShort(Signature::SRAT));
+            }
+            let entry = &entries[..len];
+            entries = &entries[len..];
+
+            match (kind, len) {
+                (0, 16..) => srat.processors.push(ProcessorAffinity {
+                    apic_id: entry[3] as u32,
+                    // The low byte comes first, the upper three bytes were added later.
+                    proximity_domain: u32::from_le_bytes([
+                        entry[2], entry[9], entry[10], entry[11],
+                    ]),
+                    enabled: read_u32(entry, 4) & 1 != 0,
+                }),
+                (1, 40..) => {
+                    let flags = read_u32(entry, 28);
+                    srat.memory.push(MemoryAffinity {
+                        base: PhysAddr::new(read_u64(entry, 8)),
+                        length: read_u64(entry, 16),
+                        proximity_domain: read_u32(entry, 2),
+                        enabled: flags & 1 != 0,
+                        hot_pluggable: flags & 2 != 0,
+                        non_volatile: flags & 4 != 0,
+                    })
+                }
+                (2, 24..) => srat.processors.push(ProcessorAffinity {
+                    apic_id: read_u32(entry, 8),
+                    proximity_domain: read_u32(entry, 4),
+                    enabled: read_u32(entry, 12) & 1 != 0,
+                }),
+                _ => {}
+            }
+        }
+
+        Ok(srat)
+    }
+
+    /// The proximity domain of a processor, by its APIC ID.
+    pub fn processor_domain(&self, apic_id: u32) -> Option<u32> {
+        self.processors
+            .iter()
+            .find(|p| p.enabled && p.apic_id == apic_id)
+            .map(|p| p.proximity_domain)
+    }
+
+    /// The proximity domain of a physical address.
+    pub fn memory_domain(&self, addr: PhysAddr) -> Option<u32> {
+        self.memory
+            .iter()
+            .find(|m| m.enabled && m.contains(addr))
+            .map(|m| m.proximity_domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::{super::build_table, *};
+
+    #[test]
+    pub fn parses_two_node_srat() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // APIC 0 in domain 0, APIC 1 in domain 1
+        body.extend_from_slice(&[0, 16, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        body.extend_from_slice(&[0, 16, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // 0..1 GiB in domain 0, 1..2 GiB in domain 1
+        for (domain, base) in [(0u32, 0u64), (1, 0x4000_0000)] {
+            body.extend_from_slice(&[1, 40]);
+            body.extend_from_slice(&domain.to_le_bytes());
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(&base.to_le_bytes());
+            body.extend_from_slice(&0x4000_0000u64.to_le_bytes());
+            body.extend_from_slice(&[0; 4]);
+            body.extend_from_slice(&1u32.to_le_bytes());
+            body.extend_from_slice(&[0; 8]);
+        }
+        let table = build_table(b"SRAT", &body);
+
+        let srat = Srat::parse(&table).unwrap();
+        assert_eq!(2, srat.processors.len());
+        assert_eq!(Some(1), srat.processor_domain(1));
+        assert_eq!(Some(0), srat.memory_domain(PhysAddr::new(0x1000)));
+        assert_eq!(Some(1), srat.memory_domain(PhysAddr::new(0x4000_0000)));
+        assert_eq!(None, srat.memory_domain(PhysAddr::new(0x8000_0000)));
+    }
+}
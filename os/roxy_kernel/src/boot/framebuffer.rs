@@ -110,7 +110,8 @@ impl FrameBufferWriter {
     }
 
     fn scroll(&mut self, lines: usize) {
-        let start_y_pos = lines * (font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING) + BORDER_PADDING;
+        let start_y_pos =
+            lines * (font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING) + BORDER_PADDING;
         let end_y_pos = self.height() - BORDER_PADDING - start_y_pos;
         for y in BORDER_PADDING..end_y_pos {
             self.copy_line(y + start_y_pos, y);
@@ -119,7 +120,9 @@ impl FrameBufferWriter {
         let end_y_offset = end_y_pos * self.info.stride * self.info.bytes_per_pixel;
         self.framebuffer[end_y_offset..].fill(0);
 
-        self.y_pos = self.height() - BORDER_PADDING - (lines * (font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING));
+        self.y_pos = self.height()
+            - BORDER_PADDING
+            - (lines * (font_constants::CHAR_RASTER_HEIGHT.val() + LINE_SPACING));
         self.x_pos = BORDER_PADDING;
     }
 
@@ -162,7 +165,10 @@ impl FrameBufferWriter {
         let dest_byte_offset = dest_pixel_offset * bytes_per_pixel;
         let length = bytes_per_pixel * self.width();
 
-        self.framebuffer.copy_within(source_byte_offset..(source_byte_offset + length), dest_byte_offset);
+        self.framebuffer.copy_within(
+            source_byte_offset..(source_byte_offset + length),
+            dest_byte_offset,
+        );
     }
 }
 
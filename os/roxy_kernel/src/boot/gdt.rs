@@ -1,3 +1,5 @@
+use alloc::boxed::Box;
+use core::{cell::Cell, ptr};
 use x86_64::{
     instructions::tables::load_tss,
     registers::segmentation::{Segment, CS, SS},
@@ -5,51 +7,133 @@ use x86_64::{
         gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
         tss::TaskStateSegment,
     },
-    VirtAddr,
+    PrivilegeLevel, VirtAddr,
 };
 
+use crate::{percpu, vmm};
+
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
 
-struct Selectors {
-    kernel_code_selector: SegmentSelector,
-    kernel_data_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
-}
+const IST_INDICES: [u16; 3] = [
+    DOUBLE_FAULT_IST_INDEX,
+    NMI_IST_INDEX,
+    MACHINE_CHECK_IST_INDEX,
+];
+const IST_STACK_PAGES: u64 = 5;
+
+pub const KERNEL_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(1, PrivilegeLevel::Ring0);
+pub const KERNEL_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(2, PrivilegeLevel::Ring0);
+/// User data comes right before user code, where `sysret` expects them.
+pub const USER_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(3, PrivilegeLevel::Ring3);
+pub const USER_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(4, PrivilegeLevel::Ring3);
 
 lazy_static::lazy_static! {
-    static ref TSS: TaskStateSegment = {
+    /// The TSS used while booting, before the VMM can hand out guarded IST stacks.
+    ///
+    /// All IST entries share one static emergency stack; [`init_cpu`] replaces it.
+    static ref BOOT_TSS: TaskStateSegment = {
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            let stack_end = stack_start + STACK_SIZE as u64;
-            stack_end
-        };
+        let stack_end = VirtAddr::from_ptr(&raw const STACK) + STACK_SIZE as u64;
+        for index in IST_INDICES {
+            tss.interrupt_stack_table[index as usize] = stack_end;
+        }
         tss
     };
 }
 
+percpu! {
+    /// The calling CPU's own TSS, once [`init_cpu`] has given it one.
+    static TSS: Cell<*mut TaskStateSegment> = Cell::new(ptr::null_mut());
+}
+
 lazy_static::lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let kernel_code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let kernel_data_selector = gdt.append(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
-        (gdt, Selectors {
-            kernel_code_selector,
-            kernel_data_selector,
-            tss_selector
-        })
-    };
+    static ref BOOT_GDT: (GlobalDescriptorTable, SegmentSelector) =
+        build_gdt(Descriptor::tss_segment(&BOOT_TSS));
 }
 
-pub fn init() {
-    GDT.0.load();
+fn build_gdt(tss: Descriptor) -> (GlobalDescriptorTable, SegmentSelector) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let selectors = [
+        gdt.append(Descriptor::kernel_code_segment()),
+        gdt.append(Descriptor::kernel_data_segment()),
+        gdt.append(Descriptor::user_data_segment()),
+        gdt.append(Descriptor::user_code_segment()),
+    ];
+    assert_eq!(
+        selectors,
+        [
+            KERNEL_CODE_SELECTOR,
+            KERNEL_DATA_SELECTOR,
+            USER_DATA_SELECTOR,
+            USER_CODE_SELECTOR
+        ],
+        "GDT to have the layout the selector constants assume"
+    );
+    let tss_selector = gdt.append(tss);
+    (gdt, tss_selector)
+}
+
+fn load(gdt: &'static (GlobalDescriptorTable, SegmentSelector)) {
+    gdt.0.load();
     unsafe {
-        CS::set_reg(GDT.1.kernel_code_selector);
-        SS::set_reg(GDT.1.kernel_data_selector);
-        load_tss(GDT.1.tss_selector);
+        CS::set_reg(KERNEL_CODE_SELECTOR);
+        SS::set_reg(KERNEL_DATA_SELECTOR);
+        load_tss(gdt.1);
     }
 }
+
+pub fn init() {
+    load(&BOOT_GDT);
+}
+
+/// Switches the calling CPU to its own GDT and TSS, whose IST entries point at dedicated,
+/// VMM-allocated stacks with guard pages, one each for #DF, NMI and #MC.
+///
+/// Every CPU needs a TSS of its own, since loading one marks it busy. Must be called once the VMM
+/// is initialized.
+pub fn init_cpu() {
+    assert!(
+        TSS.with(Cell::get).is_null(),
+        "CPU to only be given its own GDT once"
+    );
+
+    let mut tss = TaskStateSegment::new();
+    {
+        let mut vmm = vmm::get();
+        for index in IST_INDICES {
+            let stack = vmm
+                .allocate_kernel_stack(IST_STACK_PAGES)
+                .expect("to be able to allocate an IST stack");
+            log::debug!(
+                "IST stack {}: {:p} - {:p}",
+                index,
+                stack.bottom(),
+                stack.top()
+            );
+            tss.interrupt_stack_table[index as usize] = stack.top();
+        }
+    }
+
+    // Never freed, and only ever changed through the per-CPU pointer.
+    let tss = Box::into_raw(Box::new(tss));
+    let descriptor = unsafe { Descriptor::tss_segment_unchecked(tss) };
+    load(Box::leak(Box::new(build_gdt(descriptor))));
+    TSS.with(|cpu_tss| cpu_tss.set(tss));
+}
+
+/// Sets the stack the calling CPU switches to when an interrupt arrives in user mode.
+///
+/// Interrupts must be disabled, and stay disabled until the CPU is running whatever the stack is
+/// for.
+pub fn set_privilege_stack(top: VirtAddr) {
+    let tss = unsafe { TSS.current() }.get();
+    assert!(!tss.is_null(), "CPU to have its own TSS");
+    // SAFETY: The TSS is this CPU's alone, and the CPU only reads the entry on a privilege change,
+    // which can't happen while interrupts are disabled in the kernel.
+    unsafe { (*tss).privilege_stack_table[0] = top };
+}
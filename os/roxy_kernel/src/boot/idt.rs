@@ -1,7 +1,16 @@
 use conquer_once::spin::OnceCell;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::{
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+    VirtAddr,
+};
 
-use crate::boot::gdt;
+use crate::{
+    boot::gdt,
+    interrupts, sched,
+    smp::{self, percpu},
+    time,
+    user::{self, Fault},
+};
 
 static IDT: OnceCell<InterruptDescriptorTable> = OnceCell::uninit();
 
@@ -11,11 +20,32 @@ pub fn init() {
     let idt = IDT.get_or_init(|| {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.general_protection_fault
+            .set_handler_fn(general_protection_fault_handler);
         idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.x87_floating_point
+            .set_handler_fn(x87_floating_point_handler);
+        idt.simd_floating_point
+            .set_handler_fn(simd_floating_point_handler);
+        for (vector, stub) in interrupts::stubs() {
+            idt[vector].set_handler_fn(stub);
+        }
+        idt[interrupts::LAPIC_TIMER_VECTOR].set_handler_fn(time::timer_interrupt_handler);
+        idt[interrupts::CALL_FUNCTION_VECTOR].set_handler_fn(smp::call::call_function_handler);
+        idt[interrupts::RESCHEDULE_VECTOR].set_handler_fn(sched::reschedule_handler);
+        idt[interrupts::APIC_ERROR_VECTOR].set_handler_fn(interrupts::apic_error_handler);
+        idt[interrupts::SPURIOUS_VECTOR].set_handler_fn(interrupts::spurious_handler);
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            idt.non_maskable_interrupt
+                .set_handler_fn(nmi_handler)
+                .set_stack_index(gdt::NMI_IST_INDEX);
+            idt.machine_check
+                .set_handler_fn(machine_check_handler)
+                .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
         }
         idt
     });
@@ -23,22 +53,104 @@ pub fn init() {
 }
 
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    unsafe { percpu::swapgs_on_entry(&stack_frame) };
     log::error!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+    unsafe { percpu::swapgs_on_exit(&stack_frame) };
 }
 
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) -> ! {
+    unsafe { percpu::swapgs_on_ist_entry() };
     panic!("DOUBLE FAULT 0x{:X}\n{:#?}", error_code, stack_frame);
 }
 
-extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
+/// Dumps the interrupted state and carries on. Handy with QEMU's `nmi` monitor command.
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    use x86_64::{
+        instructions::port::PortReadOnly,
+        registers::control::{Cr0, Cr2, Cr3, Cr4},
+    };
+
+    let swapped = unsafe { percpu::swapgs_on_ist_entry() };
+
+    // System Control Port B reports whether the NMI came from a parity or channel check error.
+    let port_b = unsafe { PortReadOnly::<u8>::new(0x61).read() };
+
+    log::error!("NMI");
+    log::error!(" Port B: {:#04X}", port_b);
+    log::error!(" CR0: {:?}", Cr0::read());
+    log::error!(" CR2: {:?}", Cr2::read());
+    log::error!(" CR3: {:?}", Cr3::read());
+    log::error!(" CR4: {:?}", Cr4::read());
+    log::error!("{:#?}", stack_frame);
+    unsafe { percpu::swapgs_on_ist_exit(swapped) };
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    use x86_64::registers::model_specific::Msr;
+
+    unsafe { percpu::swapgs_on_ist_entry() };
+
+    const IA32_MCG_STATUS: u32 = 0x17A;
+    let mcg_status = unsafe { Msr::new(IA32_MCG_STATUS).read() };
+    panic!(
+        "MACHINE CHECK (MCG_STATUS {:#X})\n{:#?}",
+        mcg_status, stack_frame
+    );
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
     use x86_64::registers::control::Cr2;
 
+    unsafe { percpu::swapgs_on_entry(&stack_frame) };
+    let address = Cr2::read_raw();
+    user::handle_fault(
+        &stack_frame,
+        Fault::PageFault {
+            address: VirtAddr::new_truncate(address),
+            error_code,
+        },
+    );
+
     log::error!("PAGE FAULT");
-    log::debug!(" Accessed Address: {:?}", Cr2::read());
+    log::debug!(" Accessed Address: {:#X}", address);
     log::debug!(" Error Code: {:?}", error_code);
     log::debug!("{:#?}", stack_frame);
     panic!("PAGE FAULT");
-}
\ No newline at end of file
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    unsafe { percpu::swapgs_on_entry(&stack_frame) };
+    user::handle_fault(&stack_frame, Fault::GeneralProtection { error_code });
+    panic!(
+        "GENERAL PROTECTION FAULT {:#X}\n{:#?}",
+        error_code, stack_frame
+    );
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    unsafe { percpu::swapgs_on_entry(&stack_frame) };
+    user::handle_fault(&stack_frame, Fault::InvalidOpcode);
+    panic!("INVALID OPCODE\n{:#?}", stack_frame);
+}
+
+/// Only user code uses the x87 and SSE registers, so these only come from user mode.
+extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: InterruptStackFrame) {
+    unsafe { percpu::swapgs_on_entry(&stack_frame) };
+    user::handle_fault(&stack_frame, Fault::X87FloatingPoint);
+    panic!("x87 FLOATING POINT EXCEPTION\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    unsafe { percpu::swapgs_on_entry(&stack_frame) };
+    user::handle_fault(&stack_frame, Fault::SimdFloatingPoint);
+    panic!("SIMD FLOATING POINT EXCEPTION\n{:#?}", stack_frame);
+}
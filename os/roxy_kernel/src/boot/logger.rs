@@ -1,25 +1,35 @@
 use super::{framebuffer::FrameBufferWriter, serial::SerialPort};
 use bootloader_api::info::{FrameBuffer, FrameBufferInfo};
 use conquer_once::spin::OnceCell;
-use core::fmt::Write;
+use core::{
+    cell::Cell,
+    fmt::{self, Write},
+};
 use log::LevelFilter;
-use spinning_top::Spinlock;
-use x86_64::instructions::interrupts;
+
+use crate::{percpu, smp::percpu, sync::SpinlockIrq, time};
+
+percpu! {
+    /// Set while this CPU holds the framebuffer lock.
+    static FRAMEBUFFER_HELD: Cell<bool> = Cell::new(false);
+    /// Set while this CPU holds the serial port lock.
+    static SERIAL_HELD: Cell<bool> = Cell::new(false);
+}
 
 /// The global logger instance used for the `log` crate.
 pub static LOGGER: OnceCell<LockedLogger> = OnceCell::uninit();
 
-/// A logger instance protected by a spinlock.
+/// A logger instance protected by interrupt-safe spinlocks.
 pub struct LockedLogger {
-    framebuffer: Option<Spinlock<FrameBufferWriter>>,
-    serial: Option<Spinlock<SerialPort>>,
+    framebuffer: Option<SpinlockIrq<FrameBufferWriter>>,
+    serial: Option<SpinlockIrq<SerialPort>>,
 }
 
 impl LockedLogger {
     /// Create a new instance that logs to the given framebuffer.
     pub fn new(framebuffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
-        let framebuffer = Spinlock::new(FrameBufferWriter::new(framebuffer, info));
-        let serial = Spinlock::new(unsafe { SerialPort::init() });
+        let framebuffer = SpinlockIrq::new(FrameBufferWriter::new(framebuffer, info));
+        let serial = SpinlockIrq::new(unsafe { SerialPort::init() });
 
         LockedLogger {
             framebuffer: Some(framebuffer),
@@ -34,23 +44,75 @@ impl log::Log for LockedLogger {
     }
 
     fn log(&self, record: &log::Record) {
+        // Time since the monotonic clock started, which reads zero until the timers are set up.
+        let uptime = time::now().as_nanos();
+        let (secs, micros) = (uptime / 1_000_000_000, uptime % 1_000_000_000 / 1000);
+        let cpu = percpu::current_id();
         if let Some(framebuffer) = &self.framebuffer {
-            interrupts::without_interrupts(|| {
-                let mut framebuffer = framebuffer.lock();
-                writeln!(framebuffer, "{:5}: {}", record.level(), record.args()).unwrap();
-            })
+            write_line(
+                framebuffer,
+                &FRAMEBUFFER_HELD,
+                format_args!(
+                    "[{:5}.{:06}] #{} {:5}: {}",
+                    secs,
+                    micros,
+                    cpu,
+                    record.level(),
+                    record.args()
+                ),
+            );
         }
         if let Some(serial) = &self.serial {
-            interrupts::without_interrupts(|| {
-                let mut serial = serial.lock();
-                writeln!(serial, "{:5}: {}", record.level(), record.args()).unwrap();
-            });
+            write_line(
+                serial,
+                &SERIAL_HELD,
+                format_args!(
+                    "[{:5}.{:06}] #{} {:5}: {}",
+                    secs,
+                    micros,
+                    cpu,
+                    record.level(),
+                    record.args()
+                ),
+            );
         }
     }
 
     fn flush(&self) {}
 }
 
+/// Writes a line to `writer`.
+///
+/// `held` is set before spinning on the lock, so a message logged from an NMI or exception that
+/// interrupted this CPU while it held the lock, or was about to take it, never waits for it. It's
+/// written with the lock if that's free, and without it otherwise, as waiting would never end.
+fn write_line<W: Write>(
+    writer: &SpinlockIrq<W>,
+    held: &percpu::PerCpu<Cell<bool>>,
+    line: fmt::Arguments,
+) {
+    held.with(|held| {
+        if held.get() {
+            match writer.try_lock() {
+                Some(mut writer) => writeln!(writer, "{}", line).unwrap(),
+                None => {
+                    // SAFETY: The lock holder is most likely this CPU, which can't continue until
+                    // we're done.
+                    let writer = unsafe { &mut *writer.data_ptr() };
+                    writeln!(writer, "{}", line).unwrap();
+                }
+            }
+            return;
+        }
+
+        held.set(true);
+        let mut writer = writer.lock();
+        writeln!(writer, "{}", line).unwrap();
+        drop(writer);
+        held.set(false);
+    })
+}
+
 pub fn init(fb: FrameBuffer) {
     let info = fb.info();
     let logger = LOGGER.get_or_init(move || LockedLogger::new(fb.into_buffer(), info));
@@ -195,21 +195,25 @@ mod tests {
                     start: PhysAddr::new(0x0000_0000),
                     end: PhysAddr::new(0x0000_2000),
                     kind: vmm::MemoryRegionKind::Usable,
+                    proximity_domain: None,
                 },
                 vmm::MemoryRegion {
                     start: PhysAddr::new(0x0000_2000),
                     end: PhysAddr::new(0x0000_3000),
-                    kind: vmm::MemoryRegionKind::InUse(vmm::MemoryPurpose::KernelHeap)
+                    kind: vmm::MemoryRegionKind::InUse(vmm::MemoryPurpose::KernelHeap),
+                    proximity_domain: None,
                 },
                 vmm::MemoryRegion {
                     start: PhysAddr::new(0x0000_3000),
                     end: PhysAddr::new(0x0FFF_F000),
                     kind: vmm::MemoryRegionKind::Usable,
+                    proximity_domain: None,
                 },
                 vmm::MemoryRegion {
                     start: PhysAddr::new(0x0FFF_F000),
                     end: PhysAddr::new(0x1000_0000),
                     kind: vmm::MemoryRegionKind::InUse(vmm::MemoryPurpose::KernelHeap),
+                    proximity_domain: None,
                 },
                 vmm::MemoryRegion {
                     start: PhysAddr::new(0x1000_0000),
@@ -217,26 +221,31 @@ mod tests {
                     kind: vmm::MemoryRegionKind::Reserved(
                         vmm::ReservedMemoryKind::ReservedByBootloader
                     ),
+                    proximity_domain: None,
                 },
                 vmm::MemoryRegion {
                     start: PhysAddr::new(0x2000_0000),
                     end: PhysAddr::new(0x2000_3000),
                     kind: vmm::MemoryRegionKind::InUse(vmm::MemoryPurpose::KernelHeap),
+                    proximity_domain: None,
                 },
                 vmm::MemoryRegion {
                     start: PhysAddr::new(0x2000_3000),
                     end: PhysAddr::new(0x3000_3000),
                     kind: vmm::MemoryRegionKind::Usable,
+                    proximity_domain: None,
                 },
                 vmm::MemoryRegion {
                     start: PhysAddr::new(0x3000_3000),
                     end: PhysAddr::new(0x3000_4000),
                     kind: vmm::MemoryRegionKind::InUse(vmm::MemoryPurpose::KernelHeap),
+                    proximity_domain: None,
                 },
                 vmm::MemoryRegion {
                     start: PhysAddr::new(0x3000_4000),
                     end: PhysAddr::new(0x5000_0000),
                     kind: vmm::MemoryRegionKind::Usable,
+                    proximity_domain: None,
                 },
             ],
             map.regions()
@@ -1,16 +1,22 @@
-use bootloader_api::{config::Mapping, info::Optional};
-use x86_64::VirtAddr;
+use bootloader_api::info::Optional;
+use x86_64::{PhysAddr, VirtAddr};
 
-use crate::vmm;
+use crate::{
+    acpi, deferred, drivers, efi, interrupts, numa, power,
+    smp::{self, percpu},
+    task, thread, time, user, vmm,
+};
 
 mod framebuffer;
-mod gdt;
+pub(crate) mod gdt;
 mod idt;
 mod logger;
 mod memory;
-mod serial;
+pub(crate) mod serial;
 
 pub fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    percpu::init_bsp();
+
     let mut fb = Optional::None;
     core::mem::swap(&mut fb, &mut boot_info.framebuffer);
     let fb = fb
@@ -34,16 +40,13 @@ pub fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     gdt::init();
     idt::init();
 
-    let memory_map = unsafe {
-        let phys_offset = VirtAddr::new(
-            boot_info
-                .physical_memory_offset
-                .into_option()
-                .expect("bootloader to have given us a physical memory mapping"),
-        );
-
-        memory::init(phys_offset, &boot_info.memory_regions)
-    };
+    let phys_offset = VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("bootloader to have given us a physical memory mapping"),
+    );
+    let memory_map = unsafe { memory::init(phys_offset, &boot_info.memory_regions) };
 
     log::info!(
         "Memory map initialized. {} known bytes, {} reserved bytes",
@@ -63,5 +66,57 @@ pub fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
         }
     }
 
-    todo!();
+    unsafe {
+        // SAFETY: The memory map was built from the bootloader's map, with our own allocations marked in use.
+        vmm::init(memory::get_page_table(phys_offset), memory_map);
+    }
+    gdt::init_cpu();
+    user::init_cpu();
+    thread::init();
+
+    if !efi::init() {
+        log::info!("No UEFI runtime services, using the CMOS real-time clock");
+    }
+
+    let rsdp_addr = boot_info
+        .rsdp_addr
+        .into_option()
+        .expect("bootloader to have found the RSDP");
+    unsafe { acpi::init(PhysAddr::new(rsdp_addr)) }.expect("firmware to provide valid ACPI tables");
+    numa::init();
+
+    interrupts::init();
+    time::init();
+    acpi::aml::init();
+    smp::init();
+    deferred::start_worker();
+    task::init();
+    drivers::init();
+    x86_64::instructions::interrupts::enable();
+
+    user::self_test();
+
+    power::shutdown();
+}
+
+/// Where each application processor ends up once [`smp`] has started it, on its own kernel stack.
+pub fn ap_main(cpu: usize) -> ! {
+    percpu::init_ap(cpu);
+    idt::init();
+    gdt::init_cpu();
+    user::init_cpu();
+    interrupts::init_ap();
+    time::init_ap();
+    log::info!(
+        "CPU {} online (APIC {})",
+        cpu,
+        interrupts::local_apic().id()
+    );
+    thread::init();
+    smp::set_online(cpu);
+    deferred::start_worker();
+
+    // The boot flow has nothing more to do, and leaves the CPU to its idle thread.
+    x86_64::instructions::interrupts::enable();
+    thread::exit()
 }
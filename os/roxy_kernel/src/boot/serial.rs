@@ -1,5 +1,8 @@
 use core::fmt;
 
+/// The I/O port base of the first serial port.
+pub const COM1: u16 = 0x3F8;
+
 pub struct SerialPort {
     port: uart_16550::SerialPort,
 }
@@ -9,7 +12,7 @@ impl SerialPort {
     ///
     /// unsafe because this function must only be called once
     pub unsafe fn init() -> Self {
-        let mut port = unsafe { uart_16550::SerialPort::new(0x3F8) };
+        let mut port = unsafe { uart_16550::SerialPort::new(COM1) };
         port.init();
         Self { port }
     }
@@ -0,0 +1,137 @@
+//! Work that interrupt handlers hand off to run later.
+//!
+//! Interrupt handlers run with interrupts disabled, so they should do as little as they can and
+//! defer the rest:
+//!
+//! - [Softirqs](softirq) run on the same CPU as soon as the outermost interrupt handler returns,
+//!   with interrupts enabled. There's a fixed set of them, each with one handler.
+//! - [`Tasklet`]s are callbacks scheduled from anywhere, which run in a softirq.
+//! - The [workqueue](workqueue) is for anything that takes longer, and runs on kernel threads,
+//!   outside interrupt context entirely.
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::{
+    array,
+    cell::Cell,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use x86_64::{
+    instructions::interrupts as cpu_interrupts, registers::rflags::RFlags,
+    structures::idt::InterruptStackFrame,
+};
+
+use crate::{percpu, sched, smp::percpu};
+
+pub mod softirq;
+mod tasklet;
+pub mod workqueue;
+
+pub use softirq::{in_softirq, raise, Softirq};
+pub use tasklet::Tasklet;
+pub use workqueue::{queue_work, queue_work_on, start_worker};
+
+percpu! {
+    /// How many interrupt handlers this CPU is nested in.
+    static IRQ_DEPTH: Cell<usize> = Cell::new(0);
+}
+
+/// How much deferred work a CPU has run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// How many times each softirq has run, indexed by [`Softirq`].
+    pub softirqs: [u64; Softirq::COUNT],
+    pub tasklets: u64,
+    pub work: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    softirqs: [AtomicU64; Softirq::COUNT],
+    tasklets: AtomicU64,
+    work: AtomicU64,
+}
+
+/// Each CPU's counters, indexed by CPU number.
+static COUNTERS: OnceCell<Vec<Counters>> = OnceCell::uninit();
+
+/// Sets up the per-CPU queues and statistics for `cpus` CPUs.
+pub(crate) fn init(cpus: usize) {
+    COUNTERS.init_once(|| (0..cpus).map(|_| Counters::default()).collect());
+    workqueue::init(cpus);
+    tasklet::init();
+}
+
+/// Bumps one of the calling CPU's counters.
+fn count(counter: impl FnOnce(&Counters) -> &AtomicU64) {
+    if let Some(counters) = COUNTERS.get() {
+        counter(&counters[percpu::current_id()]).fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// How much deferred work `cpu` has run, or `None` if there's no such CPU.
+pub fn stats(cpu: usize) -> Option<Stats> {
+    let counters = COUNTERS.get()?.get(cpu)?;
+    Some(Stats {
+        softirqs: array::from_fn(|i| counters.softirqs[i].load(Ordering::Relaxed)),
+        tasklets: counters.tasklets.load(Ordering::Relaxed),
+        work: counters.work.load(Ordering::Relaxed),
+    })
+}
+
+/// Whether the calling CPU is running an interrupt handler or softirqs.
+pub fn in_interrupt() -> bool {
+    IRQ_DEPTH.with(Cell::get) != 0 || in_softirq()
+}
+
+/// Must be called at the start of every hardware interrupt handler that may raise softirqs, before
+/// anything else, as it switches to the kernel's GS base if the interrupt came from user space.
+pub(crate) fn irq_enter(stack_frame: &InterruptStackFrame) {
+    unsafe {
+        // SAFETY: This is the start of the handler.
+        percpu::swapgs_on_entry(stack_frame);
+    }
+    let depth = unsafe { IRQ_DEPTH.current() };
+    depth.set(depth.get() + 1);
+}
+
+/// Must be called at the very end of every handler that called [`irq_enter`], after the EOI, and
+/// switches back to user space's GS base if that's where the handler returns to.
+///
+/// Leaving the outermost handler runs any pending softirqs, and then switches threads if the
+/// interrupted one is due to be preempted. Neither happens if the interrupt came in with
+/// interrupts disabled.
+pub(crate) fn irq_exit(stack_frame: &InterruptStackFrame) {
+    let depth = unsafe { IRQ_DEPTH.current() };
+    depth.set(depth.get() - 1);
+    if depth.get() == 0 && stack_frame.cpu_flags.contains(RFlags::INTERRUPT_FLAG) {
+        unsafe {
+            // SAFETY: Interrupts are disabled in the handler, and were enabled where it
+            // interrupted.
+            softirq::run();
+        }
+        sched::preempt_on_irq_exit();
+    }
+    unsafe {
+        // SAFETY: This is the end of the handler, which runs with interrupts disabled.
+        percpu::swapgs_on_exit(stack_frame);
+    }
+}
+
+/// Runs any pending softirqs on the calling CPU, or halts until the next interrupt if there aren't
+/// any. Meant to be called in a loop by idle CPUs.
+///
+/// Must be called with interrupts disabled, so that nothing can make more work for the caller
+/// between its own checks and the `hlt`. Interrupts are enabled on return.
+pub fn idle() {
+    unsafe {
+        // SAFETY: Interrupts are disabled, and the idle loop doesn't mind being interrupted.
+        if softirq::has_pending() {
+            softirq::run();
+            cpu_interrupts::enable();
+            return;
+        }
+    }
+    // Atomically, so that an interrupt raising a softirq can't slip in before the `hlt`.
+    cpu_interrupts::enable_and_hlt();
+}
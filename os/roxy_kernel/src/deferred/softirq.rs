@@ -0,0 +1,105 @@
+//! Softirqs: a fixed set of per-CPU flags, each with a handler that runs when an interrupt returns.
+
+use conquer_once::spin::OnceCell;
+use core::cell::Cell;
+use x86_64::instructions::interrupts;
+
+use super::count;
+use crate::percpu;
+
+/// How many times to go back for softirqs raised while the previous batch was running, before
+/// leaving the rest for the next interrupt or the idle loop.
+const MAX_RESTARTS: usize = 10;
+
+/// The softirqs, in the order they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Softirq {
+    /// Tasklets scheduled with [`Tasklet::schedule_high`](super::Tasklet::schedule_high).
+    HighTasklet,
+    /// Tasklets scheduled with [`Tasklet::schedule`](super::Tasklet::schedule).
+    Tasklet,
+    /// Moves threads between CPUs' run queues to even them out.
+    Sched,
+}
+
+impl Softirq {
+    pub const COUNT: usize = 3;
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+static HANDLERS: [OnceCell<fn()>; Softirq::COUNT] = [const { OnceCell::uninit() }; Softirq::COUNT];
+
+percpu! {
+    /// The softirqs raised on this CPU that have yet to run, one bit each.
+    static PENDING: Cell<u32> = Cell::new(0);
+    static RUNNING: Cell<bool> = Cell::new(false);
+}
+
+/// Sets the handler for a softirq. Each softirq can only be given a handler once.
+pub fn register(softirq: Softirq, handler: fn()) {
+    HANDLERS[softirq as usize]
+        .try_init_once(|| handler)
+        .expect("softirq to only be registered once");
+}
+
+/// Marks `softirq` pending on the calling CPU.
+///
+/// It runs when the current interrupt returns, or when the CPU next goes idle if this isn't an
+/// interrupt handler.
+pub fn raise(softirq: Softirq) {
+    PENDING.with(|pending| pending.set(pending.get() | softirq.bit()));
+}
+
+/// Whether the calling CPU is running softirqs.
+pub fn in_softirq() -> bool {
+    RUNNING.with(Cell::get)
+}
+
+/// Whether any softirqs are pending on the calling CPU.
+///
+/// # Safety
+///
+/// Interrupts must be disabled.
+pub(super) unsafe fn has_pending() -> bool {
+    unsafe { PENDING.current() }.get() != 0
+}
+
+/// Runs the softirqs pending on the calling CPU, with interrupts enabled.
+///
+/// Does nothing if this CPU is already running softirqs further down the stack, which will pick
+/// up anything raised in the meantime.
+///
+/// # Safety
+///
+/// Interrupts must be disabled, and are again on return. Whatever the CPU was doing before must
+/// be fine with interrupts being taken.
+pub(super) unsafe fn run() {
+    let (pending, running) = unsafe { (PENDING.current(), RUNNING.current()) };
+    if running.get() {
+        return;
+    }
+    running.set(true);
+
+    for _ in 0..MAX_RESTARTS {
+        let raised = pending.replace(0);
+        if raised == 0 {
+            break;
+        }
+        interrupts::enable();
+        for softirq in [Softirq::HighTasklet, Softirq::Tasklet, Softirq::Sched] {
+            if raised & softirq.bit() == 0 {
+                continue;
+            }
+            if let Some(handler) = HANDLERS[softirq as usize].get() {
+                handler();
+            }
+            count(|stats| &stats.softirqs[softirq as usize]);
+        }
+        interrupts::disable();
+    }
+
+    running.set(false);
+}
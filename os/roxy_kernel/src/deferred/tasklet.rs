@@ -0,0 +1,139 @@
+//! Tasklets: callbacks that interrupt handlers schedule to run in a softirq.
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc};
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use super::{
+    count,
+    softirq::{self, Softirq},
+};
+use crate::{percpu, smp::percpu};
+
+const SCHEDULED: u8 = 1 << 0;
+const RUNNING: u8 = 1 << 1;
+
+type Queue = RefCell<VecDeque<Arc<Tasklet>>>;
+
+percpu! {
+    static HIGH_QUEUE: Queue = RefCell::new(VecDeque::new());
+    static QUEUE: Queue = RefCell::new(VecDeque::new());
+}
+
+/// A callback that runs once in a softirq each time it's scheduled.
+///
+/// Scheduling a tasklet that is already scheduled does nothing, and a tasklet never runs on two
+/// CPUs at once, so the callback doesn't need to be reentrant.
+pub struct Tasklet {
+    func: Box<dyn Fn() + Send + Sync>,
+    state: AtomicU8,
+}
+
+impl Tasklet {
+    pub fn new(func: impl Fn() + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            func: Box::new(func),
+            state: AtomicU8::new(0),
+        })
+    }
+
+    /// Runs the tasklet on the calling CPU, after any interrupt handlers.
+    pub fn schedule(self: &Arc<Self>) {
+        self.schedule_on(&QUEUE, Softirq::Tasklet);
+    }
+
+    /// Like [`schedule`](Self::schedule), but ahead of any tasklets scheduled normally.
+    pub fn schedule_high(self: &Arc<Self>) {
+        self.schedule_on(&HIGH_QUEUE, Softirq::HighTasklet);
+    }
+
+    pub fn is_scheduled(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & SCHEDULED != 0
+    }
+
+    fn schedule_on(self: &Arc<Self>, queue: &percpu::PerCpu<Queue>, softirq: Softirq) {
+        if !self.mark_scheduled() {
+            return;
+        }
+        queue.with(|queue| queue.borrow_mut().push_back(self.clone()));
+        softirq::raise(softirq);
+    }
+
+    /// Returns `false` if the tasklet was already scheduled.
+    fn mark_scheduled(&self) -> bool {
+        self.state.fetch_or(SCHEDULED, Ordering::AcqRel) & SCHEDULED == 0
+    }
+
+    /// Claims a scheduled tasklet to run it. Returns `false` if it's running on another CPU.
+    ///
+    /// The tasklet is no longer scheduled once claimed, so it can be scheduled again while it
+    /// runs.
+    fn start(&self) -> bool {
+        if self.state.fetch_or(RUNNING, Ordering::Acquire) & RUNNING != 0 {
+            return false;
+        }
+        self.state.fetch_and(!SCHEDULED, Ordering::AcqRel);
+        true
+    }
+
+    fn finish(&self) {
+        self.state.fetch_and(!RUNNING, Ordering::Release);
+    }
+}
+
+/// Registers the tasklet softirqs.
+pub(super) fn init() {
+    softirq::register(Softirq::HighTasklet, || {
+        run(&HIGH_QUEUE, Softirq::HighTasklet)
+    });
+    softirq::register(Softirq::Tasklet, || run(&QUEUE, Softirq::Tasklet));
+}
+
+/// Runs the tasklets that were queued on the calling CPU when the softirq started.
+fn run(queue: &percpu::PerCpu<Queue>, softirq: Softirq) {
+    let tasklets = queue.with(|queue| queue.take());
+    for tasklet in tasklets {
+        if !tasklet.start() {
+            // It'll be finished on the other CPU soon.
+            queue.with(|queue| queue.borrow_mut().push_back(tasklet));
+            softirq::raise(softirq);
+            continue;
+        }
+        (tasklet.func)();
+        tasklet.finish();
+        count(|stats| &stats.tasklets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn scheduling_twice_queues_once() {
+        let tasklet = Tasklet::new(|| {});
+        assert!(tasklet.mark_scheduled());
+        assert!(!tasklet.mark_scheduled());
+        assert!(tasklet.is_scheduled());
+
+        assert!(tasklet.start());
+        assert!(!tasklet.is_scheduled());
+        assert!(tasklet.mark_scheduled());
+    }
+
+    #[test]
+    pub fn does_not_run_on_two_cpus_at_once() {
+        let tasklet = Tasklet::new(|| {});
+        tasklet.mark_scheduled();
+        assert!(tasklet.start());
+
+        tasklet.mark_scheduled();
+        assert!(!tasklet.start());
+        assert!(tasklet.is_scheduled());
+
+        tasklet.finish();
+        assert!(tasklet.start());
+    }
+}
@@ -0,0 +1,81 @@
+//! The system workqueue: work that may take a while, run outside interrupt context.
+//!
+//! Each CPU has its own queue, and a kworker thread that sleeps until there's work on it. Work runs
+//! on that thread like any other code in a thread, so it may sleep, take a [`Mutex`] or wait on a
+//! [`WaitQueue`].
+//!
+//! [`Mutex`]: crate::sync::Mutex
+
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+use conquer_once::spin::OnceCell;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::count;
+use crate::{
+    smp::{percpu, CpuSet},
+    sync::{SpinlockIrq, WaitQueue},
+    thread,
+};
+
+type Work = Box<dyn FnOnce() + Send>;
+
+/// A CPU's queue of work, and its kworker waiting for more.
+struct Worker {
+    queue: SpinlockIrq<VecDeque<Work>>,
+    wait: WaitQueue,
+}
+
+/// Each CPU's worker, indexed by CPU number.
+static WORKERS: OnceCell<Vec<Worker>> = OnceCell::uninit();
+
+pub(super) fn init(cpus: usize) {
+    WORKERS.init_once(|| {
+        (0..cpus)
+            .map(|_| Worker {
+                queue: SpinlockIrq::new(VecDeque::new()),
+                wait: WaitQueue::new(),
+            })
+            .collect()
+    });
+}
+
+fn workers() -> &'static [Worker] {
+    WORKERS.get().expect("deferred work not initialized")
+}
+
+/// Starts the calling CPU's kworker thread, which runs any work queued for the CPU so far, and all
+/// work queued from then on.
+pub fn start_worker() {
+    let cpu = percpu::current_id();
+    let kworker = thread::spawn_with_affinity(CpuSet::single(cpu), move || run(cpu))
+        .expect("calling CPU to be online");
+    log::debug!("Thread {} is CPU {}'s kworker", kworker.id(), cpu);
+}
+
+/// Queues `work` to run on the calling CPU's kworker.
+pub fn queue_work(work: impl FnOnce() + Send + 'static) {
+    without_interrupts(|| queue_work_on(percpu::current_id(), work));
+}
+
+/// Queues `work` to run on `cpu`'s kworker.
+pub fn queue_work_on(cpu: usize, work: impl FnOnce() + Send + 'static) {
+    let worker = &workers()[cpu];
+    worker.queue.lock().push_back(Box::new(work));
+    worker.wait.wake_one();
+}
+
+/// The body of `cpu`'s kworker.
+fn run(cpu: usize) -> ! {
+    let worker = &workers()[cpu];
+    loop {
+        worker.wait.wait_until(|| !worker.queue.lock().is_empty());
+        loop {
+            // Not `while let`, which would hold the lock while the work runs.
+            let Some(work) = worker.queue.lock().pop_front() else {
+                break;
+            };
+            work();
+            count(|stats| &stats.work);
+        }
+    }
+}
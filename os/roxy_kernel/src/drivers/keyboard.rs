@@ -0,0 +1,64 @@
+//! The PS/2 keyboard, behind the 8042 controller.
+
+use conquer_once::spin::OnceCell;
+use x86_64::instructions::port::Port;
+
+use super::Input;
+use crate::{
+    acpi,
+    interrupts::{self, IrqFlags, IrqReturn},
+    task::Receiver,
+};
+
+const DATA: u16 = 0x60;
+const STATUS: u16 = 0x64;
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const ISA_IRQ: u32 = 1;
+/// How many scancodes to hold for a reader that's fallen behind.
+const BUFFER_SIZE: usize = 128;
+
+static INPUT: OnceCell<Input> = OnceCell::uninit();
+
+pub(super) fn init() {
+    if acpi::fadt().is_some_and(|fadt| !fadt.has_8042()) {
+        log::info!("No 8042 keyboard controller");
+        return;
+    }
+    INPUT.init_once(|| Input::new(BUFFER_SIZE));
+
+    // Anything left over from the firmware would hold the interrupt line high.
+    while read_scancode().is_some() {}
+    match interrupts::request_irq(ISA_IRQ, handle, IrqFlags::ISA | IrqFlags::EDGE) {
+        Ok(_) => log::info!("PS/2 keyboard ready"),
+        Err(e) => log::warn!("Couldn't attach the PS/2 keyboard interrupt: {:?}", e),
+    }
+}
+
+/// Takes the stream of scancodes from the keyboard, in scancode set 1, since the controller
+/// translates to it. There's only one stream, for the first caller.
+pub fn scancodes() -> Option<Receiver<u8>> {
+    INPUT.get()?.take()
+}
+
+/// How many scancodes were lost because the reader fell behind.
+pub fn dropped() -> u64 {
+    INPUT.get().map_or(0, Input::dropped)
+}
+
+fn read_scancode() -> Option<u8> {
+    // SAFETY: Reading the 8042's status and data ports has no effect besides taking the byte.
+    unsafe {
+        let status: u8 = Port::new(STATUS).read();
+        (status & STATUS_OUTPUT_FULL != 0).then(|| Port::new(DATA).read())
+    }
+}
+
+fn handle() -> IrqReturn {
+    let Some(scancode) = read_scancode() else {
+        return IrqReturn::NotMine;
+    };
+    if let Some(input) = INPUT.get() {
+        input.push(scancode);
+    }
+    IrqReturn::Handled
+}
@@ -0,0 +1,50 @@
+//! Device drivers.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use spinning_top::Spinlock;
+
+use crate::task::{channel, Receiver, Sender};
+
+pub mod keyboard;
+pub mod serial;
+
+/// Starts the drivers for whichever devices are present.
+pub fn init() {
+    keyboard::init();
+    serial::init();
+}
+
+/// Bytes an interrupt handler receives from a device, for a task to read.
+struct Input {
+    sender: Sender<u8>,
+    /// Given to the first reader to ask for it.
+    receiver: Spinlock<Option<Receiver<u8>>>,
+    /// Bytes that arrived while the buffer was full.
+    dropped: AtomicU64,
+}
+
+impl Input {
+    fn new(capacity: usize) -> Self {
+        let (sender, receiver) = channel(capacity);
+        Self {
+            sender,
+            receiver: Spinlock::new(Some(receiver)),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Buffers a byte, or drops it if the reader has fallen behind.
+    fn push(&self, byte: u8) {
+        if self.sender.try_send(byte).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn take(&self) -> Option<Receiver<u8>> {
+        self.receiver.lock().take()
+    }
+
+    fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,56 @@
+//! Input from the first serial port, which the kernel log is also written to.
+
+use conquer_once::spin::OnceCell;
+use x86_64::instructions::port::Port;
+
+use super::Input;
+use crate::{
+    boot::serial::COM1,
+    interrupts::{self, IrqFlags, IrqReturn},
+    task::Receiver,
+};
+
+const LINE_STATUS: u16 = COM1 + 5;
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+const ISA_IRQ: u32 = 4;
+const BUFFER_SIZE: usize = 256;
+
+static INPUT: OnceCell<Input> = OnceCell::uninit();
+
+pub(super) fn init() {
+    INPUT.init_once(|| Input::new(BUFFER_SIZE));
+    // The port was set up to interrupt on received data when the logger opened it.
+    if let Err(e) = interrupts::request_irq(ISA_IRQ, handle, IrqFlags::ISA | IrqFlags::EDGE) {
+        log::warn!("Couldn't attach the serial port interrupt: {:?}", e);
+    }
+}
+
+/// Takes the stream of bytes received on the serial port. There's only one stream, for the first
+/// caller.
+pub fn input() -> Option<Receiver<u8>> {
+    INPUT.get()?.take()
+}
+
+/// How many received bytes were lost because the reader fell behind.
+pub fn dropped() -> u64 {
+    INPUT.get().map_or(0, Input::dropped)
+}
+
+fn read_byte() -> Option<u8> {
+    // SAFETY: Reading the line status and receive buffer registers only takes the received byte.
+    unsafe {
+        let status: u8 = Port::new(LINE_STATUS).read();
+        (status & LINE_STATUS_DATA_READY != 0).then(|| Port::new(COM1).read())
+    }
+}
+
+fn handle() -> IrqReturn {
+    let mut handled = IrqReturn::NotMine;
+    while let Some(byte) = read_byte() {
+        if let Some(input) = INPUT.get() {
+            input.push(byte);
+        }
+        handled = IrqReturn::Handled;
+    }
+    handled
+}
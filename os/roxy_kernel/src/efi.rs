@@ -0,0 +1,163 @@
+//! Access to UEFI runtime services after boot services have been exited.
+//!
+//! The bootloader neither hands us the EFI system table nor calls `SetVirtualAddressMap`, so the
+//! system table is found by scanning the runtime services data, and the runtime regions are
+//! identity mapped for the duration of each call into the firmware.
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use spinning_top::Spinlock;
+use x86_64::{
+    instructions::interrupts::without_interrupts, structures::paging::PageTableFlags, PhysAddr,
+};
+
+use crate::vmm::{self, MemoryRegionKind, ReservedMemoryKind};
+
+const EFI_RUNTIME_SERVICES_CODE: u32 = 5;
+const EFI_RUNTIME_SERVICES_DATA: u32 = 6;
+const EFI_MEMORY_MAPPED_IO: u32 = 11;
+const EFI_MEMORY_MAPPED_IO_PORT_SPACE: u32 = 12;
+
+/// "IBI SYST"
+const SYSTEM_TABLE_SIGNATURE: u64 = 0x5453_5953_2049_4249;
+/// "RUNTSERV"
+const RUNTIME_SERVICES_SIGNATURE: u64 = 0x5652_4553_544E_5552;
+const SYSTEM_TABLE_RUNTIME_SERVICES_OFFSET: u64 = 88;
+
+/// A region the firmware needs mapped while it runs.
+struct RuntimeRegion {
+    kind: u32,
+    start: PhysAddr,
+    end: PhysAddr,
+}
+
+impl RuntimeRegion {
+    fn contains(&self, addr: u64) -> bool {
+        (self.start.as_u64()..self.end.as_u64()).contains(&addr)
+    }
+
+    fn flags(&self) -> PageTableFlags {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        match self.kind {
+            EFI_RUNTIME_SERVICES_CODE => flags,
+            EFI_RUNTIME_SERVICES_DATA => flags | PageTableFlags::NO_EXECUTE,
+            _ => flags | PageTableFlags::NO_CACHE | PageTableFlags::NO_EXECUTE,
+        }
+    }
+}
+
+static RUNTIME_REGIONS: OnceCell<Vec<RuntimeRegion>> = OnceCell::uninit();
+/// Runtime services are not reentrant.
+static RUNTIME_LOCK: Spinlock<()> = Spinlock::new(());
+
+/// Looks for the EFI system table. Returns `false` on BIOS boots, or if it can't be found.
+///
+/// Must be called after [`vmm::init`].
+pub fn init() -> bool {
+    let regions = RUNTIME_REGIONS.get_or_init(|| {
+        vmm::get()
+            .memory_map()
+            .regions()
+            .iter()
+            .filter_map(|r| match r.kind {
+                MemoryRegionKind::Reserved(ReservedMemoryKind::ReservedByUefi(
+                    kind @ (EFI_RUNTIME_SERVICES_CODE
+                    | EFI_RUNTIME_SERVICES_DATA
+                    | EFI_MEMORY_MAPPED_IO
+                    | EFI_MEMORY_MAPPED_IO_PORT_SPACE),
+                )) => Some(RuntimeRegion {
+                    kind,
+                    start: r.start,
+                    end: r.end,
+                }),
+                _ => None,
+            })
+            .collect()
+    });
+
+    let read = |addr: u64| unsafe {
+        // SAFETY: Runtime services data is never handed out, so it's still intact.
+        core::ptr::read_volatile(vmm::phys_to_virt(PhysAddr::new(addr)).as_ptr::<u64>())
+    };
+    let in_data = |addr: u64| {
+        regions
+            .iter()
+            .any(|r| r.kind == EFI_RUNTIME_SERVICES_DATA && r.contains(addr))
+    };
+    let system_table = regions
+        .iter()
+        .filter(|r| r.kind == EFI_RUNTIME_SERVICES_DATA)
+        .flat_map(|r| (r.start.as_u64()..r.end.as_u64()).step_by(8))
+        .find(|&addr| {
+            if read(addr) != SYSTEM_TABLE_SIGNATURE {
+                return false;
+            }
+            let runtime_services = read(addr + SYSTEM_TABLE_RUNTIME_SERVICES_OFFSET);
+            in_data(runtime_services) && read(runtime_services) == RUNTIME_SERVICES_SIGNATURE
+        });
+
+    let Some(addr) = system_table else {
+        return false;
+    };
+    log::info!(
+        "Found EFI system table at {:#X}, {} runtime regions",
+        addr,
+        regions.len()
+    );
+    unsafe {
+        // SAFETY: The firmware is only entered while the runtime regions are identity mapped, in
+        // `with_runtime_services`.
+        uefi::table::set_system_table(addr as *const _);
+    }
+    true
+}
+
+/// Whether UEFI runtime services can be called.
+pub fn is_available() -> bool {
+    uefi::table::system_table_raw().is_some()
+}
+
+/// Runs `f`, which may call the `uefi` crate's runtime services, with the runtime regions identity
+/// mapped and interrupts disabled. Returns `None` if runtime services are unavailable.
+pub fn with_runtime_services<R>(f: impl FnOnce() -> R) -> Option<R> {
+    enter_runtime_services(f, true)
+}
+
+/// Like [`with_runtime_services`], but also returns `None` rather than wait for another caller or
+/// the VMM, which the caller may have been interrupted holding. Meant for the panic path.
+pub fn try_with_runtime_services<R>(f: impl FnOnce() -> R) -> Option<R> {
+    enter_runtime_services(f, false)
+}
+
+fn enter_runtime_services<R>(f: impl FnOnce() -> R, wait: bool) -> Option<R> {
+    if !is_available() {
+        return None;
+    }
+    let regions = RUNTIME_REGIONS.get()?;
+
+    without_interrupts(|| {
+        let _lock = if wait {
+            RUNTIME_LOCK.lock()
+        } else {
+            RUNTIME_LOCK.try_lock()?
+        };
+        {
+            let mut vmm = if wait { vmm::get() } else { vmm::try_get()? };
+            for region in regions {
+                unsafe {
+                    // SAFETY: Nothing else is mapped in the lower half of the kernel's address space.
+                    vmm.identity_map(region.start, region.end, region.flags())
+                }
+                .expect("to be able to identity map the UEFI runtime regions");
+            }
+        }
+
+        let result = f();
+
+        let mut vmm = vmm::get();
+        for region in regions {
+            unsafe { vmm.unmap_identity(region.start, region.end) };
+        }
+        Some(result)
+    })
+}
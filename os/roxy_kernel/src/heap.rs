@@ -1,4 +1,34 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ops::Deref,
+};
+
 use linked_list_allocator::LockedHeap;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// The kernel heap.
+///
+/// Interrupts are disabled while the heap lock is held, so that interrupt handlers can allocate
+/// and free memory without deadlocking against the code they interrupted.
+pub struct KernelHeap(LockedHeap);
+
+impl Deref for KernelHeap {
+    type Target = LockedHeap;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| unsafe { self.0.alloc(layout) })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| unsafe { self.0.dealloc(ptr, layout) })
+    }
+}
 
 #[cfg_attr(not(test), global_allocator)]
-pub static ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub static ALLOCATOR: KernelHeap = KernelHeap(LockedHeap::empty());
@@ -0,0 +1,142 @@
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::{
+    acpi::{Polarity, TriggerMode},
+    vmm,
+};
+
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+
+const REG_ID: u32 = 0x00;
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION_TABLE: u32 = 0x10;
+
+/// An entry in the I/O APIC redirection table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedirectionEntry {
+    pub vector: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+    pub masked: bool,
+    /// The APIC ID of the CPU the interrupt is delivered to.
+    pub destination: u32,
+}
+
+impl RedirectionEntry {
+    const ACTIVE_LOW: u64 = 1 << 13;
+    const LEVEL_TRIGGERED: u64 = 1 << 15;
+    const MASKED: u64 = 1 << 16;
+
+    /// Encodes the entry for fixed delivery in physical destination mode.
+    ///
+    /// Conforming polarity and trigger mode must already have been resolved by the caller;
+    /// they are treated as ISA defaults (active high, edge triggered) here.
+    fn encode(&self) -> u64 {
+        let mut value = self.vector as u64 | ((self.destination as u64 & 0xFF) << 56);
+        if self.polarity == Polarity::ActiveLow {
+            value |= Self::ACTIVE_LOW;
+        }
+        if self.trigger == TriggerMode::Level {
+            value |= Self::LEVEL_TRIGGERED;
+        }
+        if self.masked {
+            value |= Self::MASKED;
+        }
+        value
+    }
+}
+
+pub struct IoApic {
+    id: u8,
+    window: VirtAddr,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    /// Maps the I/O APIC registers at `address` and reads its capabilities.
+    pub fn new(id: u8, address: PhysAddr, gsi_base: u32) -> Self {
+        let window = vmm::get()
+            .map_mmio(address, 0x20)
+            .expect("to be able to map an I/O APIC");
+        let mut ioapic = Self {
+            id,
+            window,
+            gsi_base,
+            entries: 0,
+        };
+        ioapic.entries = unsafe { (ioapic.read(REG_VERSION) >> 16) & 0xFF } + 1;
+        ioapic
+    }
+
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// The APIC ID programmed into the hardware, which should match [`Self::id`].
+    pub fn hardware_id(&mut self) -> u8 {
+        unsafe { ((self.read(REG_ID) >> 24) & 0x0F) as u8 }
+    }
+
+    pub fn gsi_base(&self) -> u32 {
+        self.gsi_base
+    }
+
+    /// The number of redirection entries, and so the number of GSIs handled.
+    pub fn entries(&self) -> u32 {
+        self.entries
+    }
+
+    pub fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.entries
+    }
+
+    pub fn set_redirection(&mut self, gsi: u32, entry: RedirectionEntry) {
+        assert!(
+            self.handles(gsi),
+            "GSI {gsi} not handled by I/O APIC {}",
+            self.id
+        );
+        let reg = REG_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        let value = entry.encode();
+        unsafe {
+            // Mask the entry while both halves are updated.
+            self.write(reg, RedirectionEntry::MASKED as u32);
+            self.write(reg + 1, (value >> 32) as u32);
+            self.write(reg, value as u32);
+        }
+    }
+
+    pub fn set_masked(&mut self, gsi: u32, masked: bool) {
+        assert!(
+            self.handles(gsi),
+            "GSI {gsi} not handled by I/O APIC {}",
+            self.id
+        );
+        let reg = REG_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        unsafe {
+            let low = self.read(reg);
+            let low = if masked {
+                low | RedirectionEntry::MASKED as u32
+            } else {
+                low & !(RedirectionEntry::MASKED as u32)
+            };
+            self.write(reg, low);
+        }
+    }
+
+    unsafe fn read(&mut self, reg: u32) -> u32 {
+        unsafe {
+            core::ptr::write_volatile((self.window + IOREGSEL).as_mut_ptr::<u32>(), reg);
+            core::ptr::read_volatile((self.window + IOWIN).as_ptr::<u32>())
+        }
+    }
+
+    unsafe fn write(&mut self, reg: u32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile((self.window + IOREGSEL).as_mut_ptr::<u32>(), reg);
+            core::ptr::write_volatile((self.window + IOWIN).as_mut_ptr::<u32>(), value);
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! Dynamic registration of handlers for hardware interrupt lines.
+//!
+//! Every vector in `DYNAMIC_VECTOR_START..DYNAMIC_VECTOR_END` has a stub in the IDT that dispatches
+//! to whatever handlers are currently registered for it, so drivers can attach to and detach from
+//! interrupt lines at any time without touching the IDT.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::idt::{HandlerFunc, InterruptStackFrame};
+
+use super::{ioapic::RedirectionEntry, local_apic, route_gsi, set_masked};
+use crate::{
+    acpi::{self, Polarity, TriggerMode},
+    deferred,
+    sync::SpinlockIrq,
+};
+
+/// The first vector handed out to interrupt lines.
+pub const DYNAMIC_VECTOR_START: u8 = 0x30;
+/// One past the last vector handed out to interrupt lines. Vectors from here up are reserved for
+/// the kernel's own fixed uses.
+pub const DYNAMIC_VECTOR_END: u8 = 0xF0;
+
+const DYNAMIC_VECTOR_COUNT: usize = (DYNAMIC_VECTOR_END - DYNAMIC_VECTOR_START) as usize;
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct IrqFlags: u32 {
+        /// The line may be shared with other handlers that also pass `SHARED`.
+        const SHARED = 1 << 0;
+        /// The number passed to [`request_irq`] is an ISA IRQ, to be translated into a GSI
+        /// through the MADT's interrupt source overrides.
+        const ISA = 1 << 1;
+        const EDGE = 1 << 2;
+        const LEVEL = 1 << 3;
+        const ACTIVE_HIGH = 1 << 4;
+        const ACTIVE_LOW = 1 << 5;
+    }
+}
+
+/// What a handler did with an interrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrqReturn {
+    /// The interrupt came from this handler's device and has been dealt with.
+    Handled,
+    /// The interrupt did not come from this handler's device.
+    NotMine,
+}
+
+/// A handler for an interrupt line.
+///
+/// Handlers run in interrupt context, with interrupts disabled, and must not register or free
+/// interrupt handlers themselves.
+pub trait IrqHandler: Send + Sync {
+    fn handle(&self) -> IrqReturn;
+}
+
+impl<F: Fn() -> IrqReturn + Send + Sync> IrqHandler for F {
+    fn handle(&self) -> IrqReturn {
+        self()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrqError {
+    /// No I/O APIC handles the requested GSI.
+    NoSuchGsi(u32),
+    /// The line is already in use, and either it or the new handler is not shareable, or their
+    /// trigger modes disagree.
+    Busy(u32),
+    /// Every dynamic vector is in use.
+    NoFreeVectors,
+}
+
+/// Identifies a registered handler, so that it can be freed again.
+#[derive(Debug, PartialEq, Eq)]
+pub struct IrqHandle {
+    gsi: u32,
+    vector: u8,
+    id: u64,
+}
+
+impl IrqHandle {
+    pub fn gsi(&self) -> u32 {
+        self.gsi
+    }
+
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+}
+
+struct Line {
+    gsi: u32,
+    flags: IrqFlags,
+    /// Replaced wholesale on registration changes, so dispatch can take a reference and drop the
+    /// lock before calling any handlers.
+    handlers: Arc<[(u64, Arc<dyn IrqHandler>)]>,
+}
+
+static LINES: [SpinlockIrq<Option<Line>>; DYNAMIC_VECTOR_COUNT] =
+    [const { SpinlockIrq::new(None) }; DYNAMIC_VECTOR_COUNT];
+/// Serializes registration, so vector allocation and GSI lookup can't race.
+static REGISTRATION: SpinlockIrq<()> = SpinlockIrq::new(());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Attaches `handler` to the interrupt line `gsi`, allocating a vector and routing the line
+/// through its I/O APIC if it is not in use yet.
+pub fn request_irq(
+    gsi: u32,
+    handler: impl IrqHandler + 'static,
+    flags: IrqFlags,
+) -> Result<IrqHandle, IrqError> {
+    let (gsi, polarity, trigger) = resolve(gsi, flags);
+    let handler: Arc<dyn IrqHandler> = Arc::new(handler);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    let _registration = REGISTRATION.lock();
+
+    if let Some(vector) = find_vector(gsi) {
+        let mut line = LINES[index(vector)].lock();
+        let line = line.as_mut().expect("line to be in use");
+        let same_trigger = line.flags.contains(IrqFlags::LEVEL) == (trigger == TriggerMode::Level);
+        if !line.flags.contains(IrqFlags::SHARED)
+            || !flags.contains(IrqFlags::SHARED)
+            || !same_trigger
+        {
+            return Err(IrqError::Busy(gsi));
+        }
+        let mut handlers = line.handlers.to_vec();
+        handlers.push((id, handler));
+        line.handlers = handlers.into();
+        return Ok(IrqHandle { gsi, vector, id });
+    }
+
+    let vector = (DYNAMIC_VECTOR_START..DYNAMIC_VECTOR_END)
+        .find(|v| LINES[index(*v)].lock().is_none())
+        .ok_or(IrqError::NoFreeVectors)?;
+
+    let entry = RedirectionEntry {
+        vector,
+        polarity,
+        trigger,
+        masked: true,
+        destination: local_apic().id(),
+    };
+    if !route_gsi(gsi, entry) {
+        return Err(IrqError::NoSuchGsi(gsi));
+    }
+
+    let mut line_flags = flags & IrqFlags::SHARED;
+    if trigger == TriggerMode::Level {
+        line_flags |= IrqFlags::LEVEL;
+    }
+    *LINES[index(vector)].lock() = Some(Line {
+        gsi,
+        flags: line_flags,
+        handlers: Arc::from([(id, handler)]),
+    });
+    set_masked(gsi, false);
+    log::debug!("GSI {} routed to vector {:#X}", gsi, vector);
+
+    Ok(IrqHandle { gsi, vector, id })
+}
+
+/// Detaches a handler. Once the last handler of a line is gone, the line is masked and its vector
+/// freed.
+pub fn free_irq(handle: IrqHandle) {
+    let _registration = REGISTRATION.lock();
+
+    let mut slot = LINES[index(handle.vector)].lock();
+    let Some(line) = slot.as_mut() else {
+        return;
+    };
+    let handlers: Vec<_> = line
+        .handlers
+        .iter()
+        .filter(|(id, _)| *id != handle.id)
+        .cloned()
+        .collect();
+
+    if handlers.is_empty() {
+        set_masked(line.gsi, true);
+        *slot = None;
+        log::debug!("GSI {} released vector {:#X}", handle.gsi, handle.vector);
+    } else {
+        line.handlers = handlers.into();
+    }
+}
+
+/// Resolves the GSI, polarity and trigger mode for a request, from the MADT and the flags.
+fn resolve(irq: u32, flags: IrqFlags) -> (u32, Polarity, TriggerMode) {
+    let (gsi, polarity, trigger) = if flags.contains(IrqFlags::ISA) {
+        let (gsi, polarity, trigger) = acpi::madt()
+            .map(|madt| madt.isa_irq(irq as u8))
+            .unwrap_or((irq, Polarity::Conforming, TriggerMode::Conforming));
+        // ISA interrupts default to edge triggered, active high.
+        let polarity = match polarity {
+            Polarity::Conforming => Polarity::ActiveHigh,
+            p => p,
+        };
+        let trigger = match trigger {
+            TriggerMode::Conforming => TriggerMode::Edge,
+            t => t,
+        };
+        (gsi, polarity, trigger)
+    } else {
+        // Anything else is assumed to be PCI: level triggered, active low.
+        (irq, Polarity::ActiveLow, TriggerMode::Level)
+    };
+
+    let polarity = if flags.contains(IrqFlags::ACTIVE_HIGH) {
+        Polarity::ActiveHigh
+    } else if flags.contains(IrqFlags::ACTIVE_LOW) {
+        Polarity::ActiveLow
+    } else {
+        polarity
+    };
+    let trigger = if flags.contains(IrqFlags::EDGE) {
+        TriggerMode::Edge
+    } else if flags.contains(IrqFlags::LEVEL) {
+        TriggerMode::Level
+    } else {
+        trigger
+    };
+    (gsi, polarity, trigger)
+}
+
+fn find_vector(gsi: u32) -> Option<u8> {
+    (DYNAMIC_VECTOR_START..DYNAMIC_VECTOR_END).find(|v| {
+        LINES[index(*v)]
+            .lock()
+            .as_ref()
+            .is_some_and(|line| line.gsi == gsi)
+    })
+}
+
+fn index(vector: u8) -> usize {
+    (vector - DYNAMIC_VECTOR_START) as usize
+}
+
+fn dispatch(vector: u8) {
+    let handlers = LINES[index(vector)]
+        .lock()
+        .as_ref()
+        .map(|line| line.handlers.clone());
+
+    let handled = handlers
+        .iter()
+        .flat_map(|handlers| handlers.iter())
+        .fold(false, |handled, (_, handler)| {
+            handler.handle() == IrqReturn::Handled || handled
+        });
+    if !handled {
+        log::warn!("Unhandled interrupt on vector {:#X}", vector);
+    }
+
+    super::eoi();
+}
+
+extern "x86-interrupt" fn irq_stub<const VECTOR: u8>(stack_frame: InterruptStackFrame) {
+    deferred::irq_enter(&stack_frame);
+    dispatch(VECTOR);
+    deferred::irq_exit(&stack_frame);
+}
+
+macro_rules! stub_row {
+    ($row:literal) => {
+        [
+            irq_stub::<{ $row * 16 }>,
+            irq_stub::<{ $row * 16 + 0x1 }>,
+            irq_stub::<{ $row * 16 + 0x2 }>,
+            irq_stub::<{ $row * 16 + 0x3 }>,
+            irq_stub::<{ $row * 16 + 0x4 }>,
+            irq_stub::<{ $row * 16 + 0x5 }>,
+            irq_stub::<{ $row * 16 + 0x6 }>,
+            irq_stub::<{ $row * 16 + 0x7 }>,
+            irq_stub::<{ $row * 16 + 0x8 }>,
+            irq_stub::<{ $row * 16 + 0x9 }>,
+            irq_stub::<{ $row * 16 + 0xA }>,
+            irq_stub::<{ $row * 16 + 0xB }>,
+            irq_stub::<{ $row * 16 + 0xC }>,
+            irq_stub::<{ $row * 16 + 0xD }>,
+            irq_stub::<{ $row * 16 + 0xE }>,
+            irq_stub::<{ $row * 16 + 0xF }>,
+        ]
+    };
+}
+
+const STUBS: [[HandlerFunc; 16]; DYNAMIC_VECTOR_COUNT / 16] = [
+    stub_row!(0x3),
+    stub_row!(0x4),
+    stub_row!(0x5),
+    stub_row!(0x6),
+    stub_row!(0x7),
+    stub_row!(0x8),
+    stub_row!(0x9),
+    stub_row!(0xA),
+    stub_row!(0xB),
+    stub_row!(0xC),
+    stub_row!(0xD),
+    stub_row!(0xE),
+];
+
+/// The IDT entry point for every dynamic vector.
+pub(crate) fn stubs() -> impl Iterator<Item = (u8, HandlerFunc)> {
+    STUBS
+        .iter()
+        .flatten()
+        .copied()
+        .zip(DYNAMIC_VECTOR_START..DYNAMIC_VECTOR_END)
+        .map(|(stub, vector)| (vector, stub))
+}
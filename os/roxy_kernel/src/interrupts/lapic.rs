@@ -0,0 +1,202 @@
+use core::arch::x86_64::__cpuid;
+
+use x86_64::{registers::model_specific::Msr, PhysAddr, VirtAddr};
+
+use crate::vmm;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+/// Local APIC register offsets, as seen in the xAPIC MMIO window.
+///
+/// In x2APIC mode, register `r` is MSR `0x800 + (r >> 4)`.
+pub mod reg {
+    pub const ID: u32 = 0x020;
+    pub const VERSION: u32 = 0x030;
+    pub const TPR: u32 = 0x080;
+    pub const EOI: u32 = 0x0B0;
+    pub const SPURIOUS: u32 = 0x0F0;
+    pub const ESR: u32 = 0x280;
+    pub const ICR_LOW: u32 = 0x300;
+    pub const ICR_HIGH: u32 = 0x310;
+    pub const LVT_TIMER: u32 = 0x320;
+    pub const LVT_LINT0: u32 = 0x350;
+    pub const LVT_LINT1: u32 = 0x360;
+    pub const LVT_ERROR: u32 = 0x370;
+    pub const TIMER_INITIAL_COUNT: u32 = 0x380;
+    pub const TIMER_CURRENT_COUNT: u32 = 0x390;
+    pub const TIMER_DIVIDE: u32 = 0x3E0;
+}
+
+/// Bits shared by the Local Vector Table registers.
+pub mod lvt {
+    pub const MASKED: u32 = 1 << 16;
+    pub const LEVEL_TRIGGERED: u32 = 1 << 15;
+    pub const ACTIVE_LOW: u32 = 1 << 13;
+    pub const DELIVERY_NMI: u32 = 0b100 << 8;
+    pub const TIMER_PERIODIC: u32 = 0b01 << 17;
+    pub const TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+}
+
+/// Bits of the low half of the Interrupt Command Register.
+pub mod icr {
+    pub const DELIVERY_INIT: u32 = 0b101 << 8;
+    pub const DELIVERY_STARTUP: u32 = 0b110 << 8;
+    /// Set while the local APIC is still sending the previous IPI. xAPIC only.
+    pub const DELIVERY_PENDING: u32 = 1 << 12;
+    pub const LEVEL_ASSERT: u32 = 1 << 14;
+    /// Send to every CPU, including the sender. The destination is ignored.
+    pub const SHORTHAND_ALL: u32 = 0b10 << 18;
+    /// Send to every CPU but the sender. The destination is ignored.
+    pub const SHORTHAND_ALL_BUT_SELF: u32 = 0b11 << 18;
+}
+
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LocalApicMode {
+    /// Registers are accessed through a memory-mapped window.
+    XApic(VirtAddr),
+    /// Registers are accessed through MSRs.
+    X2Apic,
+}
+
+/// The local APIC of the current CPU.
+///
+/// Every CPU sees its own local APIC at the same address (or MSRs), so one instance serves all CPUs.
+pub struct LocalApic {
+    mode: LocalApicMode,
+}
+
+impl LocalApic {
+    /// Detects the best supported local APIC mode, mapping the xAPIC register window if needed.
+    ///
+    /// `base` is the physical address reported by the MADT.
+    pub fn new(base: PhysAddr) -> Self {
+        let features = __cpuid(1);
+        assert!(features.edx & (1 << 9) != 0, "CPU has no local APIC");
+
+        let mode = if features.ecx & (1 << 21) != 0 {
+            LocalApicMode::X2Apic
+        } else {
+            let window = vmm::get()
+                .map_mmio(base, 0x1000)
+                .expect("to be able to map the local APIC");
+            LocalApicMode::XApic(window)
+        };
+        Self { mode }
+    }
+
+    pub fn mode(&self) -> LocalApicMode {
+        self.mode
+    }
+
+    /// Enables the local APIC of the calling CPU.
+    ///
+    /// # Safety
+    ///
+    /// Interrupts must be disabled, and the vectors must have handlers installed.
+    pub unsafe fn enable(&self, spurious_vector: u8, error_vector: u8) {
+        unsafe {
+            let mut base = Msr::new(IA32_APIC_BASE);
+            let value = base.read() | APIC_BASE_ENABLE;
+            base.write(value);
+            // x2APIC mode can only be entered from an enabled xAPIC.
+            if self.mode == LocalApicMode::X2Apic {
+                base.write(value | APIC_BASE_X2APIC);
+            }
+
+            self.write(reg::TPR, 0);
+            // Legacy PIC interrupts arrive through LINT0 as ExtINT; we don't want any of them.
+            self.write(reg::LVT_LINT0, lvt::MASKED);
+            self.write(reg::LVT_ERROR, error_vector as u32);
+            // The ESR must be written before it is read.
+            self.write(reg::ESR, 0);
+            self.write(reg::SPURIOUS, SPURIOUS_APIC_ENABLE | spurious_vector as u32);
+        }
+    }
+
+    /// The APIC ID of the calling CPU.
+    pub fn id(&self) -> u32 {
+        let id = unsafe { self.read(reg::ID) };
+        match self.mode {
+            LocalApicMode::XApic(_) => id >> 24,
+            LocalApicMode::X2Apic => id,
+        }
+    }
+
+    pub fn version(&self) -> u8 {
+        unsafe { self.read(reg::VERSION) as u8 }
+    }
+
+    /// Signals the end of the interrupt currently being serviced.
+    pub fn eoi(&self) {
+        unsafe { self.write(reg::EOI, 0) };
+    }
+
+    /// Sends an inter-processor interrupt to the CPU with APIC ID `destination`.
+    ///
+    /// `command` is the low half of the Interrupt Command Register: the vector, delivery mode and
+    /// so on.
+    ///
+    /// # Safety
+    ///
+    /// The IPI must not break memory safety on the destination CPU, for example by sending INIT to a
+    /// CPU that is running kernel code.
+    pub unsafe fn send_ipi(&self, destination: u32, command: u32) {
+        match self.mode {
+            LocalApicMode::XApic(_) => unsafe {
+                self.write(reg::ICR_HIGH, destination << 24);
+                self.write(reg::ICR_LOW, command);
+                while self.read(reg::ICR_LOW) & icr::DELIVERY_PENDING != 0 {
+                    core::hint::spin_loop();
+                }
+            },
+            // The x2APIC ICR is a single 64-bit MSR, and writing it sends the IPI in one go. The
+            // write isn't serializing, so earlier stores have to be made visible first.
+            LocalApicMode::X2Apic => unsafe {
+                core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+                Msr::new(X2APIC_MSR_BASE + (reg::ICR_LOW >> 4))
+                    .write((destination as u64) << 32 | command as u64)
+            },
+        }
+    }
+
+    /// Reads and clears the Error Status Register.
+    pub fn error_status(&self) -> u32 {
+        unsafe {
+            self.write(reg::ESR, 0);
+            self.read(reg::ESR)
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `reg` must be a readable local APIC register.
+    pub unsafe fn read(&self, reg: u32) -> u32 {
+        match self.mode {
+            LocalApicMode::XApic(window) => unsafe {
+                core::ptr::read_volatile((window + reg as u64).as_ptr::<u32>())
+            },
+            LocalApicMode::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (reg >> 4)).read() as u32
+            },
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `reg` must be a writable local APIC register, and the write must not break memory safety.
+    pub unsafe fn write(&self, reg: u32, value: u32) {
+        match self.mode {
+            LocalApicMode::XApic(window) => unsafe {
+                core::ptr::write_volatile((window + reg as u64).as_mut_ptr::<u32>(), value)
+            },
+            LocalApicMode::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (reg >> 4)).write(value as u64)
+            },
+        }
+    }
+}
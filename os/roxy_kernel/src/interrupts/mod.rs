@@ -0,0 +1,173 @@
+//! Hardware interrupt controllers: the legacy 8259 PIC, the local APIC and the I/O APICs.
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use spinning_top::Spinlock;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::{
+    acpi::{self, Madt, Polarity, TriggerMode},
+    smp::percpu,
+};
+
+pub mod ioapic;
+mod irq;
+pub mod lapic;
+mod pic;
+
+pub(crate) use irq::stubs;
+pub use irq::{free_irq, request_irq, IrqError, IrqFlags, IrqHandle, IrqHandler, IrqReturn};
+
+use ioapic::{IoApic, RedirectionEntry};
+use lapic::{lvt, LocalApic};
+
+/// Where the legacy PIC is remapped to before it is masked.
+pub const PIC_VECTOR_BASE: u8 = 0x20;
+pub use irq::{DYNAMIC_VECTOR_END, DYNAMIC_VECTOR_START};
+pub const LAPIC_TIMER_VECTOR: u8 = 0xF0;
+pub const CALL_FUNCTION_VECTOR: u8 = 0xF1;
+pub const RESCHEDULE_VECTOR: u8 = 0xF3;
+pub const APIC_ERROR_VECTOR: u8 = 0xFE;
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+static IO_APICS: OnceCell<Vec<Spinlock<IoApic>>> = OnceCell::uninit();
+
+/// Switches from the legacy PIC to the APICs described by the MADT.
+///
+/// Every I/O APIC entry is left masked until a driver attaches to it with [`request_irq`].
+pub fn init() {
+    let madt = acpi::madt().expect("firmware to provide a MADT");
+
+    if madt.pcat_compat {
+        unsafe {
+            // SAFETY: Interrupts have not been enabled yet.
+            pic::disable(PIC_VECTOR_BASE);
+        }
+    }
+
+    let lapic = LOCAL_APIC.get_or_init(|| LocalApic::new(madt.local_apic_address));
+    init_local_apic();
+    log::info!(
+        "Local APIC {} enabled ({:?}, version {:#X})",
+        lapic.id(),
+        lapic.mode(),
+        lapic.version()
+    );
+
+    let io_apics = IO_APICS.get_or_init(|| {
+        madt.io_apics
+            .iter()
+            .map(|info| Spinlock::new(IoApic::new(info.id, info.address, info.gsi_base)))
+            .collect()
+    });
+    for io_apic in io_apics {
+        let mut io_apic = io_apic.lock();
+        for gsi in io_apic.gsi_base()..io_apic.gsi_base() + io_apic.entries() {
+            io_apic.set_masked(gsi, true);
+        }
+        log::info!(
+            "I/O APIC {} handles GSIs {} - {}",
+            io_apic.id(),
+            io_apic.gsi_base(),
+            io_apic.gsi_base() + io_apic.entries() - 1
+        );
+    }
+}
+
+/// Enables the local APIC of an application processor, once the BSP has run [`init`].
+pub fn init_ap() {
+    init_local_apic();
+}
+
+/// Enables and configures the local APIC of the calling CPU.
+fn init_local_apic() {
+    let lapic = local_apic();
+    unsafe {
+        // SAFETY: Interrupts are disabled, and the IDT has handlers for both vectors.
+        lapic.enable(SPURIOUS_VECTOR, APIC_ERROR_VECTOR);
+    }
+    if let Some(madt) = acpi::madt() {
+        configure_lint_nmis(madt, lapic);
+    }
+}
+
+/// Programs the LINT pins the MADT says are wired to NMI on this CPU.
+fn configure_lint_nmis(madt: &Madt, lapic: &LocalApic) {
+    let apic_id = lapic.id();
+    let uid = madt
+        .processors
+        .iter()
+        .find(|p| p.apic_id == apic_id)
+        .map(|p| p.processor_uid);
+
+    for nmi in &madt.local_apic_nmis {
+        if nmi.processor_uid.is_some() && nmi.processor_uid != uid {
+            continue;
+        }
+        let mut value = lvt::DELIVERY_NMI;
+        if nmi.polarity == Polarity::ActiveLow {
+            value |= lvt::ACTIVE_LOW;
+        }
+        if nmi.trigger == TriggerMode::Level {
+            value |= lvt::LEVEL_TRIGGERED;
+        }
+        let reg = match nmi.lint {
+            0 => lapic::reg::LVT_LINT0,
+            _ => lapic::reg::LVT_LINT1,
+        };
+        unsafe { lapic.write(reg, value) };
+    }
+}
+
+/// The local APIC, once [`init`] has run.
+pub fn local_apic() -> &'static LocalApic {
+    LOCAL_APIC.get().expect("interrupts not initialized")
+}
+
+fn io_apic_for(gsi: u32) -> Option<&'static Spinlock<IoApic>> {
+    IO_APICS.get()?.iter().find(|io| io.lock().handles(gsi))
+}
+
+/// Programs the redirection entry for a Global System Interrupt.
+///
+/// Returns `false` if no I/O APIC handles the GSI.
+pub fn route_gsi(gsi: u32, entry: RedirectionEntry) -> bool {
+    match io_apic_for(gsi) {
+        Some(io_apic) => {
+            io_apic.lock().set_redirection(gsi, entry);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Masks or unmasks a Global System Interrupt.
+///
+/// Returns `false` if no I/O APIC handles the GSI.
+pub fn set_masked(gsi: u32, masked: bool) -> bool {
+    match io_apic_for(gsi) {
+        Some(io_apic) => {
+            io_apic.lock().set_masked(gsi, masked);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Signals the end of the current interrupt to the local APIC.
+pub fn eoi() {
+    local_apic().eoi();
+}
+
+pub(crate) extern "x86-interrupt" fn apic_error_handler(stack_frame: InterruptStackFrame) {
+    unsafe { percpu::swapgs_on_entry(&stack_frame) };
+    let lapic = local_apic();
+    log::error!("APIC ERROR: ESR {:#X}", lapic.error_status());
+    lapic.eoi();
+    unsafe { percpu::swapgs_on_exit(&stack_frame) };
+}
+
+pub(crate) extern "x86-interrupt" fn spurious_handler(_stack_frame: InterruptStackFrame) {
+    // Spurious interrupts must not be acknowledged.
+}
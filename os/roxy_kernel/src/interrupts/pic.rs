@@ -0,0 +1,49 @@
+use x86_64::instructions::port::Port;
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+const ICW1_INIT: u8 = 0x11;
+const ICW4_8086: u8 = 0x01;
+
+/// Remaps the legacy 8259 PICs to `vector_base..vector_base + 16` and masks every line.
+///
+/// The PICs are remapped even though we never use them, so that a spurious interrupt they raise
+/// can't be mistaken for a CPU exception.
+///
+/// # Safety
+///
+/// Must only be called while interrupts are disabled.
+pub unsafe fn disable(vector_base: u8) {
+    let mut pic1_command = Port::<u8>::new(PIC1_COMMAND);
+    let mut pic1_data = Port::<u8>::new(PIC1_DATA);
+    let mut pic2_command = Port::<u8>::new(PIC2_COMMAND);
+    let mut pic2_data = Port::<u8>::new(PIC2_DATA);
+    let mut wait_port = Port::<u8>::new(0x80);
+    let mut wait = || unsafe { wait_port.write(0) };
+
+    unsafe {
+        pic1_command.write(ICW1_INIT);
+        wait();
+        pic2_command.write(ICW1_INIT);
+        wait();
+        pic1_data.write(vector_base);
+        wait();
+        pic2_data.write(vector_base + 8);
+        wait();
+        // Tell the primary PIC about the secondary on IRQ 2, and the secondary its cascade identity.
+        pic1_data.write(4);
+        wait();
+        pic2_data.write(2);
+        wait();
+        pic1_data.write(ICW4_8086);
+        wait();
+        pic2_data.write(ICW4_8086);
+        wait();
+
+        pic1_data.write(0xFF);
+        pic2_data.write(0xFF);
+    }
+}
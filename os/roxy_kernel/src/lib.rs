@@ -11,6 +11,20 @@ extern crate test;
 // Just don't use it until the heap is active!
 extern crate alloc;
 
+pub mod acpi;
 pub mod boot;
+pub mod deferred;
+pub mod drivers;
+pub mod efi;
 pub mod heap;
+pub mod interrupts;
+pub mod numa;
+pub mod power;
+pub mod sched;
+pub mod smp;
+pub mod sync;
+pub mod task;
+pub mod thread;
+pub mod time;
+pub mod user;
 pub mod vmm;
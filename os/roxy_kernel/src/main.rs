@@ -1,7 +1,10 @@
 #![no_std]
 #![no_main]
 
-use core::panic::PanicInfo;
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use bootloader_api::config::Mapping;
 
@@ -18,8 +21,15 @@ static CONFIG: bootloader_api::BootloaderConfig = {
 
 bootloader_api::entry_point!(roxy_kernel::boot::kernel_main, config = &CONFIG);
 
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // Don't try to shut down again if shutting down is what panicked.
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        roxy_kernel::power::halt();
+    }
+
     if let Some(loc) = info.location() {
         log::error!(
             "PANIC ({}:{}:{}): {:#?}",
@@ -32,5 +42,5 @@ fn panic(info: &PanicInfo) -> ! {
         log::error!("PANIC (<unknown>): {:#?}", info.message());
     }
 
-    loop {}
+    roxy_kernel::power::panic_shutdown()
 }
@@ -0,0 +1,136 @@
+//! The NUMA topology: which processors and memory belong to which node, and how far apart the
+//! nodes are. Nodes are identified by their ACPI proximity domain.
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+use crate::{
+    acpi,
+    vmm::{self, MemoryRegionKind},
+};
+
+/// The distance from a node to itself.
+pub const LOCAL_DISTANCE: u8 = 10;
+/// The distance assumed between two different nodes when there's no SLIT.
+pub const REMOTE_DISTANCE: u8 = 20;
+
+static TOPOLOGY: OnceCell<Topology> = OnceCell::uninit();
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub domain: u32,
+    /// The APIC IDs of the node's enabled processors.
+    pub processors: Vec<u32>,
+    /// Bytes of usable memory attached to the node.
+    pub memory: u64,
+}
+
+pub struct Topology {
+    nodes: Vec<Node>,
+}
+
+impl Topology {
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn node_of_processor(&self, apic_id: u32) -> Option<u32> {
+        self.nodes
+            .iter()
+            .find(|n| n.processors.contains(&apic_id))
+            .map(|n| n.domain)
+    }
+}
+
+/// Reads the topology from the SRAT, and tells the VMM which node each range of memory is on.
+///
+/// Without an SRAT, the machine is treated as a single node and [`topology`] returns `None`.
+pub fn init() {
+    let Some(srat) = acpi::srat() else {
+        log::info!("No SRAT, treating memory as uniform");
+        return;
+    };
+
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut node = |domain: u32| -> usize {
+        match nodes.iter().position(|n| n.domain == domain) {
+            Some(i) => i,
+            None => {
+                nodes.push(Node {
+                    domain,
+                    processors: Vec::new(),
+                    memory: 0,
+                });
+                nodes.len() - 1
+            }
+        }
+    };
+    let mut processors = Vec::new();
+    for processor in srat.processors.iter().filter(|p| p.enabled) {
+        processors.push((node(processor.proximity_domain), processor.apic_id));
+    }
+    let mut memory = Vec::new();
+    {
+        let mut vmm = vmm::get();
+        vmm.assign_proximity_domains(&srat.memory, distance);
+        for region in vmm.memory_map().regions() {
+            if let (MemoryRegionKind::Usable, Some(domain)) = (region.kind, region.proximity_domain)
+            {
+                memory.push((node(domain), region.size()));
+            }
+        }
+    }
+    for (i, apic_id) in processors {
+        nodes[i].processors.push(apic_id);
+    }
+    for (i, size) in memory {
+        nodes[i].memory += size;
+    }
+    nodes.sort_by_key(|n| n.domain);
+
+    for n in &nodes {
+        log::info!(
+            "NUMA node {}: {} processors, {} MiB",
+            n.domain,
+            n.processors.len(),
+            n.memory / (1024 * 1024)
+        );
+    }
+    TOPOLOGY.init_once(|| Topology { nodes });
+}
+
+/// Returns the topology, if the firmware described one.
+pub fn topology() -> Option<&'static Topology> {
+    TOPOLOGY.get()
+}
+
+/// The node of the processor with the given APIC ID.
+pub fn node_of_processor(apic_id: u32) -> Option<u32> {
+    topology()?.node_of_processor(apic_id)
+}
+
+/// The node of the processor we're running on.
+pub fn current_node() -> Option<u32> {
+    node_of_processor(current_apic_id())
+}
+
+/// The relative distance between two nodes, from the SLIT if there is one.
+pub fn distance(from: u32, to: u32) -> u8 {
+    acpi::slit()
+        .and_then(|slit| slit.distance(from, to))
+        .unwrap_or(if from == to {
+            LOCAL_DISTANCE
+        } else {
+            REMOTE_DISTANCE
+        })
+}
+
+/// Reads this processor's APIC ID, preferring the full 32-bit x2APIC ID where there is one.
+fn current_apic_id() -> u32 {
+    if __cpuid(0).eax >= 0xB && __cpuid_count(0xB, 0).ebx != 0 {
+        __cpuid_count(0xB, 0).edx
+    } else {
+        __cpuid(1).ebx >> 24
+    }
+}
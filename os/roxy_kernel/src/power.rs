@@ -0,0 +1,247 @@
+//! Powering the machine off and rebooting it.
+
+use alloc::{vec, vec::Vec};
+use core::time::Duration;
+use x86_64::{
+    instructions::{self, port::Port, tables::lidt},
+    structures::DescriptorTablePointer,
+    VirtAddr,
+};
+
+use crate::{
+    acpi::{
+        self,
+        aml::{self, AmlError, Path, Value},
+        AcpiError, Fadt, FadtFlags,
+    },
+    efi, time,
+};
+
+/// Enables the ACPI event model in the PM1 control register.
+const PM1_SCI_EN: u64 = 1 << 0;
+const PM1_SLP_TYP_SHIFT: u64 = 10;
+const PM1_SLP_EN: u64 = 1 << 13;
+const SLEEP_CONTROL_SLP_TYP_SHIFT: u64 = 2;
+const SLEEP_CONTROL_SLP_EN: u64 = 1 << 5;
+
+const KBC_STATUS: u16 = 0x64;
+const KBC_COMMAND: u16 = 0x64;
+const KBC_STATUS_INPUT_FULL: u8 = 1 << 1;
+/// Pulses the CPU reset line.
+const KBC_RESET: u8 = 0xFE;
+
+/// How long to give each method before moving on to the next.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PowerError {
+    NoFadt,
+    /// There's no `\_S5` object telling us how to enter the soft-off state.
+    NoS5,
+    /// The FADT doesn't describe a usable PM1 control or sleep control register.
+    NoControlRegister,
+    Acpi(AcpiError),
+    /// The AML interpreter was already in use.
+    AmlBusy,
+    Aml(AmlError),
+}
+
+impl From<AcpiError> for PowerError {
+    fn from(e: AcpiError) -> Self {
+        PowerError::Acpi(e)
+    }
+}
+
+impl From<AmlError> for PowerError {
+    fn from(e: AmlError) -> Self {
+        PowerError::Aml(e)
+    }
+}
+
+/// Turns the machine off, through ACPI or failing that UEFI runtime services.
+pub fn shutdown() -> ! {
+    log::info!("Shutting down");
+    power_off(false)
+}
+
+/// Turns the machine off after a panic. Unlike [`shutdown`], this doesn't wait for any lock the
+/// panicking code may hold, skipping what would need one: telling the firmware with `\_PTS`, which
+/// may map memory, and UEFI runtime services if the VMM is busy.
+pub fn panic_shutdown() -> ! {
+    log::info!("Shutting down after a panic");
+    power_off(true)
+}
+
+fn power_off(panicking: bool) -> ! {
+    instructions::interrupts::disable();
+
+    match enter_s5(panicking) {
+        Ok(()) => settle(),
+        Err(e) => log::warn!("ACPI shutdown failed: {:?}", e),
+    }
+    let reset = || {
+        uefi::runtime::reset(
+            uefi::runtime::ResetType::SHUTDOWN,
+            uefi::Status::SUCCESS,
+            None,
+        )
+    };
+    if panicking {
+        efi::try_with_runtime_services(reset);
+    } else {
+        efi::with_runtime_services(reset);
+    }
+
+    log::error!("Failed to shut down, halting");
+    halt()
+}
+
+/// Resets the machine, through the ACPI reset register, the 8042 keyboard controller or, as a last
+/// resort, a triple fault.
+pub fn reboot() -> ! {
+    log::info!("Rebooting");
+    instructions::interrupts::disable();
+
+    if let Some(fadt) = acpi::fadt() {
+        match reset_register(fadt) {
+            Ok(true) => settle(),
+            Ok(false) => {}
+            Err(e) => log::warn!("ACPI reset failed: {:?}", e),
+        }
+    }
+
+    if acpi::fadt().is_none_or(Fadt::has_8042) {
+        if kbc_wait_input_empty() {
+            unsafe { Port::<u8>::new(KBC_COMMAND).write(KBC_RESET) };
+            settle();
+        } else {
+            log::warn!("8042 keyboard controller isn't accepting commands");
+        }
+    }
+
+    log::warn!("Falling back to a triple fault");
+    unsafe {
+        // With an empty IDT, the breakpoint can't be delivered, and neither can the double
+        // fault that follows.
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::zero(),
+        });
+        core::arch::asm!("int3");
+    }
+    halt()
+}
+
+/// Halts this CPU forever.
+pub fn halt() -> ! {
+    loop {
+        instructions::interrupts::disable();
+        instructions::hlt();
+    }
+}
+
+/// Writes the reset value to the FADT reset register, if it has one.
+fn reset_register(fadt: &Fadt) -> Result<bool, AcpiError> {
+    let Some(register) = fadt.reset_register else {
+        return Ok(false);
+    };
+    if !fadt.flags.contains(FadtFlags::RESET_REG_SUP) {
+        return Ok(false);
+    }
+    unsafe { register.write(fadt.reset_value as u64) }?;
+    Ok(true)
+}
+
+/// Puts the machine into the S5 soft-off sleep state, skipping `\_PTS` if `panicking`.
+fn enter_s5(panicking: bool) -> Result<(), PowerError> {
+    let fadt = acpi::fadt().ok_or(PowerError::NoFadt)?;
+    let (typ_a, typ_b) = s5_sleep_type(!panicking)?;
+
+    if fadt.flags.contains(FadtFlags::HW_REDUCED_ACPI) {
+        let register = fadt
+            .sleep_control_register
+            .ok_or(PowerError::NoControlRegister)?;
+        let value = (typ_a as u64) << SLEEP_CONTROL_SLP_TYP_SHIFT | SLEEP_CONTROL_SLP_EN;
+        unsafe { register.write(value) }?;
+        return Ok(());
+    }
+
+    let pm1a = fadt
+        .pm1a_control_block
+        .ok_or(PowerError::NoControlRegister)?;
+    enable_acpi(fadt)?;
+    unsafe {
+        let value = pm1a.read()? & !(0b111 << PM1_SLP_TYP_SHIFT);
+        pm1a.write(value | (typ_a as u64) << PM1_SLP_TYP_SHIFT | PM1_SLP_EN)?;
+        if let Some(pm1b) = fadt.pm1b_control_block {
+            let value = pm1b.read()? & !(0b111 << PM1_SLP_TYP_SHIFT);
+            pm1b.write(value | (typ_b as u64) << PM1_SLP_TYP_SHIFT | PM1_SLP_EN)?;
+        }
+    }
+    Ok(())
+}
+
+/// Switches the firmware from legacy mode to ACPI mode, if it isn't already.
+fn enable_acpi(fadt: &Fadt) -> Result<(), PowerError> {
+    let pm1a = fadt
+        .pm1a_control_block
+        .ok_or(PowerError::NoControlRegister)?;
+    if unsafe { pm1a.read() }? & PM1_SCI_EN != 0
+        || fadt.smi_command_port == 0
+        || fadt.acpi_enable == 0
+    {
+        return Ok(());
+    }
+
+    unsafe { Port::<u8>::new(fadt.smi_command_port as u16).write(fadt.acpi_enable) };
+    for _ in 0..100 {
+        if unsafe { pm1a.read() }? & PM1_SCI_EN != 0 {
+            return Ok(());
+        }
+        time::delay(Duration::from_millis(10));
+    }
+    log::warn!("Firmware didn't switch to ACPI mode");
+    Ok(())
+}
+
+/// Finds the SLP_TYPa and SLP_TYPb values for S5 by evaluating `\_S5`, after telling the
+/// firmware with `\_PTS` that we're about to enter it if `prepare` is set.
+fn s5_sleep_type(prepare: bool) -> Result<(u8, u8), PowerError> {
+    let interpreter = aml::interpreter().ok_or(PowerError::NoS5)?;
+    // We may have panicked while the interpreter was running, so don't wait for it.
+    let mut interpreter = interpreter.try_lock().ok_or(PowerError::AmlBusy)?;
+
+    if prepare {
+        let pts = Path::root().child("_PTS");
+        if let Err(e) = interpreter.evaluate_if_present(&pts, vec![Value::Integer(5)]) {
+            log::warn!("Failed to run \\_PTS: {:?}", e);
+        }
+    }
+    let Some(Value::Package(package)) =
+        interpreter.evaluate_if_present(&Path::root().child("_S5"), Vec::new())?
+    else {
+        return Err(PowerError::NoS5);
+    };
+    let package = package.lock();
+    let typ_a = package.first().ok_or(PowerError::NoS5)?.as_integer()?;
+    let typ_b = package.get(1).map_or(Ok(0), Value::as_integer)?;
+    Ok((typ_a as u8, typ_b as u8))
+}
+
+/// Waits for the 8042 keyboard controller to take a command, giving up after a while in case it's
+/// missing or wedged.
+fn kbc_wait_input_empty() -> bool {
+    let mut status = Port::<u8>::new(KBC_STATUS);
+    for _ in 0..100 {
+        if unsafe { status.read() } & KBC_STATUS_INPUT_FULL == 0 {
+            return true;
+        }
+        time::delay(Duration::from_millis(1));
+    }
+    false
+}
+
+/// Gives the hardware a moment to act on a request before trying something else.
+fn settle() {
+    time::delay(SETTLE_TIME);
+}
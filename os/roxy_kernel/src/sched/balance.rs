@@ -0,0 +1,137 @@
+//! Spreading threads across CPUs.
+//!
+//! A thread that becomes ready goes back to the CPU it last ran on if that one is idle, as its
+//! caches may still be warm, and otherwise to the least busy CPU it's allowed on. Threads move
+//! after that too: a CPU that runs out of threads steals one from the busiest CPU, and every
+//! [`BALANCE_INTERVAL`] each CPU pulls a thread over if another is busier by two or more.
+
+use core::{cmp::Reverse, sync::atomic::Ordering, time::Duration};
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::queues;
+use crate::{
+    smp::{self, percpu},
+    thread::Thread,
+    time::TICK_PERIOD,
+};
+
+/// How often each CPU checks whether it should take threads off busier ones.
+pub const BALANCE_INTERVAL: Duration = Duration::from_millis(100);
+pub(super) const BALANCE_INTERVAL_TICKS: u32 =
+    (BALANCE_INTERVAL.as_nanos() / TICK_PERIOD.as_nanos()) as u32;
+
+/// How busy a CPU is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct Load {
+    pub cpu: usize,
+    pub idle: bool,
+    pub queued: usize,
+}
+
+impl Load {
+    /// The threads the CPU has to run, counting the one running.
+    fn threads(&self) -> usize {
+        self.queued + usize::from(!self.idle)
+    }
+}
+
+/// Picks the CPU to queue a thread on, out of the loads of the CPUs it may run on. The CPU it last
+/// ran on wins ties.
+fn choose(previous: usize, loads: impl Iterator<Item = Load>) -> Option<usize> {
+    loads
+        .max_by_key(|load| (Reverse(load.threads()), load.cpu == previous))
+        .map(|load| load.cpu)
+}
+
+/// Picks the CPU that `local` should take a thread from, if any is busy enough to spare one.
+fn busiest(local: Load, others: impl Iterator<Item = Load>) -> Option<usize> {
+    others
+        .filter(|load| load.queued > 0 && load.threads() >= local.threads() + 2)
+        .max_by_key(|load| load.threads())
+        .map(|load| load.cpu)
+}
+
+pub(super) fn online_cpus() -> impl Iterator<Item = usize> {
+    smp::cpus()
+        .iter()
+        .filter(|cpu| cpu.is_online())
+        .map(|cpu| cpu.id)
+}
+
+/// Picks the CPU to queue a thread that's become ready on.
+pub(super) fn select_cpu(thread: &Thread) -> usize {
+    let Some(queues) = queues() else {
+        return percpu::current_id();
+    };
+    let affinity = thread.affinity();
+    let allowed = online_cpus().filter(|&cpu| affinity.contains(cpu));
+    choose(thread.cpu(), allowed.map(|cpu| queues[cpu].load())).unwrap_or(thread.cpu())
+}
+
+/// Moves a thread from the busiest CPU over to the calling one, if the two are out of balance.
+/// Returns whether a thread was moved.
+pub(super) fn pull() -> bool {
+    let Some(queues) = queues() else {
+        return false;
+    };
+    without_interrupts(|| {
+        let cpu = percpu::current_id();
+        let others = online_cpus().filter(|&other| other != cpu);
+        let Some(busiest) = busiest(queues[cpu].load(), others.map(|cpu| queues[cpu].load()))
+        else {
+            return false;
+        };
+        // Taken and queued under one lock at a time, so that two CPUs pulling from each other
+        // can't deadlock.
+        let Some(thread) = queues[busiest].queue.lock().take_for(cpu) else {
+            return false;
+        };
+        let local = &queues[cpu];
+        let mut queue = local.queue.lock();
+        thread.sched().set_cpu(cpu);
+        queue.enqueue_migrated(thread);
+        local.migrations.fetch_add(1, Ordering::Relaxed);
+        true
+    })
+}
+
+/// The body of the scheduler's softirq, raised on every CPU once per [`BALANCE_INTERVAL`].
+pub(super) fn rebalance() {
+    pull();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(cpu: usize, idle: bool, queued: usize) -> Load {
+        Load { cpu, idle, queued }
+    }
+
+    #[test]
+    pub fn prefers_the_previous_cpu_then_the_least_busy() {
+        let loads = [load(0, false, 2), load(1, true, 0), load(2, true, 0)];
+        assert_eq!(Some(2), choose(2, loads.into_iter()));
+        assert_eq!(Some(1), choose(0, loads[..2].iter().copied()));
+        assert_eq!(
+            Some(0),
+            choose(0, [load(0, false, 1), load(1, false, 1)].into_iter())
+        );
+        assert_eq!(None, choose(0, [].into_iter()));
+    }
+
+    #[test]
+    pub fn only_pulls_from_cpus_with_threads_to_spare() {
+        let idle = load(0, true, 0);
+        assert_eq!(None, busiest(idle, [load(1, false, 0)].into_iter()));
+        assert_eq!(None, busiest(idle, [load(1, true, 1)].into_iter()));
+        assert_eq!(
+            Some(2),
+            busiest(idle, [load(1, false, 1), load(2, false, 3)].into_iter())
+        );
+
+        let busy = load(0, false, 1);
+        assert_eq!(None, busiest(busy, [load(1, false, 2)].into_iter()));
+        assert_eq!(Some(1), busiest(busy, [load(1, false, 3)].into_iter()));
+    }
+}
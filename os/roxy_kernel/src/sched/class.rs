@@ -0,0 +1,108 @@
+use alloc::{boxed::Box, sync::Arc};
+use core::{fmt, time::Duration};
+
+use crate::thread::{Thread, ThreadId};
+
+/// The highest real-time priority. Real-time priorities start at 1.
+pub const MAX_RT_PRIORITY: u8 = 99;
+pub const MIN_NICE: i8 = -20;
+pub const MAX_NICE: i8 = 19;
+
+/// How a thread is scheduled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Runs ahead of every fair thread and every real-time thread of a lower priority, taking
+    /// turns with others of the same priority.
+    RealTime { priority: u8 },
+    /// Shares the CPU with the other fair threads, weighted by how nice each one is. Lower is
+    /// less nice and gets more.
+    Fair { nice: i8 },
+}
+
+impl Policy {
+    pub const DEFAULT: Policy = Policy::Fair { nice: 0 };
+
+    /// The real-time priority, for a real-time policy.
+    pub fn rt_priority(self) -> Option<u8> {
+        match self {
+            Policy::RealTime { priority } => Some(priority),
+            Policy::Fair { .. } => None,
+        }
+    }
+
+    pub fn validate(self) -> Result<Self, PolicyError> {
+        match self {
+            Policy::RealTime { priority } if !(1..=MAX_RT_PRIORITY).contains(&priority) => {
+                Err(PolicyError::PriorityOutOfRange(priority))
+            }
+            Policy::Fair { nice } if !(MIN_NICE..=MAX_NICE).contains(&nice) => {
+                Err(PolicyError::NiceOutOfRange(nice))
+            }
+            policy => Ok(policy),
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PolicyError {
+    PriorityOutOfRange(u8),
+    NiceOutOfRange(i8),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::PriorityOutOfRange(priority) => write!(
+                f,
+                "real-time priority {} is not in 1..={}",
+                priority, MAX_RT_PRIORITY
+            ),
+            PolicyError::NiceOutOfRange(nice) => {
+                write!(
+                    f,
+                    "nice value {} is not in {}..={}",
+                    nice, MIN_NICE, MAX_NICE
+                )
+            }
+        }
+    }
+}
+
+/// A scheduling class: a run queue for the threads of one kind of [`Policy`], and the rules for
+/// which of them runs next.
+///
+/// The run queue asks the classes for a thread in order, so every ready thread of one class runs
+/// ahead of those of the classes after it.
+pub trait SchedClass: Send {
+    fn name(&self) -> &'static str;
+
+    fn enqueue(&mut self, thread: Arc<Thread>);
+
+    /// Takes a specific thread off the queue, if it's queued here.
+    fn remove(&mut self, id: ThreadId) -> Option<Arc<Thread>>;
+
+    /// Takes the thread that should run next off the queue.
+    fn pick_next(&mut self) -> Option<Arc<Thread>>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The queued threads, in the order they'd run.
+    fn threads(&self) -> Box<dyn Iterator<Item = &Arc<Thread>> + '_>;
+
+    /// Charges a thread of this class for time it spent running.
+    fn account(&mut self, thread: &Thread, ran: Duration);
+
+    /// Whether the running thread, of this class, should give way to one on the queue.
+    /// `slice_expired` is set once it has run for a whole [`TIME_SLICE`](super::TIME_SLICE).
+    fn should_preempt(&self, current: &Thread, slice_expired: bool) -> bool;
+}
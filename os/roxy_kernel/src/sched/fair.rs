@@ -0,0 +1,157 @@
+//! The fair class: each thread gets a share of the CPU in proportion to its weight.
+//!
+//! Every thread has a virtual runtime, the time it has run scaled down by its weight, and the
+//! thread with the least runs next. A thread that has only just become ready starts level with the
+//! least virtual runtime on the queue, rather than catching up on everything it missed.
+
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
+use core::time::Duration;
+
+use super::class::{Policy, SchedClass, MIN_NICE};
+use crate::thread::{Thread, ThreadId};
+
+/// How far ahead of the next thread in virtual runtime the running one gets before it's preempted,
+/// so that threads with similar runtimes don't switch back and forth on every tick.
+const GRANULARITY: Duration = Duration::from_millis(3);
+
+/// The weight of each nice value, from -20 to 19. Each step is worth about 10% of CPU time.
+const WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+const NICE_0_WEIGHT: u64 = 1024;
+
+fn weight(thread: &Thread) -> u64 {
+    match thread.policy() {
+        Policy::Fair { nice } => WEIGHTS[(nice - MIN_NICE) as usize],
+        Policy::RealTime { .. } => NICE_0_WEIGHT,
+    }
+}
+
+pub struct Fair {
+    /// Keyed by the virtual runtime each thread was queued with, which doesn't change while it's
+    /// queued.
+    queue: BTreeMap<(u64, ThreadId), Arc<Thread>>,
+    /// Never goes backwards, so threads that join the queue late can't claim more than their share.
+    min_vruntime: u64,
+}
+
+impl Fair {
+    pub const fn new() -> Self {
+        Self {
+            queue: BTreeMap::new(),
+            min_vruntime: 0,
+        }
+    }
+
+    /// Makes a thread's virtual runtime relative to this queue's, as it leaves for another CPU.
+    pub fn detach(&self, thread: &Thread) {
+        let entity = thread.sched();
+        entity.set_vruntime(entity.vruntime().saturating_sub(self.min_vruntime));
+    }
+
+    /// Undoes [`detach`](Self::detach) against this queue, as a thread arrives from another CPU.
+    pub fn attach(&self, thread: &Thread) {
+        let entity = thread.sched();
+        entity.set_vruntime(entity.vruntime() + self.min_vruntime);
+    }
+}
+
+impl Default for Fair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchedClass for Fair {
+    fn name(&self) -> &'static str {
+        "fair"
+    }
+
+    fn enqueue(&mut self, thread: Arc<Thread>) {
+        let entity = thread.sched();
+        let vruntime = entity.vruntime().max(self.min_vruntime);
+        entity.set_vruntime(vruntime);
+        self.queue.insert((vruntime, thread.id()), thread);
+    }
+
+    fn remove(&mut self, id: ThreadId) -> Option<Arc<Thread>> {
+        let key = *self.queue.iter().find(|(_, thread)| thread.id() == id)?.0;
+        self.queue.remove(&key)
+    }
+
+    fn pick_next(&mut self) -> Option<Arc<Thread>> {
+        let ((vruntime, _), thread) = self.queue.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        Some(thread)
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn threads(&self) -> Box<dyn Iterator<Item = &Arc<Thread>> + '_> {
+        Box::new(self.queue.values())
+    }
+
+    fn account(&mut self, thread: &Thread, ran: Duration) {
+        let entity = thread.sched();
+        let weighted = ran.as_nanos() as u64 * NICE_0_WEIGHT / weight(thread);
+        entity.set_vruntime(entity.vruntime() + weighted);
+    }
+
+    fn should_preempt(&self, current: &Thread, _slice_expired: bool) -> bool {
+        self.queue.first_key_value().is_some_and(|((next, _), _)| {
+            current.sched().vruntime() > next + GRANULARITY.as_nanos() as u64
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thread(nice: i8) -> Arc<Thread> {
+        Thread::for_tests(Policy::Fair { nice })
+    }
+
+    #[test]
+    pub fn runs_the_thread_that_has_run_least() {
+        let mut fair = Fair::new();
+        let (a, b) = (thread(0), thread(0));
+        fair.enqueue(a.clone());
+        fair.enqueue(b.clone());
+
+        let first = fair.pick_next().unwrap();
+        assert_eq!(a.id(), first.id());
+        fair.account(&first, Duration::from_millis(10));
+        assert!(fair.should_preempt(&first, false));
+        fair.enqueue(first);
+
+        assert_eq!(b.id(), fair.pick_next().unwrap().id());
+    }
+
+    #[test]
+    pub fn weights_runtime_by_niceness() {
+        let mut fair = Fair::new();
+        let (greedy, nice) = (thread(-5), thread(5));
+        fair.account(&greedy, Duration::from_millis(10));
+        fair.account(&nice, Duration::from_millis(10));
+        assert!(greedy.sched().vruntime() < Duration::from_millis(4).as_nanos() as u64);
+        assert!(nice.sched().vruntime() > Duration::from_millis(30).as_nanos() as u64);
+    }
+
+    #[test]
+    pub fn late_joiners_start_level() {
+        let mut fair = Fair::new();
+        let old = thread(0);
+        fair.account(&old, Duration::from_millis(50));
+        fair.enqueue(old.clone());
+        fair.pick_next();
+
+        let new = thread(0);
+        fair.enqueue(new.clone());
+        assert_eq!(old.sched().vruntime(), new.sched().vruntime());
+    }
+}
@@ -0,0 +1,769 @@
+//! The scheduler: which thread runs on each CPU, and for how long.
+//!
+//! Each CPU has its own run queue of ready threads, sorted into [scheduling classes](SchedClass)
+//! by their [`Policy`]: real-time threads run ahead of fair ones, by priority, and fair threads
+//! share what's left by weight. A thread runs until it yields, exits or its class says another
+//! should have a turn. The timer tick charges the running thread for its time and asks its class,
+//! and a thread that's due to be preempted is switched out as the interrupt returns. Each CPU has
+//! an idle thread for when nothing else is ready, which halts until an interrupt brings more work.
+//! The tick stops while a CPU idles, so it only wakes for timers, other interrupts and, every
+//! [`BALANCE_INTERVAL`], to look for threads to take from busier CPUs.
+//!
+//! Threads are spread across the CPUs in their [affinity](CpuSet) mask, and rebalanced every
+//! [`BALANCE_INTERVAL`]. A CPU that
+//! has a thread queued by another is sent a reschedule IPI if it's idle, or if the thread is
+//! real-time and may have to preempt whatever is running there. Otherwise it notices at its next
+//! tick.
+//!
+//! Code that mustn't be switched out halfway through, like anything holding a
+//! [`SpinlockIrq`], disables preemption with [`preempt_disable`]. A preemption that comes due in
+//! the meantime waits for the matching [`preempt_enable`].
+//!
+//! A thread that switches away is only put back on a run queue by the thread taking over from it,
+//! once its registers have been saved, so no other CPU can resume it halfway through. A thread that
+//! blocks isn't put back at all, but by whatever wakes it, unless it was woken before it was off
+//! its CPU.
+
+use alloc::{sync::Arc, vec::Vec};
+use conquer_once::spin::OnceCell;
+use core::{
+    cell::Cell,
+    fmt, ptr,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+use x86_64::{
+    instructions::interrupts::{self as cpu_interrupts, without_interrupts},
+    structures::idt::InterruptStackFrame,
+};
+
+use crate::{
+    deferred::{self, softirq, Softirq},
+    interrupts, percpu,
+    smp::{
+        ipi::{self, Target},
+        percpu, CpuSet,
+    },
+    sync::SpinlockIrq,
+    thread::{State, Thread},
+    time::{self, Instant, TimerHandle, TICK_PERIOD},
+    user,
+};
+
+mod balance;
+mod class;
+mod fair;
+mod realtime;
+mod run_queue;
+pub(crate) mod switch;
+
+pub use balance::BALANCE_INTERVAL;
+pub use class::{Policy, PolicyError, SchedClass, MAX_NICE, MAX_RT_PRIORITY, MIN_NICE};
+
+use balance::{Load, BALANCE_INTERVAL_TICKS};
+use run_queue::RunQueue;
+
+/// How long a real-time thread runs before another of the same priority gets a turn.
+pub const TIME_SLICE: Duration = Duration::from_millis(20);
+const TIME_SLICE_TICKS: u32 = (TIME_SLICE.as_nanos() / TICK_PERIOD.as_nanos()) as u32;
+
+/// A CPU's run queue, and what other CPUs need to know about it.
+struct CpuQueue {
+    cpu: usize,
+    queue: SpinlockIrq<RunQueue>,
+    /// Whether the CPU is running its idle thread.
+    idle: AtomicBool,
+    switches: AtomicU64,
+    migrations: AtomicU64,
+}
+
+impl CpuQueue {
+    fn new(cpu: usize) -> Self {
+        Self {
+            cpu,
+            queue: SpinlockIrq::new(RunQueue::new()),
+            idle: AtomicBool::new(false),
+            switches: AtomicU64::new(0),
+            migrations: AtomicU64::new(0),
+        }
+    }
+
+    fn load(&self) -> Load {
+        Load {
+            cpu: self.cpu,
+            idle: self.idle.load(Ordering::Acquire),
+            queued: self.queue.lock().len(),
+        }
+    }
+}
+
+/// Each CPU's run queue, indexed by CPU number.
+static QUEUES: OnceCell<Vec<CpuQueue>> = OnceCell::uninit();
+static TICK_HANDLER: OnceCell<()> = OnceCell::uninit();
+
+percpu! {
+    /// This CPU's idle thread. Holds a reference of its own.
+    static IDLE: Cell<*const Thread> = Cell::new(ptr::null());
+    /// Set when the running thread should be switched out at the next chance.
+    static NEED_RESCHED: Cell<bool> = Cell::new(false);
+    /// Ticks left in the running thread's time slice.
+    static SLICE_LEFT: Cell<u32> = Cell::new(TIME_SLICE_TICKS);
+    static PREEMPT_COUNT: Cell<usize> = Cell::new(0);
+    /// When the running thread was last charged for its time.
+    static CHARGED_UNTIL: Cell<Instant> = Cell::new(Instant::from_nanos(0));
+    /// Ticks left until this CPU next looks for threads to take off busier ones.
+    static BALANCE_TICKS_LEFT: Cell<u32> = Cell::new(BALANCE_INTERVAL_TICKS);
+}
+
+/// How much scheduling a CPU has done.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// How many threads are waiting in its run queue.
+    pub queued: usize,
+    /// How many times it has switched threads.
+    pub switches: u64,
+    /// How many threads it has pulled over from other CPUs' run queues.
+    pub migrations: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AffinityError {
+    /// None of the CPUs in the mask are online.
+    NoOnlineCpus,
+}
+
+impl fmt::Display for AffinityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AffinityError::NoOnlineCpus => write!(f, "none of the CPUs in the mask are online"),
+        }
+    }
+}
+
+/// The scheduler's bookkeeping for one thread.
+///
+/// The timer tick reads the policy and affinity, so their locks disable interrupts.
+pub(crate) struct SchedEntity {
+    policy: SpinlockIrq<Policy>,
+    /// Real-time priorities lent to the thread by the waiters on mutexes it holds, each with the
+    /// address of its mutex.
+    inherited: SpinlockIrq<Vec<(usize, u8)>>,
+    /// The time the thread has run, weighted by its class. Only the fair class uses it.
+    vruntime: AtomicU64,
+    cpu_time: AtomicU64,
+    affinity: SpinlockIrq<CpuSet>,
+    /// The CPU whose run queue the thread is on, or that it last ran on. Only changes with the new
+    /// CPU's run queue locked.
+    cpu: AtomicUsize,
+}
+
+impl SchedEntity {
+    pub(crate) const fn new(policy: Policy) -> Self {
+        Self {
+            policy: SpinlockIrq::new(policy),
+            inherited: SpinlockIrq::new(Vec::new()),
+            vruntime: AtomicU64::new(0),
+            cpu_time: AtomicU64::new(0),
+            affinity: SpinlockIrq::new(CpuSet::ALL),
+            cpu: AtomicUsize::new(0),
+        }
+    }
+
+    /// The policy the thread is scheduled by: its own, or the highest real-time priority it has
+    /// inherited if that's higher.
+    pub(crate) fn policy(&self) -> Policy {
+        let policy = self.base_policy();
+        let inherited = self
+            .inherited
+            .lock()
+            .iter()
+            .map(|&(_, priority)| priority)
+            .max();
+        match inherited {
+            Some(priority) if Some(priority) > policy.rt_priority() => {
+                Policy::RealTime { priority }
+            }
+            _ => policy,
+        }
+    }
+
+    pub(crate) fn base_policy(&self) -> Policy {
+        *self.policy.lock()
+    }
+
+    pub(crate) fn vruntime(&self) -> u64 {
+        self.vruntime.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_vruntime(&self, vruntime: u64) {
+        self.vruntime.store(vruntime, Ordering::Relaxed);
+    }
+
+    pub(crate) fn cpu_time(&self) -> Duration {
+        Duration::from_nanos(self.cpu_time.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn affinity(&self) -> CpuSet {
+        *self.affinity.lock()
+    }
+
+    pub(crate) fn set_affinity(&self, affinity: CpuSet) {
+        *self.affinity.lock() = affinity;
+    }
+
+    pub(crate) fn cpu(&self) -> usize {
+        self.cpu.load(Ordering::Acquire)
+    }
+
+    fn set_cpu(&self, cpu: usize) {
+        self.cpu.store(cpu, Ordering::Release);
+    }
+}
+
+/// Sets up a run queue for each of `cpus` CPUs.
+pub(crate) fn init(cpus: usize) {
+    QUEUES.init_once(|| (0..cpus).map(CpuQueue::new).collect());
+    softirq::register(Softirq::Sched, balance::rebalance);
+}
+
+fn queues() -> Option<&'static [CpuQueue]> {
+    QUEUES.get().map(Vec::as_slice)
+}
+
+/// The calling CPU's run queue.
+fn local() -> &'static CpuQueue {
+    &queues().expect("scheduler not initialized")[percpu::current_id()]
+}
+
+/// How much scheduling `cpu` has done, or `None` if there's no such CPU.
+pub fn stats(cpu: usize) -> Option<Stats> {
+    let queue = queues()?.get(cpu)?;
+    Some(Stats {
+        queued: queue.queue.lock().len(),
+        switches: queue.switches.load(Ordering::Relaxed),
+        migrations: queue.migrations.load(Ordering::Relaxed),
+    })
+}
+
+/// Makes `boot` the thread running on the calling CPU, and `idle` the thread it runs when nothing
+/// else is ready.
+pub(crate) fn init_cpu(boot: Arc<Thread>, idle: Arc<Thread>) {
+    let cpu = percpu::current_id();
+    boot.sched().set_cpu(cpu);
+    idle.sched().set_cpu(cpu);
+    without_interrupts(|| unsafe {
+        // SAFETY: `boot` is what's running on this CPU. The per-CPU area keeps both references.
+        percpu::set_current_task(Arc::into_raw(boot).cast_mut().cast());
+        IDLE.current().set(Arc::into_raw(idle));
+    });
+    TICK_HANDLER.get_or_init(|| time::on_tick(tick));
+}
+
+/// The thread running on the calling CPU.
+pub fn current() -> Arc<Thread> {
+    let thread = current_ptr();
+    assert!(!thread.is_null(), "threads not initialized on this CPU");
+    unsafe {
+        // SAFETY: The per-CPU area holds a reference for as long as the thread is current.
+        Arc::increment_strong_count(thread);
+        Arc::from_raw(thread)
+    }
+}
+
+fn current_ptr() -> *const Thread {
+    percpu::current_task().cast::<Thread>().cast_const()
+}
+
+/// Whether the calling CPU is running its idle thread.
+fn is_idle() -> bool {
+    IDLE.with(|idle| !idle.get().is_null() && ptr::eq(idle.get(), current_ptr()))
+}
+
+/// Puts a thread that's become ready on a run queue: its last CPU's if that one is idle, and
+/// otherwise the least busy allowed CPU's.
+pub(crate) fn enqueue(thread: Arc<Thread>) {
+    thread.set_state(State::Ready);
+    without_interrupts(|| enqueue_on(balance::select_cpu(&thread), thread));
+}
+
+/// Puts a thread on `cpu`'s run queue, and makes sure `cpu` notices if it should run it now.
+///
+/// Interrupts must be disabled.
+fn enqueue_on(cpu: usize, thread: Arc<Thread>) {
+    let target = &queues().expect("scheduler not initialized")[cpu];
+    let realtime = matches!(thread.policy(), Policy::RealTime { .. });
+    let mut queue = target.queue.lock();
+    if thread.cpu() != cpu {
+        // Its virtual runtime means nothing on another CPU, so it starts level with the threads
+        // there.
+        thread.sched().set_vruntime(0);
+        thread.sched().set_cpu(cpu);
+    }
+    queue.enqueue(thread);
+
+    if cpu == percpu::current_id() {
+        // SAFETY: The per-CPU area holds a reference for as long as the thread is current.
+        let current = unsafe { current_ptr().as_ref() };
+        if is_idle() || current.is_some_and(|current| queue.should_preempt(current, false)) {
+            unsafe { NEED_RESCHED.current() }.set(true);
+        }
+    } else if realtime || target.idle.load(Ordering::Acquire) {
+        drop(queue);
+        ipi::send(Target::Cpu(cpu), interrupts::RESCHEDULE_VECTOR);
+    }
+}
+
+/// Takes a thread off the run queue it's on, if it's on one.
+fn dequeue(thread: &Thread) -> Option<Arc<Thread>> {
+    let queues = queues()?;
+    loop {
+        let cpu = thread.cpu();
+        let mut queue = queues[cpu].queue.lock();
+        // It may have moved to another CPU's queue before the lock was taken.
+        if thread.cpu() == cpu {
+            return queue.remove(thread.id());
+        }
+    }
+}
+
+/// Lets another ready thread run, if there is one its scheduling class would pick first. A
+/// real-time thread goes behind the others of its priority.
+pub fn yield_now() {
+    without_interrupts(|| reschedule(State::Ready));
+}
+
+/// Ends the calling thread.
+pub fn exit() -> ! {
+    cpu_interrupts::disable();
+    reschedule(State::Exited);
+    unreachable!("exited thread was resumed");
+}
+
+/// Changes how a thread is scheduled, moving it to its new class if it's queued.
+pub fn set_policy(thread: &Arc<Thread>, policy: Policy) -> Result<(), PolicyError> {
+    let policy = policy.validate()?;
+    requeue(thread, |entity| *entity.policy.lock() = policy);
+    Ok(())
+}
+
+/// Lends `thread` a real-time priority while it holds the mutex at `mutex`, on behalf of a thread
+/// waiting for it. Only ever raises the priority it has already inherited through that mutex.
+pub(crate) fn inherit_priority(thread: &Thread, mutex: usize, priority: u8) {
+    let inherited = thread.sched().inherited.lock();
+    let current = inherited.iter().find(|&&(from, _)| from == mutex);
+    if current.is_some_and(|&(_, lent)| lent >= priority) {
+        return;
+    }
+    drop(inherited);
+    requeue(thread, |entity| {
+        let mut inherited = entity.inherited.lock();
+        inherited.retain(|&(from, _)| from != mutex);
+        inherited.push((mutex, priority));
+    });
+}
+
+/// Takes back whatever priority `thread` inherited through the mutex at `mutex`.
+pub(crate) fn restore_priority(thread: &Thread, mutex: usize) {
+    let inherited = thread.sched().inherited.lock();
+    if !inherited.iter().any(|&(from, _)| from == mutex) {
+        return;
+    }
+    drop(inherited);
+    requeue(thread, |entity| {
+        entity.inherited.lock().retain(|&(from, _)| from != mutex);
+    });
+}
+
+/// Changes how a thread is scheduled with `change`, moving it to its new class if it's queued.
+fn requeue(thread: &Thread, change: impl FnOnce(&SchedEntity)) {
+    without_interrupts(|| {
+        let queued = dequeue(thread);
+        change(thread.sched());
+        if let Some(thread) = queued {
+            enqueue_on(thread.cpu(), thread);
+        }
+        // Whatever this CPU is running may not be the right thread any more.
+        unsafe { NEED_RESCHED.current() }.set(true);
+    });
+}
+
+/// Marks the calling thread as going to sleep, ahead of [`block`]. A [`wake`] from here on wakes
+/// it, even if it comes before the thread has switched out.
+///
+/// Interrupts must be disabled, and stay disabled until [`block`].
+pub(crate) fn prepare_to_block() {
+    // SAFETY: The per-CPU area holds a reference for as long as the thread is current.
+    let current = unsafe { &*current_ptr() };
+    current.set_state(State::Blocking);
+}
+
+/// Switches away from the calling thread, marked by [`prepare_to_block`], until it's woken. Returns
+/// straight away if it was woken already.
+///
+/// Interrupts must be disabled.
+pub(crate) fn block() {
+    reschedule(State::Blocking);
+}
+
+/// Undoes [`prepare_to_block`] for a thread that's decided not to sleep after all.
+pub(crate) fn cancel_block() {
+    // SAFETY: The per-CPU area holds a reference for as long as the thread is current.
+    let current = unsafe { &*current_ptr() };
+    current.set_state(State::Running);
+}
+
+/// Wakes a thread put to sleep with [`block`]. Returns whether it was asleep, or going to sleep.
+pub(crate) fn wake(thread: &Arc<Thread>) -> bool {
+    loop {
+        match thread.state() {
+            // Still on its CPU: it sees it's been woken before it switches out, or the thread
+            // taking over from it does.
+            State::Blocking => {
+                if thread.change_state(State::Blocking, State::Ready) {
+                    return true;
+                }
+            }
+            State::Blocked => {
+                if thread.change_state(State::Blocked, State::Ready) {
+                    enqueue(thread.clone());
+                    return true;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Sleeps until the monotonic clock reaches `deadline`. Nothing runs on the thread's behalf until
+/// then, as a timer wakes it.
+#[track_caller]
+pub fn sleep_until(deadline: Instant) {
+    might_sleep();
+    if time::now() >= deadline {
+        return;
+    }
+    let timer = wake_at(current(), deadline);
+    without_interrupts(|| loop {
+        prepare_to_block();
+        if time::now() >= deadline {
+            cancel_block();
+            break;
+        }
+        block();
+    });
+    time::cancel_timer(timer);
+}
+
+/// Sets a timer to [`wake`] `thread` at `deadline`.
+///
+/// The timer may go off just as the thread stops waiting, and wake it early from whatever it
+/// sleeps on next, so anything that sleeps checks why it was woken.
+pub(crate) fn wake_at(thread: Arc<Thread>, deadline: Instant) -> TimerHandle {
+    time::add_timer(deadline, move || {
+        wake(&thread);
+    })
+}
+
+/// Panics, with `debug_assertions`, if the calling thread isn't allowed to sleep: in an interrupt
+/// handler or softirq, with interrupts disabled, with preemption disabled, as it is while a
+/// [`SpinlockIrq`] is held, or on an idle thread, which must always be ready to run.
+///
+/// Every operation that may sleep calls this first, so that mistakes show up whether or not it
+/// ends up sleeping. Plain `Spinlock`s don't disable preemption, and go unnoticed.
+#[track_caller]
+pub fn might_sleep() {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    assert!(!deferred::in_interrupt(), "sleeping in interrupt context");
+    assert!(!is_idle(), "sleeping on an idle thread");
+    assert!(
+        cpu_interrupts::are_enabled(),
+        "sleeping with interrupts disabled"
+    );
+    let count = PREEMPT_COUNT.with(Cell::get);
+    assert!(
+        count == 0,
+        "sleeping with preemption disabled {} times, as by a held SpinlockIrq",
+        count
+    );
+}
+
+/// Whether the calling thread may sleep: it's a thread other than the idle thread, and none of
+/// the things [`might_sleep`] checks for are true.
+pub fn can_sleep() -> bool {
+    !current_ptr().is_null() && !deferred::in_interrupt() && !is_idle() && preemptible()
+}
+
+/// Restricts a thread to the CPUs in `affinity`, moving it off any other CPU it's queued or
+/// running on.
+pub fn set_affinity(thread: &Arc<Thread>, affinity: CpuSet) -> Result<(), AffinityError> {
+    check_affinity(affinity)?;
+    without_interrupts(|| {
+        thread.sched().set_affinity(affinity);
+        let cpu = thread.cpu();
+        if affinity.contains(cpu) {
+            return;
+        }
+        if let Some(queued) = dequeue(thread) {
+            enqueue_on(balance::select_cpu(&queued), queued);
+        } else if cpu == percpu::current_id() {
+            unsafe { NEED_RESCHED.current() }.set(true);
+        } else if thread.state() == State::Running {
+            ipi::send(Target::Cpu(cpu), interrupts::RESCHEDULE_VECTOR);
+        }
+    });
+    if Arc::ptr_eq(thread, &current()) && preemptible() {
+        yield_now();
+    }
+    Ok(())
+}
+
+/// Checks that a thread restricted to `affinity` has somewhere to run.
+pub(crate) fn check_affinity(affinity: CpuSet) -> Result<(), AffinityError> {
+    if !balance::online_cpus().any(|cpu| affinity.contains(cpu)) {
+        return Err(AffinityError::NoOnlineCpus);
+    }
+    Ok(())
+}
+
+/// Disables preemption on the calling CPU, until the matching [`preempt_enable`]. Calls nest.
+pub fn preempt_disable() {
+    PREEMPT_COUNT.with(|count| count.set(count.get() + 1));
+}
+
+/// Undoes a [`preempt_disable`]. Once preemption is enabled again, a preemption that came due
+/// while it was disabled happens straight away, unless interrupts are disabled too.
+pub fn preempt_enable() {
+    let due = without_interrupts(|| {
+        let count = unsafe { PREEMPT_COUNT.current() };
+        count.set(count.get() - 1);
+        count.get() == 0 && unsafe { NEED_RESCHED.current() }.get()
+    });
+    if due && cpu_interrupts::are_enabled() {
+        yield_now();
+    }
+}
+
+/// Runs `f` with preemption disabled.
+pub fn without_preemption<R>(f: impl FnOnce() -> R) -> R {
+    preempt_disable();
+    let result = f();
+    preempt_enable();
+    result
+}
+
+/// Whether the running thread could be switched out by an interrupt.
+pub fn preemptible() -> bool {
+    cpu_interrupts::are_enabled() && PREEMPT_COUNT.with(Cell::get) == 0
+}
+
+/// Charges the running thread for its time and counts down its slice, on every tick of every
+/// CPU, and marks it for preemption if it should give way. Also raises the scheduler's softirq
+/// once every [`BALANCE_INTERVAL`].
+fn tick() {
+    let (slice, need_resched, balance) = unsafe {
+        (
+            SLICE_LEFT.current(),
+            NEED_RESCHED.current(),
+            BALANCE_TICKS_LEFT.current(),
+        )
+    };
+    slice.set(slice.get().saturating_sub(1));
+    balance.set(balance.get().saturating_sub(1));
+    if balance.get() == 0 {
+        balance.set(BALANCE_INTERVAL_TICKS);
+        softirq::raise(Softirq::Sched);
+    }
+
+    // SAFETY: The per-CPU area holds a reference for as long as the thread is current.
+    let (Some(queues), Some(current)) = (queues(), unsafe { current_ptr().as_ref() }) else {
+        return;
+    };
+    let cpu = percpu::current_id();
+    let mut queue = queues[cpu].queue.lock();
+    charge(&mut queue, current);
+    if should_switch(&queue, current, cpu, slice.get() == 0) {
+        need_resched.set(true);
+    }
+}
+
+/// Whether `cpu`, running `current`, should switch to another thread.
+fn should_switch(queue: &RunQueue, current: &Thread, cpu: usize, slice_expired: bool) -> bool {
+    if is_idle() {
+        !queue.is_empty()
+    } else {
+        !current.affinity().contains(cpu) || queue.should_preempt(current, slice_expired)
+    }
+}
+
+/// Handles the IPI another CPU sends when it queues a thread here that may have to run now.
+pub(crate) extern "x86-interrupt" fn reschedule_handler(stack_frame: InterruptStackFrame) {
+    deferred::irq_enter(&stack_frame);
+    // SAFETY: The per-CPU area holds a reference for as long as the thread is current.
+    if let (Some(queues), Some(current)) = (queues(), unsafe { current_ptr().as_ref() }) {
+        let cpu = percpu::current_id();
+        if should_switch(&queues[cpu].queue.lock(), current, cpu, false) {
+            unsafe { NEED_RESCHED.current() }.set(true);
+        }
+    }
+    interrupts::eoi();
+    deferred::irq_exit(&stack_frame);
+}
+
+/// Charges the running thread for the time since it was last charged.
+fn charge(queue: &mut RunQueue, current: &Thread) {
+    let now = time::now();
+    let ran = now.duration_since(unsafe { CHARGED_UNTIL.current() }.replace(now));
+    current
+        .sched()
+        .cpu_time
+        .fetch_add(ran.as_nanos() as u64, Ordering::Relaxed);
+    if !is_idle() {
+        queue.account(current, ran);
+    }
+}
+
+/// Switches to another thread if the running one is due to be preempted.
+///
+/// Called by the outermost interrupt handler as it returns to code that had interrupts enabled.
+pub(crate) fn preempt_on_irq_exit() {
+    let (count, need_resched) = unsafe { (PREEMPT_COUNT.current(), NEED_RESCHED.current()) };
+    // Not from an interrupt that came in while this CPU was running softirqs, which have to finish
+    // on the thread that started them.
+    if count.get() == 0 && need_resched.get() && !deferred::in_softirq() {
+        reschedule(State::Ready);
+    }
+}
+
+/// The body of every idle thread.
+pub(crate) fn idle() -> ! {
+    loop {
+        // Checked with interrupts disabled, so a wakeup can't slip in before the `hlt`.
+        cpu_interrupts::disable();
+        if local().queue.lock().is_empty() && !balance::pull() {
+            time::stop_tick(time::now() + BALANCE_INTERVAL);
+            deferred::idle();
+        } else {
+            reschedule(State::Ready);
+            cpu_interrupts::enable();
+        }
+    }
+}
+
+/// Switches the calling CPU to the thread that should run next, leaving the current one in
+/// `state`.
+///
+/// A thread that's still ready, and still allowed on this CPU, is a candidate itself, and keeps
+/// running if it's still the best choice. If nothing is ready, any other thread gives way to the
+/// idle thread. A thread that's blocking keeps running if it was woken in the meantime.
+/// Interrupts must be disabled.
+fn reschedule(state: State) {
+    let (count, need_resched, slice) = unsafe {
+        (
+            PREEMPT_COUNT.current(),
+            NEED_RESCHED.current(),
+            SLICE_LEFT.current(),
+        )
+    };
+    debug_assert_eq!(count.get(), 0, "switching threads with preemption disabled");
+    need_resched.set(false);
+    slice.set(TIME_SLICE_TICKS);
+
+    let current = current();
+    if state == State::Blocking && current.change_state(State::Ready, State::Running) {
+        return;
+    }
+    let mut queue = local().queue.lock();
+    charge(&mut queue, &current);
+    let next = if is_idle() {
+        queue.pick_next()
+    } else if state == State::Ready && current.affinity().contains(percpu::current_id()) {
+        queue.pick_next_instead_of(&current)
+    } else {
+        Some(queue.pick_next().unwrap_or_else(|| unsafe {
+            // SAFETY: The per-CPU area holds a reference to the idle thread for good.
+            let idle = IDLE.current().get();
+            Arc::increment_strong_count(idle);
+            Arc::from_raw(idle)
+        }))
+    };
+    drop(queue);
+    drop(current);
+
+    if let Some(next) = next {
+        switch_to(next, state);
+    }
+}
+
+/// Switches from the calling thread to `next`, leaving the calling thread in `state`.
+///
+/// Interrupts must be disabled.
+fn switch_to(next: Arc<Thread>, state: State) {
+    let previous = current_ptr();
+    let next_rsp = next.saved_rsp().load(Ordering::Relaxed);
+    next.set_state(State::Running);
+    let local = local();
+    let idle = unsafe { IDLE.current() }.get();
+    let to_idle = ptr::eq(Arc::as_ptr(&next), idle);
+    local.idle.store(to_idle, Ordering::Release);
+    if !to_idle {
+        time::restart_tick();
+    }
+    // SAFETY: The calling thread is running, so the per-CPU area holds a reference to it.
+    user::prepare_switch(unsafe { &*previous }, &next);
+    local.switches.fetch_add(1, Ordering::Relaxed);
+    unsafe {
+        // SAFETY: The reference moves from `next` to the per-CPU area, and the previous thread's
+        // reference is handed over to whoever runs next, with the previous thread.
+        if state != State::Blocking {
+            // A blocking thread is already marked, and may have been woken since.
+            (*previous).set_state(state);
+        }
+        percpu::set_current_task(Arc::into_raw(next).cast_mut().cast());
+        let previous = switch::switch((*previous).saved_rsp().as_ptr(), next_rsp, previous);
+        finish_switch(previous);
+    }
+}
+
+/// Deals with the thread that ran before the calling one on this CPU, now that it's off the CPU.
+///
+/// # Safety
+///
+/// `previous` must be the reference to the previous thread that the per-CPU area held.
+pub(crate) unsafe fn finish_switch(previous: *const Thread) {
+    let previous = unsafe { Arc::from_raw(previous) };
+    if IDLE.with(|idle| ptr::eq(idle.get(), Arc::as_ptr(&previous))) {
+        // Idle threads never go on the run queue.
+        return;
+    }
+    let mut state = previous.state();
+    if state == State::Blocking {
+        if previous.change_state(State::Blocking, State::Blocked) {
+            // Whatever wakes it queues it again.
+            return;
+        }
+        // Woken while it was switching out.
+        state = State::Ready;
+    }
+    match state {
+        State::Ready if previous.affinity().contains(percpu::current_id()) => {
+            local().queue.lock().enqueue(previous)
+        }
+        // Preempted to move it off this CPU.
+        State::Ready => enqueue(previous),
+        State::Exited => {
+            log::debug!(
+                "Thread {} exited after {:?} of CPU time",
+                previous.id(),
+                previous.cpu_time()
+            );
+            // Freeing the stack takes the VMM lock, which the thread this CPU is resuming may hold,
+            // so it's left to the CPU's kworker.
+            deferred::queue_work(move || previous.release_stack());
+        }
+        State::Running | State::Blocking | State::Blocked => {
+            unreachable!("thread {} switched out {:?}", previous.id(), state)
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! The real-time class: strict priorities, with threads of the same priority taking turns.
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc};
+use core::time::Duration;
+
+use super::class::{Policy, SchedClass, MAX_RT_PRIORITY};
+use crate::thread::{Thread, ThreadId};
+
+const LEVELS: usize = MAX_RT_PRIORITY as usize + 1;
+
+fn priority(thread: &Thread) -> u8 {
+    match thread.policy() {
+        Policy::RealTime { priority } => priority,
+        Policy::Fair { .. } => 0,
+    }
+}
+
+pub struct RealTime {
+    queues: [VecDeque<Arc<Thread>>; LEVELS],
+    /// Which of `queues` are non-empty, one bit each.
+    occupied: u128,
+}
+
+impl RealTime {
+    pub const fn new() -> Self {
+        Self {
+            queues: [const { VecDeque::new() }; LEVELS],
+            occupied: 0,
+        }
+    }
+
+    fn highest(&self) -> Option<u8> {
+        (self.occupied != 0).then(|| (127 - self.occupied.leading_zeros()) as u8)
+    }
+}
+
+impl Default for RealTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchedClass for RealTime {
+    fn name(&self) -> &'static str {
+        "real-time"
+    }
+
+    fn enqueue(&mut self, thread: Arc<Thread>) {
+        let priority = priority(&thread);
+        self.queues[priority as usize].push_back(thread);
+        self.occupied |= 1 << priority;
+    }
+
+    fn remove(&mut self, id: ThreadId) -> Option<Arc<Thread>> {
+        let (priority, queue) = self
+            .queues
+            .iter_mut()
+            .enumerate()
+            .find(|(_, queue)| queue.iter().any(|thread| thread.id() == id))?;
+        let index = queue.iter().position(|thread| thread.id() == id)?;
+        let thread = queue.remove(index);
+        if queue.is_empty() {
+            self.occupied &= !(1 << priority);
+        }
+        thread
+    }
+
+    fn pick_next(&mut self) -> Option<Arc<Thread>> {
+        let priority = self.highest()?;
+        let queue = &mut self.queues[priority as usize];
+        let thread = queue.pop_front();
+        if queue.is_empty() {
+            self.occupied &= !(1 << priority);
+        }
+        thread
+    }
+
+    fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.occupied == 0
+    }
+
+    fn threads(&self) -> Box<dyn Iterator<Item = &Arc<Thread>> + '_> {
+        Box::new(self.queues.iter().rev().flatten())
+    }
+
+    fn account(&mut self, _thread: &Thread, _ran: Duration) {}
+
+    fn should_preempt(&self, current: &Thread, slice_expired: bool) -> bool {
+        let current = priority(current);
+        self.highest()
+            .is_some_and(|next| next > current || (next == current && slice_expired))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thread(priority: u8) -> Arc<Thread> {
+        Thread::for_tests(Policy::RealTime { priority })
+    }
+
+    #[test]
+    pub fn runs_the_highest_priority_first() {
+        let mut rt = RealTime::new();
+        let (low, high, highest) = (thread(1), thread(50), thread(99));
+        rt.enqueue(low.clone());
+        rt.enqueue(highest.clone());
+        rt.enqueue(high.clone());
+
+        assert!(rt.should_preempt(&low, false));
+        assert_eq!(highest.id(), rt.pick_next().unwrap().id());
+        assert_eq!(high.id(), rt.pick_next().unwrap().id());
+        assert_eq!(low.id(), rt.pick_next().unwrap().id());
+        assert!(rt.is_empty());
+    }
+
+    #[test]
+    pub fn takes_turns_within_a_priority() {
+        let mut rt = RealTime::new();
+        let (a, b) = (thread(10), thread(10));
+        rt.enqueue(b.clone());
+
+        assert!(!rt.should_preempt(&a, false));
+        assert!(rt.should_preempt(&a, true));
+        assert_eq!(Some(b.id()), rt.remove(b.id()).map(|t| t.id()));
+        assert!(!rt.should_preempt(&a, true));
+    }
+}
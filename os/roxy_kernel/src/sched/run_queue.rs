@@ -0,0 +1,164 @@
+use alloc::sync::Arc;
+use core::time::Duration;
+
+use super::{
+    class::{Policy, SchedClass},
+    fair::Fair,
+    realtime::RealTime,
+};
+use crate::thread::{Thread, ThreadId};
+
+/// The ready threads of one CPU, sorted into the scheduling class of their policy.
+pub struct RunQueue {
+    realtime: RealTime,
+    fair: Fair,
+}
+
+impl RunQueue {
+    pub const fn new() -> Self {
+        Self {
+            realtime: RealTime::new(),
+            fair: Fair::new(),
+        }
+    }
+
+    /// The classes, in the order they're asked for a thread to run.
+    fn classes(&self) -> [&dyn SchedClass; 2] {
+        [&self.realtime, &self.fair]
+    }
+
+    fn rank(policy: Policy) -> usize {
+        match policy {
+            Policy::RealTime { .. } => 0,
+            Policy::Fair { .. } => 1,
+        }
+    }
+
+    fn class_mut(&mut self, policy: Policy) -> &mut dyn SchedClass {
+        match policy {
+            Policy::RealTime { .. } => &mut self.realtime,
+            Policy::Fair { .. } => &mut self.fair,
+        }
+    }
+
+    pub fn enqueue(&mut self, thread: Arc<Thread>) {
+        self.class_mut(thread.policy()).enqueue(thread);
+    }
+
+    /// Takes a thread off the queue, if it's queued.
+    ///
+    /// Every class is searched, as the thread's policy may have changed since it was queued.
+    pub fn remove(&mut self, id: ThreadId) -> Option<Arc<Thread>> {
+        self.realtime.remove(id).or_else(|| self.fair.remove(id))
+    }
+
+    pub fn pick_next(&mut self) -> Option<Arc<Thread>> {
+        self.realtime.pick_next().or_else(|| self.fair.pick_next())
+    }
+
+    /// Picks the thread that should run next, counting `current` as one of the candidates.
+    /// Returns `None` if that's `current`, which is left off the queue either way.
+    pub fn pick_next_instead_of(&mut self, current: &Arc<Thread>) -> Option<Arc<Thread>> {
+        self.enqueue(current.clone());
+        let next = self.pick_next()?;
+        if Arc::ptr_eq(&next, current) {
+            return None;
+        }
+        self.remove(current.id());
+        Some(next)
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes().iter().map(|class| class.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes().iter().all(|class| class.is_empty())
+    }
+
+    /// Takes the next thread that may run on `cpu` off the queue, for it to move there with
+    /// [`enqueue_migrated`](Self::enqueue_migrated).
+    pub fn take_for(&mut self, cpu: usize) -> Option<Arc<Thread>> {
+        let id = self
+            .classes()
+            .into_iter()
+            .flat_map(|class| class.threads())
+            .find(|thread| thread.affinity().contains(cpu))?
+            .id();
+        let thread = self.remove(id)?;
+        self.fair.detach(&thread);
+        Some(thread)
+    }
+
+    /// Queues a thread taken off another CPU's queue with [`take_for`](Self::take_for).
+    pub fn enqueue_migrated(&mut self, thread: Arc<Thread>) {
+        self.fair.attach(&thread);
+        self.enqueue(thread);
+    }
+
+    /// Charges `thread` for time it spent running.
+    pub fn account(&mut self, thread: &Thread, ran: Duration) {
+        self.class_mut(thread.policy()).account(thread, ran);
+    }
+
+    /// Whether the running thread should give way to a queued one, either of a class ahead of its
+    /// own or by the rules of its own class.
+    pub fn should_preempt(&self, current: &Thread, slice_expired: bool) -> bool {
+        let classes = self.classes();
+        let rank = Self::rank(current.policy());
+        classes[..rank].iter().any(|class| !class.is_empty())
+            || classes[rank].should_preempt(current, slice_expired)
+    }
+}
+
+impl Default for RunQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smp::CpuSet;
+
+    #[test]
+    pub fn real_time_threads_run_ahead_of_fair_ones() {
+        let mut queue = RunQueue::new();
+        let fair = Thread::for_tests(Policy::DEFAULT);
+        let rt = Thread::for_tests(Policy::RealTime { priority: 1 });
+        queue.enqueue(fair.clone());
+        assert!(!queue.should_preempt(&fair, true));
+
+        queue.enqueue(rt.clone());
+        assert!(queue.should_preempt(&fair, false));
+        assert_eq!(rt.id(), queue.pick_next().unwrap().id());
+        assert!(queue.pick_next_instead_of(&rt).is_none());
+        assert_eq!(fair.id(), queue.pick_next().unwrap().id());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    pub fn migrates_threads_allowed_on_the_new_cpu() {
+        let (mut from, mut to) = (RunQueue::new(), RunQueue::new());
+        let pinned = Thread::for_tests(Policy::DEFAULT);
+        pinned.sched().set_affinity(CpuSet::single(0));
+        let free = Thread::for_tests(Policy::DEFAULT);
+        from.enqueue(pinned.clone());
+        from.enqueue(free.clone());
+        free.sched().set_vruntime(free.sched().vruntime() + 5);
+
+        let moved = from.take_for(1).unwrap();
+        assert_eq!(free.id(), moved.id());
+        assert!(from.take_for(1).is_none());
+        assert_eq!(1, from.len());
+
+        // Leaves the other queue's minimum virtual runtime at 100.
+        let ran = Thread::for_tests(Policy::DEFAULT);
+        ran.sched().set_vruntime(100);
+        to.enqueue(ran);
+        to.pick_next();
+        to.enqueue_migrated(moved);
+        assert_eq!(105, free.sched().vruntime());
+    }
+}
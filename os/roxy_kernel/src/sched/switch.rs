@@ -0,0 +1,80 @@
+//! Switching stacks between threads.
+//!
+//! A thread that isn't running is described entirely by its saved stack pointer: the callee-saved
+//! registers are pushed onto its stack, under the address [`switch`] returns to. A new thread's
+//! stack is made to look the same, except that it returns into `thread_start`.
+
+use core::arch::global_asm;
+use x86_64::VirtAddr;
+
+use crate::thread::{self, Thread};
+
+global_asm!(
+    r#"
+    .global roxy_switch_context
+roxy_switch_context:
+    push rbp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    mov [rdi], rsp
+    mov rsp, rsi
+    mov rax, rdx
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    pop rbp
+    ret
+
+    .global roxy_thread_start
+roxy_thread_start:
+    mov rdi, rax
+    call {entry}
+    ud2
+    "#,
+    entry = sym thread::thread_entry,
+);
+
+extern "C" {
+    fn roxy_switch_context(save: *mut u64, load: u64, previous: *const ()) -> *const ();
+    fn roxy_thread_start() -> !;
+}
+
+/// The registers `roxy_switch_context` pops, in the order it pushes them.
+const SAVED_REGISTERS: usize = 6;
+
+/// Builds the initial frame on a new thread's stack, returning the stack pointer to save for it.
+///
+/// # Safety
+///
+/// `top` must be the top of a mapped stack that nothing else uses.
+pub(crate) unsafe fn prepare(top: VirtAddr) -> u64 {
+    let top = top.align_down(16u64).as_mut_ptr::<u64>();
+    unsafe {
+        // `roxy_thread_start` is entered with the stack aligned, as its `call` expects.
+        let frame = top.sub(SAVED_REGISTERS + 1);
+        frame.write_bytes(0, SAVED_REGISTERS);
+        frame
+            .add(SAVED_REGISTERS)
+            .write(roxy_thread_start as *const () as u64);
+        frame as u64
+    }
+}
+
+/// Saves the calling thread's stack pointer to `save`, and resumes the thread whose stack pointer
+/// is `load`.
+///
+/// Returns once something switches back to the calling thread, with whatever that passed as
+/// `previous`. A new thread gets it as the argument of `thread_entry` instead.
+///
+/// # Safety
+///
+/// Interrupts must be disabled, and `load` must be the saved stack pointer of a thread that isn't
+/// running.
+pub(super) unsafe fn switch(save: *mut u64, load: u64, previous: *const Thread) -> *const Thread {
+    unsafe { roxy_switch_context(save, load, previous.cast()).cast() }
+}
@@ -0,0 +1,96 @@
+//! Running functions on other CPUs.
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::{instructions::interrupts::without_interrupts, structures::idt::InterruptStackFrame};
+
+use super::{
+    ipi::{self, Target},
+    percpu,
+};
+use crate::{deferred, interrupts, sync::SpinlockIrq};
+
+struct Call {
+    func: Box<dyn Fn() + Send + Sync>,
+    /// The number of CPUs that have yet to run `func`.
+    pending: AtomicUsize,
+}
+
+/// Each CPU's queue of calls to run, indexed by CPU number.
+static QUEUES: OnceCell<Vec<SpinlockIrq<VecDeque<Arc<Call>>>>> = OnceCell::uninit();
+
+pub(super) fn init(cpus: usize) {
+    QUEUES.init_once(|| {
+        (0..cpus)
+            .map(|_| SpinlockIrq::new(VecDeque::new()))
+            .collect()
+    });
+}
+
+/// Runs `f` on each online CPU in `target`, and waits until they've all finished.
+///
+/// `f` runs in interrupt context, with interrupts disabled, including on the calling CPU. It must
+/// not take locks that could be held by the code it interrupts.
+pub fn call(target: Target, f: impl Fn() + Send + Sync + 'static) {
+    let current = percpu::current_id();
+    let local = match target {
+        Target::Cpu(id) => id == current,
+        Target::All => true,
+        Target::AllButSelf => false,
+    };
+    let mut remote = target.cpus().filter(|cpu| cpu.id != current).peekable();
+
+    let Some(queues) = QUEUES.get().filter(|_| remote.peek().is_some()) else {
+        // There's nobody else to ask.
+        if local {
+            without_interrupts(f);
+        }
+        return;
+    };
+
+    let call = Arc::new(Call {
+        func: Box::new(f),
+        pending: AtomicUsize::new(0),
+    });
+    for cpu in remote {
+        call.pending.fetch_add(1, Ordering::Relaxed);
+        queues[cpu.id].lock().push_back(call.clone());
+        ipi::send(Target::Cpu(cpu.id), interrupts::CALL_FUNCTION_VECTOR);
+    }
+
+    if local {
+        without_interrupts(&call.func);
+    }
+    // Keep answering other CPUs while we wait, in case one of them is waiting on us.
+    while call.pending.load(Ordering::Acquire) != 0 {
+        run_pending();
+        core::hint::spin_loop();
+    }
+}
+
+/// Runs every call queued for the calling CPU.
+///
+/// This is done by the call function interrupt, and by code that spins with interrupts disabled
+/// somewhere another CPU might be waiting for it.
+pub fn run_pending() {
+    let Some(queues) = QUEUES.get() else {
+        return;
+    };
+    let queue = &queues[percpu::current_id()];
+    without_interrupts(|| loop {
+        // Not `while let`, which would hold the lock while the call runs.
+        let Some(call) = queue.lock().pop_front() else {
+            break;
+        };
+        (call.func)();
+        call.pending.fetch_sub(1, Ordering::Release);
+    });
+}
+
+pub(crate) extern "x86-interrupt" fn call_function_handler(stack_frame: InterruptStackFrame) {
+    deferred::irq_enter(&stack_frame);
+    run_pending();
+    interrupts::eoi();
+    deferred::irq_exit(&stack_frame);
+}
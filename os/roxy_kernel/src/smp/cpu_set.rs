@@ -0,0 +1,94 @@
+//! Sets of CPUs, like the ones a thread is allowed to run on.
+
+use core::fmt;
+
+/// The most CPUs that are brought online. Any more in the MADT are left alone.
+pub const MAX_CPUS: usize = 256;
+
+const WORDS: usize = MAX_CPUS / u64::BITS as usize;
+
+/// A set of CPUs, by [`Cpu::id`](super::Cpu::id).
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CpuSet([u64; WORDS]);
+
+impl CpuSet {
+    pub const EMPTY: Self = Self([0; WORDS]);
+    /// Every CPU, including any that aren't online.
+    pub const ALL: Self = Self([u64::MAX; WORDS]);
+
+    pub const fn single(cpu: usize) -> Self {
+        let mut set = Self::EMPTY;
+        set.0[cpu / 64] = 1 << (cpu % 64);
+        set
+    }
+
+    pub fn insert(&mut self, cpu: usize) {
+        assert!(cpu < MAX_CPUS, "CPU {} is out of range", cpu);
+        self.0[cpu / 64] |= 1 << (cpu % 64);
+    }
+
+    pub fn remove(&mut self, cpu: usize) {
+        if cpu < MAX_CPUS {
+            self.0[cpu / 64] &= !(1 << (cpu % 64));
+        }
+    }
+
+    pub fn contains(&self, cpu: usize) -> bool {
+        cpu < MAX_CPUS && self.0[cpu / 64] & (1 << (cpu % 64)) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&word| word == 0)
+    }
+
+    /// The CPUs in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..MAX_CPUS).filter(|&cpu| self.contains(cpu))
+    }
+}
+
+impl Default for CpuSet {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl FromIterator<usize> for CpuSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut set = Self::EMPTY;
+        for cpu in iter {
+            set.insert(cpu);
+        }
+        set
+    }
+}
+
+impl fmt::Debug for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Self::ALL {
+            return f.write_str("ALL");
+        }
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn holds_cpus_across_words() {
+        let mut set: CpuSet = [0, 63, 64, 255].into_iter().collect();
+        assert!(set.contains(63) && set.contains(64) && set.contains(255));
+        assert!(!set.contains(1) && !set.contains(256));
+
+        set.remove(64);
+        assert_eq!(
+            std::vec![0, 63, 255],
+            set.iter().collect::<std::vec::Vec<_>>()
+        );
+        assert!(!set.is_empty());
+        assert!(CpuSet::EMPTY.is_empty());
+        assert_eq!(CpuSet::single(5), [5].into_iter().collect());
+    }
+}
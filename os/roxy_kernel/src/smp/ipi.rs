@@ -0,0 +1,56 @@
+//! Sending inter-processor interrupts.
+
+use super::{cpus, percpu, Cpu};
+use crate::interrupts::{self, lapic::icr};
+
+/// The CPUs an IPI is sent to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Cpu(usize),
+    All,
+    AllButSelf,
+}
+
+impl Target {
+    /// The online CPUs the target covers.
+    pub fn cpus(self) -> impl Iterator<Item = &'static Cpu> {
+        let current = percpu::current_id();
+        cpus().iter().filter(move |cpu| {
+            cpu.is_online()
+                && match self {
+                    Target::Cpu(id) => cpu.id == id,
+                    Target::All => true,
+                    Target::AllButSelf => cpu.id != current,
+                }
+        })
+    }
+}
+
+/// Sends a fixed interrupt with `vector` to the online CPUs in `target`.
+///
+/// The vector must have a handler installed.
+pub fn send(target: Target, vector: u8) {
+    let lapic = interrupts::local_apic();
+    let command = vector as u32;
+
+    // The shorthands also reach CPUs that aren't online, which may not be ready for an interrupt.
+    let shorthand = match target {
+        Target::Cpu(_) => None,
+        Target::All => Some(icr::SHORTHAND_ALL),
+        Target::AllButSelf => Some(icr::SHORTHAND_ALL_BUT_SELF),
+    };
+    if let Some(shorthand) = shorthand.filter(|_| cpus().iter().all(Cpu::is_online)) {
+        unsafe {
+            // SAFETY: A fixed interrupt only runs the handler for its vector.
+            lapic.send_ipi(0, command | shorthand);
+        }
+        return;
+    }
+
+    for cpu in target.cpus() {
+        unsafe {
+            // SAFETY: As above.
+            lapic.send_ipi(cpu.apic_id, command);
+        }
+    }
+}
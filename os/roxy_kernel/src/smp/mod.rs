@@ -0,0 +1,251 @@
+//! Symmetric multiprocessing: finds the processors in the MADT and starts the application
+//! processors (APs).
+//!
+//! Each AP is started with the INIT-SIPI-SIPI sequence into a [trampoline](trampoline) below
+//! 1 MiB, which enters long mode and calls [`ap_entry`] on a kernel stack the BSP allocated for
+//! it. APs are started one at a time, since they share the trampoline.
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr0Flags, Cr3, Cr3Flags, Cr4, Cr4Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{PageTable, PageTableFlags, PhysFrame},
+    PhysAddr,
+};
+
+use crate::{
+    acpi, boot, deferred,
+    interrupts::{self, lapic::icr},
+    sched, time, vmm,
+};
+
+pub mod call;
+mod cpu_set;
+pub mod ipi;
+pub mod percpu;
+mod trampoline;
+
+pub use cpu_set::{CpuSet, MAX_CPUS};
+
+use trampoline::Trampoline;
+
+const AP_STACK_PAGES: u64 = 16;
+/// How long to wait for an AP to reach [`ap_entry`] after the second startup IPI.
+const STARTUP_TIMEOUT: Duration = Duration::from_millis(100);
+/// How long to wait for an AP to come online once it has reached [`ap_entry`].
+const ONLINE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A processor listed in the MADT.
+#[derive(Debug)]
+pub struct Cpu {
+    /// Roxy's own number for the CPU. The BSP is 0, and the APs follow in MADT order.
+    pub id: usize,
+    pub apic_id: u32,
+    online: AtomicBool,
+}
+
+impl Cpu {
+    fn new(id: usize, apic_id: u32) -> Self {
+        Self {
+            id,
+            apic_id,
+            online: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the CPU has been started and is running kernel code.
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+}
+
+/// The BSP's control registers, which each AP copies once it's in long mode.
+struct ControlRegisters {
+    cr0: Cr0Flags,
+    cr3: (PhysFrame, Cr3Flags),
+    cr4: Cr4Flags,
+    efer: EferFlags,
+}
+
+static CPUS: OnceCell<Vec<Cpu>> = OnceCell::uninit();
+static CONTROL_REGISTERS: OnceCell<ControlRegisters> = OnceCell::uninit();
+/// Set by an AP once it's done with the trampoline.
+static AP_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Starts every enabled processor in the MADT.
+///
+/// Must be called on the BSP, once interrupts and timekeeping are initialized.
+pub fn init() {
+    let madt = acpi::madt().expect("firmware to provide a MADT");
+    let bsp = interrupts::local_apic().id();
+
+    let cpus = CPUS.get_or_init(|| {
+        let aps = madt
+            .processors
+            .iter()
+            .filter(|p| p.enabled && p.apic_id != bsp)
+            .map(|p| p.apic_id);
+        let cpus = core::iter::once(bsp).chain(aps);
+        let found = cpus.clone().count();
+        if found > MAX_CPUS {
+            log::warn!("SMP: only using {} of {} CPUs", MAX_CPUS, found);
+        }
+        cpus.take(MAX_CPUS)
+            .enumerate()
+            .map(|(id, apic_id)| Cpu::new(id, apic_id))
+            .collect()
+    });
+    cpus[0].online.store(true, Ordering::Release);
+    call::init(cpus.len());
+    deferred::init(cpus.len());
+    sched::init(cpus.len());
+
+    if cpus.len() > 1 {
+        start_aps(&cpus[1..]);
+    }
+    log::info!("SMP: {} of {} CPUs online", online_count(), cpus.len());
+}
+
+fn start_aps(aps: &[Cpu]) {
+    CONTROL_REGISTERS.init_once(|| ControlRegisters {
+        cr0: Cr0::read(),
+        cr3: Cr3::read(),
+        cr4: Cr4::read(),
+        efer: Efer::read(),
+    });
+
+    let mut vmm = vmm::get();
+    let frame = vmm
+        .allocate_frame_below(PhysAddr::new(0x10_0000))
+        .expect("a free frame below 1 MiB for the AP trampoline");
+    let page_table = vmm
+        .allocate_frame_below(PhysAddr::new(0x1_0000_0000))
+        .expect("a free frame below 4 GiB for the AP page table");
+    let (start, end) = (frame.start_address(), frame.start_address() + frame.size());
+    unsafe {
+        // SAFETY: The frame is ours, and nothing else is mapped in the lower half.
+        vmm.identity_map(
+            start,
+            end,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+        )
+    }
+    .expect("to be able to identity map the AP trampoline");
+    drop(vmm);
+
+    let trampoline = unsafe {
+        // SAFETY: The active PML4 maps the kernel and, now, the trampoline, and the copy shares
+        // all of its lower level tables.
+        let active = Cr3::read().0.start_address();
+        let copy = vmm::phys_to_virt(page_table.start_address()).as_mut_ptr::<PageTable>();
+        *copy = (*vmm::phys_to_virt(active).as_ptr::<PageTable>()).clone();
+        Trampoline::install(frame, page_table, ap_entry)
+    };
+
+    for cpu in aps {
+        if !start_ap(&trampoline, cpu) {
+            // It might still turn up later, and it'll expect the trampoline to be unchanged.
+            log::warn!("CPU {} (APIC {}) did not start", cpu.id, cpu.apic_id);
+            break;
+        }
+    }
+
+    // Both frames are kept for good, as an AP that never started could still wake up in them.
+    let (start, end) = trampoline.range();
+    unsafe { vmm::get().unmap_identity(start, end) };
+}
+
+/// Starts one AP, and waits for it to come online.
+fn start_ap(trampoline: &Trampoline, cpu: &Cpu) -> bool {
+    let stack = vmm::get()
+        .allocate_kernel_stack(AP_STACK_PAGES)
+        .expect("to be able to allocate an AP stack");
+    trampoline.prepare(stack.top(), cpu.id);
+    AP_STARTED.store(false, Ordering::Release);
+
+    let lapic = interrupts::local_apic();
+    unsafe {
+        // SAFETY: The AP hasn't been started, so it isn't running anything that INIT could break.
+        lapic.send_ipi(cpu.apic_id, icr::DELIVERY_INIT | icr::LEVEL_ASSERT);
+    }
+    time::spin_wait(Duration::from_millis(10));
+
+    // The second startup IPI is only for CPUs that missed the first, and is ignored by the rest.
+    let timeouts = [Duration::from_micros(200), STARTUP_TIMEOUT];
+    let started = timeouts.into_iter().any(|timeout| {
+        unsafe {
+            // SAFETY: As above, and the trampoline has been prepared for this AP.
+            lapic.send_ipi(
+                cpu.apic_id,
+                icr::DELIVERY_STARTUP | trampoline.vector() as u32,
+            );
+        }
+        spin_until(timeout, || AP_STARTED.load(Ordering::Acquire))
+    });
+    if !started {
+        return false;
+    }
+
+    // It can still panic or hang setting itself up.
+    if !spin_until(ONLINE_TIMEOUT, || cpu.is_online()) {
+        log::warn!(
+            "CPU {} (APIC {}) started but never came online",
+            cpu.id,
+            cpu.apic_id
+        );
+        return false;
+    }
+    true
+}
+
+/// Spins until `condition` holds, or `timeout` has passed. Returns whether it held.
+fn spin_until(timeout: Duration, condition: impl Fn() -> bool) -> bool {
+    let deadline = time::now() + timeout;
+    while time::now() < deadline {
+        if condition() {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+/// Where the trampoline hands over to Rust, still on its copy of the page table.
+extern "C" fn ap_entry(cpu: usize) -> ! {
+    let registers = CONTROL_REGISTERS
+        .get()
+        .expect("APs to be started after the BSP saved its control registers");
+    unsafe {
+        // SAFETY: These are the BSP's settings, which the kernel already runs under.
+        let (frame, flags) = registers.cr3;
+        Cr3::write(frame, flags);
+        Cr4::write(registers.cr4);
+        Cr0::write(registers.cr0);
+        Efer::write(registers.efer);
+    }
+    AP_STARTED.store(true, Ordering::Release);
+
+    boot::ap_main(cpu)
+}
+
+/// Marks the calling AP as online, letting the BSP start the next one.
+pub(crate) fn set_online(cpu: usize) {
+    cpus()[cpu].online.store(true, Ordering::Release);
+}
+
+/// Every processor found in the MADT, whether or not it's online. Empty until [`init`] has run.
+pub fn cpus() -> &'static [Cpu] {
+    CPUS.get().map_or(&[], Vec::as_slice)
+}
+
+/// The number of CPUs that are online.
+pub fn online_count() -> usize {
+    cpus().iter().filter(|cpu| cpu.is_online()).count()
+}
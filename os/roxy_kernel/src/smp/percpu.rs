@@ -0,0 +1,313 @@
+//! Per-CPU data, found through the GS base.
+//!
+//! Variables declared with [`percpu!`](crate::percpu) are linked into the `percpu` section, which
+//! serves as a template: each CPU gets its own copy of the section, and finds it through a small
+//! header at its GS base. The header also holds the fields that need to be reachable with a single
+//! `gs:` access, like the CPU number and the current task.
+//!
+//! In the kernel, `IA32_GS_BASE` points at the header. Once there is user space, `swapgs` on entry
+//! and exit trades it with `IA32_KERNEL_GS_BASE`, which holds user space's GS base while the
+//! kernel runs.
+
+use alloc::alloc::{alloc, handle_alloc_error, Layout};
+use core::{arch::asm, cell::UnsafeCell, mem::offset_of, ptr, slice};
+use x86_64::{
+    instructions::{interrupts::without_interrupts, segmentation::GS},
+    registers::model_specific::{GsBase, KernelGsBase},
+    structures::idt::InterruptStackFrame,
+    PrivilegeLevel, VirtAddr,
+};
+
+use crate::user::USER_SPACE_END;
+
+/// The number of scratch slots, for code that needs somewhere to put a register before it has a
+/// stack.
+pub const SCRATCH_SLOTS: usize = 4;
+
+/// The offset of the scratch slots from the GS base, for assembly.
+pub const SCRATCH_OFFSET: usize = offset_of!(Header, scratch);
+
+/// The BSP's copy of the variables is static, so it can be used before there's a heap.
+const BSP_VARIABLES_SIZE: usize = 0x1000;
+
+#[repr(C, align(64))]
+struct Header {
+    cpu_id: usize,
+    current_task: *mut (),
+    /// This CPU's copy of the `percpu` section.
+    variables: *mut u8,
+    scratch: [u64; SCRATCH_SLOTS],
+}
+
+#[repr(C)]
+struct BspArea {
+    header: Header,
+    variables: [u8; BSP_VARIABLES_SIZE],
+}
+
+static mut BSP_AREA: BspArea = BspArea {
+    header: Header {
+        cpu_id: 0,
+        current_task: ptr::null_mut(),
+        variables: ptr::null_mut(),
+        scratch: [0; SCRATCH_SLOTS],
+    },
+    variables: [0; BSP_VARIABLES_SIZE],
+};
+
+extern "C" {
+    static __start_percpu: u8;
+    static __stop_percpu: u8;
+}
+
+/// The initial values of every per-CPU variable. Nothing writes to it, it's only copied.
+fn template() -> &'static [u8] {
+    let start = &raw const __start_percpu;
+    let len = &raw const __stop_percpu as usize - start as usize;
+    unsafe { slice::from_raw_parts(start, len) }
+}
+
+/// Declares per-CPU variables.
+///
+/// ```ignore
+/// percpu! {
+///     static TICKS: Cell<u64> = Cell::new(0);
+/// }
+/// ```
+///
+/// Each variable is a [`PerCpu`], and the initializer must be a constant expression.
+#[macro_export]
+macro_rules! percpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            #[link_section = "percpu"]
+            $vis static $name: $crate::smp::percpu::PerCpu<$ty> =
+                $crate::smp::percpu::PerCpu::new($init);
+        )*
+    };
+}
+
+/// A variable with a separate copy for each CPU. Declare these with [`percpu!`](crate::percpu).
+///
+/// The static itself is only the template the copies start from.
+pub struct PerCpu<T> {
+    template: UnsafeCell<T>,
+}
+
+// SAFETY: Each CPU only ever accesses its own copy.
+unsafe impl<T> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    #[doc(hidden)]
+    pub const fn new(value: T) -> Self {
+        Self {
+            template: UnsafeCell::new(value),
+        }
+    }
+
+    /// Runs `f` with the calling CPU's copy, with interrupts disabled so that nothing else can get
+    /// at it in the meantime.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        without_interrupts(|| f(unsafe { self.current() }))
+    }
+
+    /// The calling CPU's copy.
+    ///
+    /// # Safety
+    ///
+    /// Interrupts must stay disabled for as long as the reference is used, and this CPU's per-CPU
+    /// area must have been set up.
+    pub unsafe fn current(&self) -> &T {
+        let offset = self.template.get() as usize - &raw const __start_percpu as usize;
+        let variables: *mut u8;
+        unsafe {
+            asm!(
+                "mov {}, gs:[{}]",
+                out(reg) variables,
+                const offset_of!(Header, variables),
+                options(nostack, preserves_flags, readonly)
+            );
+            &*variables.add(offset).cast::<T>()
+        }
+    }
+}
+
+/// Sets up the BSP's per-CPU area. Must be the first thing the BSP does, as even logging uses it.
+pub fn init_bsp() {
+    let template = template();
+    assert!(
+        template.len() <= BSP_VARIABLES_SIZE,
+        "per-CPU variables to fit in the BSP's area"
+    );
+    unsafe {
+        // SAFETY: Nothing else has touched the area yet, and only the BSP ever will.
+        let area = &raw mut BSP_AREA;
+        let variables = (&raw mut (*area).variables).cast::<u8>();
+        ptr::copy_nonoverlapping(template.as_ptr(), variables, template.len());
+        (*area).header.variables = variables;
+        load(&raw mut (*area).header);
+    }
+}
+
+/// Allocates and sets up the calling AP's per-CPU area.
+pub fn init_ap(cpu: usize) {
+    let template = template();
+    let layout =
+        Layout::from_size_align(size_of::<Header>() + template.len(), align_of::<Header>())
+            .expect("per-CPU area to have a valid layout");
+    unsafe {
+        // SAFETY: The layout has a non-zero size, and the area is never freed.
+        let area = alloc(layout);
+        if area.is_null() {
+            handle_alloc_error(layout);
+        }
+        let header = area.cast::<Header>();
+        let variables = area.add(size_of::<Header>());
+        ptr::copy_nonoverlapping(template.as_ptr(), variables, template.len());
+        header.write(Header {
+            cpu_id: cpu,
+            current_task: ptr::null_mut(),
+            variables,
+            scratch: [0; SCRATCH_SLOTS],
+        });
+        load(header);
+    }
+}
+
+unsafe fn load(header: *mut Header) {
+    GsBase::write(VirtAddr::from_ptr(header));
+    KernelGsBase::write(VirtAddr::zero());
+}
+
+/// The number of the calling CPU, as in [`Cpu::id`](super::Cpu::id).
+pub fn current_id() -> usize {
+    let id;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{}]",
+            out(reg) id,
+            const offset_of!(Header, cpu_id),
+            options(nostack, preserves_flags, readonly)
+        );
+    }
+    id
+}
+
+/// The task running on the calling CPU, or null if there isn't one yet.
+pub fn current_task() -> *mut () {
+    let task;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{}]",
+            out(reg) task,
+            const offset_of!(Header, current_task),
+            options(nostack, preserves_flags, readonly)
+        );
+    }
+    task
+}
+
+/// Records the task running on the calling CPU.
+///
+/// # Safety
+///
+/// `task` must really be about to run on this CPU, as everything that looks at the current task
+/// trusts it.
+pub unsafe fn set_current_task(task: *mut ()) {
+    unsafe {
+        asm!(
+            "mov gs:[{}], {}",
+            const offset_of!(Header, current_task),
+            in(reg) task,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Reads one of the calling CPU's scratch slots.
+pub fn scratch(slot: usize) -> u64 {
+    assert!(slot < SCRATCH_SLOTS, "scratch slot out of range");
+    let value;
+    unsafe {
+        asm!(
+            "mov {}, gs:[{} + 8 * {}]",
+            out(reg) value,
+            const SCRATCH_OFFSET,
+            in(reg) slot,
+            options(nostack, preserves_flags, readonly)
+        );
+    }
+    value
+}
+
+/// Writes one of the calling CPU's scratch slots.
+pub fn set_scratch(slot: usize, value: u64) {
+    assert!(slot < SCRATCH_SLOTS, "scratch slot out of range");
+    unsafe {
+        asm!(
+            "mov gs:[{} + 8 * {}], {}",
+            const SCRATCH_OFFSET,
+            in(reg) slot,
+            in(reg) value,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Switches to the kernel's GS base at the start of an interrupt or exception handler, if it
+/// interrupted user space. Must come before anything that uses per-CPU data. Handlers on IST stacks
+/// use [`swapgs_on_ist_entry`] instead.
+///
+/// # Safety
+///
+/// Must be called once, first thing in the handler, with the frame it was entered with.
+pub unsafe fn swapgs_on_entry(stack_frame: &InterruptStackFrame) {
+    if stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3 {
+        unsafe { GS::swap() };
+    }
+}
+
+/// Switches back to user space's GS base at the end of a handler that called [`swapgs_on_entry`],
+/// if it's returning to user space. Nothing may use per-CPU data after it.
+///
+/// # Safety
+///
+/// Must be called once, last thing in the handler, with interrupts disabled.
+pub unsafe fn swapgs_on_exit(stack_frame: &InterruptStackFrame) {
+    if stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3 {
+        unsafe { GS::swap() };
+    }
+}
+
+/// Switches to the kernel's GS base at the start of a handler on an IST stack, like the NMI and
+/// machine check handlers, if it isn't loaded already. Returns whether it switched, for
+/// [`swapgs_on_ist_exit`].
+///
+/// These can arrive in the kernel between `syscall` and the `swapgs` after it, or between a
+/// `swapgs` and the `sysretq` or `iretq` it goes with, where the interrupted code segment is the
+/// kernel's but the GS base is user space's. So this goes by the GS base itself, which is only
+/// ever in the kernel's half while the kernel's is loaded.
+///
+/// # Safety
+///
+/// Must be called once, first thing in the handler.
+pub unsafe fn swapgs_on_ist_entry() -> bool {
+    let swap = GsBase::read().as_u64() < USER_SPACE_END;
+    if swap {
+        unsafe { GS::swap() };
+    }
+    swap
+}
+
+/// Switches back to the GS base a handler that called [`swapgs_on_ist_entry`] interrupted, if
+/// `swapped` says that one switched. Nothing may use per-CPU data after it.
+///
+/// # Safety
+///
+/// Must be called once, last thing in the handler, with what [`swapgs_on_ist_entry`] returned.
+pub unsafe fn swapgs_on_ist_exit(swapped: bool) {
+    if swapped {
+        unsafe { GS::swap() };
+    }
+}
@@ -0,0 +1,186 @@
+//! The real-mode code an application processor starts in.
+//!
+//! The startup IPI starts the AP at the beginning of a page below 1 MiB, with `CS` pointing at the
+//! page and paging off. The trampoline goes straight from real mode to long mode, which only
+//! needs a PML4 that can be loaded with 32 bits and an identity mapping of the trampoline itself,
+//! then calls the entry point on the stack the BSP left for it.
+
+use core::{arch::global_asm, ptr};
+use x86_64::{structures::paging::PhysFrame, PhysAddr, VirtAddr};
+
+use crate::vmm;
+
+global_asm!(
+    r#"
+    .pushsection .text.ap_trampoline, "ax"
+    .global ap_trampoline_start
+    .global ap_trampoline_data
+    .global ap_trampoline_end
+
+    .code16
+ap_trampoline_start:
+    cli
+    cld
+    mov %cs, %ax
+    mov %ax, %ds
+
+    # CR4.PAE
+    mov %cr4, %eax
+    or $(1 << 5), %eax
+    mov %eax, %cr4
+
+    mov (ap_trampoline_page_table - ap_trampoline_start), %eax
+    mov %eax, %cr3
+
+    # IA32_EFER.LME and IA32_EFER.NXE, since the kernel's mappings use the NX bit.
+    mov $0xC0000080, %ecx
+    rdmsr
+    or $((1 << 8) | (1 << 11)), %eax
+    wrmsr
+
+    lgdtl (ap_trampoline_gdtr - ap_trampoline_start)
+
+    # CR0.PG and CR0.PE together, which drops us into compatibility mode.
+    mov %cr0, %eax
+    or $0x80000001, %eax
+    mov %eax, %cr0
+
+    ljmpl *(ap_trampoline_jump - ap_trampoline_start)
+
+    .code64
+ap_trampoline_long_mode:
+    xor %eax, %eax
+    mov %ax, %ds
+    mov %ax, %es
+    mov %ax, %ss
+    mov %ax, %fs
+    mov %ax, %gs
+
+    mov ap_trampoline_stack(%rip), %rsp
+    mov ap_trampoline_cpu(%rip), %rdi
+    mov ap_trampoline_entry(%rip), %rax
+    call *%rax
+    ud2
+
+    .balign 8
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00AF9A000000FFFF
+    .quad 0x00CF92000000FFFF
+ap_trampoline_gdt_end:
+
+    # Must match `TrampolineData`.
+    .balign 8
+ap_trampoline_data:
+    .word 0
+ap_trampoline_gdtr:
+    .word ap_trampoline_gdt_end - ap_trampoline_gdt - 1
+    .long ap_trampoline_gdt - ap_trampoline_start
+ap_trampoline_jump:
+    .long ap_trampoline_long_mode - ap_trampoline_start
+    .word 0x08
+    .word 0
+ap_trampoline_page_table:
+    .quad 0
+ap_trampoline_stack:
+    .quad 0
+ap_trampoline_entry:
+    .quad 0
+ap_trampoline_cpu:
+    .quad 0
+ap_trampoline_end:
+
+    .popsection
+    "#,
+    options(att_syntax)
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// The variables at the end of the trampoline.
+///
+/// The GDT base and the long mode entry point are assembled as offsets from the start of the
+/// trampoline, and have to be relocated once it's copied.
+#[repr(C)]
+struct TrampolineData {
+    _padding: u16,
+    gdt_limit: u16,
+    gdt_base: u32,
+    long_mode: u32,
+    code_selector: u16,
+    _padding2: u16,
+    page_table: u64,
+    stack: u64,
+    entry: u64,
+    cpu: u64,
+}
+
+/// An installed copy of the trampoline.
+pub struct Trampoline {
+    frame: PhysFrame,
+}
+
+impl Trampoline {
+    /// Copies the trampoline into `frame`, which enters long mode with the PML4 in `page_table`
+    /// and calls `entry` with the CPU number.
+    ///
+    /// # Safety
+    ///
+    /// `frame` must be an otherwise unused frame below 1 MiB, and `page_table` a PML4 below 4 GiB
+    /// that identity maps `frame` and maps the kernel.
+    pub unsafe fn install(
+        frame: PhysFrame,
+        page_table: PhysFrame,
+        entry: extern "C" fn(usize) -> !,
+    ) -> Self {
+        let start = &raw const ap_trampoline_start;
+        let len = &raw const ap_trampoline_end as usize - start as usize;
+        let base = frame.start_address().as_u64() as u32;
+
+        let trampoline = Self { frame };
+        unsafe {
+            ptr::copy_nonoverlapping(start, trampoline.address(start).as_mut_ptr(), len);
+            let data = trampoline.data();
+            (*data).gdt_base += base;
+            (*data).long_mode += base;
+            (*data).page_table = page_table.start_address().as_u64();
+            (*data).entry = entry as usize as u64;
+        }
+        trampoline
+    }
+
+    /// The startup IPI vector that starts a CPU in the trampoline.
+    pub fn vector(&self) -> u8 {
+        (self.frame.start_address().as_u64() >> 12) as u8
+    }
+
+    /// The physical range the trampoline occupies.
+    pub fn range(&self) -> (PhysAddr, PhysAddr) {
+        let start = self.frame.start_address();
+        (start, start + self.frame.size())
+    }
+
+    /// Sets the stack and CPU number the next CPU to start will use.
+    pub fn prepare(&self, stack: VirtAddr, cpu: usize) {
+        unsafe {
+            // SAFETY: No CPU is running the trampoline while it's being prepared.
+            let data = self.data();
+            ptr::write_volatile(&raw mut (*data).stack, stack.as_u64());
+            ptr::write_volatile(&raw mut (*data).cpu, cpu as u64);
+        }
+    }
+
+    /// Where `symbol` in the trampoline was copied to.
+    fn address(&self, symbol: *const u8) -> VirtAddr {
+        let offset = symbol as usize - &raw const ap_trampoline_start as usize;
+        vmm::phys_to_virt(self.frame.start_address()) + offset as u64
+    }
+
+    fn data(&self) -> *mut TrampolineData {
+        self.address(&raw const ap_trampoline_data).as_mut_ptr()
+    }
+}
@@ -0,0 +1,60 @@
+use super::{MutexGuard, WaitQueue};
+use crate::sched;
+
+/// Lets threads sleep until a condition on the data behind a [`Mutex`](super::Mutex) holds.
+///
+/// Waking up doesn't mean the condition holds, so waiters check it again, as
+/// [`wait_while`](Self::wait_while) does.
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlocks the mutex and sleeps until notified, then locks it again.
+    #[track_caller]
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        sched::might_sleep();
+        let mutex = guard.mutex();
+        // Unlocked once the thread is on the queue, so a notification can't be missed in between.
+        self.waiters.sleep_unless(|| {
+            drop(guard);
+            false
+        });
+        mutex.lock()
+    }
+
+    /// Sleeps for as long as `condition` returns true, checking it each time the thread is
+    /// notified.
+    #[track_caller]
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wakes the thread that has waited longest.
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,20 @@
+//! Locks and other synchronization primitives.
+//!
+//! [`SpinlockIrq`] busy-waits, and can be taken anywhere, including in interrupt handlers. The
+//! rest put the calling thread to sleep while it waits, so they can only be used where a thread may
+//! sleep: not in interrupt context, and not while holding a `SpinlockIrq`. Debug builds check
+//! this with [`might_sleep`](crate::sched::might_sleep).
+
+mod condvar;
+mod mutex;
+mod rwlock;
+mod semaphore;
+mod spinlock_irq;
+mod wait_queue;
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use spinlock_irq::{SpinlockIrq, SpinlockIrqGuard};
+pub use wait_queue::WaitQueue;
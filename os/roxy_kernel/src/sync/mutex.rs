@@ -0,0 +1,257 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    cell::UnsafeCell,
+    cmp::Reverse,
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::SpinlockIrq;
+use crate::{
+    sched,
+    thread::{self, Thread},
+    time::{self, Instant},
+};
+
+/// A lock that puts threads to sleep while they wait for it.
+///
+/// Waiters get the lock in order of real-time priority, then in the order they came. While a
+/// real-time thread waits, the owner inherits its priority, so that threads of a priority in
+/// between can't keep the owner, and so the waiter, from running. Only the owner is boosted: if it
+/// is itself waiting for another mutex, that mutex's owner isn't.
+pub struct Mutex<T: ?Sized> {
+    state: SpinlockIrq<State>,
+    data: UnsafeCell<T>,
+}
+
+struct State {
+    owner: Option<Arc<Thread>>,
+    waiters: VecDeque<Arc<Thread>>,
+}
+
+impl State {
+    /// Takes the waiter that gets the mutex next off the queue.
+    fn take_next_waiter(&mut self) -> Option<Arc<Thread>> {
+        let next = (0..self.waiters.len())
+            .max_by_key(|&i| (self.waiters[i].policy().rt_priority(), Reverse(i)))?;
+        self.waiters.remove(next)
+    }
+
+    /// Takes a waiter that's timed out off the queue, and takes back the priority the owner
+    /// inherited from it.
+    fn give_up(&mut self, waiter: &Arc<Thread>, mutex: usize) {
+        self.waiters.retain(|other| !Arc::ptr_eq(other, waiter));
+        let Some(owner) = &self.owner else {
+            return;
+        };
+        sched::restore_priority(owner, mutex);
+        if let Some(priority) = self.waiting_priority() {
+            sched::inherit_priority(owner, mutex, priority);
+        }
+    }
+
+    /// The highest real-time priority of any waiter.
+    fn waiting_priority(&self) -> Option<u8> {
+        self.waiters
+            .iter()
+            .filter_map(|waiter| waiter.policy().rt_priority())
+            .max()
+    }
+}
+
+// SAFETY: The data is only reachable through a guard, and only one guard exists at a time.
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: SpinlockIrq::new(State {
+                owner: None,
+                waiters: VecDeque::new(),
+            }),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Sleeps until the lock is free, and takes it.
+    ///
+    /// Panics if the calling thread already holds it.
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.lock_until(None)
+            .expect("lock without a deadline to be taken")
+    }
+
+    /// Sleeps until the lock is free, and takes it, unless `timeout` passes first.
+    ///
+    /// Panics if the calling thread already holds it.
+    #[track_caller]
+    pub fn try_lock_for(&self, timeout: Duration) -> Option<MutexGuard<'_, T>> {
+        self.lock_until(Some(time::now() + timeout))
+    }
+
+    /// Sleeps until the lock is free, and takes it, unless the monotonic clock reaches `deadline`
+    /// first.
+    ///
+    /// Panics if the calling thread already holds it.
+    #[track_caller]
+    pub fn try_lock_until(&self, deadline: Instant) -> Option<MutexGuard<'_, T>> {
+        self.lock_until(Some(deadline))
+    }
+
+    #[track_caller]
+    fn lock_until(&self, deadline: Option<Instant>) -> Option<MutexGuard<'_, T>> {
+        sched::might_sleep();
+        let current = thread::current();
+        let owner = self.state.lock().owner.clone();
+        assert!(
+            !owner.is_some_and(|owner| Arc::ptr_eq(&owner, &current)),
+            "thread {} tried to lock a mutex it already holds",
+            current.id()
+        );
+
+        let timer = deadline.map(|deadline| sched::wake_at(current.clone(), deadline));
+        let locked = without_interrupts(|| loop {
+            let mut state = self.state.lock();
+            let Some(owner) = &state.owner else {
+                state.owner = Some(current.clone());
+                break true;
+            };
+            if Arc::ptr_eq(owner, &current) {
+                // Handed over by the last owner.
+                break true;
+            }
+            if deadline.is_some_and(|deadline| time::now() >= deadline) {
+                state.give_up(&current, self.addr());
+                break false;
+            }
+            if let Some(priority) = current.policy().rt_priority() {
+                sched::inherit_priority(owner, self.addr(), priority);
+            }
+            if !state
+                .waiters
+                .iter()
+                .any(|waiter| Arc::ptr_eq(waiter, &current))
+            {
+                state.waiters.push_back(current.clone());
+            }
+            sched::prepare_to_block();
+            drop(state);
+            sched::block();
+        });
+        if let Some(timer) = timer {
+            time::cancel_timer(timer);
+        }
+        locked.then(|| MutexGuard::new(self))
+    }
+
+    /// Takes the lock if it's free.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let mut state = self.state.lock();
+        if state.owner.is_some() {
+            return None;
+        }
+        state.owner = Some(thread::current());
+        Some(MutexGuard::new(self))
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.state.lock().owner.is_some()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Identifies the mutex to the threads that inherit priority through it.
+    fn addr(&self) -> usize {
+        (self as *const Self).cast::<()>() as usize
+    }
+
+    /// Hands the lock over to the next waiter, if there is one, or frees it.
+    fn unlock(&self) {
+        let mut state = self.state.lock();
+        if let Some(owner) = state.owner.take() {
+            sched::restore_priority(&owner, self.addr());
+        }
+        let Some(next) = state.take_next_waiter() else {
+            return;
+        };
+        if let Some(priority) = state.waiting_priority() {
+            sched::inherit_priority(&next, self.addr(), priority);
+        }
+        state.owner = Some(next.clone());
+        drop(state);
+        sched::wake(&next);
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
+            None => f.write_str("Mutex { <locked> }"),
+        }
+    }
+}
+
+/// Holds a [`Mutex`] until it's dropped. Must be dropped by the thread that took it.
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+// SAFETY: Sharing the guard only shares the data.
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    fn new(mutex: &'a Mutex<T>) -> Self {
+        Self {
+            mutex,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// The mutex this guard holds.
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The guard holds the lock.
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The guard holds the lock.
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
@@ -0,0 +1,204 @@
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+use super::{SpinlockIrq, WaitQueue};
+
+/// A lock that any number of readers can hold at once, or one writer, with threads sleeping while
+/// they wait for it.
+///
+/// Writers go first: once one is waiting, new readers wait behind it.
+pub struct RwLock<T: ?Sized> {
+    state: SpinlockIrq<State>,
+    readers: WaitQueue,
+    writers: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+struct State {
+    readers: usize,
+    writer: bool,
+    writers_waiting: usize,
+}
+
+// SAFETY: Readers share the data between threads, and writers send it between them.
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: SpinlockIrq::new(State {
+                readers: 0,
+                writer: false,
+                writers_waiting: 0,
+            }),
+            readers: WaitQueue::new(),
+            writers: WaitQueue::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Sleeps until there's no writer, or writer waiting, and takes a read lock.
+    #[track_caller]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.readers.wait_until(|| self.try_lock_read());
+        RwLockReadGuard { lock: self }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.try_lock_read().then(|| RwLockReadGuard { lock: self })
+    }
+
+    /// Sleeps until nothing else holds the lock, and takes a write lock.
+    #[track_caller]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        if let Some(guard) = self.try_write() {
+            return guard;
+        }
+        self.state.lock().writers_waiting += 1;
+        self.writers.wait_until(|| {
+            let mut state = self.state.lock();
+            let free = state.readers == 0 && !state.writer;
+            if free {
+                state.writer = true;
+                state.writers_waiting -= 1;
+            }
+            free
+        });
+        RwLockWriteGuard { lock: self }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let mut state = self.state.lock();
+        if state.readers != 0 || state.writer {
+            return None;
+        }
+        state.writer = true;
+        Some(RwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn try_lock_read(&self) -> bool {
+        let mut state = self.state.lock();
+        let free = !state.writer && state.writers_waiting == 0;
+        if free {
+            state.readers += 1;
+        }
+        free
+    }
+
+    fn unlock_read(&self) {
+        let mut state = self.state.lock();
+        state.readers -= 1;
+        let wake_writer = state.readers == 0 && state.writers_waiting > 0;
+        drop(state);
+        if wake_writer {
+            self.writers.wake_one();
+        }
+    }
+
+    fn unlock_write(&self) {
+        let mut state = self.state.lock();
+        state.writer = false;
+        let writers_waiting = state.writers_waiting > 0;
+        drop(state);
+        if writers_waiting {
+            self.writers.wake_one();
+        } else {
+            self.readers.wake_all();
+        }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => f.debug_struct("RwLock").field("data", &&*guard).finish(),
+            None => f.write_str("RwLock { <locked> }"),
+        }
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The guard holds a read lock, so nothing is writing.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock_read();
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The guard holds the write lock.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The guard holds the write lock.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock_write();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn shares_between_readers_but_not_with_a_writer() {
+        let lock = RwLock::new(1);
+        let first = lock.try_read().unwrap();
+        let second = lock.try_read().unwrap();
+        assert_eq!(2, *first + *second);
+        assert!(lock.try_write().is_none());
+
+        drop((first, second));
+        let mut writer = lock.try_write().unwrap();
+        *writer = 5;
+        assert!(lock.try_read().is_none());
+        drop(writer);
+        assert_eq!(5, *lock.try_read().unwrap());
+    }
+}
@@ -0,0 +1,69 @@
+use core::time::Duration;
+
+use super::{SpinlockIrq, WaitQueue};
+
+/// A count of permits, which threads sleep waiting for when there are none left.
+pub struct Semaphore {
+    permits: SpinlockIrq<usize>,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: SpinlockIrq::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Sleeps until there's a permit, and takes it.
+    #[track_caller]
+    pub fn acquire(&self) {
+        self.waiters.wait_until(|| self.try_acquire());
+    }
+
+    /// Sleeps until there's a permit, and takes it, unless `timeout` passes first. Returns whether
+    /// it took one.
+    #[track_caller]
+    pub fn acquire_timeout(&self, timeout: Duration) -> bool {
+        self.waiters
+            .wait_until_timeout(|| self.try_acquire(), timeout)
+    }
+
+    /// Takes a permit if there's one left.
+    pub fn try_acquire(&self) -> bool {
+        let mut permits = self.permits.lock();
+        let Some(left) = permits.checked_sub(1) else {
+            return false;
+        };
+        *permits = left;
+        true
+    }
+
+    /// Gives back a permit, waking a waiter if there is one.
+    pub fn release(&self) {
+        *self.permits.lock() += 1;
+        self.waiters.wake_one();
+    }
+
+    pub fn available_permits(&self) -> usize {
+        *self.permits.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn hands_out_each_permit_once() {
+        let semaphore = Semaphore::new(2);
+        assert!(semaphore.try_acquire());
+        assert!(semaphore.try_acquire());
+        assert!(!semaphore.try_acquire());
+
+        semaphore.release();
+        assert_eq!(1, semaphore.available_permits());
+        assert!(semaphore.try_acquire());
+    }
+}
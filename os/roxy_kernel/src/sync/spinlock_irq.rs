@@ -0,0 +1,263 @@
+use core::{
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+use spinning_top::{guard::SpinlockGuard, Spinlock};
+
+/// A spinlock that disables interrupts while it's held, so it can be shared with interrupt
+/// handlers. Preemption is disabled too, so the holder is never switched out.
+///
+/// Locking saves whether interrupts were enabled before disabling them, and dropping the guard
+/// unlocks and restores the interrupt flag. Guards for nested locks must be dropped in the reverse
+/// order they were taken, or interrupts come back on while a lock is still held.
+///
+/// With `debug_assertions`, taking a lock the calling CPU already holds panics instead of
+/// deadlocking, and the longest time the lock has been held for is recorded.
+pub struct SpinlockIrq<T: ?Sized> {
+    #[cfg(debug_assertions)]
+    debug: debug::LockDebug,
+    inner: Spinlock<T>,
+}
+
+impl<T> SpinlockIrq<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            #[cfg(debug_assertions)]
+            debug: debug::LockDebug::new(),
+            inner: Spinlock::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinlockIrq<T> {
+    /// Disables interrupts and spins until the lock is free.
+    pub fn lock(&self) -> SpinlockIrqGuard<'_, T> {
+        let were_enabled = enter_critical();
+        #[cfg(debug_assertions)]
+        self.debug.check_recursion();
+        let guard = self.inner.lock();
+        self.guard(guard, were_enabled)
+    }
+
+    /// Takes the lock if it's free, with interrupts disabled until the guard is dropped.
+    pub fn try_lock(&self) -> Option<SpinlockIrqGuard<'_, T>> {
+        let were_enabled = enter_critical();
+        match self.inner.try_lock() {
+            Some(guard) => Some(self.guard(guard, were_enabled)),
+            None => {
+                leave_critical(were_enabled);
+                None
+            }
+        }
+    }
+
+    fn guard<'a>(
+        &'a self,
+        guard: SpinlockGuard<'a, T>,
+        were_enabled: bool,
+    ) -> SpinlockIrqGuard<'a, T> {
+        SpinlockIrqGuard {
+            guard: ManuallyDrop::new(guard),
+            were_enabled,
+            #[cfg(debug_assertions)]
+            debug: self.debug.acquired(),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// A pointer to the protected value, for when the lock can't be taken but it's known that
+    /// nothing else is using the value, like after a panic.
+    pub fn data_ptr(&self) -> *mut T {
+        self.inner.data_ptr()
+    }
+
+    /// The longest the lock has been held for so far.
+    #[cfg(debug_assertions)]
+    pub fn max_hold_time(&self) -> core::time::Duration {
+        self.debug.max_hold_time()
+    }
+}
+
+impl<T: Default> Default for SpinlockIrq<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SpinlockIrq<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f
+                .debug_struct("SpinlockIrq")
+                .field("data", &&*guard)
+                .finish(),
+            None => f.write_str("SpinlockIrq { <locked> }"),
+        }
+    }
+}
+
+pub struct SpinlockIrqGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<SpinlockGuard<'a, T>>,
+    were_enabled: bool,
+    #[cfg(debug_assertions)]
+    debug: debug::Held<'a>,
+}
+
+impl<T: ?Sized> Deref for SpinlockIrqGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinlockIrqGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for SpinlockIrqGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        self.debug.release();
+        unsafe {
+            // SAFETY: The guard is never used again.
+            ManuallyDrop::drop(&mut self.guard);
+        }
+        leave_critical(self.were_enabled);
+    }
+}
+
+/// Disables interrupts and preemption, returning whether interrupts were enabled.
+fn enter_critical() -> bool {
+    // Host tests run in user mode, where `cli` faults, and have no scheduler.
+    #[cfg(not(test))]
+    {
+        use x86_64::instructions::interrupts;
+        let were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        crate::sched::preempt_disable();
+        were_enabled
+    }
+    #[cfg(test)]
+    false
+}
+
+/// Restores the interrupt flag, and then enables preemption again, which may switch threads.
+fn leave_critical(were_enabled: bool) {
+    if were_enabled {
+        x86_64::instructions::interrupts::enable();
+    }
+    #[cfg(not(test))]
+    crate::sched::preempt_enable();
+}
+
+#[cfg(debug_assertions)]
+mod debug {
+    use core::{
+        sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use crate::time::{self, Instant};
+
+    const NO_OWNER: usize = usize::MAX;
+
+    /// Tracks who holds a lock and for how long.
+    pub struct LockDebug {
+        owner: AtomicUsize,
+        max_hold_nanos: AtomicU64,
+    }
+
+    impl LockDebug {
+        pub const fn new() -> Self {
+            Self {
+                owner: AtomicUsize::new(NO_OWNER),
+                max_hold_nanos: AtomicU64::new(0),
+            }
+        }
+
+        /// Panics if the calling CPU already holds the lock, as waiting for it would never end.
+        pub fn check_recursion(&self) {
+            let cpu = current_cpu();
+            assert!(
+                self.owner.load(Ordering::Relaxed) != cpu,
+                "CPU {} tried to take a SpinlockIrq it already holds",
+                cpu
+            );
+        }
+
+        pub fn acquired(&self) -> Held<'_> {
+            self.owner.store(current_cpu(), Ordering::Relaxed);
+            Held {
+                lock: self,
+                since: time::now(),
+            }
+        }
+
+        pub fn max_hold_time(&self) -> Duration {
+            Duration::from_nanos(self.max_hold_nanos.load(Ordering::Relaxed))
+        }
+    }
+
+    pub struct Held<'a> {
+        lock: &'a LockDebug,
+        since: Instant,
+    }
+
+    impl Held<'_> {
+        /// Called just before the lock is released.
+        pub fn release(&self) {
+            let held = time::now().duration_since(self.since).as_nanos() as u64;
+            self.lock.max_hold_nanos.fetch_max(held, Ordering::Relaxed);
+            self.lock.owner.store(NO_OWNER, Ordering::Relaxed);
+        }
+    }
+
+    fn current_cpu() -> usize {
+        // Host tests have no per-CPU area, and only one "CPU".
+        #[cfg(not(test))]
+        return crate::smp::percpu::current_id();
+        #[cfg(test)]
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpinlockIrq;
+
+    #[test]
+    pub fn try_lock_fails_while_held() {
+        let lock = SpinlockIrq::new(1);
+        {
+            let mut guard = lock.lock();
+            *guard += 1;
+            assert!(lock.try_lock().is_none());
+        }
+        assert_eq!(Some(2), lock.try_lock().map(|guard| *guard));
+        assert_eq!(2, lock.into_inner());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "already holds")]
+    pub fn detects_recursive_locking() {
+        let lock = SpinlockIrq::new(());
+        let _guard = lock.lock();
+        let _again = lock.lock();
+    }
+}
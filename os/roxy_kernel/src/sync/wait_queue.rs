@@ -0,0 +1,120 @@
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::time::Duration;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::SpinlockIrq;
+use crate::{
+    sched,
+    thread::{self, Thread},
+    time::{self, Instant},
+};
+
+/// Threads sleeping until something they're waiting for happens.
+///
+/// Waiters check their condition once they're on the queue, and wakers change it before waking
+/// them, so a wakeup can't be missed between the check and going to sleep.
+pub struct WaitQueue {
+    waiters: SpinlockIrq<VecDeque<Arc<Thread>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinlockIrq::new(VecDeque::new()),
+        }
+    }
+
+    /// Sleeps until `condition` returns true. It's checked first, and again each time the calling
+    /// thread is woken.
+    ///
+    /// `condition` runs with interrupts disabled, so it should only look at some state and maybe
+    /// update it, like taking a count.
+    #[track_caller]
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        sched::might_sleep();
+        while !self.sleep_unless(&mut condition) {}
+    }
+
+    /// Like [`wait_until`](Self::wait_until), but gives up once `timeout` has passed. Returns
+    /// whether `condition` returned true.
+    #[track_caller]
+    pub fn wait_until_timeout(&self, condition: impl FnMut() -> bool, timeout: Duration) -> bool {
+        self.wait_until_deadline(condition, time::now() + timeout)
+    }
+
+    /// Like [`wait_until`](Self::wait_until), but gives up once the monotonic clock reaches
+    /// `deadline`. Returns whether `condition` returned true.
+    #[track_caller]
+    pub fn wait_until_deadline(
+        &self,
+        mut condition: impl FnMut() -> bool,
+        deadline: Instant,
+    ) -> bool {
+        sched::might_sleep();
+        let timer = sched::wake_at(thread::current(), deadline);
+        let met = loop {
+            let mut timed_out = false;
+            let done = self.sleep_unless(|| {
+                if condition() {
+                    return true;
+                }
+                timed_out = time::now() >= deadline;
+                timed_out
+            });
+            if done {
+                break !timed_out;
+            }
+        };
+        time::cancel_timer(timer);
+        met
+    }
+
+    /// Puts the calling thread on the queue and sleeps, unless `ready` returns true once it's on
+    /// the queue. Returns what `ready` returned.
+    pub(super) fn sleep_unless(&self, ready: impl FnOnce() -> bool) -> bool {
+        without_interrupts(|| {
+            let current = thread::current();
+            sched::prepare_to_block();
+            self.waiters.lock().push_back(current.clone());
+            let ready = ready();
+            if ready {
+                sched::cancel_block();
+            } else {
+                sched::block();
+            }
+            // Still queued if it didn't sleep, or something other than this queue woke it.
+            self.waiters
+                .lock()
+                .retain(|waiter| !Arc::ptr_eq(waiter, &current));
+            ready
+        })
+    }
+
+    /// Wakes the thread that has waited longest. Returns whether there was one.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let Some(waiter) = self.waiters.lock().pop_front() else {
+                return false;
+            };
+            if sched::wake(&waiter) {
+                return true;
+            }
+        }
+    }
+
+    /// Wakes every waiting thread, returning how many there were.
+    pub fn wake_all(&self) -> usize {
+        let waiters: Vec<_> = self.waiters.lock().drain(..).collect();
+        waiters.iter().filter(|waiter| sched::wake(waiter)).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,232 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    future::poll_fn,
+    task::{Poll, Waker},
+};
+
+use crate::sync::SpinlockIrq;
+
+/// Makes a channel that holds up to `capacity` values, for any number of senders and one
+/// receiver.
+///
+/// Sending without waiting works anywhere, including in interrupt handlers, so a channel can carry
+/// data from an interrupt handler to a task.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must be at least 1");
+    let shared = Arc::new(SpinlockIrq::new(Shared {
+        values: VecDeque::with_capacity(capacity),
+        capacity,
+        senders: 1,
+        receiver_alive: true,
+        receiver: None,
+        blocked_senders: VecDeque::new(),
+    }));
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+struct Shared<T> {
+    values: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receiver_alive: bool,
+    /// The receiver, while it waits for a value.
+    receiver: Option<Waker>,
+    /// Senders waiting for room.
+    blocked_senders: VecDeque<Waker>,
+}
+
+/// The value couldn't be sent because the receiver is gone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is full.
+    Full(T),
+    /// The receiver is gone.
+    Closed(T),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    /// The channel is empty, and every sender is gone.
+    Closed,
+}
+
+pub struct Sender<T> {
+    shared: Arc<SpinlockIrq<Shared<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Waits for room in the channel, and sends `value`.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut value = Some(value);
+        poll_fn(|cx| {
+            let unsent = value
+                .take()
+                .expect("send to not be polled after completing");
+            match self.try_send(unsent) {
+                Ok(()) => Poll::Ready(Ok(())),
+                Err(TrySendError::Closed(unsent)) => Poll::Ready(Err(SendError(unsent))),
+                Err(TrySendError::Full(unsent)) => {
+                    value = Some(unsent);
+                    let mut shared = self.shared.lock();
+                    // Room might have been made since trying.
+                    if shared.values.len() < shared.capacity || !shared.receiver_alive {
+                        drop(shared);
+                        cx.waker().wake_by_ref();
+                    } else if !shared
+                        .blocked_senders
+                        .iter()
+                        .any(|waker| waker.will_wake(cx.waker()))
+                    {
+                        shared.blocked_senders.push_back(cx.waker().clone());
+                    }
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Sends `value` if there's room for it.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut shared = self.shared.lock();
+        if !shared.receiver_alive {
+            return Err(TrySendError::Closed(value));
+        }
+        if shared.values.len() == shared.capacity {
+            return Err(TrySendError::Full(value));
+        }
+        shared.values.push_back(value);
+        let receiver = shared.receiver.take();
+        drop(shared);
+        if let Some(receiver) = receiver {
+            receiver.wake();
+        }
+        Ok(())
+    }
+
+    /// Whether the receiver has been dropped.
+    pub fn is_closed(&self) -> bool {
+        !self.shared.lock().receiver_alive
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock();
+        shared.senders -= 1;
+        let receiver = if shared.senders == 0 {
+            shared.receiver.take()
+        } else {
+            None
+        };
+        drop(shared);
+        if let Some(receiver) = receiver {
+            receiver.wake();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<SpinlockIrq<Shared<T>>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value. Returns `None` once the channel is empty and every sender is
+    /// gone.
+    pub async fn recv(&self) -> Option<T> {
+        poll_fn(|cx| match self.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => {
+                let mut shared = self.shared.lock();
+                if shared.values.is_empty() && shared.senders > 0 {
+                    shared.receiver = Some(cx.waker().clone());
+                } else {
+                    drop(shared);
+                    cx.waker().wake_by_ref();
+                }
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Takes the next value, if there is one.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut shared = self.shared.lock();
+        let Some(value) = shared.values.pop_front() else {
+            return Err(if shared.senders == 0 {
+                TryRecvError::Closed
+            } else {
+                TryRecvError::Empty
+            });
+        };
+        let sender = shared.blocked_senders.pop_front();
+        drop(shared);
+        if let Some(sender) = sender {
+            sender.wake();
+        }
+        Ok(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.lock().values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock();
+        shared.receiver_alive = false;
+        let values = core::mem::take(&mut shared.values);
+        let senders = core::mem::take(&mut shared.blocked_senders);
+        drop(shared);
+        drop(values);
+        senders.into_iter().for_each(Waker::wake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn closes_when_either_side_is_dropped() {
+        let (sender, receiver) = channel(2);
+        sender.try_send(1).unwrap();
+        sender.clone().try_send(2).unwrap();
+        assert_eq!(Err(TrySendError::Full(3)), sender.try_send(3));
+
+        assert_eq!(Ok(1), receiver.try_recv());
+        drop(sender);
+        assert_eq!(Ok(2), receiver.try_recv());
+        assert_eq!(Err(TryRecvError::Closed), receiver.try_recv());
+
+        let (sender, receiver) = channel(1);
+        drop(receiver);
+        assert!(sender.is_closed());
+        assert_eq!(Err(TrySendError::Closed(4)), sender.try_send(4));
+    }
+}
@@ -0,0 +1,232 @@
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, task::Wake};
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
+use spinning_top::Spinlock;
+
+use crate::sync::{SpinlockIrq, WaitQueue};
+
+/// Identifies a task, across all executors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Polls futures whenever they're woken.
+///
+/// An executor runs on one thread at a time, which sleeps whenever no task is ready, leaving its
+/// CPU to other threads or to halt. Tasks can be woken from anywhere, including interrupt
+/// handlers.
+pub struct Executor {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    ready: SpinlockIrq<VecDeque<Arc<Task>>>,
+    /// The thread running the executor, while it sleeps with no task ready.
+    idle: WaitQueue,
+}
+
+struct Task {
+    id: TaskId,
+    /// Dropped once the future completes.
+    future: Spinlock<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    /// Set while the task is on the ready queue, so that it's only on it once.
+    queued: AtomicBool,
+    executor: Arc<Shared>,
+}
+
+impl Task {
+    fn schedule(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.executor.ready.lock().push_back(self.clone());
+        self.executor.idle.wake_one();
+    }
+
+    fn poll(self: &Arc<Self>) {
+        // Cleared first, so that a wakeup while it's being polled polls it again.
+        self.queued.store(false, Ordering::Release);
+        let mut slot = self.future.lock();
+        let Some(future) = slot.as_mut() else {
+            return;
+        };
+        let waker = Waker::from(self.clone());
+        if future
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_ready()
+        {
+            *slot = None;
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.schedule();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.schedule();
+    }
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                ready: SpinlockIrq::new(VecDeque::new()),
+                idle: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Adds a task polling `future`, which runs until it completes even if the handle is dropped.
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let join = Arc::new(SpinlockIrq::new(JoinState {
+            output: None,
+            waker: None,
+        }));
+        let task = Arc::new(Task {
+            id: TaskId::new(),
+            future: Spinlock::new(Some(Box::pin({
+                let join = join.clone();
+                async move {
+                    let output = future.await;
+                    let mut join = join.lock();
+                    join.output = Some(output);
+                    let waker = join.waker.take();
+                    drop(join);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            }))),
+            queued: AtomicBool::new(false),
+            executor: self.shared.clone(),
+        });
+        task.schedule();
+        JoinHandle {
+            id: task.id,
+            state: join,
+        }
+    }
+
+    /// Polls tasks until none is ready. Returns how many polls that took.
+    pub fn run_until_idle(&self) -> usize {
+        let mut polls = 0;
+        loop {
+            let Some(task) = self.shared.ready.lock().pop_front() else {
+                return polls;
+            };
+            task.poll();
+            polls += 1;
+        }
+    }
+
+    /// Polls tasks forever on the calling thread, sleeping while none is ready.
+    #[track_caller]
+    pub fn run(&self) -> ! {
+        loop {
+            self.run_until_idle();
+            self.shared
+                .idle
+                .wait_until(|| !self.shared.ready.lock().is_empty());
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Waits for a task to complete, and gives back its output.
+pub struct JoinHandle<T> {
+    id: TaskId,
+    state: Arc<SpinlockIrq<JoinState<T>>>,
+}
+
+struct JoinState<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock();
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::channel;
+
+    #[test]
+    pub fn polls_tasks_again_once_woken() {
+        let executor = Executor::new();
+        let (sender, receiver) = channel(1);
+        let sum = executor.spawn(async move {
+            let mut sum = 0;
+            while let Some(value) = receiver.recv().await {
+                sum += value;
+            }
+            sum
+        });
+        let total = executor.spawn(async move { sum.await * 10 });
+        executor.run_until_idle();
+
+        executor.spawn(async move {
+            for value in 1..=3 {
+                sender.send(value).await.unwrap();
+            }
+        });
+        executor.run_until_idle();
+
+        let mut total = core::pin::pin!(total);
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(Poll::Ready(60), total.as_mut().poll(&mut cx));
+    }
+}
@@ -0,0 +1,48 @@
+//! Asynchronous tasks.
+//!
+//! A task is a future the kernel polls to completion. The kernel's [`Executor`] runs them all on
+//! one thread, which sleeps when no task is ready, so the CPU halts in its idle loop if nothing
+//! else wants it. Anything can wake a task, including an interrupt handler: [`WakerSlot`] holds a
+//! waker for one, and [`channel`]s can be sent to without waiting.
+//!
+//! Tasks must not block the thread they're polled on, with the sleeping locks in [`sync`] or
+//! otherwise, since that stops every other task too. They wait with futures instead, such as
+//! [`sleep`] and [`Receiver::recv`].
+//!
+//! [`sync`]: crate::sync
+
+use conquer_once::spin::OnceCell;
+use core::future::Future;
+
+use crate::thread;
+
+mod channel;
+mod executor;
+mod timer;
+mod waker;
+
+pub use channel::{channel, Receiver, SendError, Sender, TryRecvError, TrySendError};
+pub use executor::{Executor, JoinHandle, TaskId};
+pub use timer::{sleep, sleep_until, timeout, Elapsed, Sleep};
+pub use waker::WakerSlot;
+
+static EXECUTOR: OnceCell<Executor> = OnceCell::uninit();
+
+/// Starts the kernel's executor on a thread of its own.
+pub fn init() {
+    EXECUTOR.init_once(Executor::new);
+    thread::spawn(|| executor().run());
+}
+
+fn executor() -> &'static Executor {
+    EXECUTOR.get().expect("executor not initialized")
+}
+
+/// Starts a task on the kernel's executor.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    executor().spawn(future)
+}
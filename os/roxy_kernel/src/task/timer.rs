@@ -0,0 +1,90 @@
+use alloc::sync::Arc;
+use core::{
+    future::{poll_fn, Future},
+    pin::{pin, Pin},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use super::WakerSlot;
+use crate::time::{self, Instant, TimerHandle};
+
+/// Completes once `duration` has passed.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(time::now() + duration)
+}
+
+/// Completes once the monotonic clock reaches `deadline`.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        timer: None,
+    }
+}
+
+/// The time limit passed before the future completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Runs `future` until it completes or `duration` passes, whichever is first.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    let mut future = pin!(future);
+    let mut sleep = sleep(duration);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut sleep).poll(cx).map(|()| Err(Elapsed))
+    })
+    .await
+}
+
+/// A future that completes at a deadline, from [`sleep`] or [`sleep_until`].
+///
+/// It only sets a timer once it's first polled, and cancels it if dropped before then.
+pub struct Sleep {
+    deadline: Instant,
+    timer: Option<(TimerHandle, Arc<WakerSlot>)>,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if time::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        match &self.timer {
+            Some((_, waker)) => waker.register(cx.waker()),
+            None => {
+                let waker = Arc::new(WakerSlot::new());
+                waker.register(cx.waker());
+                let handle = time::add_timer(self.deadline, {
+                    let waker = waker.clone();
+                    move || waker.wake()
+                });
+                self.timer = Some((handle, waker));
+            }
+        }
+        // The timer might have fired before the waker was registered.
+        if time::now() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some((handle, _)) = self.timer.take() {
+            time::cancel_timer(handle);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use core::task::Waker;
+
+use crate::sync::SpinlockIrq;
+
+/// The waker of a task waiting for something to happen, such as an interrupt.
+///
+/// Both sides can use it from anywhere, including interrupt handlers.
+pub struct WakerSlot {
+    waker: SpinlockIrq<Option<Waker>>,
+}
+
+impl WakerSlot {
+    pub const fn new() -> Self {
+        Self {
+            waker: SpinlockIrq::new(None),
+        }
+    }
+
+    /// Makes `waker` the one to wake, replacing any other.
+    pub fn register(&self, waker: &Waker) {
+        let mut slot = self.waker.lock();
+        if !slot.as_ref().is_some_and(|old| old.will_wake(waker)) {
+            *slot = Some(waker.clone());
+        }
+    }
+
+    /// Wakes the registered task, if there is one. It has to register again to be woken again.
+    pub fn wake(&self) {
+        let waker = self.waker.lock().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for WakerSlot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
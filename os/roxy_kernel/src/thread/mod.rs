@@ -0,0 +1,294 @@
+//! Kernel threads.
+//!
+//! Every flow of control in the kernel is a [`Thread`]: each CPU's boot flow becomes one in
+//! [`init`], and [`spawn`] starts more, each on its own kernel stack. The [scheduler](crate::sched)
+//! decides which of them runs where.
+
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    fmt, ptr,
+    sync::atomic::{AtomicPtr, AtomicU64, AtomicU8, Ordering},
+    time::Duration,
+};
+use spinning_top::Spinlock;
+use x86_64::instructions::interrupts as cpu_interrupts;
+
+use crate::{
+    sched::{self, switch, AffinityError, Policy, PolicyError, SchedEntity},
+    smp::{percpu, CpuSet},
+    time::{self, Instant},
+    user::UserContext,
+    vmm::{self, KernelStack},
+};
+
+const STACK_PAGES: u64 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum State {
+    Running,
+    Ready,
+    /// Going to sleep until something wakes it, but still on its CPU.
+    Blocking,
+    /// Asleep until something wakes it.
+    Blocked,
+    Exited,
+}
+
+type Entry = Box<dyn FnOnce() + Send>;
+
+pub struct Thread {
+    id: ThreadId,
+    state: AtomicU8,
+    /// The saved stack pointer, while the thread isn't running.
+    rsp: AtomicU64,
+    /// `None` for the boot flows, which run on the stacks they were started with.
+    stack: Spinlock<Option<KernelStack>>,
+    entry: Spinlock<Option<Entry>>,
+    sched: SchedEntity,
+    /// Set while the thread is in [`user::run`](crate::user::run).
+    user_context: AtomicPtr<UserContext>,
+}
+
+impl Thread {
+    fn new(state: State, stack: Option<KernelStack>, entry: Option<Entry>, policy: Policy) -> Self {
+        Self {
+            id: ThreadId::next(),
+            state: AtomicU8::new(state as u8),
+            rsp: AtomicU64::new(0),
+            stack: Spinlock::new(stack),
+            entry: Spinlock::new(entry),
+            sched: SchedEntity::new(policy),
+            user_context: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// A thread that's never going to run, for testing the scheduler's bookkeeping.
+    #[cfg(test)]
+    pub(crate) fn for_tests(policy: Policy) -> Arc<Self> {
+        Arc::new(Self::new(State::Ready, None, None, policy))
+    }
+
+    /// A thread that starts running `entry` on a fresh stack once it's scheduled.
+    fn with_stack(entry: Entry, policy: Policy) -> Self {
+        let stack = vmm::get()
+            .allocate_kernel_stack(STACK_PAGES)
+            .expect("to be able to allocate a thread stack");
+        let rsp = unsafe {
+            // SAFETY: The stack was just allocated for this thread alone.
+            switch::prepare(stack.top())
+        };
+        let thread = Self::new(State::Ready, Some(stack), Some(entry), policy);
+        thread.rsp.store(rsp, Ordering::Relaxed);
+        thread
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn state(&self) -> State {
+        match self.state.load(Ordering::Acquire) {
+            0 => State::Running,
+            1 => State::Ready,
+            2 => State::Blocking,
+            3 => State::Blocked,
+            _ => State::Exited,
+        }
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.sched.policy()
+    }
+
+    /// How long the thread has run for, as of the last timer tick or thread switch on its CPU.
+    pub fn cpu_time(&self) -> Duration {
+        self.sched.cpu_time()
+    }
+
+    /// The CPUs the thread may run on.
+    pub fn affinity(&self) -> CpuSet {
+        self.sched.affinity()
+    }
+
+    /// The CPU the thread is queued on, or last ran on.
+    pub fn cpu(&self) -> usize {
+        self.sched.cpu()
+    }
+
+    pub(crate) fn sched(&self) -> &SchedEntity {
+        &self.sched
+    }
+
+    pub(crate) fn set_state(&self, state: State) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    /// Moves the thread from state `from` to `to`, if it's in state `from`. Returns whether it
+    /// was.
+    pub(crate) fn change_state(&self, from: State, to: State) -> bool {
+        self.state
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    /// Where the stack pointer is saved while the thread isn't running.
+    pub(crate) fn saved_rsp(&self) -> &AtomicU64 {
+        &self.rsp
+    }
+
+    /// The thread's user mode context, or null if it isn't running user code.
+    pub(crate) fn user_context(&self) -> *mut UserContext {
+        self.user_context.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_user_context(&self, context: *mut UserContext) {
+        self.user_context.store(context, Ordering::Release);
+    }
+
+    /// Frees the thread's stack, once it has exited and is off it for good. It takes the VMM lock,
+    /// so the scheduler leaves it to a kworker rather than call it while switching threads.
+    pub(crate) fn release_stack(&self) {
+        debug_assert_eq!(self.state(), State::Exited);
+        if let Some(stack) = self.stack.lock().take() {
+            unsafe {
+                // SAFETY: As above.
+                vmm::get().free_kernel_stack(stack);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Thread")
+            .field("id", &self.id)
+            .field("state", &self.state())
+            .field("policy", &self.policy())
+            .field("cpu_time", &self.cpu_time())
+            .field("cpu", &self.cpu())
+            .field("affinity", &self.affinity())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Turns the calling CPU's boot flow into its first thread, and gives the CPU an idle thread.
+pub fn init() {
+    let thread = Arc::new(Thread::new(State::Running, None, None, Policy::DEFAULT));
+    // The idle thread's policy doesn't matter, as it's never queued.
+    let idle = Arc::new(Thread::with_stack(
+        Box::new(|| sched::idle()),
+        Policy::DEFAULT,
+    ));
+    log::debug!(
+        "Thread {} is CPU {}'s boot flow, thread {} its idle thread",
+        thread.id,
+        percpu::current_id(),
+        idle.id
+    );
+    sched::init_cpu(thread, idle);
+}
+
+/// The thread running on the calling CPU.
+pub fn current() -> Arc<Thread> {
+    sched::current()
+}
+
+/// Starts a new thread running `f`, with the [default policy](Policy::DEFAULT). It exits when `f`
+/// returns.
+pub fn spawn(f: impl FnOnce() + Send + 'static) -> Arc<Thread> {
+    spawn_with_policy(Policy::DEFAULT, f).expect("default policy to be valid")
+}
+
+/// Starts a new thread running `f`, scheduled according to `policy`.
+pub fn spawn_with_policy(
+    policy: Policy,
+    f: impl FnOnce() + Send + 'static,
+) -> Result<Arc<Thread>, PolicyError> {
+    let thread = Arc::new(Thread::with_stack(Box::new(f), policy.validate()?));
+    sched::enqueue(thread.clone());
+    Ok(thread)
+}
+
+/// Starts a new thread running `f`, with the [default policy](Policy::DEFAULT), that only ever runs
+/// on the CPUs in `affinity`.
+pub fn spawn_with_affinity(
+    affinity: CpuSet,
+    f: impl FnOnce() + Send + 'static,
+) -> Result<Arc<Thread>, AffinityError> {
+    sched::check_affinity(affinity)?;
+    let thread = Arc::new(Thread::with_stack(Box::new(f), Policy::DEFAULT));
+    // Before it's queued, so that it's never queued anywhere else.
+    thread.sched.set_affinity(affinity);
+    sched::enqueue(thread.clone());
+    Ok(thread)
+}
+
+/// Changes how a thread is scheduled, from the next time the scheduler looks at it.
+pub fn set_policy(thread: &Arc<Thread>, policy: Policy) -> Result<(), PolicyError> {
+    sched::set_policy(thread, policy)
+}
+
+/// Restricts a thread to the CPUs in `affinity`. If it's on another CPU, it moves straight away.
+pub fn set_affinity(thread: &Arc<Thread>, affinity: CpuSet) -> Result<(), AffinityError> {
+    sched::set_affinity(thread, affinity)
+}
+
+/// Lets another ready thread run, if there is one its scheduling class would pick first. A
+/// real-time thread goes behind the others of its priority.
+pub fn yield_now() {
+    sched::yield_now();
+}
+
+/// Sleeps for at least `duration`.
+#[track_caller]
+pub fn sleep(duration: Duration) {
+    sched::sleep_until(time::now() + duration);
+}
+
+/// Sleeps until the monotonic clock reaches `deadline`.
+#[track_caller]
+pub fn sleep_until(deadline: Instant) {
+    sched::sleep_until(deadline);
+}
+
+/// Ends the calling thread.
+pub fn exit() -> ! {
+    sched::exit()
+}
+
+/// Where a new thread starts, once the scheduler has switched onto its stack.
+pub(crate) extern "C" fn thread_entry(previous: *const Thread) -> ! {
+    unsafe {
+        // SAFETY: The switch passes on the previous thread's reference.
+        sched::finish_switch(previous);
+    }
+    let entry = current()
+        .entry
+        .lock()
+        .take()
+        .expect("new thread to have an entry point");
+    cpu_interrupts::enable();
+    entry();
+    exit()
+}
@@ -0,0 +1,234 @@
+//! The High Precision Event Timer.
+
+use conquer_once::spin::OnceCell;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::{
+    acpi::{self, AddressSpace},
+    vmm,
+};
+
+const REG_CAPABILITIES: u64 = 0x000;
+const REG_CONFIG: u64 = 0x010;
+const REG_INTERRUPT_STATUS: u64 = 0x020;
+const REG_MAIN_COUNTER: u64 = 0x0F0;
+const REG_TIMER_BASE: u64 = 0x100;
+const TIMER_STRIDE: u64 = 0x20;
+const TIMER_CONFIG: u64 = 0x00;
+const TIMER_COMPARATOR: u64 = 0x08;
+
+const CAP_COUNTER_64BIT: u64 = 1 << 13;
+const CONFIG_ENABLE: u64 = 1 << 0;
+
+const TIMER_LEVEL_TRIGGERED: u64 = 1 << 1;
+const TIMER_INTERRUPT_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+const TIMER_64BIT_CAPABLE: u64 = 1 << 5;
+const TIMER_SET_ACCUMULATOR: u64 = 1 << 6;
+const TIMER_ROUTE_SHIFT: u64 = 9;
+const TIMER_ROUTE_MASK: u64 = 0x1F << TIMER_ROUTE_SHIFT;
+
+const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+static HPET: OnceCell<Option<Hpet>> = OnceCell::uninit();
+
+/// Locates the HPET through ACPI, maps its registers and starts the main counter.
+///
+/// Returns `None` if there is no usable HPET.
+pub fn init() -> Option<&'static Hpet> {
+    HPET.get_or_init(|| {
+        let table = acpi::hpet()?;
+        if table.base_address.address_space != AddressSpace::SystemMemory {
+            log::warn!("HPET is not memory mapped, ignoring it");
+            return None;
+        }
+        let hpet = Hpet::new(PhysAddr::new(table.base_address.address));
+        hpet.enable();
+        log::info!(
+            "HPET at {:#X}: {} comparators, {} kHz, {}-bit counter",
+            table.base_address.address,
+            hpet.comparator_count(),
+            hpet.frequency() / 1000,
+            if hpet.is_64bit() { 64 } else { 32 },
+        );
+        Some(hpet)
+    })
+    .as_ref()
+}
+
+/// The HPET, if [`init`] found one.
+pub fn get() -> Option<&'static Hpet> {
+    HPET.get().and_then(Option::as_ref)
+}
+
+pub struct Hpet {
+    registers: VirtAddr,
+    period_fs: u64,
+    comparators: u8,
+    counter_64bit: bool,
+}
+
+impl Hpet {
+    fn new(address: PhysAddr) -> Self {
+        let registers = vmm::get()
+            .map_mmio(address, 0x400)
+            .expect("to be able to map the HPET");
+        let mut hpet = Self {
+            registers,
+            period_fs: 0,
+            comparators: 0,
+            counter_64bit: false,
+        };
+        let capabilities = hpet.read(REG_CAPABILITIES);
+        hpet.period_fs = capabilities >> 32;
+        hpet.comparators = ((capabilities >> 8) & 0x1F) as u8 + 1;
+        hpet.counter_64bit = capabilities & CAP_COUNTER_64BIT != 0;
+        hpet
+    }
+
+    /// Starts the main counter.
+    pub fn enable(&self) {
+        self.write(REG_CONFIG, self.read(REG_CONFIG) | CONFIG_ENABLE);
+    }
+
+    /// Halts the main counter.
+    pub fn disable(&self) {
+        self.write(REG_CONFIG, self.read(REG_CONFIG) & !CONFIG_ENABLE);
+    }
+
+    /// Reads the main counter.
+    pub fn counter(&self) -> u64 {
+        self.read(REG_MAIN_COUNTER)
+    }
+
+    /// The main counter period, in femtoseconds.
+    pub fn period_fs(&self) -> u64 {
+        self.period_fs
+    }
+
+    /// The main counter frequency, in Hz.
+    pub fn frequency(&self) -> u64 {
+        FEMTOS_PER_SEC / self.period_fs
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        self.counter_64bit
+    }
+
+    pub fn comparator_count(&self) -> u8 {
+        self.comparators
+    }
+
+    pub fn comparator(&self, index: u8) -> Option<Comparator<'_>> {
+        (index < self.comparators).then_some(Comparator { hpet: self, index })
+    }
+
+    pub fn comparators(&self) -> impl Iterator<Item = Comparator<'_>> {
+        (0..self.comparators).map(|index| Comparator { hpet: self, index })
+    }
+
+    /// Reads and clears the interrupt status of level-triggered comparators.
+    pub fn take_interrupt_status(&self) -> u64 {
+        let status = self.read(REG_INTERRUPT_STATUS);
+        self.write(REG_INTERRUPT_STATUS, status);
+        status
+    }
+
+    /// Busy-waits for `micros` microseconds, calling `start` right as the wait begins.
+    pub fn wait_micros(&self, micros: u64, start: impl FnOnce()) {
+        let ticks = micros * 1_000_000_000 / self.period_fs;
+        let begin = self.counter();
+        start();
+        while self.counter().wrapping_sub(begin) < ticks {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn read(&self, reg: u64) -> u64 {
+        unsafe { core::ptr::read_volatile((self.registers + reg).as_ptr::<u64>()) }
+    }
+
+    fn write(&self, reg: u64, value: u64) {
+        unsafe { core::ptr::write_volatile((self.registers + reg).as_mut_ptr::<u64>(), value) }
+    }
+}
+
+/// One of the HPET's timers, which fires when the main counter reaches its comparator value.
+pub struct Comparator<'a> {
+    hpet: &'a Hpet,
+    index: u8,
+}
+
+impl Comparator<'_> {
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// The I/O APIC inputs this comparator can be routed to, as a bitmask.
+    pub fn route_capabilities(&self) -> u32 {
+        (self.config() >> 32) as u32
+    }
+
+    pub fn supports_periodic(&self) -> bool {
+        self.config() & TIMER_PERIODIC_CAPABLE != 0
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        self.config() & TIMER_64BIT_CAPABLE != 0
+    }
+
+    /// Routes the comparator's interrupt to I/O APIC input `gsi`, leaving it disabled.
+    pub fn configure(&self, gsi: u32, level_triggered: bool) {
+        assert!(
+            1u32.checked_shl(gsi)
+                .is_some_and(|bit| self.route_capabilities() & bit != 0),
+            "HPET comparator {} can't be routed to GSI {}",
+            self.index,
+            gsi
+        );
+        let mut config = self.config()
+            & !(TIMER_ROUTE_MASK | TIMER_LEVEL_TRIGGERED | TIMER_INTERRUPT_ENABLE | TIMER_PERIODIC);
+        config |= (gsi as u64) << TIMER_ROUTE_SHIFT;
+        if level_triggered {
+            config |= TIMER_LEVEL_TRIGGERED;
+        }
+        self.set_config(config);
+    }
+
+    /// Fires once, when the main counter reaches `counter`.
+    pub fn set_oneshot(&self, counter: u64) {
+        self.set_config((self.config() & !TIMER_PERIODIC) | TIMER_INTERRUPT_ENABLE);
+        self.hpet.write(self.reg(TIMER_COMPARATOR), counter);
+    }
+
+    /// Fires every `period` main counter ticks, starting at `first`.
+    pub fn set_periodic(&self, first: u64, period: u64) {
+        assert!(
+            self.supports_periodic(),
+            "HPET comparator {} is not periodic capable",
+            self.index
+        );
+        self.set_config(
+            self.config() | TIMER_PERIODIC | TIMER_INTERRUPT_ENABLE | TIMER_SET_ACCUMULATOR,
+        );
+        self.hpet.write(self.reg(TIMER_COMPARATOR), first);
+        self.hpet.write(self.reg(TIMER_COMPARATOR), period);
+    }
+
+    pub fn disable(&self) {
+        self.set_config(self.config() & !TIMER_INTERRUPT_ENABLE);
+    }
+
+    fn config(&self) -> u64 {
+        self.hpet.read(self.reg(TIMER_CONFIG))
+    }
+
+    fn set_config(&self, value: u64) {
+        self.hpet.write(self.reg(TIMER_CONFIG), value);
+    }
+
+    fn reg(&self, offset: u64) -> u64 {
+        REG_TIMER_BASE + self.index as u64 * TIMER_STRIDE + offset
+    }
+}
@@ -0,0 +1,424 @@
+//! Monotonic time, the timer interrupt and pending timer callbacks.
+//!
+//! The TSC is the clock source, unless it isn't invariant and there is an HPET to fall back on.
+//! Both the TSC and the local APIC timer are calibrated against the HPET, or the PIT without one.
+//! The local APIC timer is the event source, and is only ever armed for the next event: the
+//! periodic tick is just a recurring deadline, so one-shot timers and the tick share the same
+//! hardware.
+//!
+//! Wall-clock time is read once from the real-time clock at boot, and then advanced by the
+//! monotonic clock.
+
+use alloc::{boxed::Box, vec::Vec};
+use conquer_once::spin::OnceCell;
+use core::{
+    cell::Cell,
+    ops::{Add, AddAssign, Sub},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::{
+    instructions::interrupts::without_interrupts, registers::model_specific::Msr,
+    structures::idt::InterruptStackFrame,
+};
+
+use crate::{
+    deferred,
+    interrupts::{
+        self,
+        lapic::{lvt, reg},
+        LAPIC_TIMER_VECTOR,
+    },
+    percpu,
+    smp::percpu,
+    sync::SpinlockIrq,
+};
+
+pub mod hpet;
+pub mod pit;
+pub mod rtc;
+mod system_time;
+mod timer_queue;
+pub mod tsc;
+
+pub use system_time::{DateTime, SystemTime};
+pub use timer_queue::{TimerCallback, TimerHandle, TimerQueue};
+
+/// How often the periodic tick runs.
+pub const TICK_HZ: u64 = 100;
+pub const TICK_PERIOD: Duration = Duration::from_nanos(NANOS_PER_SEC / TICK_HZ);
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const CALIBRATION_MICROS: u64 = 10_000;
+const IA32_TSC_DEADLINE: u32 = 0x6E0;
+const LAPIC_TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+/// A point on the monotonic clock, in nanoseconds since the clock was started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self(nanos)
+    }
+
+    pub fn now() -> Self {
+        now()
+    }
+
+    pub const fn as_nanos(&self) -> u64 {
+        self.0
+    }
+
+    /// The time elapsed from `earlier` to `self`, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|nanos| self.0.checked_add(nanos))
+            .map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        self.checked_add(rhs)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_sub(rhs.as_nanos() as u64))
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// The counter the monotonic clock is read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockSource {
+    Tsc,
+    Hpet,
+}
+
+/// Frequencies measured against the reference clock at boot.
+#[derive(Clone, Copy, Debug)]
+pub struct Calibration {
+    pub clock_source: ClockSource,
+    /// TSC ticks per second.
+    pub tsc_hz: u64,
+    /// Local APIC timer ticks per second, with the divider we use.
+    pub lapic_timer_hz: u64,
+    /// The TSC value at which the monotonic clock reads zero.
+    pub tsc_epoch: u64,
+    /// The HPET main counter value at which the monotonic clock reads zero.
+    pub hpet_epoch: u64,
+    pub tsc_deadline: bool,
+}
+
+static CALIBRATION: OnceCell<Calibration> = OnceCell::uninit();
+/// The wall-clock time at which the monotonic clock read zero.
+static BOOT_TIME: OnceCell<SystemTime> = OnceCell::uninit();
+static TIMERS: SpinlockIrq<TimerQueue> = SpinlockIrq::new(TimerQueue::new());
+/// Counted on the BSP.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static TICK_HANDLERS: SpinlockIrq<Vec<fn()>> = SpinlockIrq::new(Vec::new());
+
+percpu! {
+    /// When the calling CPU's next periodic tick is due, in nanoseconds on the monotonic clock.
+    static NEXT_TICK: Cell<u64> = Cell::new(0);
+    /// While the calling CPU's tick is stopped, when it should next wake anyway, in nanoseconds on
+    /// the monotonic clock. Zero while the tick runs.
+    static TICK_STOPPED_UNTIL: Cell<u64> = Cell::new(0);
+}
+
+/// Calibrates the TSC and local APIC timer, and starts the periodic tick.
+///
+/// Must be called after [`interrupts::init`].
+pub fn init() {
+    let calibration = CALIBRATION.get_or_init(|| without_interrupts(calibrate));
+    if calibration.clock_source == ClockSource::Tsc && !tsc::is_invariant() {
+        log::warn!("TSC is not invariant and there is no HPET, timekeeping may drift");
+    }
+    log::info!(
+        "Clock source is {:?}. TSC runs at {} kHz, local APIC timer at {} kHz{}",
+        calibration.clock_source,
+        calibration.tsc_hz / 1000,
+        calibration.lapic_timer_hz / 1000,
+        if calibration.tsc_deadline {
+            " (TSC-deadline mode)"
+        } else {
+            ""
+        }
+    );
+
+    start_local_timer();
+
+    let boot_time = BOOT_TIME.get_or_init(|| match rtc::read() {
+        Some((time, source)) => {
+            let time = time
+                .to_unix_nanos()
+                .map_or(SystemTime::UNIX_EPOCH, SystemTime::from_unix_nanos);
+            log::info!("Real-time clock ({:?}) reads {}", source, time);
+            time - now().duration_since(Instant(0))
+        }
+        None => {
+            log::warn!("Failed to read the real-time clock, wall-clock time starts at the epoch");
+            SystemTime::UNIX_EPOCH
+        }
+    });
+    log::debug!("Booted at {}", boot_time);
+}
+
+/// Starts the periodic tick on an application processor, once the BSP has run [`init`].
+pub fn init_ap() {
+    start_local_timer();
+}
+
+/// Points the calling CPU's local APIC timer at the timer interrupt, and arms it for the first
+/// tick.
+fn start_local_timer() {
+    let calibration = CALIBRATION.get().expect("time not initialized");
+    let lapic = interrupts::local_apic();
+    let mode = if calibration.tsc_deadline {
+        lvt::TIMER_TSC_DEADLINE
+    } else {
+        0
+    };
+    unsafe {
+        lapic.write(reg::TIMER_DIVIDE, LAPIC_TIMER_DIVIDE_BY_16);
+        lapic.write(reg::LVT_TIMER, mode | LAPIC_TIMER_VECTOR as u32);
+    }
+
+    without_interrupts(|| {
+        let next_tick = unsafe { NEXT_TICK.current() };
+        next_tick.set((now() + TICK_PERIOD).as_nanos());
+        program_next_event();
+    });
+}
+
+fn calibrate() -> Calibration {
+    let hpet = hpet::init().filter(|hpet| hpet.is_64bit());
+    let lapic = interrupts::local_apic();
+    let mut tsc_start = 0;
+    let start = || unsafe {
+        tsc_start = tsc::read();
+        lapic.write(reg::TIMER_INITIAL_COUNT, u32::MAX);
+    };
+    unsafe {
+        lapic.write(reg::LVT_TIMER, lvt::MASKED);
+        lapic.write(reg::TIMER_DIVIDE, LAPIC_TIMER_DIVIDE_BY_16);
+    }
+    match hpet {
+        Some(hpet) => hpet.wait_micros(CALIBRATION_MICROS, start),
+        // SAFETY: Nothing else uses PIT channel 2.
+        None => unsafe { pit::wait_micros(CALIBRATION_MICROS, start) },
+    }
+    let tsc_end = tsc::read();
+    let hpet_end = hpet.map_or(0, |hpet| hpet.counter());
+    let lapic_elapsed = u32::MAX - unsafe { lapic.read(reg::TIMER_CURRENT_COUNT) };
+    unsafe { lapic.write(reg::TIMER_INITIAL_COUNT, 0) };
+
+    let clock_source = match hpet {
+        Some(_) if !tsc::is_invariant() => ClockSource::Hpet,
+        _ => ClockSource::Tsc,
+    };
+    Calibration {
+        clock_source,
+        tsc_hz: (tsc_end - tsc_start) * 1_000_000 / CALIBRATION_MICROS,
+        lapic_timer_hz: lapic_elapsed as u64 * 1_000_000 / CALIBRATION_MICROS,
+        tsc_epoch: tsc_end,
+        hpet_epoch: hpet_end,
+        // TSC deadlines are only meaningful if the TSC is what we keep time with.
+        tsc_deadline: clock_source == ClockSource::Tsc && tsc::has_deadline_mode(),
+    }
+}
+
+/// The results of the boot-time clock calibration, once [`init`] has run.
+pub fn calibration() -> Option<&'static Calibration> {
+    CALIBRATION.get()
+}
+
+/// Reads the monotonic clock. Before [`init`], this always reads zero.
+pub fn now() -> Instant {
+    let Some(c) = CALIBRATION.get() else {
+        return Instant(0);
+    };
+    match c.clock_source {
+        ClockSource::Tsc => Instant(scale(
+            tsc::read().saturating_sub(c.tsc_epoch),
+            NANOS_PER_SEC,
+            c.tsc_hz,
+        )),
+        ClockSource::Hpet => {
+            let hpet = hpet::get().expect("HPET clock source to have an HPET");
+            Instant(scale(
+                hpet.counter().saturating_sub(c.hpet_epoch),
+                hpet.period_fs(),
+                1_000_000,
+            ))
+        }
+    }
+}
+
+/// Reads the wall-clock time. Before [`init`], this reads the UNIX epoch.
+pub fn wall_clock() -> SystemTime {
+    let boot_time = BOOT_TIME.get().copied().unwrap_or(SystemTime::UNIX_EPOCH);
+    boot_time + now().duration_since(Instant(0))
+}
+
+/// The number of periodic ticks on the BSP since [`init`].
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Busy-waits for `duration`.
+pub fn spin_wait(duration: Duration) {
+    let deadline = now() + duration;
+    while now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Busy-waits for `duration`, with the PIT if the monotonic clock isn't running yet.
+pub fn delay(duration: Duration) {
+    if calibration().is_some() {
+        spin_wait(duration);
+    } else {
+        // The PIT can only count down about 55ms at a time.
+        let mut micros = duration.as_micros() as u64;
+        while micros > 0 {
+            let chunk = micros.min(50_000);
+            unsafe { pit::wait_micros(chunk, || {}) };
+            micros -= chunk;
+        }
+    }
+}
+
+/// Registers a function to run on every periodic tick of every CPU, in interrupt context.
+pub fn on_tick(handler: fn()) {
+    TICK_HANDLERS.lock().push(handler);
+}
+
+/// Runs `callback` in interrupt context once the monotonic clock reaches `deadline`.
+pub fn add_timer(deadline: Instant, callback: impl FnOnce() + Send + 'static) -> TimerHandle {
+    let callback: TimerCallback = Box::new(callback);
+    without_interrupts(|| {
+        let handle = TIMERS.lock().insert(deadline, callback);
+        program_next_event();
+        handle
+    })
+}
+
+/// Cancels a pending timer. Returns `false` if it has already fired or been cancelled.
+pub fn cancel_timer(handle: TimerHandle) -> bool {
+    TIMERS.lock().cancel(handle)
+}
+
+/// Stops the calling CPU's periodic tick until `until`, or until it's restarted, so that an idle
+/// CPU only wakes for timers and other interrupts. Ticks missed in the meantime are counted when
+/// it restarts.
+///
+/// Interrupts must be disabled.
+pub(crate) fn stop_tick(until: Instant) {
+    unsafe { TICK_STOPPED_UNTIL.current() }.set(until.as_nanos());
+    program_next_event();
+}
+
+/// Restarts the calling CPU's periodic tick after [`stop_tick`], if it's stopped.
+///
+/// Interrupts must be disabled.
+pub(crate) fn restart_tick() {
+    if unsafe { TICK_STOPPED_UNTIL.current() }.replace(0) != 0 {
+        program_next_event();
+    }
+}
+
+/// Arms the local APIC timer for the next tick or timer deadline, whichever is first.
+fn program_next_event() {
+    let Some(calibration) = CALIBRATION.get() else {
+        return;
+    };
+    let next_tick = Instant(
+        NEXT_TICK
+            .with(Cell::get)
+            .max(TICK_STOPPED_UNTIL.with(Cell::get)),
+    );
+    let deadline = TIMERS
+        .lock()
+        .next_deadline()
+        .map_or(next_tick, |d| d.min(next_tick));
+
+    let lapic = interrupts::local_apic();
+    if calibration.tsc_deadline {
+        let tsc =
+            calibration.tsc_epoch + scale(deadline.as_nanos(), calibration.tsc_hz, NANOS_PER_SEC);
+        unsafe { Msr::new(IA32_TSC_DEADLINE).write(tsc) };
+    } else {
+        let nanos = deadline.duration_since(now()).as_nanos() as u64;
+        let count =
+            scale(nanos, calibration.lapic_timer_hz, NANOS_PER_SEC).clamp(1, u32::MAX as u64);
+        unsafe { lapic.write(reg::TIMER_INITIAL_COUNT, count as u32) };
+    }
+}
+
+/// Computes `value * mul / div` without intermediate overflow.
+fn scale(value: u64, mul: u64, div: u64) -> u64 {
+    (value as u128 * mul as u128 / div as u128) as u64
+}
+
+pub(crate) extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    deferred::irq_enter(&stack_frame);
+    interrupts::eoi();
+    let now = now();
+
+    let next_tick = unsafe { NEXT_TICK.current() };
+    if now.as_nanos() >= next_tick.get() {
+        // Skip over any ticks we missed rather than running them all back to back.
+        let period = TICK_PERIOD.as_nanos() as u64;
+        let elapsed = (now.as_nanos() - next_tick.get()) / period + 1;
+        if percpu::current_id() == 0 {
+            TICKS.fetch_add(elapsed, Ordering::Relaxed);
+        }
+        next_tick.set(next_tick.get() + elapsed * period);
+
+        for handler in TICK_HANDLERS.lock().iter() {
+            handler();
+        }
+    }
+
+    // Pop timers one at a time, so callbacks can add new timers.
+    loop {
+        let Some(callback) = TIMERS.lock().pop_expired(now) else {
+            break;
+        };
+        callback();
+    }
+
+    program_next_event();
+    deferred::irq_exit(&stack_frame);
+}
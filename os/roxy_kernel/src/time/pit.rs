@@ -0,0 +1,48 @@
+use x86_64::instructions::port::Port;
+
+/// The PIT input clock, in Hz.
+pub const FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_2_DATA: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+const PORT_B: u16 = 0x61;
+
+const PORT_B_GATE_2: u8 = 1 << 0;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_OUT_2: u8 = 1 << 5;
+
+/// Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count), binary.
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+/// Busy-waits for `micros` microseconds using PIT channel 2, calling `start` right as the countdown
+/// begins. Used to calibrate the other clocks, and doesn't need interrupts.
+///
+/// At most about 54ms can be measured at once.
+///
+/// # Safety
+///
+/// Nothing else may be using PIT channel 2 or the PC speaker.
+pub unsafe fn wait_micros(micros: u64, start: impl FnOnce()) {
+    let count = (FREQUENCY * micros / 1_000_000).clamp(1, u16::MAX as u64) as u16;
+
+    let mut port_b = Port::<u8>::new(PORT_B);
+    let mut command = Port::<u8>::new(COMMAND);
+    let mut data = Port::<u8>::new(CHANNEL_2_DATA);
+    unsafe {
+        // Gate low while programming, with the speaker disconnected.
+        let value = port_b.read() & !(PORT_B_GATE_2 | PORT_B_SPEAKER);
+        port_b.write(value);
+
+        command.write(CHANNEL_2_ONE_SHOT);
+        data.write(count as u8);
+        data.write((count >> 8) as u8);
+
+        // Raising the gate starts the countdown; OUT2 goes high once it reaches zero.
+        port_b.write(value | PORT_B_GATE_2);
+        start();
+        while port_b.read() & PORT_B_OUT_2 == 0 {
+            core::hint::spin_loop();
+        }
+        port_b.write(value);
+    }
+}
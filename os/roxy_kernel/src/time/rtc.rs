@@ -0,0 +1,144 @@
+//! Reading the battery-backed real-time clock, through UEFI runtime services or the CMOS.
+
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+use super::DateTime;
+use crate::{
+    acpi::{self, IapcBootArch},
+    efi,
+};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const HOURS_PM: u8 = 1 << 7;
+
+/// The clock the time was read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtcSource {
+    Uefi,
+    Cmos,
+}
+
+/// Reads the current UTC date and time, preferring UEFI runtime services over the CMOS.
+pub fn read() -> Option<(DateTime, RtcSource)> {
+    if let Some(time) = read_uefi() {
+        return Some((time, RtcSource::Uefi));
+    }
+    let fadt = acpi::fadt();
+    if fadt.is_some_and(|fadt| fadt.boot_arch.contains(IapcBootArch::CMOS_RTC_NOT_PRESENT)) {
+        return None;
+    }
+    read_cmos(fadt.and_then(|fadt| fadt.century_register())).map(|time| (time, RtcSource::Cmos))
+}
+
+/// Reads the time through the UEFI `GetTime` runtime service, if it's available.
+pub fn read_uefi() -> Option<DateTime> {
+    let time = efi::with_runtime_services(uefi::runtime::get_time)?
+        .inspect_err(|e| log::warn!("UEFI GetTime failed: {:?}", e))
+        .ok()?;
+    let local = DateTime {
+        year: time.year(),
+        month: time.month(),
+        day: time.day(),
+        hour: time.hour(),
+        minute: time.minute(),
+        second: time.second(),
+        nanosecond: time.nanosecond(),
+    };
+
+    // Local time is UTC minus the time zone offset, in minutes.
+    let offset = time.time_zone().unwrap_or(0) as i64 * 60 * super::NANOS_PER_SEC as i64;
+    let utc = local.to_unix_nanos()? as i64 + offset;
+    Some(DateTime::from_unix_nanos(u64::try_from(utc).ok()?))
+}
+
+/// Reads the time from the CMOS RTC, which is assumed to be kept in UTC.
+///
+/// `century_register` is the CMOS register holding the century, as given by the FADT. Without
+/// one, the year is assumed to be in the 21st century.
+pub fn read_cmos(century_register: Option<u8>) -> Option<DateTime> {
+    without_interrupts(|| {
+        // The registers can change under us mid-update, so read until two reads agree.
+        let mut last = read_cmos_registers(century_register);
+        loop {
+            let current = read_cmos_registers(century_register);
+            if current == last {
+                break;
+            }
+            last = current;
+        }
+        let [second, minute, hour, day, month, year, century] = last;
+
+        let status_b = unsafe { read_register(REG_STATUS_B) };
+        let decode = |v: u8| {
+            if status_b & STATUS_B_BINARY != 0 {
+                v
+            } else {
+                (v >> 4) * 10 + (v & 0x0F)
+            }
+        };
+
+        let mut hour_24 = decode(hour & !HOURS_PM);
+        if status_b & STATUS_B_24_HOUR == 0 {
+            hour_24 %= 12;
+            if hour & HOURS_PM != 0 {
+                hour_24 += 12;
+            }
+        }
+        let century = match century_register {
+            Some(_) => decode(century) as u16,
+            None => 20,
+        };
+
+        let time = DateTime {
+            year: century * 100 + decode(year) as u16,
+            month: decode(month),
+            day: decode(day),
+            hour: hour_24,
+            minute: decode(minute),
+            second: decode(second),
+            nanosecond: 0,
+        };
+        time.is_valid().then_some(time)
+    })
+}
+
+fn read_cmos_registers(century_register: Option<u8>) -> [u8; 7] {
+    unsafe {
+        while read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+            core::hint::spin_loop();
+        }
+        [
+            read_register(REG_SECONDS),
+            read_register(REG_MINUTES),
+            read_register(REG_HOURS),
+            read_register(REG_DAY),
+            read_register(REG_MONTH),
+            read_register(REG_YEAR),
+            century_register.map_or(0, |reg| read_register(reg)),
+        ]
+    }
+}
+
+/// # Safety
+///
+/// Interrupts must be disabled, so nothing else can select a different register in between.
+unsafe fn read_register(reg: u8) -> u8 {
+    unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(reg);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
@@ -0,0 +1,184 @@
+use core::{
+    fmt,
+    ops::{Add, Sub},
+    time::Duration,
+};
+
+use super::NANOS_PER_SEC;
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// A point in wall-clock time, in nanoseconds since the UNIX epoch, UTC.
+///
+/// Unlike [`Instant`](super::Instant), this isn't monotonic: it's only as good as the real-time
+/// clock it was read from at boot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime(u64);
+
+impl SystemTime {
+    pub const UNIX_EPOCH: SystemTime = SystemTime(0);
+
+    pub fn now() -> Self {
+        super::wall_clock()
+    }
+
+    pub const fn from_unix_nanos(nanos: u64) -> Self {
+        Self(nanos)
+    }
+
+    pub const fn as_unix_nanos(&self) -> u64 {
+        self.0
+    }
+
+    /// The time elapsed from `earlier` to `self`, or `None` if `earlier` is later.
+    pub fn duration_since(&self, earlier: SystemTime) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_nanos)
+    }
+
+    /// The calendar date and time, in UTC.
+    pub fn to_date_time(&self) -> DateTime {
+        DateTime::from_unix_nanos(self.0)
+    }
+}
+
+impl Add<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn add(self, rhs: Duration) -> SystemTime {
+        SystemTime(self.0 + rhs.as_nanos() as u64)
+    }
+}
+
+impl Sub<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn sub(self, rhs: Duration) -> SystemTime {
+        SystemTime(self.0.saturating_sub(rhs.as_nanos() as u64))
+    }
+}
+
+impl fmt::Display for SystemTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_date_time().fmt(f)
+    }
+}
+
+/// A broken down calendar date and time, as read from a real-time clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+}
+
+impl DateTime {
+    /// Converts a time since the UNIX epoch into a date, in the proleptic Gregorian calendar.
+    pub fn from_unix_nanos(nanos: u64) -> Self {
+        let secs = nanos / NANOS_PER_SEC;
+        let time = secs % SECS_PER_DAY;
+
+        // Shift the epoch to 0000-03-01, so leap days fall at the end of the year.
+        // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let days = (secs / SECS_PER_DAY) as i64 + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+            nanosecond: (nanos % NANOS_PER_SEC) as u32,
+        }
+    }
+
+    /// Converts the date into a time since the UNIX epoch, or `None` if it's before 1970.
+    pub fn to_unix_nanos(&self) -> Option<u64> {
+        // See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let year = self.year as i64 - (self.month <= 2) as i64;
+        let month = self.month as i64;
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year =
+            (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = u64::try_from(era * 146_097 + day_of_era - 719_468).ok()?;
+
+        let secs = days * SECS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64;
+        Some(secs * NANOS_PER_SEC + self.nanosecond as u64)
+    }
+
+    /// Whether every field is within its range. Real-time clocks can hold garbage.
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+            && self.nanosecond < NANOS_PER_SEC as u32
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn date(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            nanosecond: 0,
+        }
+    }
+
+    #[test]
+    pub fn converts_dates_to_and_from_unix_time() {
+        let cases = [
+            (date(1970, 1, 1, 0, 0, 0), 0),
+            (date(2000, 2, 29, 12, 0, 0), 951_825_600),
+            (date(2024, 12, 31, 23, 59, 59), 1_735_689_599),
+            (date(2100, 3, 1, 0, 0, 0), 4_107_542_400),
+        ];
+        for (date, secs) in cases {
+            assert_eq!(date.to_unix_nanos(), Some(secs * NANOS_PER_SEC));
+            assert_eq!(DateTime::from_unix_nanos(secs * NANOS_PER_SEC), date);
+        }
+        assert_eq!(date(1969, 12, 31, 23, 59, 59).to_unix_nanos(), None);
+    }
+}
@@ -0,0 +1,101 @@
+use alloc::{boxed::Box, collections::BTreeMap};
+
+use super::Instant;
+
+pub type TimerCallback = Box<dyn FnOnce() + Send>;
+
+/// Identifies a pending timer, so that it can be cancelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerHandle {
+    deadline: Instant,
+    id: u64,
+}
+
+impl TimerHandle {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+/// Pending timer callbacks, ordered by deadline and then by insertion order.
+#[derive(Default)]
+pub struct TimerQueue {
+    timers: BTreeMap<(Instant, u64), TimerCallback>,
+    next_id: u64,
+}
+
+impl TimerQueue {
+    pub const fn new() -> Self {
+        Self {
+            timers: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn insert(&mut self, deadline: Instant, callback: TimerCallback) -> TimerHandle {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.timers.insert((deadline, id), callback);
+        TimerHandle { deadline, id }
+    }
+
+    /// Removes a pending timer. Returns `false` if it has already fired or been cancelled.
+    pub fn cancel(&mut self, handle: TimerHandle) -> bool {
+        self.timers.remove(&(handle.deadline, handle.id)).is_some()
+    }
+
+    /// The deadline of the earliest pending timer.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers
+            .first_key_value()
+            .map(|((deadline, _), _)| *deadline)
+    }
+
+    /// Removes and returns the earliest timer, if it is due at `now`.
+    pub fn pop_expired(&mut self, now: Instant) -> Option<TimerCallback> {
+        match self.timers.first_entry() {
+            Some(entry) if entry.key().0 <= now => Some(entry.remove()),
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{boxed::Box, sync::Arc, sync::Mutex, vec::Vec};
+
+    use super::{Instant, TimerQueue};
+
+    #[test]
+    pub fn fires_expired_timers_in_deadline_order() {
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let mut queue = TimerQueue::new();
+        for (deadline, name) in [(300, "c"), (100, "a"), (200, "b"), (100, "a2")] {
+            let fired = fired.clone();
+            queue.insert(
+                Instant::from_nanos(deadline),
+                Box::new(move || fired.lock().unwrap().push(name)),
+            );
+        }
+        let cancelled = queue.insert(Instant::from_nanos(150), Box::new(|| panic!("cancelled")));
+
+        assert_eq!(Some(Instant::from_nanos(100)), queue.next_deadline());
+        assert!(queue.cancel(cancelled));
+        assert!(!queue.cancel(cancelled));
+
+        while let Some(callback) = queue.pop_expired(Instant::from_nanos(250)) {
+            callback();
+        }
+        assert_eq!(std::vec!["a", "a2", "b"], *fired.lock().unwrap());
+        assert_eq!(Some(Instant::from_nanos(300)), queue.next_deadline());
+        assert_eq!(1, queue.len());
+    }
+}
@@ -0,0 +1,18 @@
+use core::arch::x86_64::{__cpuid, _rdtsc};
+
+/// Reads the time stamp counter.
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Whether the TSC runs at a constant rate regardless of power states, making it a usable
+/// clock source.
+pub fn is_invariant() -> bool {
+    let max_extended = __cpuid(0x8000_0000).eax;
+    max_extended >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+/// Whether the local APIC timer supports TSC-deadline mode.
+pub fn has_deadline_mode() -> bool {
+    __cpuid(1).ecx & (1 << 24) != 0
+}
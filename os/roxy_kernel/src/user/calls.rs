@@ -0,0 +1,74 @@
+//! The system calls themselves, which [`syscall`](super::syscall) dispatches to.
+//!
+//! Each takes the six argument registers and returns a result or an error number, as described in
+//! [`roxy_abi::nr`].
+
+use alloc::{string::String, vec};
+use core::time::Duration;
+use roxy_abi::{Errno, Timespec, CLOCK_MONOTONIC, CLOCK_REALTIME, MAX_WRITE, STDERR, STDOUT};
+
+use super::{
+    copy::{copy_from_user, read_user, write_user},
+    UserExit,
+};
+use crate::{thread, time};
+
+pub(super) type Args = [u64; 6];
+
+pub(super) fn exit(args: Args) -> Result<u64, Errno> {
+    super::leave(UserExit::Exited(args[0]))
+}
+
+pub(super) fn yield_now(_: Args) -> Result<u64, Errno> {
+    thread::yield_now();
+    Ok(0)
+}
+
+/// Writes to the kernel log, a line at a time, as there's no console for user programs yet.
+pub(super) fn write(args: Args) -> Result<u64, Errno> {
+    let [fd, buf, len, ..] = args;
+    if fd != STDOUT && fd != STDERR {
+        return Err(Errno::EBADF);
+    }
+    let len = usize::try_from(len).unwrap_or(usize::MAX).min(MAX_WRITE);
+    let mut bytes = vec![0; len];
+    copy_from_user(&mut bytes, buf)?;
+
+    let id = thread::current().id();
+    let text = String::from_utf8_lossy(&bytes);
+    for line in text.lines() {
+        if fd == STDERR {
+            log::warn!("[thread {}] {}", id, line);
+        } else {
+            log::info!("[thread {}] {}", id, line);
+        }
+    }
+    Ok(len as u64)
+}
+
+pub(super) fn clock_get(args: Args) -> Result<u64, Errno> {
+    let [clock, time_ptr, ..] = args;
+    let nanos = match clock {
+        CLOCK_MONOTONIC => time::now().as_nanos(),
+        CLOCK_REALTIME => time::wall_clock().as_unix_nanos(),
+        _ => return Err(Errno::EINVAL),
+    };
+    write_user(time_ptr, &Timespec::from_nanos(nanos))?;
+    Ok(0)
+}
+
+pub(super) fn sleep(args: Args) -> Result<u64, Errno> {
+    let duration: Timespec = read_user(args[0])?;
+    if !duration.is_valid() {
+        return Err(Errno::EINVAL);
+    }
+    let deadline = time::now()
+        .checked_add(Duration::new(duration.seconds, duration.nanoseconds as u32))
+        .ok_or(Errno::EINVAL)?;
+    thread::sleep_until(deadline);
+    Ok(0)
+}
+
+pub(super) fn thread_id(_: Args) -> Result<u64, Errno> {
+    Ok(thread::current().id().as_u64())
+}
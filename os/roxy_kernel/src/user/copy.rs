@@ -0,0 +1,120 @@
+//! Copying to and from user memory.
+//!
+//! These work on the calling thread's address space, so they're only for system calls. User
+//! pointers are checked against the page table a page at a time, with the VMM locked only for the
+//! lookup, and copied through the physical memory map. Nothing but the thread itself changes the
+//! address space it's running in, and it's busy with the system call, so the memory stays mapped
+//! for the copy. A bad pointer makes the copy fail with [`Errno::EFAULT`], instead of faulting in
+//! the kernel.
+
+use core::mem::MaybeUninit;
+use roxy_abi::{Errno, Timespec};
+use x86_64::VirtAddr;
+
+use super::USER_SPACE_END;
+use crate::vmm;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// A type that any bytes are a valid value of, so it can be copied from user memory.
+///
+/// # Safety
+///
+/// Every bit pattern of the right size must be a valid value, and the type must have no padding.
+pub unsafe trait UserData: Copy {}
+
+// SAFETY: Integers and structures made of them, without padding.
+unsafe impl UserData for u8 {}
+unsafe impl UserData for u32 {}
+unsafe impl UserData for u64 {}
+unsafe impl UserData for Timespec {}
+
+/// Checks that `len` bytes at `addr` are all in user space.
+pub fn check_range(addr: u64, len: usize) -> Result<VirtAddr, Errno> {
+    match addr.checked_add(len as u64) {
+        Some(end) if addr < USER_SPACE_END && end <= USER_SPACE_END => Ok(VirtAddr::new(addr)),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// Calls `copy` on each piece of `len` bytes at `addr` that lies within one page, with the piece's
+/// address in the physical memory map and its offset from `addr`.
+fn for_each_piece(
+    addr: u64,
+    len: usize,
+    write: bool,
+    mut copy: impl FnMut(*mut u8, usize, usize),
+) -> Result<(), Errno> {
+    let start = check_range(addr, len)?;
+    let space = super::current_space();
+    if space.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    // SAFETY: The thread is in a system call, so it can't have left user mode.
+    let space = unsafe { &*space };
+    let mut done = 0;
+    while done < len {
+        let piece_addr = start + done as u64;
+        let piece_len = (PAGE_SIZE - piece_addr.as_u64() % PAGE_SIZE).min((len - done) as u64);
+        let phys = vmm::get()
+            .translate_user(space, piece_addr, write)
+            .ok_or(Errno::EFAULT)?;
+        copy(
+            vmm::phys_to_virt(phys).as_mut_ptr(),
+            done,
+            piece_len as usize,
+        );
+        done += piece_len as usize;
+    }
+    Ok(())
+}
+
+/// Fills `dst` from user memory at `src`.
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> Result<(), Errno> {
+    for_each_piece(src, dst.len(), false, |piece, offset, len| unsafe {
+        // SAFETY: The piece is mapped user memory, which stays mapped while the thread is in the
+        // system call.
+        piece.copy_to_nonoverlapping(dst[offset..].as_mut_ptr(), len);
+    })
+}
+
+/// Copies `src` to user memory at `dst`.
+pub fn copy_to_user(dst: u64, src: &[u8]) -> Result<(), Errno> {
+    for_each_piece(dst, src.len(), true, |piece, offset, len| unsafe {
+        // SAFETY: As above, and the piece is writable.
+        piece.copy_from_nonoverlapping(src[offset..].as_ptr(), len);
+    })
+}
+
+/// Reads a `T` from user memory at `src`, which needn't be aligned.
+pub fn read_user<T: UserData>(src: u64) -> Result<T, Errno> {
+    let mut value = MaybeUninit::<T>::uninit();
+    // SAFETY: `T` has no padding, so all of it is bytes to fill in.
+    let bytes =
+        unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr().cast::<u8>(), size_of::<T>()) };
+    copy_from_user(bytes, src)?;
+    // SAFETY: Every byte was filled in, and any bytes are a valid `T`.
+    Ok(unsafe { value.assume_init() })
+}
+
+/// Writes `value` to user memory at `dst`, which needn't be aligned.
+pub fn write_user<T: UserData>(dst: u64, value: &T) -> Result<(), Errno> {
+    // SAFETY: `T` has no padding, so all of it is initialized bytes.
+    let bytes =
+        unsafe { core::slice::from_raw_parts((value as *const T).cast::<u8>(), size_of::<T>()) };
+    copy_to_user(dst, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn rejects_ranges_outside_user_space() {
+        assert_eq!(Ok(VirtAddr::new(0x1000)), check_range(0x1000, 8));
+        assert!(check_range(USER_SPACE_END - 8, 8).is_ok());
+        assert_eq!(Err(Errno::EFAULT), check_range(USER_SPACE_END - 8, 9));
+        assert_eq!(Err(Errno::EFAULT), check_range(USER_SPACE_END, 0));
+        assert_eq!(Err(Errno::EFAULT), check_range(u64::MAX - 2, 8));
+    }
+}
@@ -0,0 +1,453 @@
+//! Parsing static ELF64 executables for x86-64.
+//!
+//! [`Elf::parse`] checks everything the loader relies on up front, so that a file it accepts can be
+//! loaded without further checks: every loadable segment lies within the file and within user
+//! space, clear of page 0 and of the stack, and no two share a page.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::{
+    loader::{STACK_SIZE, STACK_TOP},
+    USER_SPACE_END,
+};
+
+const PAGE_SIZE: u64 = 0x1000;
+
+const HEADER_SIZE: usize = 64;
+/// The size of a program header, as [`AT_PHENT`](roxy_abi::auxv::AT_PHENT) reports it.
+pub const PROGRAM_HEADER_SIZE: usize = 56;
+
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+
+bitflags::bitflags! {
+    /// What a segment's memory may be used for.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct SegmentFlags: u32 {
+        const EXECUTE = 1 << 0;
+        const WRITE = 1 << 1;
+        const READ = 1 << 2;
+    }
+}
+
+/// A segment to load into memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Segment<'a> {
+    /// Where the segment starts in memory, which needn't be page-aligned.
+    pub address: u64,
+    /// How much memory the segment takes, which is at least `data.len()`. The rest is zeroed.
+    pub memory_size: u64,
+    /// What to fill the start of the segment with.
+    pub data: &'a [u8],
+    pub flags: SegmentFlags,
+}
+
+impl Segment<'_> {
+    pub fn end(&self) -> u64 {
+        self.address + self.memory_size
+    }
+}
+
+/// A parsed, checked executable.
+#[derive(Debug)]
+pub struct Elf<'a> {
+    entry: u64,
+    segments: Vec<Segment<'a>>,
+    program_headers: Option<u64>,
+    program_header_count: u16,
+}
+
+/// Why a file isn't an executable the loader can run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file is too short to hold an ELF header.
+    Truncated,
+    /// The file doesn't start with the ELF magic number.
+    NotElf,
+    /// The file isn't 64-bit.
+    UnsupportedClass(u8),
+    /// The file isn't little-endian.
+    UnsupportedEncoding(u8),
+    UnsupportedVersion(u8),
+    /// The file isn't an executable, but a relocatable file, a shared object or a position-
+    /// independent executable.
+    NotExecutable(u16),
+    /// The file isn't for x86-64.
+    UnsupportedMachine(u16),
+    BadProgramHeaderSize(u16),
+    /// The program headers go past the end of the file.
+    ProgramHeadersOutOfBounds,
+    /// The file asks for an interpreter, so it's dynamically linked.
+    DynamicallyLinked,
+    /// A segment's data goes past the end of the file.
+    SegmentOutOfBounds {
+        index: usize,
+    },
+    /// A segment has more data in the file than memory to hold it.
+    SegmentDataTooLarge {
+        index: usize,
+    },
+    /// A segment goes past the end of user space.
+    SegmentOutsideUserSpace {
+        index: usize,
+    },
+    /// A segment overlaps page 0, or the stack and the unmapped page above it, which the loader
+    /// keeps for itself.
+    SegmentInReservedRegion {
+        index: usize,
+    },
+    /// A segment's address and file offset don't agree modulo its alignment, or the alignment
+    /// isn't a power of two.
+    MisalignedSegment {
+        index: usize,
+    },
+    /// A segment shares a page with, or comes before, the segment before it.
+    OverlappingSegments {
+        index: usize,
+    },
+    NoLoadableSegments,
+    /// The entry point isn't in an executable segment.
+    BadEntryPoint(u64),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::Truncated => write!(f, "file is too short for an ELF header"),
+            ElfError::NotElf => write!(f, "file is not an ELF file"),
+            ElfError::UnsupportedClass(class) => write!(f, "ELF class {} is not 64-bit", class),
+            ElfError::UnsupportedEncoding(encoding) => {
+                write!(f, "ELF data encoding {} is not little-endian", encoding)
+            }
+            ElfError::UnsupportedVersion(version) => {
+                write!(f, "ELF version {} is not supported", version)
+            }
+            ElfError::NotExecutable(kind) => {
+                write!(f, "ELF type {} is not a static executable", kind)
+            }
+            ElfError::UnsupportedMachine(machine) => {
+                write!(f, "ELF machine {} is not x86-64", machine)
+            }
+            ElfError::BadProgramHeaderSize(size) => write!(
+                f,
+                "program headers are {} bytes instead of {}",
+                size, PROGRAM_HEADER_SIZE
+            ),
+            ElfError::ProgramHeadersOutOfBounds => {
+                write!(f, "program headers go past the end of the file")
+            }
+            ElfError::DynamicallyLinked => {
+                write!(
+                    f,
+                    "executable is dynamically linked, which is not supported"
+                )
+            }
+            ElfError::SegmentOutOfBounds { index } => {
+                write!(f, "segment {} goes past the end of the file", index)
+            }
+            ElfError::SegmentDataTooLarge { index } => {
+                write!(f, "segment {} has more file data than memory", index)
+            }
+            ElfError::SegmentOutsideUserSpace { index } => {
+                write!(f, "segment {} is not in user space", index)
+            }
+            ElfError::SegmentInReservedRegion { index } => {
+                write!(f, "segment {} overlaps page 0 or the stack", index)
+            }
+            ElfError::MisalignedSegment { index } => {
+                write!(f, "segment {} is misaligned", index)
+            }
+            ElfError::OverlappingSegments { index } => write!(
+                f,
+                "segment {} shares a page with, or comes before, the segment before it",
+                index
+            ),
+            ElfError::NoLoadableSegments => write!(f, "executable has no loadable segments"),
+            ElfError::BadEntryPoint(entry) => {
+                write!(
+                    f,
+                    "entry point {:#X} is not in an executable segment",
+                    entry
+                )
+            }
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[..4] != *b"\x7FELF" {
+            return Err(ElfError::NotElf);
+        }
+        match (data[4], data[5], data[6]) {
+            (ELFCLASS64, ELFDATA2LSB, EV_CURRENT) => {}
+            (ELFCLASS64, ELFDATA2LSB, version) => {
+                return Err(ElfError::UnsupportedVersion(version))
+            }
+            (ELFCLASS64, encoding, _) => return Err(ElfError::UnsupportedEncoding(encoding)),
+            (class, _, _) => return Err(ElfError::UnsupportedClass(class)),
+        }
+        let kind = read_u16(data, 16);
+        if kind != ET_EXEC {
+            return Err(ElfError::NotExecutable(kind));
+        }
+        let machine = read_u16(data, 18);
+        if machine != EM_X86_64 {
+            return Err(ElfError::UnsupportedMachine(machine));
+        }
+        let entry = read_u64(data, 24);
+        let header_offset = read_u64(data, 32);
+        let header_size = read_u16(data, 54);
+        let header_count = read_u16(data, 56);
+        if header_size as usize != PROGRAM_HEADER_SIZE && header_count != 0 {
+            return Err(ElfError::BadProgramHeaderSize(header_size));
+        }
+        let headers = usize::try_from(header_offset)
+            .ok()
+            .and_then(|start| {
+                let end = start.checked_add(header_count as usize * PROGRAM_HEADER_SIZE)?;
+                data.get(start..end)
+            })
+            .ok_or(ElfError::ProgramHeadersOutOfBounds)?;
+
+        let mut segments: Vec<Segment<'a>> = Vec::new();
+        let mut program_headers = None;
+        for (index, header) in headers
+            .as_chunks::<PROGRAM_HEADER_SIZE>()
+            .0
+            .iter()
+            .enumerate()
+        {
+            let kind = read_u32(header, 0);
+            let offset = read_u64(header, 8);
+            let address = read_u64(header, 16);
+            match kind {
+                PT_LOAD => {}
+                PT_INTERP => return Err(ElfError::DynamicallyLinked),
+                PT_PHDR => {
+                    program_headers = Some(address);
+                    continue;
+                }
+                _ => continue,
+            }
+            let flags = SegmentFlags::from_bits_truncate(read_u32(header, 4));
+            let file_size = read_u64(header, 32);
+            let memory_size = read_u64(header, 40);
+            let align = read_u64(header, 48);
+            if memory_size == 0 {
+                continue;
+            }
+            if file_size > memory_size {
+                return Err(ElfError::SegmentDataTooLarge { index });
+            }
+            let segment_data = usize::try_from(offset)
+                .ok()
+                .zip(usize::try_from(file_size).ok())
+                .and_then(|(start, len)| data.get(start..start.checked_add(len)?))
+                .ok_or(ElfError::SegmentOutOfBounds { index })?;
+            if address
+                .checked_add(memory_size)
+                .is_none_or(|end| end > USER_SPACE_END)
+            {
+                return Err(ElfError::SegmentOutsideUserSpace { index });
+            }
+            if address < PAGE_SIZE || address + memory_size > STACK_TOP - STACK_SIZE {
+                return Err(ElfError::SegmentInReservedRegion { index });
+            }
+            if align > 1 && (!align.is_power_of_two() || address % align != offset % align) {
+                return Err(ElfError::MisalignedSegment { index });
+            }
+            if let Some(previous) = segments.last() {
+                if address / PAGE_SIZE < previous.end().div_ceil(PAGE_SIZE) {
+                    return Err(ElfError::OverlappingSegments { index });
+                }
+            }
+            // Without a PT_PHDR, the headers are found through the segment they're loaded with.
+            if program_headers.is_none()
+                && (offset..offset + file_size).contains(&header_offset)
+                && header_offset + headers.len() as u64 <= offset + file_size
+            {
+                program_headers = Some(address + (header_offset - offset));
+            }
+            segments.push(Segment {
+                address,
+                memory_size,
+                data: segment_data,
+                flags,
+            });
+        }
+
+        if segments.is_empty() {
+            return Err(ElfError::NoLoadableSegments);
+        }
+        if !segments.iter().any(|segment| {
+            segment.flags.contains(SegmentFlags::EXECUTE)
+                && (segment.address..segment.end()).contains(&entry)
+        }) {
+            return Err(ElfError::BadEntryPoint(entry));
+        }
+        Ok(Self {
+            entry,
+            segments,
+            program_headers,
+            program_header_count: header_count,
+        })
+    }
+
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// The loadable segments, in address order.
+    pub fn segments(&self) -> &[Segment<'a>] {
+        &self.segments
+    }
+
+    /// Where the program headers end up in memory, if they're loaded at all.
+    pub fn program_headers(&self) -> Option<u64> {
+        self.program_headers
+    }
+
+    pub fn program_header_count(&self) -> u16 {
+        self.program_header_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    const HELLO: &[u8] = include_bytes!("fixtures/hello.elf");
+
+    #[test]
+    pub fn parses_static_executable() {
+        let elf = Elf::parse(HELLO).unwrap();
+        assert_eq!(0x401000, elf.entry());
+        assert_eq!(Some(0x400040), elf.program_headers());
+        assert_eq!(5, elf.program_header_count());
+
+        let segments = elf.segments();
+        let layout: Vec<_> = segments
+            .iter()
+            .map(|segment| {
+                (
+                    segment.address,
+                    segment.memory_size,
+                    segment.data.len(),
+                    segment.flags,
+                )
+            })
+            .collect();
+        let (r, x, w) = (
+            SegmentFlags::READ,
+            SegmentFlags::EXECUTE,
+            SegmentFlags::WRITE,
+        );
+        assert_eq!(
+            [
+                (0x400000, 0x158, 0x158, r),
+                (0x401000, 0x3C, 0x3C, r | x),
+                (0x402000, 0x15, 0x15, r),
+                (0x403015, 0x13, 0x8, r | w),
+            ],
+            layout[..]
+        );
+        assert_eq!(b"Hello from user mode\n", segments[2].data);
+    }
+
+    #[test]
+    pub fn rejects_malformed_files() {
+        fn patched(offset: usize, bytes: &[u8]) -> Vec<u8> {
+            let mut file = HELLO.to_vec();
+            file[offset..offset + bytes.len()].copy_from_slice(bytes);
+            file
+        }
+        // The fourth program header, for the data segment.
+        let data_header = 64 + 3 * PROGRAM_HEADER_SIZE;
+
+        assert_eq!(
+            Err(ElfError::Truncated),
+            Elf::parse(&HELLO[..32]).map(|_| ())
+        );
+        let cases = [
+            (patched(0, b"\x7FELG"), ElfError::NotElf),
+            (patched(4, &[1]), ElfError::UnsupportedClass(1)),
+            (patched(5, &[2]), ElfError::UnsupportedEncoding(2)),
+            (patched(16, &3u16.to_le_bytes()), ElfError::NotExecutable(3)),
+            (
+                patched(18, &3u16.to_le_bytes()),
+                ElfError::UnsupportedMachine(3),
+            ),
+            (
+                patched(32, &u64::MAX.to_le_bytes()),
+                ElfError::ProgramHeadersOutOfBounds,
+            ),
+            (
+                patched(data_header + 8, &0x10_0015u64.to_le_bytes()),
+                ElfError::SegmentOutOfBounds { index: 3 },
+            ),
+            (
+                patched(data_header + 32, &0x100u64.to_le_bytes()),
+                ElfError::SegmentDataTooLarge { index: 3 },
+            ),
+            (
+                patched(data_header + 16, &0xFFFF_8000_0000_3015u64.to_le_bytes()),
+                ElfError::SegmentOutsideUserSpace { index: 3 },
+            ),
+            (
+                patched(data_header + 16, &0x15u64.to_le_bytes()),
+                ElfError::SegmentInReservedRegion { index: 3 },
+            ),
+            (
+                patched(
+                    data_header + 16,
+                    &(STACK_TOP - STACK_SIZE + 0x15).to_le_bytes(),
+                ),
+                ElfError::SegmentInReservedRegion { index: 3 },
+            ),
+            (
+                patched(data_header + 16, &0x403016u64.to_le_bytes()),
+                ElfError::MisalignedSegment { index: 3 },
+            ),
+            (
+                patched(data_header + 16, &0x402015u64.to_le_bytes()),
+                ElfError::OverlappingSegments { index: 3 },
+            ),
+            (
+                patched(data_header, &PT_INTERP.to_le_bytes()),
+                ElfError::DynamicallyLinked,
+            ),
+            (
+                patched(24, &0x403015u64.to_le_bytes()),
+                ElfError::BadEntryPoint(0x403015),
+            ),
+        ];
+        for (file, error) in cases {
+            assert_eq!(Err(error), Elf::parse(&file).map(|_| ()));
+        }
+    }
+}
@@ -0,0 +1,43 @@
+# A static user program for the ELF loader's tests and the boot-time self-test. It writes a line,
+# yields, and exits with 40 plus its argument count, keeping the count in .bss on the way.
+#
+# Rebuild hello.elf with:
+#   as --64 -o hello.o hello.s
+#   ld -static -nostdlib --build-id=none -z noexecstack -z separate-code -o hello.elf hello.o
+#   strip hello.elf
+
+    .intel_syntax noprefix
+
+    .section .text
+    .global _start
+_start:
+    mov rbx, [rsp]
+    mov [rip + argc], rbx
+
+    mov eax, 2                      # WRITE
+    mov edi, 1                      # STDOUT
+    lea rsi, [rip + message]
+    mov edx, [rip + message_len]
+    syscall
+
+    mov eax, 1                      # YIELD
+    syscall
+
+    mov rdi, [rip + argc]
+    add rdi, 40
+    xor eax, eax                    # EXIT
+    syscall
+    ud2
+
+    .section .rodata
+message:
+    .ascii "Hello from user mode\n"
+message_end:
+
+    .section .data
+message_len:
+    .quad message_end - message
+
+    .section .bss
+argc:
+    .quad 0
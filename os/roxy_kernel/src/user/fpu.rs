@@ -0,0 +1,83 @@
+//! The x87 and SSE registers of threads running user code.
+//!
+//! The kernel is built without floating point, so it never touches these registers itself: they
+//! belong to whichever thread last ran user code on the CPU. A thread in user mode keeps a copy in
+//! its [`UserContext`](super::UserContext) while it's switched out, which
+//! [`prepare_switch`](super::prepare_switch) saves with `fxsave` and loads again with `fxrstor`.
+//! AVX isn't enabled, so that covers every register user code can use.
+
+use core::arch::asm;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+/// The x87 control word `fninit` loads: every exception masked, 64-bit precision, round to
+/// nearest.
+const DEFAULT_FCW: u16 = 0x037F;
+/// The MXCSR value at reset: every exception masked, round to nearest.
+const DEFAULT_MXCSR: u32 = 0x1F80;
+
+/// The area `fxsave` saves the registers to.
+#[repr(C, align(16))]
+#[allow(dead_code)] // Only `fxrstor` reads the fields.
+pub(super) struct FpuState {
+    fcw: u16,
+    /// The rest of the x87 state: the status and tag words, and the last instruction and operand.
+    x87: [u8; 22],
+    mxcsr: u32,
+    mxcsr_mask: u32,
+    /// The x87 and XMM registers, and space the processor leaves alone.
+    registers: [u8; 480],
+}
+
+const _: () = assert!(size_of::<FpuState>() == 512);
+
+impl FpuState {
+    /// The state a thread starts running user code with: what `fninit` and the default MXCSR
+    /// give, with every register zeroed, so that nothing is left over from another thread.
+    pub(super) const fn new() -> Self {
+        Self {
+            fcw: DEFAULT_FCW,
+            x87: [0; 22],
+            mxcsr: DEFAULT_MXCSR,
+            mxcsr_mask: 0,
+            registers: [0; 480],
+        }
+    }
+
+    /// Saves the calling CPU's registers.
+    pub(super) fn save(&mut self) {
+        unsafe {
+            asm!(
+                "fxsave64 [{}]",
+                in(reg) &raw mut *self,
+                options(nostack, preserves_flags)
+            );
+        }
+    }
+
+    /// Loads the registers on the calling CPU.
+    pub(super) fn restore(&self) {
+        unsafe {
+            // SAFETY: The state is either the initial one or saved by `fxsave`, so its MXCSR has
+            // no reserved bits set.
+            asm!(
+                "fxrstor64 [{}]",
+                in(reg) &raw const *self,
+                options(nostack, preserves_flags, readonly)
+            );
+        }
+    }
+}
+
+/// Lets user code on the calling CPU use the x87 and SSE registers, with their exceptions reported
+/// as `#MF` and `#XM`.
+pub(super) fn init_cpu() {
+    unsafe {
+        // SAFETY: Nothing in the kernel uses these registers, and every thread that runs user code
+        // loads its own before it does.
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+}
@@ -0,0 +1,285 @@
+//! Loading static executables into address spaces of their own.
+
+use alloc::vec::Vec;
+use core::fmt;
+use roxy_abi::auxv::{AT_ENTRY, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM};
+use x86_64::{
+    instructions::random::RdRand,
+    structures::paging::{mapper::MapToError, PageTableFlags},
+    VirtAddr,
+};
+
+use super::{
+    elf::{Elf, ElfError, SegmentFlags, PROGRAM_HEADER_SIZE},
+    UserExit, USER_SPACE_END,
+};
+use crate::vmm::{self, AddressSpace};
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// The top of a program's stack. The page above is left unmapped.
+pub const STACK_TOP: u64 = USER_SPACE_END - PAGE_SIZE;
+pub const STACK_SIZE: u64 = 64 * 1024;
+
+/// How much of the stack the arguments, environment and auxiliary vector may take.
+const MAX_STACK_INFO_SIZE: u64 = STACK_SIZE / 4;
+
+/// Why an executable couldn't be loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    Elf(ElfError),
+    /// The arguments and environment don't fit on the stack.
+    ArgumentsTooLarge,
+    /// There's no memory for the program.
+    OutOfMemory,
+}
+
+impl From<ElfError> for LoadError {
+    fn from(error: ElfError) -> Self {
+        LoadError::Elf(error)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Elf(error) => write!(f, "invalid executable: {}", error),
+            LoadError::ArgumentsTooLarge => write!(
+                f,
+                "arguments and environment take more than {} bytes",
+                MAX_STACK_INFO_SIZE
+            ),
+            LoadError::OutOfMemory => write!(f, "out of memory"),
+        }
+    }
+}
+
+/// A program loaded into an address space of its own, ready to run.
+///
+/// Dropping it frees the address space.
+#[derive(Debug)]
+pub struct Program {
+    space: Option<AddressSpace>,
+    entry: VirtAddr,
+    stack_pointer: VirtAddr,
+}
+
+impl Program {
+    pub fn entry(&self) -> VirtAddr {
+        self.entry
+    }
+
+    /// Runs the program on the calling thread, until it exits or faults.
+    #[track_caller]
+    pub fn run(self) -> UserExit {
+        let space = self
+            .space
+            .as_ref()
+            .expect("program to have an address space");
+        // SAFETY: The address space only has the program's own memory in its user half.
+        unsafe { super::run(space, self.entry, self.stack_pointer) }
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        if let Some(space) = self.space.take() {
+            unsafe {
+                // SAFETY: `run` switches away from the address space before returning, and no
+                // other thread ever runs in it.
+                vmm::get().free_address_space(space);
+            }
+        }
+    }
+}
+
+/// Loads the static executable in `file` into a fresh address space, with `args` and `env` on its
+/// stack.
+pub fn load(file: &[u8], args: &[&str], env: &[&str]) -> Result<Program, LoadError> {
+    let elf = Elf::parse(file)?;
+    let mut auxv = Vec::new();
+    if let Some(program_headers) = elf.program_headers() {
+        auxv.push((AT_PHDR, program_headers));
+    }
+    auxv.extend([
+        (AT_PHENT, PROGRAM_HEADER_SIZE as u64),
+        (AT_PHNUM, elf.program_header_count().into()),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, elf.entry()),
+    ]);
+    let stack = build_stack(STACK_TOP, args, env, &auxv, random_bytes())?;
+
+    // The VMM is only locked to map each piece of memory, not for copying into it.
+    let mut space = vmm::get()
+        .new_address_space()
+        .map_err(|_| LoadError::OutOfMemory)?;
+    let mapped = elf
+        .segments()
+        .iter()
+        .try_for_each(|segment| {
+            let mut flags = PageTableFlags::empty();
+            if segment.flags.contains(SegmentFlags::WRITE) {
+                flags |= PageTableFlags::WRITABLE;
+            }
+            if !segment.flags.contains(SegmentFlags::EXECUTE) {
+                flags |= PageTableFlags::NO_EXECUTE;
+            }
+            vmm::get().map_user(
+                &mut space,
+                VirtAddr::new(segment.address),
+                segment.memory_size,
+                flags,
+            )
+        })
+        .and_then(|()| {
+            vmm::get().map_user(
+                &mut space,
+                VirtAddr::new(STACK_TOP - STACK_SIZE),
+                STACK_SIZE,
+                PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            )
+        });
+    if let Err(e) = mapped {
+        unsafe {
+            // SAFETY: The address space was never loaded.
+            vmm::get().free_address_space(space);
+        }
+        return match e {
+            MapToError::FrameAllocationFailed => Err(LoadError::OutOfMemory),
+            // `Elf::parse` rules out segments that overlap each other or the stack.
+            e => unreachable!("checked executable failed to map: {:?}", e),
+        };
+    }
+    for segment in elf.segments() {
+        vmm::write_user(&space, VirtAddr::new(segment.address), segment.data)
+            .expect("segment to be mapped");
+    }
+    vmm::write_user(&space, VirtAddr::new(stack.stack_pointer), &stack.data)
+        .expect("stack to be mapped");
+
+    Ok(Program {
+        space: Some(space),
+        entry: VirtAddr::new(elf.entry()),
+        stack_pointer: VirtAddr::new(stack.stack_pointer),
+    })
+}
+
+/// 16 bytes for [`AT_RANDOM`], from RDRAND if the CPU has it.
+fn random_bytes() -> [u8; 16] {
+    let words = match RdRand::new() {
+        Some(rdrand) => [rdrand.get_u64(), rdrand.get_u64()].map(Option::unwrap_or_default),
+        // Not random, but at least different each time.
+        None => [unsafe { core::arch::x86_64::_rdtsc() }, 0],
+    };
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&words[0].to_le_bytes());
+    bytes[8..].copy_from_slice(&words[1].to_le_bytes());
+    bytes
+}
+
+/// The start of a program's stack, as [`roxy_abi::auxv`] describes it.
+#[derive(Debug)]
+struct InitialStack {
+    stack_pointer: u64,
+    /// What goes from the stack pointer up to the top of the stack.
+    data: Vec<u8>,
+}
+
+/// Lays out the start of a stack that ends at `top`, with [`AT_RANDOM`] and [`AT_NULL`] added to
+/// `auxv`.
+fn build_stack(
+    top: u64,
+    args: &[&str],
+    env: &[&str],
+    auxv: &[(u64, u64)],
+    random: [u8; 16],
+) -> Result<InitialStack, LoadError> {
+    let strings_size: usize = args.iter().chain(env).map(|s| s.len() + 1).sum();
+    let strings_start = (top - random.len() as u64)
+        .checked_sub(strings_size as u64)
+        .ok_or(LoadError::ArgumentsTooLarge)?;
+    let words = 1 + (args.len() + 1) + (env.len() + 1) + 2 * (auxv.len() + 2);
+    let stack_pointer = ((strings_start & !0xF).saturating_sub(8 * words as u64)) & !0xF;
+    if top - stack_pointer > MAX_STACK_INFO_SIZE {
+        return Err(LoadError::ArgumentsTooLarge);
+    }
+
+    let mut data = alloc::vec![0; (top - stack_pointer) as usize];
+    let random_address = top - random.len() as u64;
+    let offset = |address: u64| (address - stack_pointer) as usize;
+    data[offset(random_address)..].copy_from_slice(&random);
+
+    let mut string_address = strings_start;
+    let mut pointers = Vec::with_capacity(words);
+    pointers.push(args.len() as u64);
+    for strings in [args, env] {
+        for string in strings {
+            let at = offset(string_address);
+            data[at..at + string.len()].copy_from_slice(string.as_bytes());
+            pointers.push(string_address);
+            string_address += string.len() as u64 + 1;
+        }
+        pointers.push(0);
+    }
+    for &(kind, value) in auxv
+        .iter()
+        .chain(&[(AT_RANDOM, random_address), (AT_NULL, 0)])
+    {
+        pointers.extend([kind, value]);
+    }
+    for (word, pointer) in data.as_chunks_mut::<8>().0.iter_mut().zip(pointers) {
+        *word = pointer.to_le_bytes();
+    }
+    Ok(InitialStack {
+        stack_pointer,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn lays_out_initial_stack() {
+        let top = 0x10000;
+        let stack = build_stack(
+            top,
+            &["hello", "world"],
+            &["LANG=C"],
+            &[(AT_PAGESZ, PAGE_SIZE)],
+            [7; 16],
+        )
+        .unwrap();
+        assert_eq!(0, stack.stack_pointer % 16);
+        assert_eq!((top - stack.stack_pointer) as usize, stack.data.len());
+
+        let word = |index: usize| {
+            u64::from_le_bytes(stack.data[index * 8..index * 8 + 8].try_into().unwrap())
+        };
+        let string_at = |address: u64| {
+            let start = (address - stack.stack_pointer) as usize;
+            let len = stack.data[start..].iter().position(|&b| b == 0).unwrap();
+            core::str::from_utf8(&stack.data[start..start + len]).unwrap()
+        };
+        assert_eq!(2, word(0));
+        assert_eq!("hello", string_at(word(1)));
+        assert_eq!("world", string_at(word(2)));
+        assert_eq!(0, word(3));
+        assert_eq!("LANG=C", string_at(word(4)));
+        assert_eq!(0, word(5));
+        assert_eq!(
+            [AT_PAGESZ, PAGE_SIZE, AT_RANDOM],
+            [word(6), word(7), word(8)]
+        );
+        assert_eq!(top - 16, word(9));
+        assert_eq!([AT_NULL, 0], [word(10), word(11)]);
+        assert_eq!([7; 16], stack.data[stack.data.len() - 16..]);
+
+        let too_long = "x".repeat(MAX_STACK_INFO_SIZE as usize);
+        assert_eq!(
+            LoadError::ArgumentsTooLarge,
+            build_stack(top, &[&too_long], &[], &[], [0; 16]).unwrap_err()
+        );
+    }
+}
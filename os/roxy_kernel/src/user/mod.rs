@@ -0,0 +1,313 @@
+//! Running code in user mode.
+//!
+//! A thread enters user mode with [`run`], and stays there until the code makes the exit system
+//! call or faults, when `run` returns with a [`UserExit`] saying which. In between, interrupts and
+//! system calls arrive on the thread's kernel stack, just below `run`'s frame, so the thread can
+//! be preempted and migrated like any other.
+//!
+//! User space is the lower half of an [`AddressSpace`], up to [`USER_SPACE_END`]; the upper half is
+//! the kernel's, the same in every address space. A thread runs in the address space it was given
+//! to `run`, and every other thread in the kernel's own page table. User space has no GS base of
+//! its own: it's always zero. Its x87 and SSE registers are saved and restored along with the
+//! thread, by [`fpu`].
+//!
+//! [`load`] loads a static ELF executable into a fresh address space, ready to run.
+
+use core::{
+    arch::global_asm,
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use x86_64::{
+    instructions::interrupts as cpu_interrupts,
+    registers::control::Cr3,
+    structures::{
+        idt::{InterruptStackFrame, PageFaultErrorCode},
+        paging::PhysFrame,
+    },
+    VirtAddr,
+};
+
+use self::fpu::FpuState;
+use crate::{
+    boot::gdt::{self, USER_CODE_SELECTOR, USER_DATA_SELECTOR},
+    sched,
+    smp::percpu,
+    thread::{self, Thread},
+    vmm::{self, AddressSpace},
+};
+
+mod calls;
+mod copy;
+mod elf;
+mod fpu;
+mod loader;
+mod syscall;
+
+pub use copy::{copy_from_user, copy_to_user, read_user, write_user, UserData};
+pub use elf::{Elf, ElfError, Segment, SegmentFlags};
+pub use loader::{load, LoadError, Program, STACK_SIZE, STACK_TOP};
+pub use syscall::SyscallFrame;
+
+/// The first address past user space: the end of the lower canonical half.
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// Why a thread came back from user mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserExit {
+    /// It made the exit system call, with this status.
+    Exited(u64),
+    /// It caused an exception at `ip`.
+    Fault { fault: Fault, ip: VirtAddr },
+}
+
+/// An exception caused by user code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    PageFault {
+        address: VirtAddr,
+        error_code: PageFaultErrorCode,
+    },
+    GeneralProtection {
+        error_code: u64,
+    },
+    InvalidOpcode,
+    X87FloatingPoint,
+    SimdFloatingPoint,
+}
+
+/// What a thread in user mode needs to get back out, kept in [`run`]'s frame.
+pub(crate) struct UserContext {
+    /// The kernel stack pointer once [`run`]'s registers are saved, which is also where interrupts
+    /// and system calls from user mode start their stack.
+    kernel_rsp: AtomicU64,
+    /// The address space [`run`] was given, which it borrows until it returns.
+    space: *const AddressSpace,
+    exit: Option<UserExit>,
+    /// The user code's x87 and SSE registers, while the thread is switched out.
+    fpu: FpuState,
+}
+
+global_asm!(
+    r#"
+    .global roxy_enter_user
+roxy_enter_user:
+    cli
+    push rbp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    // Keeps the stack aligned for the call below.
+    sub rsp, 8
+    mov rbx, rdi
+    mov r12, rsi
+    mov [rdx], rsp
+    mov rdi, rsp
+    call {set_kernel_stack}
+    push {user_data}
+    push r12
+    push 0x202
+    push {user_code}
+    push rbx
+    xor eax, eax
+    xor ebx, ebx
+    xor ecx, ecx
+    xor edx, edx
+    xor esi, esi
+    xor edi, edi
+    xor ebp, ebp
+    xor r8d, r8d
+    xor r9d, r9d
+    xor r10d, r10d
+    xor r11d, r11d
+    xor r12d, r12d
+    xor r13d, r13d
+    xor r14d, r14d
+    xor r15d, r15d
+    swapgs
+    iretq
+
+    .global roxy_leave_user
+roxy_leave_user:
+    mov rsp, rdi
+    add rsp, 8
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    pop rbp
+    ret
+    "#,
+    set_kernel_stack = sym set_kernel_stack,
+    user_data = const USER_DATA_SELECTOR.0,
+    user_code = const USER_CODE_SELECTOR.0,
+);
+
+extern "C" {
+    /// Saves the callee-saved registers, stores the stack pointer in `kernel_rsp`, and starts
+    /// running `entry` in user mode on `stack`. Returns once [`roxy_leave_user`] is called with
+    /// that stack pointer.
+    fn roxy_enter_user(entry: u64, stack: u64, kernel_rsp: *mut u64);
+    /// Returns from [`roxy_enter_user`], whose stack pointer is `kernel_rsp`.
+    fn roxy_leave_user(kernel_rsp: u64) -> !;
+}
+
+/// Sets up the calling CPU to run user code. Must be called after
+/// [`gdt::init_cpu`](crate::boot::gdt::init_cpu).
+pub fn init_cpu() {
+    syscall::init_cpu();
+    fpu::init_cpu();
+}
+
+/// Makes interrupts and system calls from user mode arrive on the stack at `top`.
+extern "C" fn set_kernel_stack(top: u64) {
+    gdt::set_privilege_stack(VirtAddr::new(top));
+    percpu::set_scratch(syscall::KERNEL_RSP_SLOT, top);
+}
+
+/// Loads the page table in `frame` on the calling CPU, unless it's loaded already.
+fn switch_page_table(frame: PhysFrame) {
+    let (current, flags) = Cr3::read();
+    if current != frame {
+        // SAFETY: Every address space maps the kernel the same way.
+        unsafe { Cr3::write(frame, flags) };
+    }
+}
+
+/// Runs the code at `entry` in user mode in `space` on the calling thread, with its stack pointer
+/// at `stack`, until it exits or faults.
+///
+/// # Safety
+///
+/// `entry` and `stack` must be user addresses, and nothing the user code can reach in `space` may
+/// be anything the kernel relies on.
+#[track_caller]
+pub unsafe fn run(space: &AddressSpace, entry: VirtAddr, stack: VirtAddr) -> UserExit {
+    sched::might_sleep();
+    assert!(
+        entry.as_u64() < USER_SPACE_END && stack.as_u64() <= USER_SPACE_END,
+        "user code to run in user space"
+    );
+    let mut context = UserContext {
+        kernel_rsp: AtomicU64::new(0),
+        space,
+        exit: None,
+        fpu: FpuState::new(),
+    };
+    let context = &raw mut context;
+    let thread = thread::current();
+    // Until the thread has its own registers loaded, switching away mustn't save them.
+    cpu_interrupts::disable();
+    unsafe {
+        // SAFETY: The context outlives its use, as `leave` only returns here, and the thread
+        // forgets it before the frame is gone.
+        thread.set_user_context(context);
+        switch_page_table(space.level_4_frame());
+        (*context).fpu.restore();
+        roxy_enter_user(
+            entry.as_u64(),
+            stack.as_u64(),
+            (*context).kernel_rsp.as_ptr(),
+        );
+    }
+    thread.set_user_context(ptr::null_mut());
+    switch_page_table(vmm::kernel_page_table());
+    cpu_interrupts::enable();
+    unsafe { (*context).exit }.expect("to only leave user mode with a reason")
+}
+
+/// Leaves user mode for good, making the calling thread's [`run`] return `exit`.
+///
+/// Must be called on the thread's kernel stack, from a system call or an exception from user mode.
+pub(crate) fn leave(exit: UserExit) -> ! {
+    cpu_interrupts::disable();
+    let context = {
+        let thread = thread::current();
+        thread.user_context()
+    };
+    assert!(!context.is_null(), "thread to be running user code");
+    unsafe {
+        // SAFETY: The context is in `run`'s frame, further up this stack, which is about to be
+        // returned to.
+        (*context).exit = Some(exit);
+        roxy_leave_user((*context).kernel_rsp.load(Ordering::Relaxed))
+    }
+}
+
+/// Handles an exception, which `fault` describes, if it came from user mode, by leaving it.
+/// Returns if it came from the kernel.
+pub(crate) fn handle_fault(stack_frame: &InterruptStackFrame, fault: Fault) {
+    if stack_frame.code_segment.rpl() == x86_64::PrivilegeLevel::Ring3 {
+        leave(UserExit::Fault {
+            fault,
+            ip: stack_frame.instruction_pointer,
+        });
+    }
+}
+
+/// Saves the x87 and SSE registers of `previous`, the calling thread, if it's in user mode. Then
+/// switches the calling CPU to `next`'s page table, and if `next` is in user mode, loads its
+/// registers and points the kernel entry stack at `next`'s. Called with interrupts disabled,
+/// before switching to `next`.
+pub(crate) fn prepare_switch(previous: &Thread, next: &Thread) {
+    let context = previous.user_context();
+    if !context.is_null() {
+        // SAFETY: The context is in the calling thread's `run` frame, further up its stack.
+        unsafe { (*context).fpu.save() };
+    }
+    let context = next.user_context();
+    if context.is_null() {
+        // Kernel threads never keep an address space loaded, so that it can be freed.
+        switch_page_table(vmm::kernel_page_table());
+        return;
+    }
+    // SAFETY: `next` isn't running, so its `run` frame, and the address space it borrows, are
+    // still there.
+    let (space, top) = unsafe {
+        (
+            &*(*context).space,
+            (*context).kernel_rsp.load(Ordering::Relaxed),
+        )
+    };
+    switch_page_table(space.level_4_frame());
+    // SAFETY: As above.
+    unsafe { (*context).fpu.restore() };
+    if top != 0 {
+        set_kernel_stack(top);
+    }
+}
+
+/// The address space the calling thread is running user code in, or null if it isn't.
+///
+/// It stays valid until the thread leaves user mode.
+fn current_space() -> *const AddressSpace {
+    let context = thread::current().user_context();
+    if context.is_null() {
+        ptr::null()
+    } else {
+        // SAFETY: The context belongs to the calling thread's `run`, further up its stack.
+        unsafe { (*context).space }
+    }
+}
+
+/// Loads and runs a small static executable, which writes a line, makes a few other system calls
+/// and exits, and logs whether it came back as expected.
+pub fn self_test() {
+    const HELLO: &[u8] = include_bytes!("fixtures/hello.elf");
+
+    let program = match load(HELLO, &["hello", "world"], &["LANG=C"]) {
+        Ok(program) => program,
+        Err(e) => {
+            log::error!("Couldn't load the user mode self-test: {}", e);
+            return;
+        }
+    };
+    // It exits with 40 plus its argument count.
+    match program.run() {
+        UserExit::Exited(42) => log::info!("User mode works"),
+        exit => log::error!("User mode self-test came back with {:?}", exit),
+    }
+}
@@ -0,0 +1,163 @@
+//! The way into the kernel from user mode: the `syscall` instruction.
+//!
+//! A system call passes its number in `rax` and its arguments in `rdi`, `rsi`, `rdx`, `r10`, `r8`
+//! and `r9`, and gets its result back in `rax`. Every other register is preserved, except `rcx`
+//! and `r11`, which `syscall` itself overwrites. The numbers, and the errors system calls return
+//! negated, are defined in [`roxy_abi`].
+
+use core::arch::global_asm;
+use x86_64::{
+    instructions::interrupts as cpu_interrupts,
+    registers::{
+        model_specific::{Efer, EferFlags, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    VirtAddr,
+};
+
+use roxy_abi::{nr, Errno};
+
+use super::{
+    calls::{self, Args},
+    Fault, UserExit, USER_SPACE_END,
+};
+use crate::{
+    boot::gdt::{
+        KERNEL_CODE_SELECTOR, KERNEL_DATA_SELECTOR, USER_CODE_SELECTOR, USER_DATA_SELECTOR,
+    },
+    smp::percpu::SCRATCH_OFFSET,
+};
+
+type Handler = fn(Args) -> Result<u64, Errno>;
+
+/// The system calls, by number.
+static TABLE: [Option<Handler>; nr::COUNT] = {
+    let mut table: [Option<Handler>; nr::COUNT] = [None; nr::COUNT];
+    table[nr::EXIT as usize] = Some(calls::exit);
+    table[nr::YIELD as usize] = Some(calls::yield_now);
+    table[nr::WRITE as usize] = Some(calls::write);
+    table[nr::CLOCK_GET as usize] = Some(calls::clock_get);
+    table[nr::SLEEP as usize] = Some(calls::sleep);
+    table[nr::THREAD_ID as usize] = Some(calls::thread_id);
+    table
+};
+
+/// The length of the `syscall` instruction.
+const SYSCALL_LEN: u64 = 2;
+
+/// The scratch slot the entry code keeps the user stack pointer in until it's on the kernel stack.
+const USER_RSP_SLOT: usize = 0;
+/// The scratch slot holding the kernel stack pointer to take system calls on.
+pub(super) const KERNEL_RSP_SLOT: usize = 1;
+
+/// The registers of the user thread that made a system call, as the entry code saves them.
+#[repr(C)]
+#[derive(Debug)]
+pub struct SyscallFrame {
+    /// The system call number, and then its result.
+    pub rax: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub r10: u64,
+    pub r8: u64,
+    pub r9: u64,
+    /// Where to return to, as `syscall` saved it in `rcx`.
+    pub rip: u64,
+    /// The flags to return with, as `syscall` saved them in `r11`.
+    pub rflags: u64,
+    pub rsp: u64,
+}
+
+global_asm!(
+    r#"
+    .global roxy_syscall_entry
+roxy_syscall_entry:
+    swapgs
+    mov gs:[{scratch} + 8 * {user_rsp}], rsp
+    mov rsp, gs:[{scratch} + 8 * {kernel_rsp}]
+    push qword ptr gs:[{scratch} + 8 * {user_rsp}]
+    push r11
+    push rcx
+    push r9
+    push r8
+    push r10
+    push rdx
+    push rsi
+    push rdi
+    push rax
+    mov rdi, rsp
+    call {handle}
+    pop rax
+    pop rdi
+    pop rsi
+    pop rdx
+    pop r10
+    pop r8
+    pop r9
+    pop rcx
+    pop r11
+    pop rsp
+    swapgs
+    sysretq
+    "#,
+    scratch = const SCRATCH_OFFSET,
+    user_rsp = const USER_RSP_SLOT,
+    kernel_rsp = const KERNEL_RSP_SLOT,
+    handle = sym handle,
+);
+
+extern "C" {
+    fn roxy_syscall_entry();
+}
+
+/// Sets up the calling CPU to take system calls.
+pub(super) fn init_cpu() {
+    Star::write(
+        USER_CODE_SELECTOR,
+        USER_DATA_SELECTOR,
+        KERNEL_CODE_SELECTOR,
+        KERNEL_DATA_SELECTOR,
+    )
+    .expect("GDT to have the layout syscall and sysret expect");
+    LStar::write(VirtAddr::new(roxy_syscall_entry as *const () as u64));
+    // The entry code runs with interrupts disabled until it's on the kernel stack.
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::ALIGNMENT_CHECK,
+    );
+    unsafe {
+        // SAFETY: The MSRs `syscall` and `sysret` depend on are set up.
+        Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS));
+    }
+}
+
+/// Handles a system call, entered with interrupts disabled and returning with them disabled.
+extern "C" fn handle(frame: &mut SyscallFrame) {
+    cpu_interrupts::enable();
+    dispatch(frame);
+    cpu_interrupts::disable();
+    // A `syscall` at the very end of user space would return to a non-canonical address, and
+    // `sysret` would fault in the kernel, on the user stack. Fault on the `syscall` instead.
+    if frame.rip >= USER_SPACE_END {
+        super::leave(UserExit::Fault {
+            fault: Fault::GeneralProtection { error_code: 0 },
+            ip: VirtAddr::new(frame.rip - SYSCALL_LEN),
+        });
+    }
+}
+
+fn dispatch(frame: &mut SyscallFrame) {
+    let handler = usize::try_from(frame.rax)
+        .ok()
+        .and_then(|nr| TABLE.get(nr).copied().flatten());
+    let result = match handler {
+        Some(handler) => handler([
+            frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9,
+        ]),
+        None => Err(Errno::ENOSYS),
+    };
+    frame.rax = roxy_abi::encode(result);
+}
@@ -0,0 +1,254 @@
+use alloc::vec::Vec;
+use x86_64::{
+    structures::paging::{
+        mapper::{MapToError, Translate, TranslateResult},
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+        PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::{phys_to_virt, tlb, VirtualMemoryManager, PHYSICAL_MAP_START};
+use crate::user::USER_SPACE_END;
+
+/// The first level 4 entry of the kernel's half of every address space.
+pub(super) const KERNEL_HALF_START: usize = 256;
+
+/// A page table of its own for user space, sharing the kernel's half with every other one.
+///
+/// Address spaces are made and freed by the [`VirtualMemoryManager`], which maps user memory into
+/// them.
+#[derive(Debug)]
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+}
+
+impl AddressSpace {
+    /// The frame holding the level 4 table, for CR3.
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+}
+
+/// The page table in `frame`, through the physical memory map.
+///
+/// # Safety
+///
+/// `frame` must hold a page table, and nothing else may be using it for as long as the reference
+/// lives.
+unsafe fn table_in<'a>(frame: PhysFrame) -> &'a mut PageTable {
+    unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr() }
+}
+
+/// The pages covering `size` bytes at `start`, which must all be in user space.
+fn user_pages(start: VirtAddr, size: u64) -> (Page, u64) {
+    assert!(
+        start
+            .as_u64()
+            .checked_add(size)
+            .is_some_and(|end| end <= USER_SPACE_END),
+        "user mapping to be in user space"
+    );
+    let first = Page::<Size4KiB>::containing_address(start);
+    let end = start.as_u64() + size;
+    (
+        first,
+        end.div_ceil(first.size()) - first.start_address().as_u64() / first.size(),
+    )
+}
+
+impl VirtualMemoryManager {
+    /// Makes an address space with an empty user half.
+    pub fn new_address_space(&mut self) -> Result<AddressSpace, MapToError<Size4KiB>> {
+        let frame = self
+            .frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        // SAFETY: The frame is fresh, so nothing else is using it.
+        let table = unsafe { table_in(frame) };
+        table.zero();
+        // The kernel's level 4 entries never change once `init` has filled them in, so copies
+        // stay in step.
+        for (entry, kernel_entry) in table
+            .iter_mut()
+            .zip(self.page_table.level_4_table().iter())
+            .skip(KERNEL_HALF_START)
+        {
+            *entry = kernel_entry.clone();
+        }
+        Ok(AddressSpace {
+            level_4_frame: frame,
+        })
+    }
+
+    /// Frees an address space, along with all of its user memory and page tables.
+    ///
+    /// # Safety
+    ///
+    /// No CPU may have the address space loaded.
+    pub unsafe fn free_address_space(&mut self, space: AddressSpace) {
+        // SAFETY: The address space is the caller's alone, and unused.
+        let level_4 = unsafe { table_in(space.level_4_frame) };
+        for entry in level_4.iter_mut().take(KERNEL_HALF_START) {
+            if let Ok(frame) = entry.frame() {
+                unsafe { self.free_user_table(frame, 3) };
+            }
+        }
+        unsafe { self.frame_allocator.deallocate_frame(space.level_4_frame) };
+    }
+
+    /// Frees the user page table in `frame` at `level`, and everything under it.
+    ///
+    /// # Safety
+    ///
+    /// As with [`Self::free_address_space`].
+    unsafe fn free_user_table(&mut self, frame: PhysFrame, level: u8) {
+        let table = unsafe { table_in(frame) };
+        for entry in table.iter() {
+            let Ok(child) = entry.frame() else {
+                // Unused, or a huge page, which user memory is never mapped with.
+                continue;
+            };
+            if level > 1 {
+                unsafe { self.free_user_table(child, level - 1) };
+            } else {
+                unsafe { self.frame_allocator.deallocate_frame(child) };
+            }
+        }
+        unsafe { self.frame_allocator.deallocate_frame(frame) };
+    }
+
+    /// Maps the pages covering `size` bytes at `start` in `space` to fresh, zeroed memory, with
+    /// `flags` on top of [`PageTableFlags::PRESENT`] and [`PageTableFlags::USER_ACCESSIBLE`].
+    ///
+    /// Fails with [`MapToError::PageAlreadyMapped`] if any of the pages is mapped already, leaving
+    /// none of them mapped.
+    pub fn map_user(
+        &mut self,
+        space: &mut AddressSpace,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let (first, pages) = user_pages(start, size);
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let parent_flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        for (i, page) in Page::range(first, first + pages).enumerate() {
+            let mapped = match self.frame_allocator.allocate_frame() {
+                Some(frame) => {
+                    unsafe {
+                        // SAFETY: The frame is fresh, so nothing else is using it.
+                        phys_to_virt(frame.start_address())
+                            .as_mut_ptr::<u8>()
+                            .write_bytes(0, first.size() as usize);
+                    }
+                    // SAFETY: `space` is borrowed mutably, and only its user half is touched.
+                    let mut table = unsafe { user_table(space) };
+                    unsafe {
+                        // SAFETY: The frame is fresh, and the page is in user space.
+                        table.map_to_with_table_flags(
+                            page,
+                            frame,
+                            flags,
+                            parent_flags,
+                            &mut self.frame_allocator,
+                        )
+                    }
+                    .inspect_err(|_| unsafe { self.frame_allocator.deallocate_frame(frame) })
+                }
+                None => Err(MapToError::FrameAllocationFailed),
+            };
+            if let Err(e) = mapped {
+                self.unmap_user(space, first.start_address(), i as u64 * first.size());
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Unmaps the pages covering `size` bytes at `start` in `space`, and frees the memory behind
+    /// them. Pages that aren't mapped are skipped.
+    pub fn unmap_user(&mut self, space: &mut AddressSpace, start: VirtAddr, size: u64) {
+        let (first, pages) = user_pages(start, size);
+        // SAFETY: As in `map_user`.
+        let mut table = unsafe { user_table(space) };
+        let mut frames = Vec::new();
+        for page in Page::range(first, first + pages) {
+            if let Ok((frame, flush)) = table.unmap(page) {
+                flush.flush();
+                frames.push(frame);
+            }
+        }
+        // As with kernel stacks, other CPUs may still have the pages cached.
+        tlb::shootdown(first.start_address(), pages);
+        for frame in frames {
+            unsafe { self.frame_allocator.deallocate_frame(frame) };
+        }
+    }
+
+    /// The physical address behind `addr` in `space`, if it's mapped for user space, and writable
+    /// if `write` is set.
+    pub fn translate_user(
+        &self,
+        space: &AddressSpace,
+        addr: VirtAddr,
+        write: bool,
+    ) -> Option<PhysAddr> {
+        if addr.as_u64() >= USER_SPACE_END {
+            return None;
+        }
+        // SAFETY: Only read, while the VMM lock keeps anything from changing it.
+        let table = unsafe { user_table(space) };
+        let TranslateResult::Mapped {
+            frame,
+            offset,
+            flags,
+        } = table.translate(addr)
+        else {
+            return None;
+        };
+        let mut required = PageTableFlags::USER_ACCESSIBLE;
+        if write {
+            required |= PageTableFlags::WRITABLE;
+        }
+        flags
+            .contains(required)
+            .then(|| frame.start_address() + offset)
+    }
+}
+
+/// Copies `data` into `space` at `addr`, whatever the pages' permissions. Returns the first address
+/// that isn't mapped, if there is one.
+///
+/// The VMM is only locked to look up each page, not for the copy: pages only come out of an
+/// address space through a mutable borrow of it, or by freeing it, so they stay mapped while
+/// `space` is borrowed.
+pub fn write_user(space: &AddressSpace, addr: VirtAddr, data: &[u8]) -> Result<(), VirtAddr> {
+    let mut done = 0;
+    while done < data.len() {
+        let piece_addr = addr + done as u64;
+        let piece_len = ((0x1000 - piece_addr.as_u64() % 0x1000) as usize).min(data.len() - done);
+        let phys = super::get()
+            .translate_user(space, piece_addr, false)
+            .ok_or(piece_addr)?;
+        unsafe {
+            // SAFETY: The piece is mapped user memory, which stays mapped while `space` is
+            // borrowed.
+            phys_to_virt(phys)
+                .as_mut_ptr::<u8>()
+                .copy_from_nonoverlapping(data[done..].as_ptr(), piece_len);
+        }
+        done += piece_len;
+    }
+    Ok(())
+}
+
+/// A mapper for `space`.
+///
+/// # Safety
+///
+/// Nothing else may change `space`'s page tables while the mapper is in use.
+unsafe fn user_table(space: &AddressSpace) -> OffsetPageTable<'_> {
+    unsafe { OffsetPageTable::new(table_in(space.level_4_frame), PHYSICAL_MAP_START) }
+}
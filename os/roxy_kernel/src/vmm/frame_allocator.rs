@@ -0,0 +1,365 @@
+use alloc::{vec, vec::Vec};
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB},
+    PhysAddr,
+};
+
+use super::{MemoryMap, MemoryRegionKind};
+use crate::numa;
+
+/// The general-purpose frame allocator used once the kernel heap is available.
+///
+/// Usable memory is split into a pool per NUMA node. Frames come from the current CPU's node where
+/// possible, and otherwise from the other nodes, nearest first. Within a pool, frames are handed out
+/// from the usable regions in order, and freed frames are kept on a free list and reused before any
+/// new frames are taken from the map.
+pub struct KernelFrameAllocator {
+    pools: Vec<Pool>,
+}
+
+struct Pool {
+    domain: Option<u32>,
+    /// Every frame that belongs to this pool, whether or not it's been handed out.
+    span: Vec<(PhysAddr, PhysAddr)>,
+    usable: Vec<(PhysAddr, PhysAddr)>,
+    region: usize,
+    next: PhysAddr,
+    free: Vec<PhysFrame>,
+    /// Frames taken out of order from later usable regions by [`Pool::allocate_below`].
+    claimed: Vec<(PhysAddr, PhysAddr)>,
+    /// The indices of the other pools, nearest first.
+    fallback: Vec<usize>,
+}
+
+impl Pool {
+    fn allocate(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free.pop() {
+            return Some(frame);
+        }
+
+        while let Some(&(_, end)) = self.usable.get(self.region) {
+            if self.next < end {
+                let frame = PhysFrame::containing_address(self.next);
+                self.next += 4096u64;
+                return Some(frame);
+            }
+
+            self.region += 1;
+            if let Some(&(start, _)) = self.usable.get(self.region) {
+                self.next = start;
+            }
+        }
+        None
+    }
+
+    /// Allocates a frame that ends at or below `limit`, taking it from wherever one is left.
+    fn allocate_below(&mut self, limit: PhysAddr) -> Option<PhysFrame> {
+        if let Some(i) = self
+            .free
+            .iter()
+            .position(|f| f.start_address() + f.size() <= limit)
+        {
+            return Some(self.free.swap_remove(i));
+        }
+
+        for i in self.region..self.usable.len() {
+            let (start, end) = self.usable[i];
+            let start = if i == self.region { self.next } else { start };
+            if start + 4096u64 > end.min(limit) {
+                continue;
+            }
+            if i == self.region {
+                self.next += 4096u64;
+            } else {
+                self.usable[i].0 += 4096u64;
+                self.claimed.push((start, start + 4096u64));
+            }
+            return Some(PhysFrame::containing_address(start));
+        }
+        None
+    }
+
+    /// The ranges that have already been handed out, not counting the free list.
+    fn consumed(&self) -> impl Iterator<Item = (PhysAddr, PhysAddr)> + '_ {
+        let done = self.region.min(self.usable.len());
+        self.usable[..done]
+            .iter()
+            .copied()
+            .chain(
+                self.usable
+                    .get(self.region)
+                    .map(|&(start, _)| (start, self.next)),
+            )
+            .chain(self.claimed.iter().copied())
+    }
+
+    fn contains(&self, addr: PhysAddr) -> bool {
+        self.span
+            .iter()
+            .any(|&(start, end)| start <= addr && addr < end)
+    }
+}
+
+/// Removes the `consumed` ranges from `start..end`.
+fn subtract(
+    start: PhysAddr,
+    end: PhysAddr,
+    consumed: &[(PhysAddr, PhysAddr)],
+) -> Vec<(PhysAddr, PhysAddr)> {
+    let mut pieces = vec![(start, end)];
+    for &(used_start, used_end) in consumed {
+        pieces = pieces
+            .into_iter()
+            .flat_map(|(start, end)| [(start, end.min(used_start)), (start.max(used_end), end)])
+            .filter(|(start, end)| start < end)
+            .collect();
+    }
+    pieces
+}
+
+impl KernelFrameAllocator {
+    pub fn new(memory_map: &MemoryMap) -> Self {
+        Self::from_map(memory_map, &[], |_, _| 0)
+    }
+
+    fn from_map(
+        memory_map: &MemoryMap,
+        consumed: &[(PhysAddr, PhysAddr)],
+        distance: impl Fn(u32, u32) -> u8,
+    ) -> Self {
+        let mut pools: Vec<Pool> = Vec::new();
+        let usable = memory_map
+            .regions()
+            .iter()
+            .filter(|r| r.kind == MemoryRegionKind::Usable)
+            .map(|r| {
+                let range = (r.start.align_up(4096u64), r.end.align_down(4096u64));
+                (range, r.proximity_domain)
+            })
+            .filter(|((start, end), _)| start < end);
+        for ((start, end), domain) in usable {
+            let pool = match pools.iter().position(|p| p.domain == domain) {
+                Some(i) => &mut pools[i],
+                None => {
+                    pools.push(Pool {
+                        domain,
+                        span: Vec::new(),
+                        usable: Vec::new(),
+                        region: 0,
+                        next: PhysAddr::zero(),
+                        free: Vec::new(),
+                        claimed: Vec::new(),
+                        fallback: Vec::new(),
+                    });
+                    pools.last_mut().unwrap()
+                }
+            };
+            pool.span.push((start, end));
+            pool.usable.extend(subtract(start, end, consumed));
+        }
+
+        for i in 0..pools.len() {
+            let mut fallback: Vec<usize> = (0..pools.len()).filter(|&j| j != i).collect();
+            fallback.sort_by_key(|&j| match (pools[i].domain, pools[j].domain) {
+                (Some(from), Some(to)) => distance(from, to),
+                _ => u8::MAX,
+            });
+            let pool = &mut pools[i];
+            pool.fallback = fallback;
+            pool.next = pool.usable.first().map(|r| r.0).unwrap_or(PhysAddr::zero());
+        }
+        Self { pools }
+    }
+
+    /// Splits the allocator into per-node pools after the memory map has been annotated with
+    /// proximity domains, keeping track of the frames that have already been handed out.
+    pub fn rebuild(&mut self, memory_map: &MemoryMap, distance: impl Fn(u32, u32) -> u8) {
+        let consumed: Vec<_> = self.pools.iter().flat_map(Pool::consumed).collect();
+        let mut rebuilt = Self::from_map(memory_map, &consumed, distance);
+        for frame in self.pools.iter_mut().flat_map(|p| p.free.drain(..)) {
+            let domain = memory_map.proximity_domain(frame.start_address());
+            let pool = rebuilt.pools.iter().position(|p| p.domain == domain);
+            if let Some(pool) = rebuilt.pools.get_mut(pool.unwrap_or(0)) {
+                pool.free.push(frame);
+            }
+        }
+        *self = rebuilt;
+    }
+
+    /// The proximity domains the allocator has memory in.
+    pub fn domains(&self) -> impl Iterator<Item = Option<u32>> + '_ {
+        self.pools.iter().map(|p| p.domain)
+    }
+
+    /// Allocates a frame from `domain` if it has any left, and otherwise from the nearest node
+    /// that does.
+    pub fn allocate_frame_on(&mut self, domain: Option<u32>) -> Option<PhysFrame> {
+        let local = self
+            .pools
+            .iter()
+            .position(|p| p.domain == domain)
+            .unwrap_or(0);
+        let pool = self.pools.get_mut(local)?;
+        if let Some(frame) = pool.allocate() {
+            return Some(frame);
+        }
+        for i in 0..self.pools[local].fallback.len() {
+            let other = self.pools[local].fallback[i];
+            if let Some(frame) = self.pools[other].allocate() {
+                return Some(frame);
+            }
+        }
+        None
+    }
+
+    /// Allocates a frame that ends at or below `limit`, for hardware that can't address all of
+    /// memory, such as a processor starting up in real mode.
+    pub fn allocate_frame_below(&mut self, limit: PhysAddr) -> Option<PhysFrame> {
+        self.pools.iter_mut().find_map(|p| p.allocate_below(limit))
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for KernelFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        // Don't bother working out where we are if there's only one node.
+        let domain = if self.pools.len() > 1 {
+            numa::current_node()
+        } else {
+            None
+        };
+        self.allocate_frame_on(domain)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for KernelFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let pool = self
+            .pools
+            .iter()
+            .position(|p| p.contains(frame.start_address()))
+            .unwrap_or(0);
+        if let Some(pool) = self.pools.get_mut(pool) {
+            pool.free.push(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use x86_64::{
+        structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame},
+        PhysAddr,
+    };
+
+    use crate::{
+        acpi::MemoryAffinity,
+        vmm::{MemoryMap, MemoryPurpose, MemoryRegion, MemoryRegionKind},
+    };
+
+    use super::KernelFrameAllocator;
+
+    #[test]
+    pub fn allocates_only_usable_frames_then_reuses_freed_ones() {
+        let mut builder = MemoryMap::builder();
+        builder.add_region(MemoryRegion::new(
+            PhysAddr::new(0x0000),
+            PhysAddr::new(0x2000),
+            MemoryRegionKind::Usable,
+        ));
+        builder.add_region(MemoryRegion::new(
+            PhysAddr::new(0x2000),
+            PhysAddr::new(0x3000),
+            MemoryRegionKind::InUse(MemoryPurpose::KernelHeap),
+        ));
+        builder.add_region(MemoryRegion::new(
+            PhysAddr::new(0x3000),
+            PhysAddr::new(0x4000),
+            MemoryRegionKind::Usable,
+        ));
+        let map = builder.build();
+
+        let mut allocator = KernelFrameAllocator::new(&map);
+        let frames: std::vec::Vec<_> = core::iter::from_fn(|| allocator.allocate_frame()).collect();
+        assert_eq!(
+            std::vec![
+                PhysFrame::containing_address(PhysAddr::new(0x0000)),
+                PhysFrame::containing_address(PhysAddr::new(0x1000)),
+                PhysFrame::containing_address(PhysAddr::new(0x3000)),
+            ],
+            frames
+        );
+
+        unsafe { allocator.deallocate_frame(frames[1]) };
+        assert_eq!(Some(frames[1]), allocator.allocate_frame());
+        assert_eq!(None, allocator.allocate_frame());
+    }
+
+    #[test]
+    pub fn prefers_the_local_node() {
+        let mut builder = MemoryMap::builder();
+        builder.add_region(MemoryRegion::new(
+            PhysAddr::new(0x0000),
+            PhysAddr::new(0x4000),
+            MemoryRegionKind::Usable,
+        ));
+        let mut map = builder.build();
+        let mut allocator = KernelFrameAllocator::new(&map);
+        let first = allocator.allocate_frame().unwrap();
+
+        let affinity = |base: u64, proximity_domain: u32| MemoryAffinity {
+            base: PhysAddr::new(base),
+            length: 0x2000,
+            proximity_domain,
+            enabled: true,
+            hot_pluggable: false,
+            non_volatile: false,
+        };
+        map.assign_proximity_domains(&[affinity(0x0000, 0), affinity(0x2000, 1)]);
+        allocator.rebuild(&map, |from, to| if from == to { 10 } else { 20 });
+
+        let frame = |addr| PhysFrame::containing_address(PhysAddr::new(addr));
+        assert_eq!(Some(frame(0x2000)), allocator.allocate_frame_on(Some(1)));
+        assert_eq!(Some(frame(0x3000)), allocator.allocate_frame_on(Some(1)));
+        // The frame handed out before the rebuild isn't handed out again.
+        assert_eq!(Some(frame(0x1000)), allocator.allocate_frame_on(Some(1)));
+
+        unsafe { allocator.deallocate_frame(first) };
+        assert_eq!(Some(first), allocator.allocate_frame_on(Some(0)));
+        assert_eq!(None, allocator.allocate_frame_on(Some(0)));
+    }
+
+    #[test]
+    pub fn allocates_below_a_limit_out_of_order() {
+        let mut builder = MemoryMap::builder();
+        builder.add_region(MemoryRegion::new(
+            PhysAddr::new(0x0000),
+            PhysAddr::new(0x1000),
+            MemoryRegionKind::Usable,
+        ));
+        builder.add_region(MemoryRegion::new(
+            PhysAddr::new(0x1000),
+            PhysAddr::new(0x2000),
+            MemoryRegionKind::InUse(MemoryPurpose::KernelHeap),
+        ));
+        builder.add_region(MemoryRegion::new(
+            PhysAddr::new(0x2000),
+            PhysAddr::new(0x4000),
+            MemoryRegionKind::Usable,
+        ));
+        let map = builder.build();
+        let mut allocator = KernelFrameAllocator::new(&map);
+
+        let frame = |addr| PhysFrame::containing_address(PhysAddr::new(addr));
+        assert_eq!(Some(frame(0x0000)), allocator.allocate_frame());
+        assert_eq!(None, allocator.allocate_frame_below(PhysAddr::new(0x2000)));
+        assert_eq!(
+            Some(frame(0x2000)),
+            allocator.allocate_frame_below(PhysAddr::new(0x3000))
+        );
+
+        // The claimed frame survives a rebuild, and isn't handed out again.
+        allocator.rebuild(&map, |_, _| 10);
+        assert_eq!(Some(frame(0x3000)), allocator.allocate_frame());
+        assert_eq!(None, allocator.allocate_frame());
+    }
+}
@@ -1,6 +1,8 @@
 use alloc::{boxed::Box, vec::Vec};
 use x86_64::PhysAddr;
 
+use crate::acpi::MemoryAffinity;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ReservedMemoryKind {
     Unknown,
@@ -28,11 +30,18 @@ pub struct MemoryRegion {
     pub start: PhysAddr,
     pub end: PhysAddr,
     pub kind: MemoryRegionKind,
+    /// The NUMA proximity domain the region belongs to, once the SRAT has been read.
+    pub proximity_domain: Option<u32>,
 }
 
 impl MemoryRegion {
     pub fn new(start: PhysAddr, end: PhysAddr, kind: MemoryRegionKind) -> Self {
-        Self { start, end, kind }
+        Self {
+            start,
+            end,
+            kind,
+            proximity_domain: None,
+        }
     }
 
     pub fn size(&self) -> u64 {
@@ -41,7 +50,10 @@ impl MemoryRegion {
 
     // This could probably be reconciled with `try_merge` to avoid duplication.
     pub fn try_append(&mut self, other: &MemoryRegion) -> bool {
-        if other.start == self.end && other.kind == self.kind {
+        if other.start == self.end
+            && other.kind == self.kind
+            && other.proximity_domain == self.proximity_domain
+        {
             self.end = other.end;
             true
         } else {
@@ -149,6 +161,44 @@ impl MemoryMap {
     pub fn reserved_memory(&self) -> u64 {
         self.total_memory - self.usable_memory
     }
+
+    /// Splits regions at the boundaries of the SRAT's memory ranges, and annotates each with the
+    /// proximity domain it falls in.
+    pub fn assign_proximity_domains(&mut self, affinities: &[MemoryAffinity]) {
+        let affinities: Vec<_> = affinities.iter().filter(|a| a.enabled).collect();
+        let mut regions = Vec::with_capacity(self.regions.len());
+        for region in self.regions.iter() {
+            let mut start = region.start;
+            while start < region.end {
+                let affinity = affinities.iter().find(|a| a.contains(start));
+                let end = match affinity {
+                    Some(a) => region.end.min(a.base + a.length),
+                    // Stop at the next range that does have a domain.
+                    None => affinities
+                        .iter()
+                        .map(|a| a.base)
+                        .filter(|&base| base > start)
+                        .fold(region.end, PhysAddr::min),
+                };
+                regions.push(MemoryRegion {
+                    start,
+                    end,
+                    kind: region.kind,
+                    proximity_domain: affinity.map(|a| a.proximity_domain),
+                });
+                start = end;
+            }
+        }
+        self.regions = regions.into_boxed_slice();
+    }
+
+    /// The proximity domain of a physical address, if it's known.
+    pub fn proximity_domain(&self, addr: PhysAddr) -> Option<u32> {
+        self.regions
+            .iter()
+            .find(|r| r.start <= addr && addr < r.end)
+            .and_then(|r| r.proximity_domain)
+    }
 }
 
 pub struct MemoryMapBuilder(Vec<MemoryRegion>);
@@ -179,7 +229,10 @@ impl MemoryMapBuilder {
 mod test {
     use x86_64::PhysAddr;
 
-    use crate::vmm::{MemoryPurpose, MemoryRegion, MemoryRegionKind};
+    use crate::{
+        acpi::MemoryAffinity,
+        vmm::{MemoryMap, MemoryPurpose, MemoryRegion, MemoryRegionKind, ReservedMemoryKind},
+    };
 
     #[test]
     pub fn try_merge_non_overlapping_or_adjacent() {
@@ -305,6 +358,49 @@ mod test {
         );
     }
 
+    #[test]
+    pub fn assigns_proximity_domains() {
+        let mut builder = MemoryMap::builder();
+        builder.add_region(MemoryRegion::new(
+            PhysAddr::new(0x0000),
+            PhysAddr::new(0x4000),
+            MemoryRegionKind::Usable,
+        ));
+        builder.add_region(MemoryRegion::new(
+            PhysAddr::new(0x4000),
+            PhysAddr::new(0x5000),
+            MemoryRegionKind::Reserved(ReservedMemoryKind::Unknown),
+        ));
+        let mut map = builder.build();
+
+        let affinity = |base: u64, length: u64, proximity_domain: u32| MemoryAffinity {
+            base: PhysAddr::new(base),
+            length,
+            proximity_domain,
+            enabled: true,
+            hot_pluggable: false,
+            non_volatile: false,
+        };
+        map.assign_proximity_domains(&[affinity(0x0000, 0x2000, 0), affinity(0x3000, 0x2000, 1)]);
+
+        let domains: std::vec::Vec<_> = map
+            .regions()
+            .iter()
+            .map(|r| (r.start.as_u64(), r.end.as_u64(), r.proximity_domain))
+            .collect();
+        assert_eq!(
+            std::vec![
+                (0x0000, 0x2000, Some(0)),
+                (0x2000, 0x3000, None),
+                (0x3000, 0x4000, Some(1)),
+                (0x4000, 0x5000, Some(1)),
+            ],
+            domains
+        );
+        assert_eq!(Some(1), map.proximity_domain(PhysAddr::new(0x3800)));
+        assert_eq!(0x5000, map.total_memory());
+    }
+
     #[test]
     pub fn try_merge_absorb_overlap() {
         let left = MemoryRegion::new(
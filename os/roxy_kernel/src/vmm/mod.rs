@@ -1,13 +1,333 @@
-use x86_64::VirtAddr;
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+use spinning_top::{guard::SpinlockGuard, Spinlock};
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page,
+        PageTable, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use crate::{acpi::MemoryAffinity, sched, smp::call};
 
 pub const KERNEL_IMAGE_START: VirtAddr = VirtAddr::new_truncate(0x8000_0000_0000);
 pub const KERNEL_STACK_START: VirtAddr = VirtAddr::new_truncate(0x9000_0000_0000);
+pub const KERNEL_STACKS_START: VirtAddr = VirtAddr::new_truncate(0x9100_0000_0000);
 pub const KERNEL_HEAP_START: VirtAddr = VirtAddr::new_truncate(0xA000_0000_0000);
+pub const MMIO_START: VirtAddr = VirtAddr::new_truncate(0xA800_0000_0000);
 pub const PHYSICAL_MAP_START: VirtAddr = VirtAddr::new_truncate(0xC000_0000_0000);
 
+mod address_space;
+mod frame_allocator;
 mod memory_map;
+mod stack;
+pub mod tlb;
+pub use address_space::*;
+pub use frame_allocator::*;
 pub use memory_map::*;
+pub use stack::*;
+
+/// Returns the address at which the bootloader's physical memory mapping makes `addr` visible.
+///
+/// This mapping is cacheable, so device registers should go through [`VirtualMemoryManager::map_mmio`] instead.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    PHYSICAL_MAP_START + addr.as_u64()
+}
+
+static VMM: OnceCell<Spinlock<VirtualMemoryManager>> = OnceCell::uninit();
+static KERNEL_PAGE_TABLE: OnceCell<PhysFrame> = OnceCell::uninit();
+
+/// Initializes the global [`VirtualMemoryManager`].
+///
+/// # Safety
+///
+/// The caller must guarantee that `page_table` is the active page table and that every frame
+/// marked as usable in `memory_map` is really unused.
+pub unsafe fn init(page_table: OffsetPageTable<'static>, memory_map: MemoryMap) {
+    KERNEL_PAGE_TABLE.init_once(|| Cr3::read().0);
+    VMM.try_init_once(|| Spinlock::new(VirtualMemoryManager::new(page_table, memory_map)))
+        .expect("VMM already initialized");
+}
+
+/// The frame holding the kernel's level 4 table, which kernel threads run with.
+pub fn kernel_page_table() -> PhysFrame {
+    *KERNEL_PAGE_TABLE.get().expect("VMM not initialized")
+}
+
+/// Locks and returns the global [`VirtualMemoryManager`].
+///
+/// Preemption is disabled while the lock is held, so the holder can't be switched out for a thread
+/// that would then spin on the lock forever.
+///
+/// Panics if [`init`] has not been called yet.
+pub fn get() -> VmmGuard {
+    let vmm = VMM.get().expect("VMM not initialized");
+    sched::preempt_disable();
+    loop {
+        if let Some(guard) = vmm.try_lock() {
+            return VmmGuard::new(guard);
+        }
+        // Whoever holds the lock may be waiting for this CPU to flush its TLB, and we could have
+        // interrupts disabled.
+        call::run_pending();
+        core::hint::spin_loop();
+    }
+}
+
+/// Locks and returns the global [`VirtualMemoryManager`], unless it's locked already.
+///
+/// For paths that can't risk waiting on a lock they may have been interrupted holding, like
+/// panicking. Panics if [`init`] has not been called yet.
+pub fn try_get() -> Option<VmmGuard> {
+    let vmm = VMM.get().expect("VMM not initialized");
+    sched::preempt_disable();
+    let guard = vmm.try_lock().map(VmmGuard::new);
+    if guard.is_none() {
+        sched::preempt_enable();
+    }
+    guard
+}
+
+/// The locked [`VirtualMemoryManager`], with preemption disabled until it's dropped.
+pub struct VmmGuard {
+    guard: ManuallyDrop<SpinlockGuard<'static, VirtualMemoryManager>>,
+}
+
+impl VmmGuard {
+    /// Wraps the guard of a lock taken with preemption disabled.
+    fn new(guard: SpinlockGuard<'static, VirtualMemoryManager>) -> Self {
+        Self {
+            guard: ManuallyDrop::new(guard),
+        }
+    }
+}
+
+impl Deref for VmmGuard {
+    type Target = VirtualMemoryManager;
+
+    fn deref(&self) -> &VirtualMemoryManager {
+        &self.guard
+    }
+}
+
+impl DerefMut for VmmGuard {
+    fn deref_mut(&mut self) -> &mut VirtualMemoryManager {
+        &mut self.guard
+    }
+}
+
+impl Drop for VmmGuard {
+    fn drop(&mut self) {
+        unsafe {
+            // SAFETY: The guard is never used again.
+            ManuallyDrop::drop(&mut self.guard);
+        }
+        sched::preempt_enable();
+    }
+}
+
+pub struct VirtualMemoryManager {
+    page_table: OffsetPageTable<'static>,
+    frame_allocator: KernelFrameAllocator,
+    memory_map: MemoryMap,
+    next_stack: VirtAddr,
+    next_mmio: VirtAddr,
+}
+
+impl VirtualMemoryManager {
+    fn new(mut page_table: OffsetPageTable<'static>, memory_map: MemoryMap) -> Self {
+        let mut frame_allocator = KernelFrameAllocator::new(&memory_map);
+        // Every level 4 entry in the kernel's half gets a table up front, so that address spaces
+        // can share them, and see whatever the kernel maps later.
+        for entry in page_table
+            .level_4_table_mut()
+            .iter_mut()
+            .skip(KERNEL_HALF_START)
+            .filter(|entry| entry.is_unused())
+        {
+            let frame = frame_allocator
+                .allocate_frame()
+                .expect("to have memory for the kernel's page tables");
+            unsafe {
+                // SAFETY: The frame is fresh, so nothing else is using it.
+                phys_to_virt(frame.start_address())
+                    .as_mut_ptr::<PageTable>()
+                    .write(PageTable::new());
+            }
+            entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
+        Self {
+            page_table,
+            frame_allocator,
+            memory_map,
+            next_stack: KERNEL_STACKS_START,
+            next_mmio: MMIO_START,
+        }
+    }
+
+    pub fn memory_map(&self) -> &MemoryMap {
+        &self.memory_map
+    }
+
+    /// Annotates the memory map with the proximity domains from the SRAT, and splits the frame
+    /// allocator into a pool per node.
+    pub fn assign_proximity_domains(
+        &mut self,
+        affinities: &[MemoryAffinity],
+        distance: impl Fn(u32, u32) -> u8,
+    ) {
+        self.memory_map.assign_proximity_domains(affinities);
+        self.frame_allocator.rebuild(&self.memory_map, distance);
+    }
+
+    /// Allocates a frame that ends at or below `limit`, for hardware that can't address all of
+    /// memory. The frame is never freed.
+    pub fn allocate_frame_below(&mut self, limit: PhysAddr) -> Option<PhysFrame> {
+        self.frame_allocator.allocate_frame_below(limit)
+    }
+
+    /// Allocates a kernel stack of `pages` pages, preceded by an unmapped guard page.
+    pub fn allocate_kernel_stack(
+        &mut self,
+        pages: u64,
+    ) -> Result<KernelStack, MapToError<Size4KiB>> {
+        let guard = Page::containing_address(self.next_stack);
+        let stack = KernelStack::new(guard, pages);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        for page in Page::range(guard + 1, guard + 1 + pages) {
+            let mapped = match self.frame_allocator.allocate_frame() {
+                Some(frame) => unsafe {
+                    // SAFETY: The stack range is reserved for this stack alone, and the frame is
+                    // fresh.
+                    self.page_table
+                        .map_to(page, frame, flags, &mut self.frame_allocator)
+                        .map(|flush| flush.flush())
+                        .inspect_err(|_| self.frame_allocator.deallocate_frame(frame))
+                },
+                None => Err(MapToError::FrameAllocationFailed),
+            };
+            if let Err(e) = mapped {
+                unsafe {
+                    // SAFETY: The stack was never handed out.
+                    self.free_kernel_stack(stack);
+                }
+                return Err(e);
+            }
+        }
+        // Only now is the range taken, so a failed allocation leaves it for the next one.
+        self.next_stack += (pages + 1) * guard.size();
+        Ok(stack)
+    }
+
+    /// Unmaps a stack returned by [`Self::allocate_kernel_stack`] and frees its frames.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may still be running on, or referencing, the stack.
+    pub unsafe fn free_kernel_stack(&mut self, stack: KernelStack) {
+        let first = stack.guard_page() + 1;
+        let pages = stack.size() / first.size();
+        let mut frames = Vec::new();
+        for page in Page::<Size4KiB>::range(first, first + pages) {
+            if let Ok((frame, flush)) = self.page_table.unmap(page) {
+                flush.flush();
+                frames.push(frame);
+            }
+        }
+        // The frames can't be reused until no CPU can reach them through a stale TLB entry.
+        tlb::shootdown(first.start_address(), pages);
+        for frame in frames {
+            unsafe { self.frame_allocator.deallocate_frame(frame) };
+        }
+    }
+
+    /// Maps `size` bytes of device memory starting at `phys` as uncacheable, returning the virtual
+    /// address corresponding to `phys`.
+    pub fn map_mmio(
+        &mut self,
+        phys: PhysAddr,
+        size: u64,
+    ) -> Result<VirtAddr, MapToError<Size4KiB>> {
+        let first = PhysFrame::<Size4KiB>::containing_address(phys);
+        let last = PhysFrame::<Size4KiB>::containing_address(phys + (size.max(1) - 1));
+        let start = Page::<Size4KiB>::containing_address(self.next_mmio);
+
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_CACHE
+            | PageTableFlags::WRITE_THROUGH
+            | PageTableFlags::NO_EXECUTE;
+        for (i, frame) in PhysFrame::range_inclusive(first, last).enumerate() {
+            unsafe {
+                // SAFETY: The MMIO range is only handed out once, and device frames are never
+                // given to the frame allocator.
+                self.page_table
+                    .map_to(start + i as u64, frame, flags, &mut self.frame_allocator)?
+                    .flush();
+            }
+        }
+        self.next_mmio = (start + (last - first) + 1).start_address();
+
+        Ok(start.start_address() + (phys - first.start_address()))
+    }
 
-pub struct VirtualMemoryManager {}
+    /// Maps the physical range `start..end` at the same virtual addresses, for calling into
+    /// firmware that expects physical addressing.
+    ///
+    /// Fails with [`MapToError::PageAlreadyMapped`] if any of the pages is mapped already, leaving
+    /// none of them mapped, so that [`Self::unmap_identity`] only ever removes what this mapped.
+    ///
+    /// # Safety
+    ///
+    /// The range must not overlap anything else mapped in the lower half.
+    pub unsafe fn identity_map(
+        &mut self,
+        start: PhysAddr,
+        end: PhysAddr,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let first = PhysFrame::<Size4KiB>::containing_address(start);
+        let last = PhysFrame::<Size4KiB>::containing_address(end - 1u64);
+        for frame in PhysFrame::range_inclusive(first, last) {
+            let mapped = unsafe {
+                self.page_table
+                    .identity_map(frame, flags, &mut self.frame_allocator)
+            };
+            match mapped {
+                Ok(flush) => flush.flush(),
+                Err(e) => {
+                    if frame != first {
+                        unsafe {
+                            // SAFETY: Nothing has used the pages mapped so far.
+                            self.unmap_identity(first.start_address(), frame.start_address())
+                        };
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
 
-impl VirtualMemoryManager {}
+    /// Removes a mapping made by [`Self::identity_map`]. The frames themselves are not freed.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may still be referencing the range through its identity mapping.
+    pub unsafe fn unmap_identity(&mut self, start: PhysAddr, end: PhysAddr) {
+        let first = Page::<Size4KiB>::containing_address(VirtAddr::new(start.as_u64()));
+        let last = Page::<Size4KiB>::containing_address(VirtAddr::new(end.as_u64() - 1));
+        for page in Page::range_inclusive(first, last) {
+            if let Ok((_, flush)) = self.page_table.unmap(page) {
+                flush.flush();
+            }
+        }
+        tlb::shootdown(first.start_address(), last - first + 1);
+    }
+}
@@ -0,0 +1,42 @@
+use x86_64::{structures::paging::Page, VirtAddr};
+
+/// A kernel stack allocated by the [`VirtualMemoryManager`](super::VirtualMemoryManager).
+///
+/// The page directly below the stack is left unmapped as a guard page, so an overflow faults
+/// instead of silently corrupting whatever is mapped below it.
+#[derive(Debug)]
+pub struct KernelStack {
+    guard: Page,
+    bottom: VirtAddr,
+    top: VirtAddr,
+}
+
+impl KernelStack {
+    pub(super) fn new(guard: Page, pages: u64) -> Self {
+        let bottom = guard.start_address() + guard.size();
+        Self {
+            guard,
+            bottom,
+            top: bottom + pages * guard.size(),
+        }
+    }
+
+    /// The unmapped guard page below the stack.
+    pub fn guard_page(&self) -> Page {
+        self.guard
+    }
+
+    /// The lowest usable address of the stack.
+    pub fn bottom(&self) -> VirtAddr {
+        self.bottom
+    }
+
+    /// The initial stack pointer value. Stacks grow down, so this is one past the highest usable address.
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+
+    pub fn size(&self) -> u64 {
+        self.top - self.bottom
+    }
+}
@@ -0,0 +1,24 @@
+//! Keeping the TLBs of every CPU in step with the shared kernel page tables.
+
+use x86_64::{instructions::tlb, VirtAddr};
+
+use crate::smp::{call, ipi::Target};
+
+/// Past this many pages, flushing the whole TLB is cheaper than flushing pages one at a time.
+const FULL_FLUSH_PAGES: u64 = 32;
+
+/// Flushes `pages` pages starting at `start` from the TLBs of the other online CPUs, and waits for
+/// them to finish. The calling CPU is expected to have flushed its own TLB already.
+pub fn shootdown(start: VirtAddr, pages: u64) {
+    call::call(Target::AllButSelf, move || flush(start, pages));
+}
+
+fn flush(start: VirtAddr, pages: u64) {
+    if pages > FULL_FLUSH_PAGES {
+        tlb::flush_all();
+    } else {
+        for page in 0..pages {
+            tlb::flush(start + page * 4096);
+        }
+    }
+}
@@ -10,6 +10,31 @@ struct Args {
 
     #[clap(long, short)]
     memory: Option<String>,
+
+    /// Split the machine into this many NUMA nodes, each with an equal share of the CPUs and
+    /// memory.
+    #[clap(long)]
+    numa: Option<u64>,
+
+    /// Number of CPUs. Defaults to one, or one per NUMA node.
+    #[clap(long)]
+    smp: Option<u64>,
+}
+
+/// QEMU's default amount of memory, in MiB.
+const DEFAULT_MEMORY_MIB: u64 = 128;
+
+/// Parses a QEMU memory size such as `512`, `512M` or `4G` into MiB.
+fn parse_mib(size: &str) -> u64 {
+    let (number, scale) = match size.trim_end_matches(['B', 'b']).char_indices().last() {
+        Some((i, 'G' | 'g')) => (&size[..i], 1024),
+        Some((i, 'M' | 'm')) => (&size[..i], 1),
+        _ => (size, 1),
+    };
+    number
+        .parse::<u64>()
+        .expect("memory size to be a number of MiB or GiB")
+        * scale
 }
 
 fn main() {
@@ -36,8 +61,41 @@ fn main() {
             .arg("-no-shutdown");
     }
 
-    if let Some(memory) = args.memory {
-        cmd.arg("-m").arg(memory);
+    if let Some(nodes) = args.numa.filter(|&n| n > 0) {
+        let total = args.memory.as_deref().map_or(DEFAULT_MEMORY_MIB, parse_mib);
+        let per_node = total / nodes;
+        // Every node needs at least one CPU; any left over go to the last node.
+        let cpus = args.smp.unwrap_or(nodes).max(nodes);
+        let cpus_per_node = cpus / nodes;
+        cmd.arg("-m").arg(format!("{}M", per_node * nodes));
+        cmd.arg("-smp").arg(cpus.to_string());
+        for node in 0..nodes {
+            let first = node * cpus_per_node;
+            let last = if node == nodes - 1 {
+                cpus - 1
+            } else {
+                first + cpus_per_node - 1
+            };
+            cmd.arg("-object")
+                .arg(format!("memory-backend-ram,id=mem{node},size={per_node}M"));
+            cmd.arg("-numa").arg(format!(
+                "node,nodeid={node},cpus={first}-{last},memdev=mem{node}"
+            ));
+        }
+        // QEMU only provides a SLIT if distances are given.
+        for src in 0..nodes {
+            for dst in (0..nodes).filter(|&dst| dst != src) {
+                cmd.arg("-numa")
+                    .arg(format!("dist,src={src},dst={dst},val=20"));
+            }
+        }
+    } else {
+        if let Some(memory) = args.memory {
+            cmd.arg("-m").arg(memory);
+        }
+        if let Some(cpus) = args.smp {
+            cmd.arg("-smp").arg(cpus.to_string());
+        }
     }
 
     let mut child = cmd.spawn().unwrap();